@@ -23,6 +23,8 @@ Source3: eni-max-pods
 
 Source5: updog-toml
 Source6: metricdog-toml
+Source7: resolv-conf
+Source8: hostname
 
 # 1xx sources: systemd units
 Source100: apiserver.service
@@ -37,6 +39,13 @@ Source111: metricdog.service
 Source112: metricdog.timer
 Source113: send-boot-success.service
 Source114: bootstrap-containers@.service
+Source115: generate-network-config.service
+Source116: restore-after-drain.service
+Source117: update-agent.service
+Source118: update-agent.timer
+Source119: update-verifier.service
+Source120: metricdog-exporter.service
+Source121: set-hostname.service
 
 # 2xx sources: tmpfilesd configs
 Source200: migration-tmpfiles.conf
@@ -44,6 +53,7 @@ Source201: host-containers-tmpfiles.conf
 Source202: thar-be-updates-tmpfiles.conf
 Source203: bootstrap-containers-tmpfiles.conf
 Source204: netdog-tmpfiles.conf
+Source205: sheepdog-tmpfiles.conf
 
 # 3xx sources: udev rules
 Source300: ephemeral-storage.rules
@@ -65,11 +75,14 @@ Requires: %{_cross_os}netdog
 Requires: %{_cross_os}prairiedog
 Requires: %{_cross_os}schnauzer
 Requires: %{_cross_os}settings-committer
+Requires: %{_cross_os}sheepdog
 Requires: %{_cross_os}signpost
 Requires: %{_cross_os}storewolf
 Requires: %{_cross_os}sundog
 Requires: %{_cross_os}thar-be-settings
 Requires: %{_cross_os}thar-be-updates
+Requires: %{_cross_os}update-agent
+Requires: %{_cross_os}update-verifier
 Requires: %{_cross_os}updog
 
 %if %{_is_k8s_variant}
@@ -125,6 +138,11 @@ Summary: Bottlerocket sysctl helper
 %description -n %{_cross_os}corndog
 %{summary}.
 
+%package -n %{_cross_os}sheepdog
+Summary: Drains workloads from the host before reboot
+%description -n %{_cross_os}sheepdog
+%{summary}.
+
 %package -n %{_cross_os}schnauzer
 Summary: Setting generator for templated settings values.
 %description -n %{_cross_os}schnauzer
@@ -140,6 +158,17 @@ Summary: Dispatches Bottlerocket update commands
 %description -n %{_cross_os}thar-be-updates
 %{summary}.
 
+%package -n %{_cross_os}update-agent
+Summary: Applies updates during a maintenance window
+%description -n %{_cross_os}update-agent
+%{summary}.
+
+%package -n %{_cross_os}update-verifier
+Summary: Rolls back updates that fail health checks
+Requires: %{_cross_os}sheepdog
+%description -n %{_cross_os}update-verifier
+%{summary}.
+
 %package -n %{_cross_os}servicedog
 Summary: Manipulates systemd units based on setting changes
 %description -n %{_cross_os}servicedog
@@ -285,6 +314,8 @@ echo "** Output from non-static builds:"
     -p bork \
     -p thar-be-settings \
     -p thar-be-updates \
+    -p update-agent \
+    -p update-verifier \
     -p servicedog \
     -p host-containers \
     -p storewolf \
@@ -297,6 +328,7 @@ echo "** Output from non-static builds:"
     -p ghostdog \
     -p growpart \
     -p corndog \
+    -p sheepdog \
     -p bootstrap-containers \
     -p prairiedog \
 %if "%{_cross_variant}" == "aws-ecs-1"
@@ -326,11 +358,12 @@ install -d %{buildroot}%{_cross_bindir}
 for p in \
   apiserver \
   early-boot-config netdog sundog schnauzer bork corndog \
-  thar-be-settings thar-be-updates servicedog host-containers \
+  thar-be-settings thar-be-updates update-agent update-verifier \
+  servicedog host-containers \
   storewolf settings-committer \
   migrator prairiedog \
   signpost updog metricdog logdog \
-  ghostdog bootstrap-containers \
+  ghostdog bootstrap-containers sheepdog \
 %if "%{_cross_variant}" == "aws-ecs-1"
   ecs-settings-applier \
 %endif
@@ -388,13 +421,14 @@ install -d %{buildroot}%{_cross_datadir}/updog
 install -p -m 0644 %{_cross_repo_root_json} %{buildroot}%{_cross_datadir}/updog
 
 install -d %{buildroot}%{_cross_templatedir}
-install -p -m 0644 %{S:5} %{S:6} %{buildroot}%{_cross_templatedir}
+install -p -m 0644 %{S:5} %{S:6} %{S:7} %{S:8} %{buildroot}%{_cross_templatedir}
 
 install -d %{buildroot}%{_cross_unitdir}
 install -p -m 0644 \
   %{S:100} %{S:101} %{S:102} %{S:103} %{S:105} \
   %{S:106} %{S:107} %{S:110} %{S:111} %{S:112} \
-  %{S:113} %{S:114} \
+  %{S:113} %{S:114} %{S:115} %{S:116} %{S:117} \
+  %{S:118} %{S:119} %{S:120} %{S:121} \
   %{buildroot}%{_cross_unitdir}
 
 install -d %{buildroot}%{_cross_tmpfilesdir}
@@ -403,6 +437,7 @@ install -p -m 0644 %{S:201} %{buildroot}%{_cross_tmpfilesdir}/host-containers.co
 install -p -m 0644 %{S:202} %{buildroot}%{_cross_tmpfilesdir}/thar-be-updates.conf
 install -p -m 0644 %{S:203} %{buildroot}%{_cross_tmpfilesdir}/bootstrap-containers.conf
 install -p -m 0644 %{S:204} %{buildroot}%{_cross_tmpfilesdir}/netdog.conf
+install -p -m 0644 %{S:205} %{buildroot}%{_cross_tmpfilesdir}/sheepdog.conf
 
 install -d %{buildroot}%{_cross_udevrulesdir}
 install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-storage.rules
@@ -428,11 +463,21 @@ install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-stor
 
 %files -n %{_cross_os}netdog
 %{_cross_bindir}/netdog
+%{_cross_unitdir}/generate-network-config.service
+%{_cross_unitdir}/set-hostname.service
 %{_cross_tmpfilesdir}/netdog.conf
+%dir %{_cross_templatedir}
+%{_cross_templatedir}/resolv-conf
+%{_cross_templatedir}/hostname
 
 %files -n %{_cross_os}corndog
 %{_cross_bindir}/corndog
 
+%files -n %{_cross_os}sheepdog
+%{_cross_bindir}/sheepdog
+%{_cross_unitdir}/restore-after-drain.service
+%{_cross_tmpfilesdir}/sheepdog.conf
+
 %files -n %{_cross_os}sundog
 %{_cross_bindir}/sundog
 %{_cross_unitdir}/sundog.service
@@ -451,6 +496,15 @@ install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-stor
 %{_cross_bindir}/thar-be-updates
 %{_cross_tmpfilesdir}/thar-be-updates.conf
 
+%files -n %{_cross_os}update-agent
+%{_cross_bindir}/update-agent
+%{_cross_unitdir}/update-agent.service
+%{_cross_unitdir}/update-agent.timer
+
+%files -n %{_cross_os}update-verifier
+%{_cross_bindir}/update-verifier
+%{_cross_unitdir}/update-verifier.service
+
 %files -n %{_cross_os}servicedog
 %{_cross_bindir}/servicedog
 
@@ -497,6 +551,7 @@ install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-stor
 %{_cross_templatedir}/metricdog-toml
 %{_cross_unitdir}/metricdog.service
 %{_cross_unitdir}/metricdog.timer
+%{_cross_unitdir}/metricdog-exporter.service
 %{_cross_unitdir}/send-boot-success.service
 
 %files -n %{_cross_os}logdog
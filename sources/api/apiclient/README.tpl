@@ -93,6 +93,7 @@ apiclient update apply
 
 This downloads and writes the update to the alternate partition set, then marks it as active.
 The next time you reboot, for example with `apiclient reboot`, the update will take effect.
+apiclient waits up to 10 minutes for the download; if you've limited the download rate with `settings.updates.max-download-rate`, you can wait longer with `--timeout SECONDS`.
 
 If you're confident that you want to update immediately to the latest version, you can do all of the above in one step:
 
@@ -102,6 +103,47 @@ apiclient update apply --check --reboot
 
 > Note that available updates are controlled by your settings under `settings.updates`; see [README](../../../README.md#updates-settings) for details.
 
+To see what's happened with updates on the host over time, including refreshes, downloads, activations, boots into new versions, migrations, and automatic rollbacks, you can check the update history:
+
+```
+apiclient update history
+```
+
+The history is kept across reboots and updates, and each event shows whether it succeeded, the versions involved, and the error if it failed.
+Only recent events are kept.
+
+### History mode
+
+Every time settings are committed, the change is recorded as a numbered "generation" in the settings history.
+You can see the recorded generations, including when each happened and the previous values of the settings it changed:
+
+```
+apiclient history list
+```
+
+If a change turns out to be a mistake, you can restore settings to the way they were after an earlier generation.
+Any later changes are undone, and the restored settings are applied to the system, just like with `apiclient set`.
+
+```
+apiclient history rollback 42
+```
+
+The rollback is recorded as a new generation, so you can undo it the same way.
+Only recent generations are kept, so you can't roll back indefinitely far.
+
+### Transaction mode
+
+Settings changes are staged in a transaction before they're committed and applied; see the raw mode walkthrough below.
+Before committing, you can see how the pending changes would affect the system's configuration files:
+
+```
+apiclient tx diff
+```
+
+This is a dry-run commit of the transaction: each configuration file that would be rewritten is rendered with the pending settings and shown as a unified diff against the file currently on disk.
+Nothing is changed, and the transaction stays pending; like a commit, it fails if there are no pending changes.
+The "default" transaction is used unless you name another one with `--tx`.
+
 ### Reboot mode
 
 This will reboot the system.
@@ -0,0 +1,49 @@
+use snafu::ResultExt;
+use std::path::Path;
+
+/// Retrieves the history of changes to live settings through the API.  Returns the response body,
+/// a JSON list of generations, oldest first.
+pub async fn list<P>(socket_path: P) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/settings/history";
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    Ok(body)
+}
+
+/// Requests that settings be restored to the state they had after the given generation, and
+/// applied to the system.  Returns the response body, a JSON list of the changed keys.
+pub async fn rollback<P>(socket_path: P, generation: u64) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let uri = format!("/settings/rollback?generation={}", generation);
+    let method = "POST";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    Ok(body)
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
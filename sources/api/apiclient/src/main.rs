@@ -6,17 +6,18 @@
 // library calls based on the given flags, etc.)  The library modules contain the code for talking
 // to the API, which is intended to be reusable by other crates.
 
-use apiclient::{apply, reboot, set, update};
+use apiclient::{apply, history, reboot, set, tx, update};
 use datastore::{serialize_scalar, Key, KeyType};
 use log::{info, log_enabled, trace, warn};
 use simplelog::{
     ColorChoice, ConfigBuilder as LogConfigBuilder, LevelFilter, TermLogger, TerminalMode,
 };
 use snafu::ResultExt;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::process;
 use std::str::FromStr;
+use std::time::Duration;
 use unindent::unindent;
 
 const DEFAULT_API_SOCKET: &str = "/run/api.sock";
@@ -42,9 +43,11 @@ impl Default for Args {
 #[derive(Debug)]
 enum Subcommand {
     Apply(ApplyArgs),
+    History(HistorySubcommand),
     Raw(RawArgs),
     Reboot(RebootArgs),
     Set(SetArgs),
+    Tx(TxSubcommand),
     Update(UpdateSubcommand),
 }
 
@@ -54,6 +57,35 @@ struct ApplyArgs {
     input_sources: Vec<String>,
 }
 
+/// Stores the 'history' subcommand specified by the user.
+#[derive(Debug)]
+enum HistorySubcommand {
+    List(HistoryListArgs),
+    Rollback(HistoryRollbackArgs),
+}
+
+/// Stores user-supplied arguments for the 'history list' subcommand.
+#[derive(Debug)]
+struct HistoryListArgs {}
+
+/// Stores user-supplied arguments for the 'history rollback' subcommand.
+#[derive(Debug)]
+struct HistoryRollbackArgs {
+    generation: u64,
+}
+
+/// Stores the 'tx' subcommand specified by the user.
+#[derive(Debug)]
+enum TxSubcommand {
+    Diff(TxDiffArgs),
+}
+
+/// Stores user-supplied arguments for the 'tx diff' subcommand.
+#[derive(Debug)]
+struct TxDiffArgs {
+    transaction: Option<String>,
+}
+
 /// Stores user-supplied arguments for the 'raw' subcommand.
 #[derive(Debug)]
 struct RawArgs {
@@ -79,6 +111,7 @@ enum UpdateSubcommand {
     Check(UpdateCheckArgs),
     Apply(UpdateApplyArgs),
     Cancel(UpdateCancelArgs),
+    History(UpdateHistoryArgs),
 }
 
 /// Stores user-supplied arguments for the 'update check' subcommand.
@@ -90,12 +123,17 @@ struct UpdateCheckArgs {}
 struct UpdateApplyArgs {
     check: bool,
     reboot: bool,
+    timeout: Duration,
 }
 
 /// Stores user-supplied arguments for the 'update cancel' subcommand.
 #[derive(Debug)]
 struct UpdateCancelArgs {}
 
+/// Stores user-supplied arguments for the 'update history' subcommand.
+#[derive(Debug)]
+struct UpdateHistoryArgs {}
+
 /// Informs the user about proper usage of the program and exits.
 fn usage() -> ! {
     let msg = &format!(
@@ -116,6 +154,10 @@ fn usage() -> ! {
             update check               Prints information about available updates.
             update apply               Applies available updates.
             update cancel              Deactivates an applied update.
+            update history             Prints the history of update events.
+            history list               Prints the history of settings changes.
+            history rollback           Restores settings as they were after a given change.
+            tx diff                    Shows how pending settings would change config files.
             reboot                     Reboots the host.
 
         raw options:
@@ -128,6 +170,18 @@ fn usage() -> ! {
                                        want to apply to the system.  If no URI is specified, or
                                        if "-" is given, reads from stdin.
 
+        history list options:
+            None.
+
+        history rollback options:
+            GENERATION                 Required; the generation number, as shown by
+                                       `history list`, whose settings you want to restore.
+                                       Later changes are undone and the result is applied.
+
+        tx diff options:
+            -t, --tx TRANSACTION       The transaction whose pending settings should be rendered.
+                                       Default: the "default" transaction.
+
         reboot options:
             None.
 
@@ -148,8 +202,14 @@ fn usage() -> ! {
         update apply options:
             -c, --check                Automatically `update check` and apply whatever is found.
             -r, --reboot               Automatically reboot if an update was found and applied.
+            -t, --timeout SECONDS      How long to wait for the update to download and apply.
+                                       Defaults to 600; downloads limited by
+                                       settings.updates.max-download-rate may need longer.
 
         update cancel options:
+            None.
+
+        update history options:
             None."#,
         socket = DEFAULT_API_SOCKET,
         method = DEFAULT_METHOD,
@@ -198,7 +258,7 @@ fn parse_args(args: env::Args) -> (Args, Subcommand) {
             }
 
             // Subcommands
-            "raw" | "apply" | "reboot" | "set" | "update"
+            "raw" | "apply" | "history" | "reboot" | "set" | "tx" | "update"
                 if subcommand.is_none() && !arg.starts_with('-') =>
             {
                 subcommand = Some(arg)
@@ -213,8 +273,10 @@ fn parse_args(args: env::Args) -> (Args, Subcommand) {
         // Default subcommand is 'raw'
         None | Some("raw") => return (global_args, parse_raw_args(subcommand_args)),
         Some("apply") => return (global_args, parse_apply_args(subcommand_args)),
+        Some("history") => return (global_args, parse_history_args(subcommand_args)),
         Some("reboot") => return (global_args, parse_reboot_args(subcommand_args)),
         Some("set") => return (global_args, parse_set_args(subcommand_args)),
+        Some("tx") => return (global_args, parse_tx_args(subcommand_args)),
         Some("update") => return (global_args, parse_update_args(subcommand_args)),
         _ => usage_msg("Missing or unknown subcommand"),
     }
@@ -286,6 +348,64 @@ fn parse_apply_args(args: Vec<String>) -> Subcommand {
     Subcommand::Apply(ApplyArgs { input_sources })
 }
 
+/// Parses the desired subcommand of 'history'.
+fn parse_history_args(args: Vec<String>) -> Subcommand {
+    let mut subcommand = None;
+    let mut subcommand_args = Vec::new();
+
+    for arg in args {
+        match arg.as_ref() {
+            // Subcommands
+            "list" | "rollback" if subcommand.is_none() && !arg.starts_with('-') => {
+                subcommand = Some(arg)
+            }
+
+            // Other arguments are passed to the subcommand parser
+            _ => subcommand_args.push(arg),
+        }
+    }
+
+    let history = match subcommand.as_deref() {
+        Some("list") => parse_history_list_args(subcommand_args),
+        Some("rollback") => parse_history_rollback_args(subcommand_args),
+        _ => usage_msg("Missing or unknown subcommand for 'history'"),
+    };
+
+    Subcommand::History(history)
+}
+
+/// Parses arguments for the 'history list' subcommand.
+fn parse_history_list_args(args: Vec<String>) -> HistorySubcommand {
+    if !args.is_empty() {
+        usage_msg(&format!("Unknown arguments: {}", args.join(", ")));
+    }
+    HistorySubcommand::List(HistoryListArgs {})
+}
+
+/// Parses arguments for the 'history rollback' subcommand.
+fn parse_history_rollback_args(args: Vec<String>) -> HistorySubcommand {
+    let mut generation = None;
+
+    for arg in args {
+        match arg {
+            x if x.starts_with('-') => usage_msg(&format!("Unknown argument '{}'", x)),
+
+            x if generation.is_none() => {
+                generation = Some(x.parse().unwrap_or_else(|_| {
+                    usage_msg(&format!("Invalid generation '{}', must be a number", x))
+                }))
+            }
+
+            _ => usage_msg("Can only roll back to a single generation"),
+        }
+    }
+
+    HistorySubcommand::Rollback(HistoryRollbackArgs {
+        generation: generation
+            .unwrap_or_else(|| usage_msg("Must specify the generation to roll back to")),
+    })
+}
+
 /// Parses arguments for the 'reboot' subcommand.
 fn parse_reboot_args(args: Vec<String>) -> Subcommand {
     if !args.is_empty() {
@@ -377,6 +497,50 @@ fn parse_set_args(args: Vec<String>) -> Subcommand {
     }
 }
 
+/// Parses the desired subcommand of 'tx'.
+fn parse_tx_args(args: Vec<String>) -> Subcommand {
+    let mut subcommand = None;
+    let mut subcommand_args = Vec::new();
+
+    for arg in args {
+        match arg.as_ref() {
+            // Subcommands
+            "diff" if subcommand.is_none() && !arg.starts_with('-') => subcommand = Some(arg),
+
+            // Other arguments are passed to the subcommand parser
+            _ => subcommand_args.push(arg),
+        }
+    }
+
+    let tx = match subcommand.as_deref() {
+        Some("diff") => parse_tx_diff_args(subcommand_args),
+        _ => usage_msg("Missing or unknown subcommand for 'tx'"),
+    };
+
+    Subcommand::Tx(tx)
+}
+
+/// Parses arguments for the 'tx diff' subcommand.
+fn parse_tx_diff_args(args: Vec<String>) -> TxSubcommand {
+    let mut transaction = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "-t" | "--tx" => {
+                transaction = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to -t | --tx")),
+                )
+            }
+
+            x => usage_msg(&format!("Unknown argument '{}'", x)),
+        }
+    }
+
+    TxSubcommand::Diff(TxDiffArgs { transaction })
+}
+
 /// Parses the desired subcommand of 'update'.
 fn parse_update_args(args: Vec<String>) -> Subcommand {
     let mut subcommand = None;
@@ -399,6 +563,7 @@ fn parse_update_args(args: Vec<String>) -> Subcommand {
         Some("check") => parse_update_check_args(subcommand_args),
         Some("apply") => parse_update_apply_args(subcommand_args),
         Some("cancel") => parse_update_cancel_args(subcommand_args),
+        Some("history") => parse_update_history_args(subcommand_args),
         _ => usage_msg("Missing or unknown subcommand for 'update'"),
     };
 
@@ -417,18 +582,31 @@ fn parse_update_check_args(args: Vec<String>) -> UpdateSubcommand {
 fn parse_update_apply_args(args: Vec<String>) -> UpdateSubcommand {
     let mut check = false;
     let mut reboot = false;
+    let mut timeout = update::DEFAULT_PREPARE_TIMEOUT;
 
     let mut iter = args.into_iter();
     while let Some(arg) = iter.next() {
         match arg.as_ref() {
             "-c" | "--check" => check = true,
             "-r" | "--reboot" => reboot = true,
+            "-t" | "--timeout" => {
+                let seconds = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -t | --timeout"));
+                timeout = Duration::from_secs(seconds.parse().unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid timeout '{}', expected seconds", seconds))
+                }));
+            }
 
             x => usage_msg(&format!("Unknown argument '{}'", x)),
         }
     }
 
-    UpdateSubcommand::Apply(UpdateApplyArgs { check, reboot })
+    UpdateSubcommand::Apply(UpdateApplyArgs {
+        check,
+        reboot,
+        timeout,
+    })
 }
 
 /// Parses arguments for the 'update cancel' subcommand.
@@ -439,9 +617,39 @@ fn parse_update_cancel_args(args: Vec<String>) -> UpdateSubcommand {
     UpdateSubcommand::Cancel(UpdateCancelArgs {})
 }
 
+/// Parses arguments for the 'update history' subcommand.
+fn parse_update_history_args(args: Vec<String>) -> UpdateSubcommand {
+    if !args.is_empty() {
+        usage_msg(&format!("Unknown arguments: {}", args.join(", ")));
+    }
+    UpdateSubcommand::History(UpdateHistoryArgs {})
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 // Helpers
 
+/// Prints a JSON response from the server in a pretty format if possible.
+fn print_json(output: &str) {
+    match serde_json::from_str::<serde_json::Value>(output) {
+        Ok(value) => println!("{:#}", value),
+        Err(e) => {
+            warn!("Unable to deserialize response (invalid JSON?): {}", e);
+            println!("{}", output);
+        }
+    }
+}
+
+/// Prints the configuration file diffs from 'tx diff', ordered by file path, so the output reads
+/// like the output of `diff -ru`.
+fn print_diffs(diffs: &BTreeMap<String, String>) {
+    if diffs.is_empty() {
+        info!("No configuration files would change");
+    }
+    for diff in diffs.values() {
+        print!("{}", diff);
+    }
+}
+
 /// Requests an update status check through the API, printing the updated status, in a pretty
 /// format if possible.
 async fn check(args: &Args) -> Result<String> {
@@ -449,13 +657,7 @@ async fn check(args: &Args) -> Result<String> {
         .await
         .context(error::UpdateCheck)?;
 
-    match serde_json::from_str::<serde_json::Value>(&output) {
-        Ok(value) => println!("{:#}", value),
-        Err(e) => {
-            warn!("Unable to deserialize response (invalid JSON?): {}", e);
-            println!("{}", output);
-        }
-    }
+    print_json(&output);
 
     Ok(output)
 }
@@ -540,6 +742,26 @@ async fn run() -> Result<()> {
                 .context(error::Apply)?;
         }
 
+        Subcommand::History(subcommand) => match subcommand {
+            HistorySubcommand::List(_list) => {
+                let output = history::list(&args.socket_path)
+                    .await
+                    .context(error::HistoryList)?;
+                print_json(&output);
+            }
+
+            HistorySubcommand::Rollback(rollback) => {
+                let output = history::rollback(&args.socket_path, rollback.generation)
+                    .await
+                    .context(error::HistoryRollback)?;
+                info!(
+                    "Rolled back settings to generation {}; changed settings:",
+                    rollback.generation
+                );
+                print_json(&output);
+            }
+        },
+
         Subcommand::Reboot(_reboot) => {
             reboot::reboot(&args.socket_path)
                 .await
@@ -572,6 +794,15 @@ async fn run() -> Result<()> {
                 .context(error::Set)?;
         }
 
+        Subcommand::Tx(subcommand) => match subcommand {
+            TxSubcommand::Diff(diff) => {
+                let output = tx::diff(&args.socket_path, diff.transaction.as_deref())
+                    .await
+                    .context(error::TxDiff)?;
+                print_diffs(&output);
+            }
+        },
+
         Subcommand::Update(subcommand) => match subcommand {
             UpdateSubcommand::Check(_check) => {
                 check(&args).await?;
@@ -587,7 +818,7 @@ async fn run() -> Result<()> {
                     }
                 }
 
-                update::apply(&args.socket_path)
+                update::apply(&args.socket_path, apply.timeout)
                     .await
                     .context(error::UpdateApply)?;
 
@@ -607,6 +838,13 @@ async fn run() -> Result<()> {
                     .await
                     .context(error::UpdateCancel)?;
             }
+
+            UpdateSubcommand::History(_history) => {
+                let output = update::history(&args.socket_path)
+                    .await
+                    .context(error::UpdateHistory)?;
+                print_json(&output);
+            }
         },
     }
 
@@ -625,7 +863,7 @@ async fn main() {
 }
 
 mod error {
-    use apiclient::{apply, reboot, set, update};
+    use apiclient::{apply, history, reboot, set, tx, update};
     use snafu::Snafu;
 
     #[derive(Debug, Snafu)]
@@ -645,6 +883,12 @@ mod error {
             source: datastore::deserialization::Error,
         },
 
+        #[snafu(display("Failed to get settings history: {}", source))]
+        HistoryList { source: history::Error },
+
+        #[snafu(display("Failed to roll back settings: {}", source))]
+        HistoryRollback { source: history::Error },
+
         #[snafu(display("Logger setup error: {}", source))]
         Logger { source: log::SetLoggerError },
 
@@ -664,6 +908,9 @@ mod error {
         #[snafu(display("Failed to change settings: {}", source))]
         Set { source: set::Error },
 
+        #[snafu(display("Failed to get configuration file diffs: {}", source))]
+        TxDiff { source: tx::Error },
+
         #[snafu(display("Failed to apply update: {}", source))]
         UpdateApply { source: update::Error },
 
@@ -672,6 +919,9 @@ mod error {
 
         #[snafu(display("Failed to check for updates: {}", source))]
         UpdateCheck { source: update::Error },
+
+        #[snafu(display("Failed to get update history: {}", source))]
+        UpdateHistory { source: update::Error },
     }
 }
 type Result<T> = std::result::Result<T, error::Error>;
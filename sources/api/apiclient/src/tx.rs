@@ -0,0 +1,96 @@
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The part of a commit preview, returned by a dry run commit, that describes the changes to
+/// configuration files.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CommitPreview {
+    configuration_files: BTreeMap<String, String>,
+}
+
+/// Requests diffs of the configuration files that would be rewritten by committing and applying
+/// the given transaction, or the "default" transaction if none is given.  This is a dry run
+/// commit, so nothing is changed.  Returns a map of file path to unified diff.
+pub async fn diff<P>(socket_path: P, transaction: Option<&str>) -> Result<BTreeMap<String, String>>
+where
+    P: AsRef<Path>,
+{
+    let uri = dry_run_commit_uri(transaction);
+    let method = "POST";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    parse_diffs(&body)
+}
+
+/// Returns the URI for a dry run commit of the given transaction, or of the "default" transaction
+/// if none is given.
+fn dry_run_commit_uri(transaction: Option<&str>) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(transaction) = transaction {
+        query.append_pair("tx", transaction);
+    }
+    query.append_pair("dry-run", "true");
+    format!("/tx/commit?{}", query.finish())
+}
+
+/// Pulls the configuration file diffs out of a dry run commit response.
+fn parse_diffs(body: &str) -> Result<BTreeMap<String, String>> {
+    let preview: CommitPreview = serde_json::from_str(body).context(error::ResponseJson)?;
+    Ok(preview.configuration_files)
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Response was not a commit preview: {}", source))]
+        ResponseJson { source: serde_json::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uri_default_transaction() {
+        assert_eq!(dry_run_commit_uri(None), "/tx/commit?dry-run=true");
+    }
+
+    #[test]
+    fn uri_encodes_transaction() {
+        assert_eq!(
+            dry_run_commit_uri(Some("my tx&dry-run=false")),
+            "/tx/commit?tx=my+tx%26dry-run%3Dfalse&dry-run=true"
+        );
+    }
+
+    #[test]
+    fn diffs_from_preview() {
+        let body = r#"{
+            "changed-keys": ["settings.motd"],
+            "affected-services": ["motd"],
+            "configuration-files": { "/etc/motd": "--- /etc/motd\n+++ /etc/motd\n" }
+        }"#;
+        let diffs = parse_diffs(body).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs["/etc/motd"], "--- /etc/motd\n+++ /etc/motd\n");
+        parse_diffs("{}").unwrap_err();
+    }
+}
@@ -23,10 +23,15 @@ use super::{raw_request, raw_request_unchecked};
 use http::StatusCode;
 use log::{debug, info, trace, warn};
 use snafu::{ensure, ResultExt};
+use std::convert::TryFrom;
 use std::path::Path;
 use std::time::Duration;
 use tokio::time;
 
+/// How long `apply` waits for an update to be downloaded and written by default.  Downloads
+/// limited by `settings.updates.max-download-rate` may need longer.
+pub const DEFAULT_PREPARE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
 /// Refresh the list of available updates and return the current status.
 pub async fn check<P>(socket_path: P) -> Result<String>
 where
@@ -69,8 +74,9 @@ pub fn required(check_output: &str) -> bool {
     }
 }
 
-/// Applies the update shown as selected in the output of check(), and makes it active.
-pub async fn apply<P>(socket_path: P) -> Result<()>
+/// Applies the update shown as selected in the output of check(), and makes it active.  We wait
+/// up to `timeout` for the update to be downloaded and written to disk.
+pub async fn apply<P>(socket_path: P, timeout: Duration) -> Result<()>
 where
     P: AsRef<Path>,
 {
@@ -81,7 +87,7 @@ where
         "POST",
         None,
         "prepare",
-        &WaitPolicy::new(Duration::from_millis(500), 2 * 60 * 10),
+        &WaitPolicy::for_timeout(Duration::from_millis(500), timeout),
     )
     .await
     .context(error::PrepareUpdate)?;
@@ -119,6 +125,21 @@ where
     Ok(status)
 }
 
+/// Retrieves the history of update events through the API.  Returns the response body, a JSON
+/// list of events, oldest first.
+pub async fn history<P>(socket_path: P) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let (_code, body) = raw_request(&socket_path, "/updates/history", "GET", None)
+        .await
+        .context(error::Request {
+            command_name: "history",
+        })?;
+
+    Ok(body)
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 /// Pulls a nested field out of a JSON string.  The input is a list of strings representing the
@@ -162,6 +183,15 @@ impl WaitPolicy {
             max_attempts,
         }
     }
+
+    /// Checks every `between_attempts` until `timeout` has passed.
+    fn for_timeout(between_attempts: Duration, timeout: Duration) -> Self {
+        let max_attempts = timeout.as_millis() / between_attempts.as_millis();
+        Self::new(
+            between_attempts,
+            u32::try_from(max_attempts).unwrap_or(u32::MAX),
+        )
+    }
 }
 
 /// This synchronously wraps a call to the update API, waiting for asynchronous status updates to
@@ -25,10 +25,28 @@ Upon making a `/tx/commit` POST call, the pending transaction is made live.
 Upon making an `/tx/apply` POST call, an external settings applier tool is called to apply the changes to the system and restart services as necessary.
 There's also `/tx/commit_and_apply` to do both, which is the most common case.
 
+Before a commit, the settings that would result are checked against the model's validation rules, which cover relationships between settings that individual types can't express.
+If they'd be invalid, the commit fails with a 422 response and nothing changes.
+Only the groups of settings the commit changes, like `settings.network`, are checked, so a live setting that breaks a rule added in a newer release doesn't block unrelated changes.
+To see what a commit would do without making it, `POST` to `/tx/commit?dry-run=true`; the response lists the keys that would change, the services they affect, and a unified diff of each configuration file that would be rewritten.
+
 If you don't specify a transaction, the "default" transaction is used, so you usually don't have to think about it.
 If you want to group changes into transactions yourself, you can add a `tx` parameter to the APIs mentioned above.
 For example, if you want the name "FOO", you can `PATCH` to `/settings?tx=FOO` and `POST` to `/tx/commit_and_apply?tx=FOO`.
 
+Some settings report system state, like `settings.network.current`, and are marked with `read-only` metadata.
+A `PATCH` to `/settings` that would change them fails with a 400 response.
+The system components that maintain them have their own endpoints; netdog reports network state with a `PATCH` to `/network/current`, in its own transaction.
+The `read-only` flag is advisory: it keeps these settings from being changed by mistake along with other settings, but it isn't access control, and any client that can reach the API socket can use those endpoints too.
+
+Each commit is recorded as a numbered generation in the settings history, which you can retrieve from `/settings/history`.
+If a change turns out to be a mistake, you can `POST` to `/settings/rollback?generation=N` to restore settings to the way they were after generation N; the restored settings are applied like `/tx/commit_and_apply`.
+The rollback is itself recorded as a new generation, so it can be undone the same way.
+
+To react to changes without polling, you can `GET` `/settings/watch?generation=N`, which waits for live settings to change after generation N and returns the latest generation and the changed keys.
+Add a `prefix` parameter like `prefix=settings.kubernetes` to only hear about matching keys.
+If nothing changes for a minute, the response has no keys, and you can watch again from the returned generation.
+
 Requests are directed by `server::router`.
 `server::controller` maps requests into our data model.
 
@@ -57,7 +75,7 @@ For more detail, see [datastore](../datastore).
 # Current limitations
 
 * Data store locking is coarse; read requests can happen in parallel, but a write request will block everything else.
-* There's no support for rolling back commits.
+* Rolling back only covers settings; changes to metadata aren't recorded in history.
 * There are no metrics.
 
 # Example usage
@@ -3,17 +3,23 @@
 
 use bottlerocket_release::BottlerocketRelease;
 use serde::de::DeserializeOwned;
+use serde::Serialize;
+use similar::TextDiff;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
-use std::io::Write;
+use std::fs;
+use std::io::{self, Write};
 use std::process::{Command, Stdio};
 
 use crate::server::error::{self, Result};
 use actix_web::HttpResponse;
 use datastore::deserialization::{from_map, from_map_with_prefix};
-use datastore::serialization::to_pairs;
-use datastore::{deserialize_scalar, Committed, DataStore, Key, KeyType, ScalarError, Value};
-use model::{ConfigurationFiles, Services, Settings};
+use datastore::serialization::{list_keys_with_prefix, to_pairs, to_pairs_with_prefix};
+use datastore::{
+    deserialize_scalar, ChangeSource, Committed, DataStore, Generation, Key, KeyType, ScalarError,
+    Value,
+};
+use model::{ConfigurationFiles, Model, NetworkState, Services, Settings, Validate};
 use num::FromPrimitive;
 use std::os::unix::process::ExitStatusExt;
 use thar_be_updates::error::TbuErrorStatus;
@@ -242,11 +248,92 @@ pub(crate) fn set_settings<D: DataStore>(
     let pending = Committed::Pending {
         tx: transaction.into(),
     };
+
+    // Lists are replaced as a whole, so remove anything pending for an earlier version of them.
+    let lists = list_keys_with_prefix("settings", settings)
+        .context(error::DataStoreSerialization { given: "Settings" })?;
+    let mut stale = HashSet::new();
+    for list in &lists {
+        for key in datastore
+            .list_populated_keys(list.name(), &pending)
+            .context(error::DataStore {
+                op: "list_populated_keys",
+            })?
+        {
+            if key.starts_with_segments(list.segments()) && !pairs.contains_key(&key) {
+                stale.insert(key);
+            }
+        }
+    }
+    datastore
+        .unset_keys(&stale, &pending)
+        .context(error::DataStore { op: "unset_keys" })?;
+
+    datastore
+        .set_keys(&pairs, &pending)
+        .context(error::DataStore { op: "set_keys" })
+}
+
+/// Sets the given network state in settings.network.current in the pending transaction.  These
+/// settings are read-only for users, so this is only for the component that reports the state.
+pub(crate) fn set_network_state<D: DataStore>(
+    datastore: &mut D,
+    state: &NetworkState,
+    transaction: &str,
+) -> Result<()> {
+    let pairs = to_pairs_with_prefix("settings.network.current", state).context(
+        error::DataStoreSerialization {
+            given: "NetworkState",
+        },
+    )?;
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
     datastore
         .set_keys(&pairs, &pending)
         .context(error::DataStore { op: "set_keys" })
 }
 
+/// Returns the keys of the lists set in the given transaction; committing the transaction
+/// replaces these lists entirely.
+fn get_pending_lists<D: DataStore>(datastore: &D, transaction: &str) -> Result<HashSet<Key>> {
+    let settings = get_transaction(datastore, transaction)?;
+    list_keys_with_prefix("settings", &settings)
+        .context(error::DataStoreSerialization { given: "Settings" })
+}
+
+/// Checks that none of the given settings are marked read-only in metadata.  Read-only settings
+/// reflect the state of the system, for example network information from a DHCP lease, so
+/// they're only changed by the components that own them.
+pub(crate) fn check_read_only<D: DataStore>(datastore: &D, settings: &Settings) -> Result<()> {
+    let pairs = to_pairs(settings).context(error::DataStoreSerialization { given: "Settings" })?;
+    let key_names = pairs.keys().map(|k| k.name().as_str()).collect();
+
+    let mut read_only: Vec<String> = read_only_keys(datastore, &key_names)?.into_iter().collect();
+    read_only.sort();
+    ensure!(
+        read_only.is_empty(),
+        error::ReadOnlySettings {
+            keys: read_only.join(", ")
+        }
+    );
+    Ok(())
+}
+
+/// Returns the names of the given data keys that are marked read-only in metadata.
+fn read_only_keys<D: DataStore>(
+    datastore: &D,
+    data_key_strs: &HashSet<&str>,
+) -> Result<HashSet<String>> {
+    Ok(
+        get_metadata_for_data_keys(datastore, "read-only", data_key_strs)?
+            .into_iter()
+            .filter(|(_key, value)| value == &Value::Bool(true))
+            .map(|(key, _value)| key)
+            .collect(),
+    )
+}
+
 // This is not as nice as get_settings, which uses Serializer/Deserializer to properly use the
 // data model and check types.
 /// Gets the value of a metadata key for the requested list of data keys.
@@ -314,16 +401,324 @@ pub(crate) fn get_metadata_for_all_data_keys<D: DataStore, S: AsRef<str>>(
     Ok(result)
 }
 
-/// Makes live any pending settings in the datastore, returning the changed keys.
+/// Makes live any pending settings in the datastore, returning the changed keys.  Fails without
+/// changing anything if the resulting settings would be invalid.
 pub(crate) fn commit_transaction<D>(datastore: &mut D, transaction: &str) -> Result<HashSet<Key>>
 where
     D: DataStore,
 {
+    validate_transaction(datastore, transaction)?;
+    let lists = get_pending_lists(datastore, transaction)?;
     datastore
-        .commit_transaction(transaction)
+        .commit_transaction_replacing_lists(transaction, &lists)
         .context(error::DataStore { op: "commit" })
 }
 
+/// Returns the pending settings data from the given transaction.
+fn get_pending_data<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<HashMap<Key, String>> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .get_prefix("settings.", &pending)
+        .context(error::DataStore { op: "get_prefix" })
+}
+
+/// Build the settings data that would be live after committing the given transaction, by applying
+/// its pending settings on top of the live settings.
+fn get_data_after_commit<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<HashMap<Key, String>> {
+    let mut data = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStore { op: "get_prefix" })?;
+    let lists = get_pending_lists(datastore, transaction)?;
+    data.retain(|key, _value| {
+        !lists
+            .iter()
+            .any(|list| key.starts_with_segments(list.segments()))
+    });
+    data.extend(get_pending_data(datastore, transaction)?);
+    Ok(data)
+}
+
+/// Build the Settings that would be live after committing the given transaction.
+fn get_settings_after_commit<D: DataStore>(datastore: &D, transaction: &str) -> Result<Settings> {
+    from_map(&get_data_after_commit(datastore, transaction)?).context(error::Deserialization {
+        given: "settings after commit",
+    })
+}
+
+/// Checks that the settings that would be live after committing the given transaction satisfy the
+/// model's validation rules.  We check the combined settings, rather than just the pending ones,
+/// because rules often relate a setting in the transaction to one that's already live.
+///
+/// Only the groups of settings the transaction changes, like `settings.network`, are checked.
+/// Rules can change between releases, so a live setting may already break them, and that
+/// shouldn't block unrelated changes.
+pub(crate) fn validate_transaction<D: DataStore>(datastore: &D, transaction: &str) -> Result<()> {
+    let pending = get_pending_data(datastore, transaction)?;
+    let groups: HashSet<&String> = pending.keys().filter_map(|k| k.segments().get(1)).collect();
+    let mut data = get_data_after_commit(datastore, transaction)?;
+    data.retain(|key, _value| {
+        key.segments()
+            .get(1)
+            .map_or(false, |group| groups.contains(group))
+    });
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let settings: Settings = from_map(&data).context(error::Deserialization {
+        given: "settings after commit",
+    })?;
+    settings.validate().context(error::InvalidSettings)
+}
+
+/// CommitPreview describes what committing a transaction would do, without changing live data.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct CommitPreview {
+    /// The keys that would be changed.
+    pub(crate) changed_keys: HashSet<Key>,
+    /// The names of services that would be affected by the changed keys, meaning their
+    /// configuration files would be rewritten and they'd be restarted.
+    pub(crate) affected_services: HashSet<String>,
+    /// Maps the path of each configuration file that would be rewritten to a unified diff of its
+    /// current contents against the newly rendered contents.  Files that wouldn't change aren't
+    /// included.
+    pub(crate) configuration_files: HashMap<String, String>,
+}
+
+/// Describes the effect of committing and applying the given transaction, without changing
+/// anything: the changed keys, the affected services, and how their configuration files would
+/// change.  Fails if the resulting settings would be invalid, as checked by a real commit.
+pub(crate) fn preview_commit<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<CommitPreview> {
+    // Find the changes the same way the commit does, including list elements it would unset.
+    let lists = get_pending_lists(datastore, transaction)?;
+    let changed_keys: HashSet<Key> = datastore
+        .pending_changes_replacing_lists(transaction, &lists)
+        .context(error::DataStore {
+            op: "pending_changes_replacing_lists",
+        })?
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    validate_transaction(datastore, transaction)?;
+    let settings = get_settings_after_commit(datastore, transaction)?;
+
+    // Find the services affected by the changed keys, like the settings applier would.
+    let key_names: HashSet<&str> = changed_keys.iter().map(|k| k.name().as_str()).collect();
+    let mut affected_services = HashSet::new();
+    for services in get_metadata_for_data_keys(datastore, "affected-services", &key_names)?.values()
+    {
+        if let Some(services) = services.as_array() {
+            affected_services.extend(services.iter().filter_map(|s| s.as_str()).map(String::from));
+        }
+    }
+
+    let service_names = affected_services.iter().map(|s| s.as_str()).collect();
+    let services = get_services_names(datastore, &service_names, &Committed::Live)?;
+    let file_names = services
+        .values()
+        .flat_map(|service| service.configuration_files.iter())
+        .map(|file| file.as_ref())
+        .collect();
+    let config_files = get_configuration_files_names(datastore, &file_names, &Committed::Live)?;
+
+    // Only build the full model if we have something to render; it includes OS release data.
+    let configuration_files = if config_files.is_empty() {
+        HashMap::new()
+    } else {
+        let model = Model {
+            settings: Some(settings),
+            services: Some(get_services(datastore)?),
+            configuration_files: Some(get_configuration_files(datastore)?),
+            os: Some(get_os_info()?),
+        };
+        render_config_diffs(&config_files, &model)?
+    };
+
+    Ok(CommitPreview {
+        changed_keys,
+        affected_services,
+        configuration_files,
+    })
+}
+
+/// Renders the given configuration files using the given model, like the settings applier would,
+/// and compares each to the file currently on disk.  Returns a map of file path to unified diff
+/// for each file whose contents would change.
+fn render_config_diffs(
+    config_files: &ConfigurationFiles,
+    model: &Model,
+) -> Result<HashMap<String, String>> {
+    let mut registry = schnauzer::build_template_registry().context(error::TemplateRegistry)?;
+
+    let mut diffs = HashMap::new();
+    for (name, config_file) in config_files {
+        let path: &str = config_file.path.as_ref();
+        let template_path: &str = config_file.template_path.as_ref();
+        registry
+            .register_template_file(name, template_path)
+            .context(error::TemplateRegister {
+                name,
+                path: template_path,
+            })?;
+        let rendered = registry
+            .render(name, model)
+            .context(error::TemplateRender { name })?;
+
+        // A file that hasn't been written yet is shown as entirely new.
+        let current = match fs::read_to_string(path) {
+            Ok(current) => current,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context(error::ConfigFileRead { path }),
+        };
+        if current == rendered {
+            continue;
+        }
+
+        let diff = TextDiff::from_lines(&current, &rendered)
+            .unified_diff()
+            .header(path, path)
+            .to_string();
+        diffs.insert(path.to_string(), diff);
+    }
+
+    Ok(diffs)
+}
+
+/// Returns the recorded history of changes to live settings, oldest first.
+pub(crate) fn get_settings_history<D: DataStore>(datastore: &D) -> Result<Vec<Generation>> {
+    datastore.list_generations().context(error::DataStore {
+        op: "list_generations",
+    })
+}
+
+/// Returns the number of the latest generation of live settings, or 0 if no changes have been
+/// recorded.  This is checked on every commit and watch request, so it's found from the next
+/// generation number rather than by loading the history.
+pub(crate) fn get_settings_generation<D: DataStore>(datastore: &D) -> Result<u64> {
+    let next = datastore.next_generation().context(error::DataStore {
+        op: "next_generation",
+    })?;
+    Ok(next - 1)
+}
+
+/// SettingsChanges describes the changes to live settings made after a given generation.
+#[derive(Debug, Serialize)]
+pub(crate) struct SettingsChanges {
+    /// The latest generation of live settings.
+    pub(crate) generation: u64,
+    /// The keys changed after the given generation, up to and including the latest generation.
+    pub(crate) keys: HashSet<Key>,
+}
+
+/// Returns the keys whose names start with `prefix` that were changed in live settings after the
+/// `since` generation, along with the latest generation.
+pub(crate) fn get_settings_changes<D, S>(
+    datastore: &D,
+    since: u64,
+    prefix: S,
+) -> Result<SettingsChanges>
+where
+    D: DataStore,
+    S: AsRef<str>,
+{
+    // The prefix is matched by whole key segments, so "settings.host" doesn't match
+    // "settings.hostname".  Allow a trailing dot, as in the "settings." prefix used elsewhere.
+    let prefix_str = prefix.as_ref();
+    let prefix =
+        Key::new(KeyType::Data, prefix_str.trim_end_matches('.')).context(error::NewKey {
+            key_type: "data",
+            name: prefix_str,
+        })?;
+    // Watchers mostly ask about the latest generation, and nothing has changed since then, so we
+    // don't need the history.
+    let latest = get_settings_generation(datastore)?;
+    if since == latest {
+        return Ok(SettingsChanges {
+            generation: latest,
+            keys: HashSet::new(),
+        });
+    }
+
+    let history = get_settings_history(datastore)?;
+    let changed = match datastore::history::changed_since(&history, latest, since) {
+        // Asking for a generation we don't have is the user's problem, not a data store problem.
+        Err(datastore::Error::GenerationNotFound { generation }) => {
+            return error::GenerationNotFound { generation }.fail()
+        }
+        result => result.context(error::DataStore {
+            op: "changed_since",
+        })?,
+    };
+
+    Ok(SettingsChanges {
+        generation: latest,
+        keys: changed
+            .into_iter()
+            .filter(|key| key.starts_with_segments(prefix.segments()))
+            .collect(),
+    })
+}
+
+/// Restores live settings to the state they had after the given generation, returning the changed
+/// keys.
+///
+/// Read-only settings are left as they are, because they reflect the current state of the system
+/// rather than a choice the user made.  The restored settings must satisfy the model's validation
+/// rules, as with a commit; rules can change between releases, so old settings aren't necessarily
+/// still valid.
+pub(crate) fn rollback_settings<D: DataStore>(
+    datastore: &mut D,
+    generation: u64,
+) -> Result<HashSet<Key>> {
+    let latest = get_settings_generation(datastore)?;
+    let history = get_settings_history(datastore)?;
+    let mut changes = match datastore::history::rollback_changes(&history, latest, generation) {
+        // Asking for a generation we don't have is the user's problem, not a data store problem.
+        Err(datastore::Error::GenerationNotFound { generation }) => {
+            return error::GenerationNotFound { generation }.fail()
+        }
+        result => result.context(error::DataStore { op: "rollback" })?,
+    };
+
+    let key_names = changes.keys().map(|k| k.name().as_str()).collect();
+    let read_only = read_only_keys(datastore, &key_names)?;
+    changes.retain(|key, _value| !read_only.contains(key.name()));
+    if changes.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    // Check the settings as they'd be after the rollback, like validate_transaction does.
+    let mut data = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStore { op: "get_prefix" })?;
+    for (key, value) in &changes {
+        match value {
+            Some(value) => data.insert(key.clone(), value.clone()),
+            None => data.remove(key),
+        };
+    }
+    let settings: Settings = from_map(&data).context(error::Deserialization {
+        given: "settings after rollback",
+    })?;
+    settings.validate().context(error::InvalidSettings)?;
+
+    datastore
+        .write_generation(&changes, ChangeSource::Rollback { generation })
+        .context(error::DataStore { op: "rollback" })
+}
+
 /// Launches the config applier to make appropriate changes to the system based on any settings
 /// that have been committed.  Can be called after a commit, with the keys that changed in that
 /// commit, or called on its own to reset configuration state with all known keys.
@@ -403,6 +798,25 @@ where
     Ok(())
 }
 
+/// Moves workloads off of the host via `sheepdog`, which checks whether the user asked for that
+pub(crate) fn drain_before_reboot() -> Result<()> {
+    let output = Command::new("/usr/bin/sheepdog")
+        .arg("drain")
+        .output()
+        .context(error::DrainDispatcher)?;
+    ensure!(
+        output.status.success(),
+        error::Drain {
+            exit_code: match output.status.code() {
+                Some(code) => code,
+                None => output.status.signal().unwrap_or(1),
+            },
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+    );
+    Ok(())
+}
+
 /// Dispatches an update command via `thar-be-updates`
 pub(crate) fn dispatch_update_command(args: &[&str]) -> Result<HttpResponse> {
     let status = Command::new("/usr/bin/thar-be-updates")
@@ -435,7 +849,9 @@ mod test {
     use datastore::{Committed, DataStore, Key, KeyType};
     use maplit::{hashmap, hashset};
     use model::Service;
+    use serde_json::json;
     use std::convert::TryInto;
+    use tempfile::TempDir;
 
     #[test]
     fn get_settings_works() {
@@ -548,6 +964,72 @@ mod test {
         );
     }
 
+    #[test]
+    fn commit_replaces_lists() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let routes = |routes: serde_json::Value| -> Settings {
+            serde_json::from_value(json!({
+                "network": { "interfaces": { "eth0": { "dhcp4": true, "routes": routes } } }
+            }))
+            .unwrap()
+        };
+        let route_keys = |ds: &MemoryDataStore| {
+            ds.list_populated_keys("settings.network.interfaces.eth0.routes", &Committed::Live)
+                .unwrap()
+        };
+
+        let two = json!([
+            { "to": "10.0.0.0/8", "via": "192.168.1.1" },
+            { "to": "172.16.0.0/12", "via": "192.168.1.1" },
+        ]);
+        set_settings(&mut ds, &routes(two), tx).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+        assert_eq!(route_keys(&ds).len(), 4);
+
+        // A shorter list, even one replacing a longer list in the same transaction, leaves no
+        // stale elements behind
+        let three = json!([
+            { "to": "10.0.0.0/8", "via": "192.168.1.1" },
+            { "to": "172.16.0.0/12", "via": "192.168.1.1" },
+            { "to": "192.168.0.0/16", "via": "192.168.1.1" },
+        ]);
+        set_settings(&mut ds, &routes(three), tx).unwrap();
+        let one = json!([{ "to": "10.0.0.0/8", "via": "192.168.1.2" }]);
+        set_settings(&mut ds, &routes(one.clone()), tx).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings, routes(one));
+
+        // An empty list replaces the elements
+        set_settings(&mut ds, &routes(json!([])), tx).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+        assert_eq!(
+            route_keys(&ds),
+            hashset!(Key::new(KeyType::Data, "settings.network.interfaces.eth0.routes").unwrap())
+        );
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings, routes(json!([])));
+    }
+
+    #[test]
+    fn check_read_only_works() {
+        let mut settings = Settings::default();
+        settings.motd = Some("tz".try_into().unwrap());
+
+        let mut ds = MemoryDataStore::new();
+        check_read_only(&ds, &settings).unwrap();
+
+        // Metadata on a parent key applies to the settings under it
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "read-only").unwrap(),
+            &Key::new(KeyType::Data, "settings").unwrap(),
+            "true",
+        )
+        .unwrap();
+        check_read_only(&ds, &settings).unwrap_err();
+    }
+
     #[test]
     fn get_metadata_keys_works() {
         let mut ds = MemoryDataStore::new();
@@ -623,4 +1105,317 @@ mod test {
         let settings = get_settings(&ds, &Committed::Live).unwrap();
         assert_eq!(settings.motd, Some("json string".try_into().unwrap()));
     }
+
+    #[test]
+    fn preview_doesnt_commit() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&motd, "\"hi\"", &pending).unwrap();
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "affected-services").unwrap(),
+            &motd,
+            "[\"motd\"]",
+        )
+        .unwrap();
+        for (key, value) in &[
+            ("services.motd.configuration-files", "[]"),
+            ("services.motd.restart-commands", "[]"),
+        ] {
+            ds.set_key(
+                &Key::new(KeyType::Data, key).unwrap(),
+                value,
+                &Committed::Live,
+            )
+            .unwrap();
+        }
+
+        let preview = preview_commit(&ds, tx).unwrap();
+        assert_eq!(preview.changed_keys, hashset!(motd.clone()));
+        assert_eq!(preview.affected_services, hashset!("motd".to_string()));
+        assert!(preview.configuration_files.is_empty());
+
+        // Still pending, not live
+        assert_eq!(ds.get_key(&motd, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            ds.get_key(&motd, &pending).unwrap(),
+            Some("\"hi\"".to_string())
+        );
+    }
+
+    #[test]
+    fn preview_includes_unset_list_elements() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let routes = |routes: serde_json::Value| -> Settings {
+            serde_json::from_value(json!({
+                "network": { "interfaces": { "eth0": { "dhcp4": true, "routes": routes } } }
+            }))
+            .unwrap()
+        };
+        let two = json!([
+            { "to": "10.0.0.0/8", "via": "192.168.1.1" },
+            { "to": "172.16.0.0/12", "via": "192.168.1.1" },
+        ]);
+        set_settings(&mut ds, &routes(two), tx).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+
+        let one = json!([{ "to": "10.0.0.0/8", "via": "192.168.1.1" }]);
+        set_settings(&mut ds, &routes(one), tx).unwrap();
+        let preview = preview_commit(&ds, tx).unwrap();
+        let removed = |name: &str| {
+            Key::new(
+                KeyType::Data,
+                format!("settings.network.interfaces.eth0.routes.1.{}", name),
+            )
+            .unwrap()
+        };
+        assert!(preview.changed_keys.contains(&removed("to")));
+        assert!(preview.changed_keys.contains(&removed("via")));
+
+        // The preview matches what the commit changes
+        assert_eq!(
+            preview.changed_keys,
+            commit_transaction(&mut ds, tx).unwrap()
+        );
+    }
+
+    #[test]
+    fn render_config_diffs_works() {
+        let dir = TempDir::new().unwrap();
+        let template_path = dir.path().join("motd.template");
+        fs::write(&template_path, "{{settings.motd}}\n").unwrap();
+        let unchanged_path = dir.path().join("unchanged");
+        fs::write(&unchanged_path, "new motd\n").unwrap();
+        let changed_path = dir.path().join("changed");
+        fs::write(&changed_path, "old motd\n").unwrap();
+        let new_path = dir.path().join("new");
+
+        let mut config_files = ConfigurationFiles::new();
+        for path in &[&unchanged_path, &changed_path, &new_path] {
+            config_files.insert(
+                path.file_name().unwrap().to_str().unwrap().to_string(),
+                serde_json::from_value(json!({
+                    "path": path.to_str().unwrap(),
+                    "template-path": template_path.to_str().unwrap(),
+                }))
+                .unwrap(),
+            );
+        }
+        let mut settings = Settings::default();
+        settings.motd = Some("new motd".try_into().unwrap());
+        let model = Model {
+            settings: Some(settings),
+            services: None,
+            configuration_files: None,
+            os: None,
+        };
+
+        let diffs = render_config_diffs(&config_files, &model).unwrap();
+        let changed = changed_path.to_str().unwrap();
+        let new = new_path.to_str().unwrap();
+        assert_eq!(
+            diffs.keys().map(|k| k.as_str()).collect::<HashSet<_>>(),
+            hashset!(changed, new)
+        );
+        assert_eq!(
+            diffs[changed],
+            format!(
+                "--- {0}\n+++ {0}\n@@ -1 +1 @@\n-old motd\n+new motd\n",
+                changed
+            )
+        );
+        assert_eq!(
+            diffs[new],
+            format!("--- {0}\n+++ {0}\n@@ -0,0 +1 @@\n+new motd\n", new)
+        );
+
+        // Nothing is written to disk
+        assert_eq!(fs::read_to_string(&changed_path).unwrap(), "old motd\n");
+        assert!(!new_path.exists());
+    }
+
+    #[test]
+    fn rollback_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        for motd in &["first", "second"] {
+            let mut settings = Settings::default();
+            settings.motd = Some((*motd).try_into().unwrap());
+            set_settings(&mut ds, &settings, tx).unwrap();
+            commit_transaction(&mut ds, tx).unwrap();
+        }
+        assert_eq!(get_settings_history(&ds).unwrap().len(), 2);
+
+        // Roll back to the first commit
+        let changed = rollback_settings(&mut ds, 1).unwrap();
+        assert_eq!(
+            changed,
+            hashset!(Key::new(KeyType::Data, "settings.motd").unwrap())
+        );
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings.motd, Some("first".try_into().unwrap()));
+        assert_eq!(get_settings_history(&ds).unwrap().len(), 3);
+
+        // Unknown generations are reported as such
+        match rollback_settings(&mut ds, 42) {
+            Err(error::Error::GenerationNotFound { generation: 42 }) => {}
+            other => panic!("Expected GenerationNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rollback_skips_read_only() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        for (motd, seed) in &[("first", 1), ("second", 2)] {
+            let mut settings = Settings::default();
+            settings.motd = Some((*motd).try_into().unwrap());
+            settings.updates = Some(serde_json::from_value(json!({ "seed": seed })).unwrap());
+            set_settings(&mut ds, &settings, tx).unwrap();
+            commit_transaction(&mut ds, tx).unwrap();
+        }
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "read-only").unwrap(),
+            &Key::new(KeyType::Data, "settings.motd").unwrap(),
+            "true",
+        )
+        .unwrap();
+
+        let changed = rollback_settings(&mut ds, 1).unwrap();
+        assert_eq!(
+            changed,
+            hashset!(Key::new(KeyType::Data, "settings.updates.seed").unwrap())
+        );
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings.motd, Some("second".try_into().unwrap()));
+        assert_eq!(settings.updates.unwrap().seed, Some(1));
+    }
+
+    #[test]
+    fn rollback_validates() {
+        let mut ds = MemoryDataStore::new();
+        // Settings from before a validation rule existed, written directly to live data.
+        let mut settings = Settings::default();
+        settings.updates =
+            Some(serde_json::from_value(json!({ "deny-versions": ["latest"] })).unwrap());
+        let changes = to_pairs(&settings)
+            .unwrap()
+            .into_iter()
+            .map(|(key, value)| (key, Some(value)))
+            .collect();
+        ds.write_generation(
+            &changes,
+            ChangeSource::Commit {
+                transaction: "old".to_string(),
+            },
+        )
+        .unwrap();
+
+        let tx = "test transaction";
+        settings.updates =
+            Some(serde_json::from_value(json!({ "deny-versions": ["1.0.0"] })).unwrap());
+        set_settings(&mut ds, &settings, tx).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+
+        match rollback_settings(&mut ds, 1) {
+            Err(error::Error::InvalidSettings { .. }) => {}
+            other => panic!("Expected InvalidSettings, got {:?}", other),
+        }
+        // Nothing changed
+        assert_eq!(get_settings_history(&ds).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn commit_ignores_unrelated_invalid_settings() {
+        let mut ds = MemoryDataStore::new();
+        // Settings from before a validation rule existed, written directly to live data.
+        let mut settings = Settings::default();
+        settings.updates =
+            Some(serde_json::from_value(json!({ "deny-versions": ["latest"] })).unwrap());
+        let changes = to_pairs(&settings)
+            .unwrap()
+            .into_iter()
+            .map(|(key, value)| (key, Some(value)))
+            .collect();
+        ds.write_generation(
+            &changes,
+            ChangeSource::Commit {
+                transaction: "old".to_string(),
+            },
+        )
+        .unwrap();
+
+        // A change to other settings goes through...
+        let tx = "test transaction";
+        let mut settings = Settings::default();
+        settings.motd = Some("hi".try_into().unwrap());
+        set_settings(&mut ds, &settings, tx).unwrap();
+        preview_commit(&ds, tx).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+
+        // ...but a change to the invalid group still has to fix it.
+        let mut settings = Settings::default();
+        settings.updates = Some(serde_json::from_value(json!({ "seed": 1 })).unwrap());
+        set_settings(&mut ds, &settings, tx).unwrap();
+        match commit_transaction(&mut ds, tx) {
+            Err(error::Error::InvalidSettings { .. }) => {}
+            other => panic!("Expected InvalidSettings, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn settings_changes_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        assert_eq!(get_settings_generation(&ds).unwrap(), 0);
+
+        let mut settings = Settings::default();
+        settings.motd = Some("hi".try_into().unwrap());
+        set_settings(&mut ds, &settings, tx).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+        assert_eq!(get_settings_generation(&ds).unwrap(), 1);
+
+        let changes = get_settings_changes(&ds, 0, "settings.").unwrap();
+        assert_eq!(changes.generation, 1);
+        assert_eq!(
+            changes.keys,
+            hashset!(Key::new(KeyType::Data, "settings.motd").unwrap())
+        );
+
+        // Changes are filtered by prefix
+        let changes = get_settings_changes(&ds, 0, "settings.kubernetes").unwrap();
+        assert_eq!(changes.generation, 1);
+        assert!(changes.keys.is_empty());
+
+        // Prefixes match whole segments, not partial ones
+        assert_eq!(
+            get_settings_changes(&ds, 0, "settings").unwrap().keys.len(),
+            1
+        );
+        assert_eq!(
+            get_settings_changes(&ds, 0, "settings.motd")
+                .unwrap()
+                .keys
+                .len(),
+            1
+        );
+        assert!(get_settings_changes(&ds, 0, "settings.mot")
+            .unwrap()
+            .keys
+            .is_empty());
+
+        // Nothing has changed since the latest generation
+        assert!(get_settings_changes(&ds, 1, "settings.")
+            .unwrap()
+            .keys
+            .is_empty());
+
+        // Unknown generations are reported as such
+        match get_settings_changes(&ds, 42, "settings.") {
+            Err(error::Error::GenerationNotFound { generation: 42 }) => {}
+            other => panic!("Expected GenerationNotFound, got {:?}", other),
+        }
+    }
 }
@@ -55,6 +55,28 @@ pub enum Error {
     #[snafu(display("Tried to commit with no pending changes"))]
     CommitWithNoPending,
 
+    #[snafu(display("Invalid generation '{}': {}", input, source))]
+    InvalidGeneration {
+        input: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display("Invalid {} value '{}': {}", name, input, source))]
+    InvalidBoolParameter {
+        name: String,
+        input: String,
+        source: std::str::ParseBoolError,
+    },
+
+    #[snafu(display("Settings are read-only: {}", keys))]
+    ReadOnlySettings { keys: String },
+
+    #[snafu(display(
+        "Tried to roll back to generation {}, which is already current",
+        generation
+    ))]
+    RollbackWithNoChanges { generation: u64 },
+
     #[snafu(display("Unable to get OS release data: {}", source))]
     ReleaseData { source: bottlerocket_release::Error },
 
@@ -67,6 +89,9 @@ pub enum Error {
     #[snafu(display("Found no '{}' in datastore", requested))]
     ListKeys { requested: String },
 
+    #[snafu(display("Generation {} not found in settings history", generation))]
+    GenerationNotFound { generation: u64 },
+
     #[snafu(display("Listed key '{}' not found on disk", key))]
     ListedKeyNotPresent { key: String },
 
@@ -125,12 +150,49 @@ pub enum Error {
     #[snafu(display("Unable to send input to config applier: {}", source))]
     ConfigApplierWrite { source: io::Error },
 
+    #[snafu(display("Settings would be invalid after commit: {}", source))]
+    InvalidSettings { source: model::validation::Error },
+
+    #[snafu(display("Unable to build template registry: {}", source))]
+    TemplateRegistry { source: schnauzer::Error },
+
+    #[snafu(display("Unable to register template '{}' from '{}': {}", name, path, source))]
+    TemplateRegister {
+        name: String,
+        path: String,
+        source: handlebars::TemplateFileError,
+    },
+
+    #[snafu(display("Unable to render template '{}': {}", name, source))]
+    TemplateRender {
+        name: String,
+        source: handlebars::RenderError,
+    },
+
+    #[snafu(display("Unable to read configuration file '{}': {}", path, source))]
+    ConfigFileRead { path: String, source: io::Error },
+
     #[snafu(display("Unable to start shutdown: {}", source))]
     Shutdown { source: io::Error },
 
     #[snafu(display("Failed to reboot, exit code: {}, stderr: {}", exit_code, stderr))]
     Reboot { exit_code: i32, stderr: String },
 
+    #[snafu(display("Unable to start draining before reboot: {}", source))]
+    DrainDispatcher { source: io::Error },
+
+    #[snafu(display("Unable to wait for draining before reboot: {}", source))]
+    DrainBlocked {
+        source: actix_web::error::BlockingError,
+    },
+
+    #[snafu(display(
+        "Failed to drain before reboot, exit code: {}, stderr: {}",
+        exit_code,
+        stderr
+    ))]
+    Drain { exit_code: i32, stderr: String },
+
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
     // Update related errors
@@ -164,6 +226,9 @@ pub enum Error {
     #[snafu(display("Failed to parse update status: {} ", source))]
     UpdateStatusParse { source: serde_json::Error },
 
+    #[snafu(display("Failed to load update history: {}", source))]
+    UpdateHistory { source: update_history::Error },
+
     #[snafu(display(
         "Failed to parse update information from '{}': {} ",
         String::from_utf8_lossy(stdout),
@@ -6,16 +6,16 @@ mod error;
 pub use error::Error;
 
 use actix_web::{
-    body::Body, error::ResponseError, web, App, BaseHttpResponse, FromRequest, HttpRequest,
+    body::Body, error::ResponseError, web, App, BaseHttpResponse, Either, FromRequest, HttpRequest,
     HttpResponse, HttpServer, Responder,
 };
 use bottlerocket_release::BottlerocketRelease;
-use datastore::{Committed, FilesystemDataStore, Key, Value};
+use datastore::{Committed, DataStore, FilesystemDataStore, Generation, Key, Value};
 use error::Result;
 use fs2::FileExt;
 use http::StatusCode;
 use log::info;
-use model::{ConfigurationFiles, Model, Services, Settings};
+use model::{ConfigurationFiles, Model, NetworkState, Services, Settings};
 use nix::unistd::{chown, Gid};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
@@ -26,7 +26,15 @@ use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
 use std::process::Command;
 use std::sync;
-use thar_be_updates::status::{UpdateStatus, UPDATE_LOCKFILE};
+use std::time::Duration;
+use thar_be_updates::status::{UpdateStatus, UPDATE_LOCKFILE, UPDATE_PROGRESS_FILE};
+use tokio::sync::watch;
+use tokio::time::{timeout_at, Instant};
+use update_history::{Event, HISTORY_FILE};
+
+/// How long a request to /settings/watch waits for a matching change before it responds with no
+/// changed keys.
+const WATCH_TIMEOUT: Duration = Duration::from_secs(60);
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
@@ -64,8 +72,11 @@ where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
 {
+    let (changed_tx, changed_rx) = watch::channel(0);
     let shared_datastore = web::Data::new(SharedDataStore {
         ds: sync::RwLock::new(FilesystemDataStore::new(datastore_path)),
+        changed_tx,
+        changed_rx,
     });
 
     let http_server = HttpServer::new(move || {
@@ -86,8 +97,12 @@ where
             .service(
                 web::scope("/settings")
                     .route("", web::get().to(get_settings))
-                    .route("", web::patch().to(patch_settings)),
+                    .route("", web::patch().to(patch_settings))
+                    .route("/history", web::get().to(get_settings_history))
+                    .route("/watch", web::get().to(watch_settings))
+                    .route("/rollback", web::post().to(rollback_settings)),
             )
+            .service(web::scope("/network").route("/current", web::patch().to(patch_network_state)))
             .service(
                 // Transaction support
                 web::scope("/tx")
@@ -106,6 +121,7 @@ where
                 web::scope("/metadata")
                     .route("/affected-services", web::get().to(get_affected_services))
                     .route("/setting-generators", web::get().to(get_setting_generators))
+                    .route("/sensitive", web::get().to(get_sensitive_settings))
                     .route("/templates", web::get().to(get_templates)),
             )
             .service(web::scope("/services").route("", web::get().to(get_services)))
@@ -121,7 +137,11 @@ where
                     .route("/activate-update", web::post().to(activate_update))
                     .route("/deactivate-update", web::post().to(deactivate_update)),
             )
-            .service(web::scope("/updates").route("/status", web::get().to(get_update_status)))
+            .service(
+                web::scope("/updates")
+                    .route("/status", web::get().to(get_update_status))
+                    .route("/history", web::get().to(get_update_history)),
+            )
     })
     .workers(threads)
     .bind_uds(socket_path.as_ref())
@@ -194,6 +214,9 @@ async fn get_settings(
 }
 
 /// Apply the requested settings to the pending data store
+///
+/// Settings marked read-only in metadata are rejected; they're maintained by system components
+/// through their own endpoints, like /network/current.
 async fn patch_settings(
     settings: web::Json<Settings>,
     query: web::Query<HashMap<String, String>>,
@@ -201,10 +224,111 @@ async fn patch_settings(
 ) -> Result<HttpResponse> {
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+    controller::check_read_only(&*datastore, &settings)?;
     controller::set_settings(&mut *datastore, &settings, transaction)?;
     Ok(HttpResponse::NoContent().finish()) // 204
 }
 
+/// Apply the reported network state to settings.network.current in the pending data store.  These
+/// settings are read-only through /settings; this is how netdog, which maintains them, reports
+/// changes.  Only the network state can be changed here.  Nothing limits this to netdog, since the
+/// API doesn't know which client is calling, so the read-only flag is only advisory.
+async fn patch_network_state(
+    state: web::Json<NetworkState>,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HttpResponse> {
+    let transaction = transaction_name(&query);
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+    controller::set_network_state(&mut *datastore, &state, transaction)?;
+    Ok(HttpResponse::NoContent().finish()) // 204
+}
+
+/// Returns the recorded history of changes to live settings.
+async fn get_settings_history(data: web::Data<SharedDataStore>) -> Result<HistoryResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let history = controller::get_settings_history(&*datastore)?;
+    Ok(HistoryResponse(history))
+}
+
+/// Waits for changes to live settings made after the generation given in the query parameters, or
+/// after the current generation if unspecified, and returns the latest generation along with the
+/// changed keys.  If 'prefix' is specified, only changes to keys under it are reported.
+///
+/// If nothing matching changes within WATCH_TIMEOUT, returns the latest generation with no keys;
+/// the caller can watch again starting from that generation.
+async fn watch_settings(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<SettingsChangesResponse> {
+    let prefix = match query.get("prefix") {
+        Some(prefix_str) if prefix_str.is_empty() => {
+            return error::EmptyInput { input: "prefix" }.fail();
+        }
+        Some(prefix_str) => prefix_str.as_str(),
+        None => "settings",
+    };
+
+    // Take our own receiver before checking for changes so we can't miss a change that's made
+    // in between.
+    let mut changed_rx = data.changed_rx.clone();
+
+    let since = match query.get("generation") {
+        Some(generation_str) => generation_str.parse().context(error::InvalidGeneration {
+            input: generation_str,
+        })?,
+        None => {
+            let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+            controller::get_settings_generation(&*datastore)?
+        }
+    };
+
+    let deadline = Instant::now() + WATCH_TIMEOUT;
+    loop {
+        let changes = {
+            let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+            controller::get_settings_changes(&*datastore, since, prefix)?
+        };
+        if !changes.keys.is_empty() {
+            return Ok(SettingsChangesResponse(changes));
+        }
+
+        // Wait for the next change to live settings; it may not match our prefix, in which case
+        // we keep waiting until the deadline.
+        match timeout_at(deadline, changed_rx.changed()).await {
+            Ok(Ok(())) => continue,
+            _ => return Ok(SettingsChangesResponse(changes)),
+        }
+    }
+}
+
+/// Restores live settings to the state they had after the generation given in the query
+/// parameters, then applies the changes, like commit_and_apply.  Returns the list of changed keys.
+async fn rollback_settings(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<ChangedKeysResponse> {
+    let generation_str = query.get("generation").context(error::MissingInput {
+        input: "generation",
+    })?;
+    let generation = generation_str.parse().context(error::InvalidGeneration {
+        input: generation_str,
+    })?;
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+
+    let changes = controller::rollback_settings(&mut *datastore, generation)?;
+
+    if changes.is_empty() {
+        return error::RollbackWithNoChanges { generation }.fail();
+    }
+    data.notify_changed(&*datastore)?;
+
+    let key_names = changes.iter().map(|k| k.name()).collect();
+    controller::apply_changes(Some(&key_names))?;
+
+    Ok(ChangedKeysResponse(changes))
+}
+
 async fn get_transaction_list(data: web::Data<SharedDataStore>) -> Result<TransactionListResponse> {
     let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
     let data = controller::list_transactions(&*datastore)?;
@@ -235,11 +359,25 @@ async fn delete_transaction(
 
 /// Save settings changes from the given transaction, or the "default" transaction if unspecified,
 /// to the live data store.  Returns the list of changed keys.
+///
+/// If 'dry-run' is "true" in query parameters, nothing is changed; instead, returns the keys that
+/// would change, the services they affect, and diffs of the configuration files that would be
+/// rewritten.
 async fn commit_transaction(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
-) -> Result<ChangedKeysResponse> {
+) -> Result<Either<ChangedKeysResponse, CommitPreviewResponse>> {
     let transaction = transaction_name(&query);
+
+    if bool_param(&query, "dry-run")? {
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        let preview = controller::preview_commit(&*datastore, transaction)?;
+        if preview.changed_keys.is_empty() {
+            return error::CommitWithNoPending.fail();
+        }
+        return Ok(Either::Right(CommitPreviewResponse(preview)));
+    }
+
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
 
     let changes = controller::commit_transaction(&mut *datastore, transaction)?;
@@ -247,8 +385,9 @@ async fn commit_transaction(
     if changes.is_empty() {
         return error::CommitWithNoPending.fail();
     }
+    data.notify_changed(&*datastore)?;
 
-    Ok(ChangedKeysResponse(changes))
+    Ok(Either::Left(ChangedKeysResponse(changes)))
 }
 
 /// Starts settings appliers for any changes that have been committed to the data store.  This
@@ -279,6 +418,7 @@ async fn commit_transaction_and_apply(
     if changes.is_empty() {
         return error::CommitWithNoPending.fail();
     }
+    data.notify_changed(&*datastore)?;
 
     let key_names = changes.iter().map(|k| k.name()).collect();
     controller::apply_changes(Some(&key_names))?;
@@ -314,6 +454,14 @@ async fn get_setting_generators(data: web::Data<SharedDataStore>) -> Result<Meta
     Ok(MetadataResponse(resp))
 }
 
+/// Get all settings marked as sensitive in metadata, like credentials, which should be kept out of
+/// anything shared outside the host
+async fn get_sensitive_settings(data: web::Data<SharedDataStore>) -> Result<MetadataResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let resp = controller::get_metadata_for_all_data_keys(&*datastore, "sensitive")?;
+    Ok(MetadataResponse(resp))
+}
+
 /// Get the template metadata for a list of data keys
 async fn get_templates(
     query: web::Query<HashMap<String, String>>,
@@ -367,7 +515,16 @@ async fn get_configuration_files(
 /// Get the update status from 'thar-be-updates'
 async fn get_update_status() -> Result<UpdateStatusResponse> {
     let lockfile = File::create(UPDATE_LOCKFILE).context(error::UpdateLockOpen)?;
-    lockfile.try_lock_shared().context(error::UpdateShareLock)?;
+    if let Err(e) = lockfile.try_lock_shared() {
+        // thar-be-updates holds the lock for as long as a command runs, which could be a long
+        // download for 'prepare'.  updog's progress file only exists during the download, and
+        // the status file isn't written until updog is done, so we can still safely show how far
+        // the download has gotten.  Otherwise, wait for the command to finish, as usual.
+        let contended = e.kind() == fs2::lock_contended_error().kind();
+        if !contended || !Path::new(UPDATE_PROGRESS_FILE).exists() {
+            return Err(e).context(error::UpdateShareLock);
+        }
+    }
     let result = thar_be_updates::status::get_update_status(&lockfile);
     match result {
         Ok(update_status) => Ok(UpdateStatusResponse(update_status)),
@@ -380,6 +537,12 @@ async fn get_update_status() -> Result<UpdateStatusResponse> {
     }
 }
 
+/// Get the persistent history of update events, oldest first
+async fn get_update_history() -> Result<UpdateHistoryResponse> {
+    let events = update_history::load(HISTORY_FILE).context(error::UpdateHistory)?;
+    Ok(UpdateHistoryResponse(events))
+}
+
 /// Refreshes the list of updates and checks if an update is available matching the configured version lock
 async fn refresh_updates() -> Result<HttpResponse> {
     controller::dispatch_update_command(&["refresh"])
@@ -400,8 +563,13 @@ async fn deactivate_update() -> Result<HttpResponse> {
     controller::dispatch_update_command(&["deactivate"])
 }
 
-/// Reboots the machine
+/// Reboots the machine, after moving workloads off of it if the user asked for that
 async fn reboot() -> Result<HttpResponse> {
+    // sheepdog reads settings from us, so it can't run on the worker thread handling this request.
+    web::block(controller::drain_before_reboot)
+        .await
+        .context(error::DrainBlocked)??;
+
     debug!("Rebooting now");
     let output = Command::new("/sbin/shutdown")
         .arg("-r")
@@ -432,6 +600,17 @@ fn comma_separated<'a>(key_name: &'static str, input: &'a str) -> Result<HashSet
     Ok(input.split(',').collect())
 }
 
+/// Returns the value of the given boolean query parameter, or false if it wasn't given.
+fn bool_param(query: &web::Query<HashMap<String, String>>, name: &str) -> Result<bool> {
+    match query.get(name) {
+        Some(value_str) => value_str.parse().context(error::InvalidBoolParameter {
+            name,
+            input: value_str,
+        }),
+        None => Ok(false),
+    }
+}
+
 fn transaction_name(query: &web::Query<HashMap<String, String>>) -> &str {
     if let Some(name_str) = query.get("tx") {
         name_str
@@ -450,6 +629,9 @@ impl ResponseError for error::Error {
             MissingInput { .. } => StatusCode::BAD_REQUEST,
             EmptyInput { .. } => StatusCode::BAD_REQUEST,
             NewKey { .. } => StatusCode::BAD_REQUEST,
+            InvalidGeneration { .. } => StatusCode::BAD_REQUEST,
+            InvalidBoolParameter { .. } => StatusCode::BAD_REQUEST,
+            ReadOnlySettings { .. } => StatusCode::BAD_REQUEST,
 
             // 404 Not Found
             MissingData { .. } => StatusCode::NOT_FOUND,
@@ -457,9 +639,13 @@ impl ResponseError for error::Error {
             UpdateDoesNotExist { .. } => StatusCode::NOT_FOUND,
             NoStagedImage { .. } => StatusCode::NOT_FOUND,
             UninitializedUpdateStatus { .. } => StatusCode::NOT_FOUND,
+            GenerationNotFound { .. } => StatusCode::NOT_FOUND,
 
             // 422 Unprocessable Entity
             CommitWithNoPending => StatusCode::UNPROCESSABLE_ENTITY,
+            InvalidSettings { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            TemplateRender { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            RollbackWithNoChanges { .. } => StatusCode::UNPROCESSABLE_ENTITY,
 
             // 423 Locked
             UpdateShareLock { .. } => StatusCode::LOCKED,
@@ -484,6 +670,9 @@ impl ResponseError for error::Error {
             ConfigApplierStdin {} => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierWait { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            TemplateRegistry { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            TemplateRegister { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ConfigFileRead { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SystemdNotify { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SystemdNotifyStatus {} => StatusCode::INTERNAL_SERVER_ERROR,
             SetPermissions { .. } => StatusCode::INTERNAL_SERVER_ERROR,
@@ -491,10 +680,14 @@ impl ResponseError for error::Error {
             ReleaseData { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Shutdown { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Reboot { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            DrainDispatcher { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            DrainBlocked { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Drain { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateDispatcher { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateStatusParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateInfoParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            UpdateHistory { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateLockOpen { .. } => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -504,6 +697,20 @@ impl ResponseError for error::Error {
 
 struct SharedDataStore {
     ds: sync::RwLock<FilesystemDataStore>,
+    // Carries the latest generation of live settings to requests watching for changes.  We keep a
+    // receiver here so requests can clone it, and so sending never fails for lack of receivers.
+    changed_tx: watch::Sender<u64>,
+    changed_rx: watch::Receiver<u64>,
+}
+
+impl SharedDataStore {
+    /// Wakes any requests watching for changes, after live settings have changed.
+    fn notify_changed<D: DataStore>(&self, datastore: &D) -> Result<()> {
+        let generation = controller::get_settings_generation(datastore)?;
+        // Can't fail; we hold a receiver ourselves.
+        let _ = self.changed_tx.send(generation);
+        Ok(())
+    }
 }
 
 /// Helper macro for implementing the actix-web Responder trait for a type.
@@ -551,6 +758,10 @@ impl_responder_for!(ServicesResponse, self, self.0);
 struct UpdateStatusResponse(UpdateStatus);
 impl_responder_for!(UpdateStatusResponse, self, self.0);
 
+/// This lets us respond from our handler methods with the update history (or Result<Vec<Event>>)
+struct UpdateHistoryResponse(Vec<Event>);
+impl_responder_for!(UpdateHistoryResponse, self, self.0);
+
 /// This lets us respond from our handler methods with a ConfigurationFiles (or
 /// Result<ConfigurationFiles>)
 struct ConfigurationFilesResponse(ConfigurationFiles);
@@ -559,5 +770,92 @@ impl_responder_for!(ConfigurationFilesResponse, self, self.0);
 struct ChangedKeysResponse(HashSet<Key>);
 impl_responder_for!(ChangedKeysResponse, self, self.0);
 
+/// This lets us respond from our handler methods with a preview of a commit (or
+/// Result<CommitPreview>)
+struct CommitPreviewResponse(controller::CommitPreview);
+impl_responder_for!(CommitPreviewResponse, self, self.0);
+
 struct TransactionListResponse(HashSet<String>);
 impl_responder_for!(TransactionListResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the settings history (or
+/// Result<Vec<Generation>>)
+struct HistoryResponse(Vec<Generation>);
+impl_responder_for!(HistoryResponse, self, self.0);
+
+/// This lets us respond from our handler methods with changes to settings (or
+/// Result<SettingsChanges>)
+struct SettingsChangesResponse(controller::SettingsChanges);
+impl_responder_for!(SettingsChangesResponse, self, self.0);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use datastore::KeyType;
+    use maplit::hashmap;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn shared_datastore(dir: &TempDir) -> web::Data<SharedDataStore> {
+        let (changed_tx, changed_rx) = watch::channel(0);
+        let mut datastore = FilesystemDataStore::new(dir.path());
+        datastore
+            .set_metadata(
+                &Key::new(KeyType::Meta, "read-only").unwrap(),
+                &Key::new(KeyType::Data, "settings.network.current").unwrap(),
+                "true",
+            )
+            .unwrap();
+        web::Data::new(SharedDataStore {
+            ds: sync::RwLock::new(datastore),
+            changed_tx,
+            changed_rx,
+        })
+    }
+
+    fn query(pairs: HashMap<String, String>) -> web::Query<HashMap<String, String>> {
+        web::Query(pairs)
+    }
+
+    #[actix_rt::test]
+    async fn patch_read_only_rejected() {
+        let dir = TempDir::new().unwrap();
+        let data = shared_datastore(&dir);
+        let settings: Settings =
+            serde_json::from_value(json!({"network": {"current": {"hostname": "example"}}}))
+                .unwrap();
+
+        // Read-only settings can only be written through /network/current
+        let result = patch_settings(web::Json(settings), query(HashMap::new()), data.clone()).await;
+        assert!(matches!(result, Err(error::Error::ReadOnlySettings { .. })));
+
+        let datastore = data.ds.read().unwrap();
+        assert!(datastore.list_transactions().unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn patch_network_state_works() {
+        let dir = TempDir::new().unwrap();
+        let data = shared_datastore(&dir);
+        let state: NetworkState = serde_json::from_value(json!({"hostname": "example"})).unwrap();
+        let tx = hashmap!("tx".to_string() => "netdog".to_string());
+
+        patch_network_state(web::Json(state), query(tx), data.clone())
+            .await
+            .unwrap();
+
+        let datastore = data.ds.read().unwrap();
+        let pending = Committed::Pending {
+            tx: "netdog".to_string(),
+        };
+        assert_eq!(
+            datastore
+                .get_key(
+                    &Key::new(KeyType::Data, "settings.network.current.hostname").unwrap(),
+                    &pending
+                )
+                .unwrap(),
+            Some("\"example\"".to_string())
+        );
+    }
+}
@@ -31,6 +31,9 @@ pub enum Error {
         source: DataStoreError,
     },
 
+    #[snafu(display("List at '{}' has non-numeric index '{}'", path, index))]
+    BadListIndex { path: String, index: String },
+
     #[snafu(display("Prefix '{}' is not a valid key: {}", prefix, source))]
     InvalidPrefix {
         prefix: String,
@@ -30,13 +30,18 @@
 //! provide the value.  We use it recursively, and at each recursion, append a dot and the name of
 //! the field to our "path" string.  In the example above, when we're looking at field "c", path
 //! would be "a.b", so we know we should look for "a.b.c" in our input mapping.
+//!
+//! Lists of structures are stored with the index of each element as a key segment, for example
+//! {"a.b.0.c": 42, "a.b.1.c": 43}, so for a list we group the keys by index and hand each group to
+//! a recursive call, in index order, using serde's SeqDeserializer.
 
 use log::{error, trace};
-use serde::de::{value::MapDeserializer, IntoDeserializer, Visitor};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{IntoDeserializer, Visitor};
 use serde::{forward_to_deserialize_any, Deserialize};
-use snafu::ResultExt;
+use snafu::{OptionExt, ResultExt};
 use std::borrow::Borrow;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::hash::Hash;
 
 use super::{error, Error, Result};
@@ -159,9 +164,28 @@ where
         }
     }
 
+    /// Lists of scalars are stored as a single value, so they're handled like any other scalar;
+    /// lists of structures are stored as indexed keys, so we recurse.
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            ValueDeserializer::Scalar(mut scalar_deserializer) => {
+                trace!("Handing off to scalar deserializer for deserialize_seq");
+                scalar_deserializer
+                    .deserialize_any(visitor)
+                    .context(error::DeserializeScalar)
+            }
+            ValueDeserializer::Compound(compound_deserializer) => {
+                compound_deserializer.deserialize_seq(visitor)
+            }
+        }
+    }
+
     forward_to_deserialize_any! {
         bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        bytes byte_buf unit unit_struct newtype_struct tuple
         tuple_struct map struct enum identifier ignored_any
     }
 }
@@ -203,6 +227,28 @@ where
     ) -> CompoundDeserializer<'de, K, S, BH> {
         CompoundDeserializer { map, keys, path }
     }
+
+    /// Remove the known path from the beginning of the keys. serde doesn't care about the name of
+    /// the top-level struct, just the fields inside, so we have to remove it before handing it to
+    /// the MapDeserializer.  (Our real customer is the one specifying the dotted keys, and we
+    /// always use the struct name there for clarity.)
+    fn strip_path(&mut self) -> Result<()> {
+        if let Some(ref path) = self.path {
+            trace!("Keys before path strip: {:?}", self.keys);
+            let mut new_keys = HashSet::new();
+            for key in self.keys.drain() {
+                new_keys.insert(key.strip_prefix_segments(&path.segments()).context(
+                    error::StripPrefix {
+                        prefix: path.name(),
+                        name: key.name(),
+                    },
+                )?);
+            }
+            self.keys = new_keys;
+            trace!("Keys after path strip: {:?}", self.keys);
+        }
+        Ok(())
+    }
 }
 
 fn bad_root<T>() -> Result<T> {
@@ -245,24 +291,7 @@ where
             trace!("Path after name check: {:?}", self.path);
         }
 
-        if let Some(ref path) = self.path {
-            // Remove the known path from the beginning of the keys. serde doesn't care about the
-            // name of the top-level struct, just the fields inside, so we have to remove it before
-            // handing it to the MapDeserializer.  (Our real customer is the one specifying the
-            // dotted keys, and we always use the struct name there for clarity.)
-            trace!("Keys before path strip: {:?}", self.keys);
-            let mut new_keys = HashSet::new();
-            for key in self.keys {
-                new_keys.insert(key.strip_prefix_segments(&path.segments()).context(
-                    error::StripPrefix {
-                        prefix: path.name(),
-                        name: key.name(),
-                    },
-                )?);
-            }
-            self.keys = new_keys;
-            trace!("Keys after path strip: {:?}", self.keys);
-        }
+        self.strip_path()?;
 
         // We have to track which structs we've already handled and skip over them.  This is
         // because we could get keys like "a.b.c" and "a.b.d", so we'll see that "a" prefix
@@ -388,6 +417,71 @@ where
         visitor.visit_some(self)
     }
 
+    /// Lists of structures are stored with each element's index as the first key segment, so we
+    /// group keys by index and recurse for each element, in index order.  Like maps, this requires
+    /// a path, because a list has no name of its own.
+    fn deserialize_seq<V>(mut self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.strip_path()?;
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return bad_root(),
+        };
+
+        // Group keys by element index; BTreeMap gives us the elements in order.
+        let mut elements: BTreeMap<usize, HashSet<Key>> = BTreeMap::new();
+        for key in self.keys.iter() {
+            // Keys always have at least one segment.
+            let index_str = &key.segments()[0];
+            let index: usize = index_str.parse().ok().context(error::BadListIndex {
+                path: path.name(),
+                index: index_str,
+            })?;
+            elements.entry(index).or_default().insert(key.clone());
+        }
+        trace!("Deserializing {} list elements at {}", elements.len(), path);
+
+        let mut deserializers = Vec::with_capacity(elements.len());
+        for (index, keys) in elements {
+            let index = index.to_string();
+            let element_path = path
+                .append_segments(&[&index])
+                .context(error::InvalidPrefix {
+                    prefix: format!("{}.{}", path, index),
+                })?;
+
+            // A key that's just the index is a scalar element, which we only expect if someone
+            // wrote keys by hand; the serializer stores lists of scalars as a single value.
+            if keys.iter().any(|key| key.segments().len() == 1) {
+                if let Some(val) = self.map.get(&element_path) {
+                    deserializers.push(ValueDeserializer::Scalar(deserializer_for_scalar(
+                        val.as_ref(),
+                    )));
+                }
+                continue;
+            }
+
+            let mut element_keys = HashSet::new();
+            for key in keys {
+                element_keys.insert(key.strip_prefix_segments(&[&index]).context(
+                    error::StripPrefix {
+                        prefix: &index,
+                        name: key.name(),
+                    },
+                )?);
+            }
+            deserializers.push(ValueDeserializer::Compound(CompoundDeserializer::new(
+                self.map,
+                element_keys,
+                Some(element_path),
+            )));
+        }
+
+        visitor.visit_seq(SeqDeserializer::new(deserializers.into_iter()))
+    }
+
     /// Scalar types, and compound types we can't use at the root, are forwarded here to be
     /// rejected.  (Compound types need to have a name to serve at the root level.)
     fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
@@ -401,7 +495,7 @@ where
     // function above that will reject them.
     forward_to_deserialize_any! {
         bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        bytes byte_buf unit unit_struct newtype_struct tuple
         tuple_struct enum identifier ignored_any
     }
 }
@@ -409,10 +503,10 @@ where
 #[cfg(test)]
 mod test {
     use super::{from_map, from_map_with_prefix};
-    use crate::{deserialization::Error, Key, KeyType};
+    use crate::{deserialization::Error, serialization::to_pairs_with_prefix, Key, KeyType};
 
     use maplit::hashmap;
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
     use std::collections::HashMap;
 
     // Helper macro for making a data Key for testing whose name we know is valid.
@@ -508,6 +602,94 @@ mod test {
         );
     }
 
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Mirrors {
+        mirrors: Vec<Mirror>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Mirror {
+        registry: Option<String>,
+        endpoints: Option<Vec<String>>,
+    }
+
+    #[test]
+    fn list_of_structs_works() {
+        let m: Mirrors = from_map(&hashmap! {
+            key!("mirrors.mirrors.0.registry") => "\"a\"".to_string(),
+            key!("mirrors.mirrors.2.registry") => "\"c\"".to_string(),
+            key!("mirrors.mirrors.10.registry") => "\"k\"".to_string(),
+            key!("mirrors.mirrors.10.endpoints") => "[\"x\", \"y\"]".to_string(),
+        })
+        .unwrap();
+        // Elements are in index order, not key string order, and gaps are skipped.
+        assert_eq!(
+            m,
+            Mirrors {
+                mirrors: vec![
+                    Mirror {
+                        registry: Some("a".to_string()),
+                        endpoints: None,
+                    },
+                    Mirror {
+                        registry: Some("c".to_string()),
+                        endpoints: None,
+                    },
+                    Mirror {
+                        registry: Some("k".to_string()),
+                        endpoints: Some(vec!["x".to_string(), "y".to_string()]),
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn list_of_structs_round_trip() {
+        let m = Mirrors {
+            mirrors: vec![
+                Mirror {
+                    registry: Some("a".to_string()),
+                    endpoints: Some(vec!["x".to_string()]),
+                },
+                Mirror {
+                    registry: Some("b".to_string()),
+                    endpoints: None,
+                },
+            ],
+        };
+        let pairs = to_pairs_with_prefix("mirrors", &m).unwrap();
+        let output: Mirrors = from_map(&pairs).unwrap();
+        assert_eq!(output, m);
+    }
+
+    #[test]
+    fn list_of_empty_structs_round_trip() {
+        // An element with nothing set has no keys of its own, so it couldn't be read back; make
+        // sure it's refused rather than silently dropped.
+        let m = Mirrors {
+            mirrors: vec![
+                Mirror {
+                    registry: Some("a".to_string()),
+                    endpoints: None,
+                },
+                Mirror {
+                    registry: None,
+                    endpoints: None,
+                },
+            ],
+        };
+        to_pairs_with_prefix("mirrors", &m).unwrap_err();
+    }
+
+    #[test]
+    fn list_bad_index() {
+        let m: Result<Mirrors, Error> = from_map(&hashmap! {
+            key!("mirrors.mirrors.first.registry") => "\"a\"".to_string(),
+        });
+        m.unwrap_err();
+    }
+
     #[derive(Debug, Deserialize, PartialEq)]
     struct Bad {
         id: u64,
@@ -59,6 +59,21 @@ pub enum Error {
 
     #[snafu(display("Key name beyond maximum length {}: {}", name, max))]
     KeyTooLong { name: String, max: usize },
+
+    #[snafu(display("Generation {} not found in settings history", generation))]
+    GenerationNotFound { generation: u64 },
+
+    #[snafu(display("Generation {} is missing from settings history", generation))]
+    GenerationMissing { generation: u64 },
+
+    #[snafu(display("Unable to serialize settings history: {}", source))]
+    HistorySerialization { source: serde_json::Error },
+
+    #[snafu(display("Settings history at '{}' is invalid: {}", path.display(), source))]
+    HistoryParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
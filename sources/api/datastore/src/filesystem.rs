@@ -3,6 +3,8 @@
 //!
 //! Data is kept in files with paths resembling the keys, e.g. a/b/c for a.b.c, and metadata is
 //! kept in a suffixed file next to the data, e.g. a/b/c.meta for metadata "meta" about a.b.c
+//!
+//! History is kept as one JSON file per generation, named by generation number, e.g. history/42.
 
 use log::{debug, error, trace};
 use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
@@ -13,6 +15,7 @@ use std::io;
 use std::path::{self, Path, PathBuf};
 use walkdir::{DirEntry, WalkDir};
 
+use super::history::{ChangeSource, Generation, MAX_GENERATIONS};
 use super::key::{Key, KeyType};
 use super::{error, Committed, DataStore, Result};
 
@@ -29,6 +32,7 @@ const ENCODE_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'_').remove(b'-')
 pub struct FilesystemDataStore {
     live_path: PathBuf,
     pending_base_path: PathBuf,
+    history_path: PathBuf,
 }
 
 impl FilesystemDataStore {
@@ -36,6 +40,7 @@ impl FilesystemDataStore {
         FilesystemDataStore {
             live_path: base_path.as_ref().join("live"),
             pending_base_path: base_path.as_ref().join("pending"),
+            history_path: base_path.as_ref().join("history"),
         }
     }
 
@@ -167,6 +172,37 @@ impl FilesystemDataStore {
         }
         Ok(())
     }
+
+    /// Returns the numbers of the generations in the history directory, from oldest to newest.
+    /// The numbers come from the file names, so the generations themselves aren't loaded.
+    fn generation_numbers(&self) -> Result<Vec<u64>> {
+        let entries = match fs::read_dir(&self.history_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                // If there's no history directory, nothing has been recorded yet.
+                if e.kind() == io::ErrorKind::NotFound {
+                    return Ok(Vec::new());
+                }
+                return Err(e).context(error::Io {
+                    path: &self.history_path,
+                });
+            }
+        };
+
+        let mut numbers = Vec::new();
+        for entry in entries {
+            let entry = entry.context(error::Io {
+                path: &self.history_path,
+            })?;
+            // Skip anything that isn't a generation, like a file that's still being written.
+            if let Some(number) = entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+                numbers.push(number);
+            }
+        }
+
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
 }
 
 // Filesystem helpers
@@ -483,8 +519,9 @@ impl DataStore for FilesystemDataStore {
     where
         S: Into<String> + AsRef<str>,
     {
+        let transaction = transaction.into();
         let pending = Committed::Pending {
-            tx: transaction.into(),
+            tx: transaction.clone(),
         };
         // Get data for changed keys
         let pending_data = self.get_prefix("settings.", &pending)?;
@@ -494,12 +531,13 @@ impl DataStore for FilesystemDataStore {
             return Ok(Default::default());
         }
 
-        // Save Keys for return value
-        let pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
-
-        // Apply changes to live
+        // Apply changes to live, recording the old values so they can be rolled back
         debug!("Writing pending keys to live");
-        self.set_keys(&pending_data, &Committed::Live)?;
+        let changes = pending_data
+            .into_iter()
+            .map(|(key, value)| (key, Some(value)))
+            .collect();
+        let pending_keys = self.write_generation(&changes, ChangeSource::Commit { transaction })?;
 
         // Remove pending
         debug!("Removing old pending keys");
@@ -509,6 +547,49 @@ impl DataStore for FilesystemDataStore {
         Ok(pending_keys)
     }
 
+    /// Generations are stored as files named by their generation number, so we read them all
+    /// and sort them by number.  A generation that can't be parsed is skipped, rather than making
+    /// the whole history unavailable.  Its number is still taken, so rollbacks and change queries
+    /// that need it fail rather than silently ignoring its changes.
+    fn list_generations(&self) -> Result<Vec<Generation>> {
+        let mut generations: Vec<Generation> = Vec::new();
+        for number in self.generation_numbers()? {
+            let path = self.history_path.join(number.to_string());
+            let data = fs::read_to_string(&path).context(error::Io { path: &path })?;
+            match serde_json::from_str(&data).context(error::HistoryParse { path: &path }) {
+                Ok(generation) => generations.push(generation),
+                Err(e) => error!("Skipping settings history generation: {}", e),
+            }
+        }
+        Ok(generations)
+    }
+
+    fn add_generation(&mut self, generation: &Generation) -> Result<()> {
+        let data = serde_json::to_string(generation).context(error::HistorySerialization)?;
+        // Write to a temporary file and rename it into place, so a crash can't leave a partial
+        // generation behind.
+        let name = generation.generation.to_string();
+        let tmp_path = self.history_path.join(format!(".{}.tmp", name));
+        write_file_mkdir(tmp_path.clone(), data)?;
+        let path = self.history_path.join(name);
+        fs::rename(&tmp_path, &path).context(error::Io { path })?;
+
+        // Remove the oldest generations beyond what we want to keep.
+        let numbers = self.generation_numbers()?;
+        let excess = numbers.len().saturating_sub(MAX_GENERATIONS);
+        for old in &numbers[..excess] {
+            let path = self.history_path.join(old.to_string());
+            debug!("Removing old history generation {}", old);
+            fs::remove_file(&path).context(error::Io { path })?;
+        }
+        Ok(())
+    }
+
+    fn next_generation(&self) -> Result<u64> {
+        // Generation files are named by number, so we don't need to parse them to find the latest.
+        Ok(self.generation_numbers()?.last().copied().unwrap_or(0) + 1)
+    }
+
     fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
@@ -583,6 +664,8 @@ impl DataStore for FilesystemDataStore {
 #[cfg(test)]
 mod test {
     use super::*;
+    use chrono::Utc;
+    use tempfile::TempDir;
 
     #[test]
     fn data_path() {
@@ -623,6 +706,50 @@ mod test {
         assert_eq!(live.into_os_string(), "/base/live/a/b/c.my-metadata");
     }
 
+    #[test]
+    fn list_generations_skips_corrupt() {
+        let dir = TempDir::new().unwrap();
+        let mut f = FilesystemDataStore::new(dir.path());
+        for number in 1..=2 {
+            f.add_generation(&Generation {
+                generation: number,
+                timestamp: Utc::now(),
+                source: ChangeSource::Commit {
+                    transaction: "test".to_string(),
+                },
+                previous: HashMap::new(),
+            })
+            .unwrap();
+        }
+        fs::write(dir.path().join("history/3"), "{\"generation\": 3, \"time").unwrap();
+        fs::write(dir.path().join("history/.4.tmp"), "").unwrap();
+
+        let generations = f.list_generations().unwrap();
+        let numbers: Vec<u64> = generations.iter().map(|g| g.generation).collect();
+        assert_eq!(numbers, vec![1, 2]);
+
+        // The corrupt generation's number is still taken.
+        assert_eq!(f.next_generation().unwrap(), 4);
+
+        // We can't tell what the corrupt generation changed, so we can't roll back past it,
+        // whether it's the latest generation or one in the middle.
+        f.rollback_generation(2).unwrap_err();
+        f.add_generation(&Generation {
+            generation: 4,
+            timestamp: Utc::now(),
+            source: ChangeSource::Commit {
+                transaction: "test".to_string(),
+            },
+            previous: HashMap::new(),
+        })
+        .unwrap();
+        match f.rollback_generation(2) {
+            Err(error::Error::GenerationMissing { generation: 3 }) => (),
+            other => panic!("expected generation 3 to be missing, got {:?}", other),
+        }
+        assert!(f.rollback_generation(4).unwrap().is_empty());
+    }
+
     #[test]
     fn encode_path_component_works() {
         assert_eq!(encode_path_component("a-b_42"), "a-b_42");
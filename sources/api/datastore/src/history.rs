@@ -0,0 +1,252 @@
+//! The history module describes the record kept of changes to live data.
+//!
+//! Every change to live data, whether from committing a transaction or rolling back earlier
+//! changes, is recorded as a numbered "generation".  A generation stores the values its keys had
+//! *before* the change, which is all we need to undo it later.  Generation numbers start at 1 and
+//! increase with each change; generation 0 represents the state of live data before any recorded
+//! change.
+//!
+//! Only the most recent `MAX_GENERATIONS` generations are kept, so you can't roll back past the
+//! oldest remaining generation.  Data stores may also fail to load a generation, for example if its
+//! record is corrupt; we can't tell what changed in it, so you can't roll back past it either.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use snafu::ensure;
+use std::collections::{HashMap, HashSet};
+
+use super::{error, Key, KeyType, Result};
+
+/// The number of generations data stores should keep; older generations are removed as new ones
+/// are recorded.
+pub const MAX_GENERATIONS: usize = 64;
+
+/// ChangeSource represents the operation that changed live data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeSource {
+    /// A pending transaction with the given name was committed.
+    Commit { transaction: String },
+    /// Live data was rolled back to the state it had after the given generation.
+    Rollback { generation: u64 },
+}
+
+/// Generation represents a single change to live data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Generation {
+    pub generation: u64,
+    pub timestamp: DateTime<Utc>,
+    pub source: ChangeSource,
+    /// Maps the name of each data key changed in this generation to the value it had before the
+    /// change, or None if the key wasn't populated.
+    pub previous: HashMap<String, Option<String>>,
+}
+
+/// Returns the number of the latest generation in the given history, which should be ordered from
+/// oldest to newest, or 0 if no changes have been recorded.
+pub fn latest_generation(history: &[Generation]) -> u64 {
+    history.last().map(|g| g.generation).unwrap_or(0)
+}
+
+/// Returns the number that should be given to the next generation recorded after the given
+/// history, which should be ordered from oldest to newest.
+pub fn next_generation(history: &[Generation]) -> u64 {
+    latest_generation(history) + 1
+}
+
+/// Ensures that the given history has every generation after `target`, up to and including the
+/// `latest` recorded generation, so we can tell what changed since then.
+fn ensure_reachable(history: &[Generation], latest: u64, target: u64) -> Result<()> {
+    // We need every generation after the target, so the oldest one we've kept must be no later
+    // than the one right after the target.
+    let oldest = history.first().map(|g| g.generation).unwrap_or(latest + 1);
+    ensure!(
+        target <= latest && target + 1 >= oldest,
+        error::GenerationNotFound { generation: target }
+    );
+
+    // Generations we couldn't load leave gaps, including at the end of the history if the latest
+    // generation is the one that couldn't be loaded.
+    let mut expected = target + 1;
+    for generation in history.iter().filter(|g| g.generation > target) {
+        ensure!(
+            generation.generation == expected,
+            error::GenerationMissing {
+                generation: expected
+            }
+        );
+        expected += 1;
+    }
+    ensure!(
+        expected == latest + 1,
+        error::GenerationMissing {
+            generation: expected
+        }
+    );
+    Ok(())
+}
+
+/// Given the recorded history, ordered from oldest to newest, returns the keys changed in any
+/// generation after the `since` generation.  `latest` is the number of the latest recorded
+/// generation, which the history may not include if it couldn't be loaded.
+///
+/// Returns an empty set if `since` is the latest generation, and Err if `since` is newer than
+/// the latest generation or older than the history we've kept, or if any generation after
+/// `since` is missing from the history.
+pub fn changed_since(history: &[Generation], latest: u64, since: u64) -> Result<HashSet<Key>> {
+    ensure_reachable(history, latest, since)?;
+
+    let mut changed = HashSet::new();
+    for generation in history.iter().rev().take_while(|g| g.generation > since) {
+        for name in generation.previous.keys() {
+            changed.insert(Key::new(KeyType::Data, name)?);
+        }
+    }
+    Ok(changed)
+}
+
+/// Given the recorded history, ordered from oldest to newest, returns the changes needed to
+/// restore live data to the state it had after the `target` generation.  Each key maps to the
+/// value it should be given, or None if it should be unset.  `latest` is the number of the latest
+/// recorded generation, which the history may not include if it couldn't be loaded.
+///
+/// Returns an empty map if `target` is the latest generation, and Err if `target` is newer than
+/// the latest generation or older than the history we've kept, or if any generation after
+/// `target` is missing from the history.
+pub fn rollback_changes(
+    history: &[Generation],
+    latest: u64,
+    target: u64,
+) -> Result<HashMap<Key, Option<String>>> {
+    ensure_reachable(history, latest, target)?;
+
+    // Undo generations from newest to oldest; if a key was changed in several generations, the
+    // value from before the oldest of those changes is the one that was live as of the target.
+    let mut changes = HashMap::new();
+    for generation in history.iter().rev().take_while(|g| g.generation > target) {
+        for (name, value) in &generation.previous {
+            changes.insert(Key::new(KeyType::Data, name)?, value.clone());
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::{hashmap, hashset};
+
+    fn generation(number: u64, previous: HashMap<String, Option<String>>) -> Generation {
+        Generation {
+            generation: number,
+            timestamp: Utc::now(),
+            source: ChangeSource::Commit {
+                transaction: "test".to_string(),
+            },
+            previous,
+        }
+    }
+
+    fn key(name: &str) -> Key {
+        Key::new(KeyType::Data, name).unwrap()
+    }
+
+    #[test]
+    fn next_generation_works() {
+        assert_eq!(next_generation(&[]), 1);
+        assert_eq!(next_generation(&[generation(41, HashMap::new())]), 42);
+    }
+
+    #[test]
+    fn changed_since_works() {
+        let history = vec![
+            generation(3, hashmap!("settings.a".to_string() => None)),
+            generation(4, hashmap!("settings.b".to_string() => None)),
+            generation(5, hashmap!("settings.a".to_string() => None)),
+        ];
+        assert_eq!(
+            changed_since(&history, 5, 3).unwrap(),
+            hashset!(key("settings.a"), key("settings.b"))
+        );
+        assert_eq!(
+            changed_since(&history, 5, 4).unwrap(),
+            hashset!(key("settings.a"))
+        );
+        assert!(changed_since(&history, 5, 5).unwrap().is_empty());
+        // Pruned, or not yet recorded.
+        changed_since(&history, 5, 1).unwrap_err();
+        changed_since(&history, 5, 6).unwrap_err();
+    }
+
+    #[test]
+    fn rollback_uses_oldest_previous_value() {
+        let history = vec![
+            generation(1, hashmap!("settings.a".to_string() => None)),
+            generation(
+                2,
+                hashmap!("settings.a".to_string() => Some("\"1\"".to_string())),
+            ),
+            generation(
+                3,
+                hashmap!(
+                    "settings.a".to_string() => Some("\"2\"".to_string()),
+                    "settings.b".to_string() => Some("\"b\"".to_string()),
+                ),
+            ),
+        ];
+
+        assert_eq!(
+            rollback_changes(&history, 3, 1).unwrap(),
+            hashmap!(
+                key("settings.a") => Some("\"1\"".to_string()),
+                key("settings.b") => Some("\"b\"".to_string()),
+            )
+        );
+        assert_eq!(
+            rollback_changes(&history, 3, 0).unwrap(),
+            hashmap!(
+                key("settings.a") => None,
+                key("settings.b") => Some("\"b\"".to_string()),
+            )
+        );
+        assert!(rollback_changes(&history, 3, 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rollback_out_of_range() {
+        let history = vec![
+            generation(5, hashmap!("settings.a".to_string() => None)),
+            generation(6, hashmap!("settings.a".to_string() => None)),
+        ];
+        // Generation 4 is the state before the oldest we kept, so it's still reachable...
+        rollback_changes(&history, 6, 4).unwrap();
+        // ...but anything older has been pruned.
+        rollback_changes(&history, 6, 3).unwrap_err();
+        // Can't roll forward.
+        rollback_changes(&history, 6, 7).unwrap_err();
+        // With no history at all, we're already at generation 0.
+        assert!(rollback_changes(&[], 0, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_generations_block_rollback() {
+        // Generation 3 couldn't be loaded.
+        let history = vec![
+            generation(1, hashmap!("settings.a".to_string() => None)),
+            generation(2, hashmap!("settings.b".to_string() => None)),
+            generation(4, hashmap!("settings.c".to_string() => None)),
+        ];
+        match rollback_changes(&history, 4, 2) {
+            Err(error::Error::GenerationMissing { generation: 3 }) => (),
+            other => panic!("expected generation 3 to be missing, got {:?}", other),
+        }
+        rollback_changes(&history, 4, 1).unwrap_err();
+        changed_since(&history, 4, 1).unwrap_err();
+        // Nothing after generation 4 is missing.
+        assert!(rollback_changes(&history, 4, 4).unwrap().is_empty());
+
+        // The latest generation, 5, couldn't be loaded.
+        changed_since(&history, 5, 4).unwrap_err();
+        rollback_changes(&history, 5, 4).unwrap_err();
+    }
+}
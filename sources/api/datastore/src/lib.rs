@@ -14,6 +14,12 @@ There's also a common error type and some methods that implementations of DataSt
 We represent scalars -- the actual values stored under a datastore key -- using JSON, just to have a convenient human-readable form.
 (TOML doesn't allow raw scalars.  The JSON spec doesn't seem to either, but this works, and the format is so simple for scalars that it could be easily swapped out if needed.)
 
+# History
+
+Each change to live data is recorded as a numbered generation holding the previous values of the changed keys, along with when and why the change was made.
+The `history` module describes these records; the `DataStore` trait lets you list them and roll live data back to an earlier generation.
+Only the most recent generations are kept.
+
 # Serialization and deserialization
 
 The `serialization` module provides code to serialize Rust types into a mapping of datastore-acceptable keys (a.b.c) and values.
@@ -23,21 +29,25 @@ The `deserialization` module provides code to deserialize datastore-acceptable k
 # Current limitations
 
 * The user (e.g. apiserver) needs to handle locking.
-* There's no support for rolling back transactions.
-* The `serialization` module can't handle complex types under lists; it assumes lists can be serialized as scalars.
+* History only covers data keys; metadata changes aren't recorded.
+* The migrator copies history into the new data store on OS version changes, but changes made by migrations aren't recorded, so values in older generations keep their pre-migration format.
+* The `serialization` module stores lists of structures with each element's index as a key segment, like `a.b.0.c`.  Data is written key by key, so to replace a list, find its keys with `list_keys_with_prefix` and commit with `commit_transaction_replacing_lists`; otherwise a shorter list leaves the extra elements behind.  Elements with nothing set would have no keys, so they're rejected.
 */
 
 pub mod deserialization;
 pub mod error;
 pub mod filesystem;
+pub mod history;
 pub mod key;
 pub mod memory;
 pub mod serialization;
 
 pub use error::{Error, Result};
 pub use filesystem::FilesystemDataStore;
+pub use history::{ChangeSource, Generation};
 pub use key::{Key, KeyType, KEY_SEPARATOR, KEY_SEPARATOR_STR};
 
+use chrono::Utc;
 use log::trace;
 use serde::{Deserialize, Serialize};
 use snafu::OptionExt;
@@ -123,12 +133,133 @@ pub trait DataStore {
     /// Ok(()); we return Err only if we failed to check or remove the key.
     fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()>;
 
-    /// Applies pending changes from the given transaction to the live datastore.  Returns the
-    /// list of changed keys.
+    /// Applies pending changes from the given transaction to the live datastore, recording them
+    /// as a new generation.  Returns the list of changed keys.
     fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>;
 
+    /// Returns the changes that commit_transaction_replacing_lists would make to live data: the
+    /// pending keys mapped to their values, and the live keys under the given lists that the
+    /// transaction doesn't set mapped to None, since they'd be unset.  There are no changes if
+    /// nothing is pending.
+    fn pending_changes_replacing_lists(
+        &self,
+        transaction: &str,
+        lists: &HashSet<Key>,
+    ) -> Result<HashMap<Key, Option<String>>> {
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        let pending_data = self.get_prefix("settings.", &pending)?;
+        let mut changes = HashMap::new();
+        if pending_data.is_empty() {
+            return Ok(changes);
+        }
+
+        for list in lists {
+            for key in self.list_populated_keys(list.name(), &Committed::Live)? {
+                if key.starts_with_segments(list.segments()) && !pending_data.contains_key(&key) {
+                    changes.insert(key, None);
+                }
+            }
+        }
+        changes.extend(
+            pending_data
+                .into_iter()
+                .map(|(key, value)| (key, Some(value))),
+        );
+        Ok(changes)
+    }
+
+    /// Like commit_transaction, but the transaction replaces each of the given lists entirely,
+    /// rather than being merged into live data key by key.  Live keys under the lists that the
+    /// transaction doesn't set are unset, so a shorter list doesn't leave stale elements behind.
+    /// Returns the list of changed keys.
+    fn commit_transaction_replacing_lists<S>(
+        &mut self,
+        transaction: S,
+        lists: &HashSet<Key>,
+    ) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        let transaction = transaction.into();
+        let changes = self.pending_changes_replacing_lists(&transaction, lists)?;
+        if changes.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let changed = self.write_generation(
+            &changes,
+            ChangeSource::Commit {
+                transaction: transaction.clone(),
+            },
+        )?;
+        self.delete_transaction(transaction)?;
+        Ok(changed)
+    }
+
+    /// Returns the recorded generations of changes to live data, ordered from oldest to newest.
+    fn list_generations(&self) -> Result<Vec<Generation>>;
+    /// Saves a record of a change to live data.  Implementers should remove the oldest
+    /// generations so that no more than history::MAX_GENERATIONS are kept.
+    fn add_generation(&mut self, generation: &Generation) -> Result<()>;
+    /// Returns the number that should be given to the next recorded generation.  Implementers
+    /// may override this if they can find it without loading every generation.
+    fn next_generation(&self) -> Result<u64> {
+        Ok(history::next_generation(&self.list_generations()?))
+    }
+
+    /// Writes the given changes to live data, recording the previous values of the changed keys
+    /// as a new generation.  Keys mapped to None are unset.  Returns the list of changed keys.
+    fn write_generation(
+        &mut self,
+        changes: &HashMap<Key, Option<String>>,
+        source: ChangeSource,
+    ) -> Result<HashSet<Key>> {
+        let generation = self.next_generation()?;
+
+        let mut previous = HashMap::with_capacity(changes.len());
+        for key in changes.keys() {
+            previous.insert(key.name().clone(), self.get_key(key, &Committed::Live)?);
+        }
+
+        // Unset keys first, so that a key being replaced by keys under it (or the reverse, as
+        // when a list changes between being stored as one key and as many) is out of the way.
+        for (key, value) in changes {
+            if value.is_none() {
+                self.unset_key(key, &Committed::Live)?;
+            }
+        }
+        for (key, value) in changes {
+            if let Some(value) = value {
+                self.set_key(key, value, &Committed::Live)?;
+            }
+        }
+
+        self.add_generation(&Generation {
+            generation,
+            timestamp: Utc::now(),
+            source,
+            previous,
+        })?;
+        Ok(changes.keys().cloned().collect())
+    }
+
+    /// Restores live data to the state it had after the given generation, undoing the changes
+    /// from any later generations.  The rollback is itself recorded as a new generation, so it
+    /// can be undone too.  Returns the list of changed keys, which is empty if the given
+    /// generation is already the latest.
+    fn rollback_generation(&mut self, generation: u64) -> Result<HashSet<Key>> {
+        let latest = self.next_generation()? - 1;
+        let changes = history::rollback_changes(&self.list_generations()?, latest, generation)?;
+        if changes.is_empty() {
+            return Ok(HashSet::new());
+        }
+        self.write_generation(&changes, ChangeSource::Rollback { generation })
+    }
+
     /// Remove the given pending transaction from the datastore.  Returns the list of removed
     /// keys.  If the transaction doesn't exist, will return Ok with an empty list.
     fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
@@ -339,6 +470,80 @@ mod test {
         assert_eq!(m.get_metadata_raw(&meta, &grandchild).unwrap(), None);
     }
 
+    #[test]
+    fn get_metadata_inheritance_list_elements() {
+        let mut m = MemoryDataStore::new();
+
+        let meta = Key::new(KeyType::Meta, "mymeta").unwrap();
+        let list = Key::new(KeyType::Data, "a.list").unwrap();
+        let second = Key::new(KeyType::Data, "a.list.1").unwrap();
+        let first_field = Key::new(KeyType::Data, "a.list.0.name").unwrap();
+        let second_field = Key::new(KeyType::Data, "a.list.1.name").unwrap();
+
+        // Set metadata on the list, and override it for one element
+        m.set_metadata(&meta, &list, "list").unwrap();
+        m.set_metadata(&meta, &second, "second").unwrap();
+
+        // Fields of elements inherit from the element, or from the list
+        assert_eq!(
+            m.get_metadata(&meta, &first_field).unwrap(),
+            Some("list".to_string())
+        );
+        assert_eq!(
+            m.get_metadata(&meta, &second_field).unwrap(),
+            Some("second".to_string())
+        );
+    }
+
+    #[test]
+    fn commit_replacing_lists() {
+        let mut m = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let list = Key::new(KeyType::Data, "settings.list").unwrap();
+        let other = Key::new(KeyType::Data, "settings.listing").unwrap();
+        let element =
+            |i: usize| Key::new(KeyType::Data, format!("settings.list.{}.name", i)).unwrap();
+
+        m.set_key(&other, "\"untouched\"", &Committed::Live)
+            .unwrap();
+        for i in 0..3 {
+            m.set_key(&element(i), "\"x\"", &pending).unwrap();
+        }
+        m.commit_transaction_replacing_lists(tx, &hashset!(list.clone()))
+            .unwrap();
+
+        // A shorter list removes the extra elements
+        m.set_key(&element(0), "\"y\"", &pending).unwrap();
+        let changed = m
+            .commit_transaction_replacing_lists(tx, &hashset!(list.clone()))
+            .unwrap();
+        assert_eq!(changed, hashset!(element(0), element(1), element(2)));
+        assert_eq!(
+            m.list_populated_keys("settings.list", &Committed::Live)
+                .unwrap(),
+            hashset!(element(0), other.clone())
+        );
+
+        // An empty list is stored as one key, replacing the elements
+        m.set_key(&list, "[]", &pending).unwrap();
+        m.commit_transaction_replacing_lists(tx, &hashset!(list.clone()))
+            .unwrap();
+        assert_eq!(
+            m.list_populated_keys("settings.list", &Committed::Live)
+                .unwrap(),
+            hashset!(list.clone(), other)
+        );
+
+        // Rolling back restores the elements
+        m.rollback_generation(2).unwrap();
+        assert_eq!(
+            m.get_key(&element(0), &Committed::Live).unwrap(),
+            Some("\"y\"".to_string())
+        );
+        assert_eq!(m.get_key(&list, &Committed::Live).unwrap(), None);
+    }
+
     #[test]
     fn get_prefix() {
         let mut m = MemoryDataStore::new();
@@ -5,6 +5,7 @@
 
 use std::collections::{HashMap, HashSet};
 
+use super::history::{ChangeSource, Generation, MAX_GENERATIONS};
 use super::{Committed, DataStore, Key, Result};
 
 #[derive(Debug)]
@@ -16,6 +17,8 @@ pub struct MemoryDataStore {
     // Map of data keys to their metadata, which in turn is a mapping of metadata keys to
     // arbitrary (string/serialized) values.
     metadata: HashMap<Key, HashMap<Key, String>>,
+    // Recorded changes to live data, oldest first.
+    history: Vec<Generation>,
 }
 
 impl MemoryDataStore {
@@ -24,6 +27,7 @@ impl MemoryDataStore {
             pending: HashMap::new(),
             live: HashMap::new(),
             metadata: HashMap::new(),
+            history: Vec::new(),
         }
     }
 
@@ -156,15 +160,33 @@ impl DataStore for MemoryDataStore {
     {
         // Remove anything pending for this transaction
         if let Some(pending) = self.pending.remove(transaction.as_ref()) {
-            // Apply pending changes to live
-            self.set_keys(&pending, &Committed::Live)?;
-            // Return keys that were committed
-            Ok(pending.keys().cloned().collect())
+            // Apply pending changes to live, returning the keys that were committed
+            let changes = pending
+                .into_iter()
+                .map(|(key, value)| (key, Some(value)))
+                .collect();
+            self.write_generation(
+                &changes,
+                ChangeSource::Commit {
+                    transaction: transaction.into(),
+                },
+            )
         } else {
             Ok(HashSet::new())
         }
     }
 
+    fn list_generations(&self) -> Result<Vec<Generation>> {
+        Ok(self.history.clone())
+    }
+
+    fn add_generation(&mut self, generation: &Generation) -> Result<()> {
+        self.history.push(generation.clone());
+        let excess = self.history.len().saturating_sub(MAX_GENERATIONS);
+        self.history.drain(..excess);
+        Ok(())
+    }
+
     fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
@@ -185,6 +207,7 @@ impl DataStore for MemoryDataStore {
 
 #[cfg(test)]
 mod test {
+    use super::super::history::{ChangeSource, MAX_GENERATIONS};
     use super::super::{Committed, DataStore, Key, KeyType};
     use super::MemoryDataStore;
     use maplit::hashset;
@@ -248,6 +271,75 @@ mod test {
         assert!(m.key_populated(&k, &Committed::Live).unwrap());
     }
 
+    #[test]
+    fn rollback() {
+        let mut m = MemoryDataStore::new();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        // Generation 1 sets a; generation 2 changes a and adds b.
+        m.set_key(&k1, "1", &pending).unwrap();
+        m.commit_transaction(tx).unwrap();
+        m.set_key(&k1, "2", &pending).unwrap();
+        m.set_key(&k2, "3", &pending).unwrap();
+        m.commit_transaction(tx).unwrap();
+        assert_eq!(m.list_generations().unwrap().len(), 2);
+
+        // Rolling back to generation 1 restores a and removes b, and is recorded itself.
+        assert_eq!(
+            m.rollback_generation(1).unwrap(),
+            hashset!(k1.clone(), k2.clone())
+        );
+        assert_eq!(
+            m.get_key(&k1, &Committed::Live).unwrap(),
+            Some("1".to_string())
+        );
+        assert!(!m.key_populated(&k2, &Committed::Live).unwrap());
+        let history = m.list_generations().unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2].source, ChangeSource::Rollback { generation: 1 });
+
+        // The rollback can be undone like any other change.
+        m.rollback_generation(2).unwrap();
+        assert_eq!(
+            m.get_key(&k1, &Committed::Live).unwrap(),
+            Some("2".to_string())
+        );
+        assert_eq!(
+            m.get_key(&k2, &Committed::Live).unwrap(),
+            Some("3".to_string())
+        );
+
+        // Nothing to do when rolling back to the latest generation.
+        assert!(m.rollback_generation(4).unwrap().is_empty());
+        m.rollback_generation(5).unwrap_err();
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        for i in 0..MAX_GENERATIONS + 2 {
+            m.set_key(&k, i.to_string(), &pending).unwrap();
+            m.commit_transaction(tx).unwrap();
+        }
+
+        let history = m.list_generations().unwrap();
+        assert_eq!(history.len(), MAX_GENERATIONS);
+        assert_eq!(history[0].generation, 3);
+        // Generation 2 is still reachable because we kept everything after it, but 1 isn't.
+        m.rollback_generation(1).unwrap_err();
+        m.rollback_generation(2).unwrap();
+        assert_eq!(
+            m.get_key(&k, &Committed::Live).unwrap(),
+            Some("1".to_string())
+        );
+    }
+
     #[test]
     fn delete_transaction() {
         let mut m = MemoryDataStore::new();
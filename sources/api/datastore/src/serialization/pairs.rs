@@ -10,8 +10,8 @@
 
 use log::trace;
 use serde::{ser, Serialize};
-use snafu::{IntoError, NoneError as NoSource, OptionExt, ResultExt};
-use std::collections::HashMap;
+use snafu::{ensure, IntoError, NoneError as NoSource, OptionExt, ResultExt};
+use std::collections::{HashMap, HashSet};
 
 use super::{error, Error, MapKeySerializer, Result};
 use crate::{serialize_scalar, Key, KeyType, ScalarError};
@@ -49,20 +49,69 @@ where
     Ok(output)
 }
 
+/// Returns the keys of the lists in the given value, with the given prefix added to the keys as in
+/// to_pairs_with_prefix.  A list may be stored as one key or as many keys under its own (see
+/// Serializer), so callers writing a new version of a list can use these keys to find and remove
+/// what was stored for an earlier version.
+pub fn list_keys_with_prefix<S, T>(prefix: S, value: &T) -> Result<HashSet<Key>>
+where
+    S: AsRef<str>,
+    T: Serialize,
+{
+    let prefix = prefix.as_ref();
+    let prefix_key = Key::new(KeyType::Data, prefix).map_err(|e| {
+        error::InvalidKey {
+            msg: format!("Prefix '{}' not valid as Key: {}", prefix, e),
+        }
+        .into_error(NoSource)
+    })?;
+
+    let value = serde_json::to_value(value).context(error::Serialization { given: "lists" })?;
+    let mut lists = HashSet::new();
+    find_lists(&value, prefix_key, &mut lists)?;
+    Ok(lists)
+}
+
+/// Adds the keys of any lists in the given value to `lists`, recursing through maps.
+fn find_lists(value: &serde_json::Value, prefix: Key, lists: &mut HashSet<Key>) -> Result<()> {
+    match value {
+        serde_json::Value::Array(_) => {
+            lists.insert(prefix);
+        }
+        serde_json::Value::Object(map) => {
+            for (name, value) in map {
+                let key = prefix.append_segments(&[name]).map_err(|e| {
+                    error::InvalidKey {
+                        msg: format!(
+                            "appending '{}' to '{}' is invalid as Key: {}",
+                            name, prefix, e
+                        ),
+                    }
+                    .into_error(NoSource)
+                })?;
+                find_lists(value, key, lists)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
 /////
 
 /// Serializer does most of the work by recursively serializing compound structures, and trivially
 /// serializing scalars.
 ///
-/// Caveat: for a list/tuple, the elements inside only have indexes, which doesn't work well with
-/// the data store.  Lists are common enough that we need some answer, so we say that lists can
-/// only contain scalars, not further compound objects.  That way we can serialize the list
-/// directly (see FlatSerializer) rather than as a compound.
+/// Caveat: for a list/tuple, the elements inside only have indexes, rather than names.  Lists of
+/// scalars are common and are most useful to users as a single value, so we serialize them
+/// directly as one blob (see SeqSerializer).  Lists of structures or maps are serialized as
+/// compound structures, using each element's index as its key segment, for example
+/// "a.b.c.0.name" and "a.b.c.1.name".  An element with nothing set would have no keys, and would
+/// silently disappear when read back, so it's rejected.
 ///
-/// (We could handle lists as proper compound structures by improving the data store such that it
-/// can store unnamed sub-components, perhaps by using a visible index ("a.b.c[0]", "a.b.c[1]").
-/// It's more common to use a HashMap in the model, and then to use named keys instead of indexes,
-/// which works fine.)
+/// (It's still more common to use a HashMap in the model, and then to use named keys instead of
+/// indexes.  Updates to the data store are made key by key, so a writer replacing a list has to
+/// remove the old list's keys itself; see list_keys_with_prefix.)
 struct Serializer<'a> {
     output: &'a mut HashMap<Key, String>,
     prefix: Option<Key>,
@@ -113,7 +162,7 @@ impl<'a> ser::Serializer for Serializer<'a> {
     type Error = Error;
 
     // See the docs on Serializer for reasoning about this.
-    type SerializeSeq = FlatSerializer<'a>;
+    type SerializeSeq = SeqSerializer<'a>;
     type SerializeTuple = ser::Impossible<(), Error>;
     type SerializeTupleStruct = ser::Impossible<(), Error>;
     type SerializeTupleVariant = ser::Impossible<(), Error>;
@@ -143,7 +192,7 @@ impl<'a> ser::Serializer for Serializer<'a> {
 
     // Compound types
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Ok(FlatSerializer::new(self.output, expect_prefix(self.prefix, "seq")?))
+        Ok(SeqSerializer::new(self.output, expect_prefix(self.prefix, "seq")?))
     }
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
         Ok(Serializer::new(self.output, self.prefix))
@@ -321,8 +370,9 @@ impl<'a> ser::SerializeStruct for Serializer<'a> {
 
 /////
 
-/// This serializes compound structures into a flat blob, for cases where recursively serializing
-/// compound structures doesn't make sense.  See Serializer for detail on why it uses this.
+/// This serializes lists, either into a flat blob if the elements are scalars, or into one key per
+/// field of each element, using the element's index as a key segment, if the elements are
+/// structures or maps.  See Serializer for detail on why it uses this.
 ///
 /// Warning; this requires hacks.  serde gives you three callbacks during serialization - starting
 /// the structure, for each element, and ending the structure.  There's no option to handle an
@@ -331,23 +381,32 @@ impl<'a> ser::SerializeStruct for Serializer<'a> {
 /// the unthinkable - serialize each element to a String, store those in a list during the
 /// serialization steps, and then at the end, deserialize the strings back into a list of the
 /// original type, and serialize the entire list.  Sorry.
-struct FlatSerializer<'a> {
+///
+/// Elements that are structures or maps are also serialized into separate output as they're seen,
+/// since we don't know until the end whether we'll need the flat blob or the indexed keys.
+struct SeqSerializer<'a> {
     output: &'a mut HashMap<Key, String>,
     prefix: Key,
     list: Vec<String>,
+    // Output from elements that are structures or maps, with keys under "prefix.index".
+    compound_output: HashMap<Key, String>,
+    // How many elements were structures or maps.
+    compound_count: usize,
 }
 
-impl<'a> FlatSerializer<'a> {
+impl<'a> SeqSerializer<'a> {
     fn new(output: &'a mut HashMap<Key, String>, prefix: Key) -> Self {
-        FlatSerializer {
+        SeqSerializer {
             output,
             prefix,
             list: Vec::new(),
+            compound_output: HashMap::new(),
+            compound_count: 0,
         }
     }
 }
 
-impl<'a> ser::SerializeSeq for FlatSerializer<'a> {
+impl<'a> ser::SerializeSeq for SeqSerializer<'a> {
     type Ok = ();
     type Error = Error;
 
@@ -356,14 +415,62 @@ impl<'a> ser::SerializeSeq for FlatSerializer<'a> {
         T: ?Sized + Serialize,
     {
         trace!("Serializing element of list");
-        self.list
-            .push(serde_json::to_string(value).context(error::Serialization {
-                given: "list element",
-            })?);
+        let element = serde_json::to_string(value).context(error::Serialization {
+            given: "list element",
+        })?;
+
+        // Structures and maps are serialized into JSON objects; those get keys of their own.
+        let original: serde_json::Value = element.parse().context(error::Deserialization {
+            given: "list element",
+        })?;
+        if original.is_object() {
+            let index = self.list.len().to_string();
+            let element_prefix = self.prefix.append_segments(&[&index]).map_err(|e| {
+                error::InvalidKey {
+                    msg: format!(
+                        "appending index '{}' to '{}' is invalid as Key: {}",
+                        index, self.prefix, e
+                    ),
+                }
+                .into_error(NoSource)
+            })?;
+            trace!(
+                "Recursively serializing list element at prefix {}",
+                element_prefix
+            );
+            let keys_before = self.compound_output.len();
+            value.serialize(Serializer::new(
+                &mut self.compound_output,
+                Some(element_prefix),
+            ))?;
+            ensure!(
+                self.compound_output.len() > keys_before,
+                error::InvalidType {
+                    typename: "list element with no values set"
+                }
+            );
+            self.compound_count += 1;
+        }
+
+        self.list.push(element);
         Ok(())
     }
 
     fn end(self) -> Result<()> {
+        if self.compound_count > 0 {
+            // A list mixing scalars and structures can't be described by a typed model, so we
+            // don't try to represent it.
+            ensure!(
+                self.compound_count == self.list.len(),
+                error::InvalidType {
+                    typename: "list mixing scalars and structures"
+                }
+            );
+            trace!("Saving keys of list of structures");
+            self.output.extend(self.compound_output);
+            return Ok(());
+        }
+
         let mut originals: Vec<serde_json::Value> = Vec::new();
         trace!("Deserializing elements of list");
         for original in self.list {
@@ -384,9 +491,9 @@ impl<'a> ser::SerializeSeq for FlatSerializer<'a> {
 
 #[cfg(test)]
 mod test {
-    use super::{to_pairs, to_pairs_with_prefix};
+    use super::{list_keys_with_prefix, to_pairs, to_pairs_with_prefix};
     use crate::{Key, KeyType};
-    use maplit::hashmap;
+    use maplit::{hashmap, hashset};
     use serde::Serialize;
 
     // Helper macro for making a data Key for testing whose name we know is valid.
@@ -485,6 +592,74 @@ mod test {
         );
     }
 
+    #[derive(PartialEq, Serialize)]
+    struct Mirrors {
+        mirrors: Vec<Mirror>,
+    }
+
+    #[derive(PartialEq, Serialize)]
+    struct Mirror {
+        registry: String,
+        endpoints: Vec<String>,
+    }
+
+    #[test]
+    fn list_of_structs_keys() {
+        let m = Mirrors {
+            mirrors: vec![
+                Mirror {
+                    registry: "a".to_string(),
+                    endpoints: vec!["x".to_string()],
+                },
+                Mirror {
+                    registry: "b".to_string(),
+                    endpoints: vec![],
+                },
+            ],
+        };
+        let keys = to_pairs(&m).unwrap();
+        assert_eq!(
+            keys,
+            hashmap!(
+                key!("Mirrors.mirrors.0.registry") => "\"a\"".to_string(),
+                key!("Mirrors.mirrors.0.endpoints") => "[\"x\"]".to_string(),
+                key!("Mirrors.mirrors.1.registry") => "\"b\"".to_string(),
+                key!("Mirrors.mirrors.1.endpoints") => "[]".to_string(),
+            )
+        );
+    }
+
+    #[test]
+    fn list_keys() {
+        let val: toml::Value = toml::from_str(
+            r#"
+            scalars = [1, 2]
+            empty = []
+            [nested]
+            mirrors = [{ registry = "a", endpoints = ["x"] }]
+            name = "b"
+            "#,
+        )
+        .unwrap();
+        let keys = list_keys_with_prefix("x", &val).unwrap();
+        assert_eq!(
+            keys,
+            hashset!(key!("x.scalars"), key!("x.empty"), key!("x.nested.mirrors"),)
+        );
+    }
+
+    #[test]
+    fn mixed_list_fails() {
+        let val: toml::Value = toml::from_str("list = [1, { a = 2 }]").unwrap();
+        to_pairs_with_prefix("x", &val).unwrap_err();
+    }
+
+    #[test]
+    fn empty_list_element_fails() {
+        let val: toml::Value = toml::from_str("list = [{ a = 1 }, {}, { a = 2 }]").unwrap();
+        to_pairs_with_prefix("x", &val).unwrap_err();
+    }
+
     #[test]
     fn concrete_fails() {
         let i = 42;
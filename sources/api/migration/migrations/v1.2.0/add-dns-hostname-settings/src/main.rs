@@ -0,0 +1,25 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddSettingsMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added `settings.dns` and `settings.network.hostname`, which override the name servers,
+/// search domains, and hostname found from DHCP leases.
+fn run() -> Result<()> {
+    migrate(AddSettingsMigration(&[
+        "settings.dns.name-servers",
+        "settings.dns.search-list",
+        "settings.network.hostname",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
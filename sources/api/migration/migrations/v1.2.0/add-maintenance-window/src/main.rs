@@ -0,0 +1,25 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddSettingsMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added `settings.updates.maintenance-window`, the window in which update-agent applies
+/// updates.
+fn run() -> Result<()> {
+    migrate(AddSettingsMigration(&[
+        "settings.updates.maintenance-window.schedule",
+        "settings.updates.maintenance-window.duration",
+        "settings.updates.maintenance-window.timezone",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,24 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddPrefixesMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added settings for metricdog's local Prometheus exporter, and the service that runs it.
+fn run() -> Result<()> {
+    migrate(AddPrefixesMigration(vec![
+        "settings.metrics.exporter-enabled",
+        "settings.metrics.exporter-port",
+        "services.metricdog-exporter",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
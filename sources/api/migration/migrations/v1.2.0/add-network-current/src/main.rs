@@ -0,0 +1,27 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddPrefixesMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added `settings.network.current`, the network state reported by netdog, and the services
+/// and configuration files that render it into /etc/resolv.conf and the hostname.
+fn run() -> Result<()> {
+    migrate(AddPrefixesMigration(vec![
+        "settings.network.current",
+        "services.dns",
+        "services.hostname",
+        "configuration-files.resolv-conf",
+        "configuration-files.hostname",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,25 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddPrefixesMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added `settings.network.interfaces` for configuring network interfaces, keyed by the
+/// interface name, and the `net-config` service it affects, which doesn't restart anything since
+/// the interfaces are configured at boot.
+fn run() -> Result<()> {
+    migrate(AddPrefixesMigration(vec![
+        "settings.network.interfaces",
+        "services.net-config",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,26 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddSettingsMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added `settings.updates.health-checks`, the checks that must pass after an update before
+/// the host commits to it.
+fn run() -> Result<()> {
+    migrate(AddSettingsMigration(&[
+        "settings.updates.health-checks.timeout",
+        "settings.updates.health-checks.services",
+        "settings.updates.health-checks.registration",
+        "settings.updates.health-checks.commands",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
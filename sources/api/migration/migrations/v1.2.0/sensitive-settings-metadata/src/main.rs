@@ -0,0 +1,33 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::{AddMetadataMigration, SettingMetadata};
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We marked settings that hold secrets with `sensitive` metadata so logdog redacts them.
+fn run() -> Result<()> {
+    migrate(AddMetadataMigration(&[
+        SettingMetadata {
+            metadata: &["sensitive"],
+            setting: "settings.kubernetes.bootstrap-token",
+        },
+        SettingMetadata {
+            metadata: &["sensitive"],
+            setting: "settings.host-containers.admin.user-data",
+        },
+        SettingMetadata {
+            metadata: &["sensitive"],
+            setting: "settings.host-containers.control.user-data",
+        },
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
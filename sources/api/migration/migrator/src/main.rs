@@ -39,9 +39,14 @@ use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::process;
 use tough::{ExpirationEnforcement, FilesystemTransport, RepositoryLoader};
+use update_history::{Event, EventType, HISTORY_FILE};
 use update_metadata::Manifest;
 use url::Url;
 
+/// The directory within a data store that holds settings history; this matches the layout used
+/// by the datastore library's FilesystemDataStore.
+const HISTORY_DIR: &str = "history";
+
 mod args;
 mod direction;
 mod error;
@@ -58,12 +63,28 @@ fn main() {
         eprintln!("{}", e);
         process::exit(1);
     }
-    if let Err(e) = run(&args) {
+    let result = run(&args);
+    record_history(&args, &result);
+    if let Err(e) = result {
         eprintln!("{}", e);
         process::exit(1);
     }
 }
 
+/// Records the result of the migration in the persistent update history.  (If the data store was
+/// already at the requested version, run exits early and there's nothing to record.)  The history
+/// is informational, so failures are only logged.
+fn record_history(args: &Args, result: &Result<()>) {
+    let event = Event {
+        target_version: Some(args.migrate_to_version.clone()),
+        message: result.as_ref().err().map(|e| e.to_string()),
+        ..Event::new(EventType::Migration, result.is_ok())
+    };
+    if let Err(e) = update_history::append(HISTORY_FILE, &event) {
+        warn!("Failed to record update history: {}", e);
+    }
+}
+
 fn get_current_version<P>(datastore_dir: P) -> Result<Version>
 where
     P: AsRef<Path>,
@@ -174,6 +195,16 @@ pub(crate) fn run(args: &Args) -> Result<()> {
             &args.datastore_path,
             &args.migrate_to_version,
         )?;
+        // Settings history isn't part of the data store format, so migrations don't carry it.
+        // Copy it ourselves so generation numbers continue and older generations stay reachable
+        // for rollback and watches.  Losing it isn't worth failing the upgrade over, though.
+        if let Err(e) = copy_history(&args.datastore_path, &copy_path) {
+            error!(
+                "Failed to copy settings history to new data store at '{}': {}",
+                copy_path.display(),
+                e
+            );
+        }
         flip_to_new_version(&args.migrate_to_version, &copy_path)?;
     }
     Ok(())
@@ -318,6 +349,36 @@ where
     Ok(target_datastore)
 }
 
+/// Copies the settings history files from one data store to another, skipping anything that
+/// isn't a committed generation, like a leftover temporary file.
+fn copy_history<P1, P2>(from_datastore: P1, to_datastore: P2) -> std::io::Result<()>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let from = from_datastore.as_ref().join(HISTORY_DIR);
+    if !from.exists() {
+        return Ok(());
+    }
+    let to = to_datastore.as_ref().join(HISTORY_DIR);
+    fs::create_dir_all(&to)?;
+
+    for entry in fs::read_dir(&from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_generation = name
+            .to_str()
+            .map(|n| n.parse::<u64>().is_ok())
+            .unwrap_or(false);
+        if !is_generation {
+            continue;
+        }
+        trace!("Copying settings history file {}", entry.path().display());
+        fs::copy(entry.path(), to.join(&name))?;
+    }
+    Ok(())
+}
+
 /// Atomically flips version symlinks to point to the given "to" datastore so that it becomes live.
 ///
 /// This includes:
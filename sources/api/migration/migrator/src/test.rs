@@ -256,3 +256,40 @@ fn migrate_backward() {
     let got: String = second_line.chars().take(want.len()).collect();
     assert_eq!(got, want);
 }
+
+/// This test ensures that settings history in the old data store is carried into the data store
+/// built by migrations, so generation numbers continue after an upgrade.
+#[test]
+fn migrate_keeps_history() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version);
+    let history_dir = test_datastore.datastore.join("history");
+    std::fs::create_dir_all(&history_dir).unwrap();
+    std::fs::write(history_dir.join("1"), "first").unwrap();
+    std::fs::write(history_dir.join("2"), "second").unwrap();
+    std::fs::write(history_dir.join(".3.tmp"), "partial").unwrap();
+    let test_repo = create_test_repo();
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+    };
+    run(&args).unwrap();
+
+    let new_datastore = std::fs::canonicalize(test_datastore.tmp.path().join("current")).unwrap();
+    assert_ne!(new_datastore, test_datastore.datastore);
+    let new_history = new_datastore.join("history");
+    assert_eq!(
+        std::fs::read_to_string(new_history.join("1")).unwrap(),
+        "first"
+    );
+    assert_eq!(
+        std::fs::read_to_string(new_history.join("2")).unwrap(),
+        "second"
+    );
+    assert!(!new_history.join(".3.tmp").exists());
+}
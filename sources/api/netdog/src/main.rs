@@ -5,28 +5,69 @@ netdog is a small helper program for wicked, to apply network settings received
 contains a subcommand `node-ip` that returns the node's current IP address in JSON format; this
 subcommand is intended for use as a settings generator.
 
-It generates `/etc/resolv.conf`, sets the hostname, and persists the current IP to file.
-*/
+When wicked gets a DHCP lease for the primary interface, netdog persists the current IP to file and reports the name servers, search domains, IP address, and hostname to the API in the read-only `settings.network.current` settings.
+It uses its own transaction, `netdog`, and commits and applies it, so `/etc/resolv.conf` and the hostname are rendered from templates like other configuration files.
+Only values that changed are sent, so lease renewals don't restart anything needlessly.
+
+IPv4 is preferred for the node's IP.
+A DHCPv6 lease is only used for the network state if there's no IPv4 lease, as on IPv6-only networks; `node-ip` likewise returns the IPv6 address only if there's no IPv4 address.
+
+netdog always reports what it finds, like the hostname from a reverse DNS lookup of the lease IP.
+The templates for the hostname and `/etc/resolv.conf` give `settings.network.hostname` and `settings.dns` precedence over the reported state, so netdog doesn't need to know about them.
+
+# Interface configuration
+
+By default, the primary interface, `eth0`, is configured with DHCP.
+Interfaces can instead be configured through `settings.network.interfaces`, for example to use static addresses, or to create bonds and VLANs:
+
+```toml
+[settings.network.interfaces.bond0.bond]
+mode = "active-backup"
+interfaces = ["eth0", "eth1"]
 
-// TODO:
-// We should rework this to store info in the API and delegate rewriting of
-// files and any required process restarts to the existing machinery.
-// This is blocked on the ability to apply and commit settings in separate
-// transactions; otherwise a lease renewal while settings were being added
-// by other processes could cause them to be applied in an incomplete state.
+[settings.network.interfaces."bond0.100"]
+addresses = ["192.168.1.10/24"]
+nameservers = ["192.168.1.2"]
+primary = true
+
+[settings.network.interfaces."bond0.100".vlan]
+device = "bond0"
+id = 100
+
+[[settings.network.interfaces."bond0.100".routes]]
+to = "0.0.0.0/0"
+via = "192.168.1.1"
+```
+
+The `generate-net-config` subcommand runs at boot, before wicked starts, and replaces wicked's interface configuration with a file for each configured interface.
+Changes to these settings take effect on the next boot.
+If the API isn't available, the default configuration is left in place, and `install` writes `/etc/resolv.conf` straight from the lease, so the host can still be reached to recover it.
+A primary interface with only static addresses doesn't get a lease, so once the network is up, the `set-hostname` subcommand reports its hostname instead, found by reverse DNS lookup of its first address.
+
+The node's IP comes from the interface marked `primary`.
+If none is, the first interface by name that uses DHCP is the primary, or else the first with a static address; members of a bond are never chosen.
+Without any interface settings, the primary is `eth0`.
+Only the primary interface's DHCP lease is used to update `settings.network.current`.
+If `nameservers` are given for any interface, those name servers and `search` domains are reported by `generate-net-config` instead, and DHCP name servers are ignored.
+*/
 
 #![deny(rust_2018_idioms)]
 
+mod wicked;
+
 use dns_lookup::lookup_addr;
 use envy;
 use ipnet::IpNet;
 use lazy_static::lazy_static;
+use model::modeled_types::{DNSDomain, NetworkInterfaceName, ValidLinuxHostname};
+use model::{NetworkInterface, NetworkSettings, NetworkState};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use regex::Regex;
 use serde::Deserialize;
-use snafu::ResultExt;
-use std::collections::BTreeSet;
+use snafu::{ensure, ResultExt};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt::{self, Write};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
@@ -34,9 +75,17 @@ use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::{env, process};
 
-static RESOLV_CONF: &str = "/etc/resolv.conf";
-static KERNEL_HOSTNAME: &str = "/proc/sys/kernel/hostname";
 static CURRENT_IP: &str = "/var/lib/netdog/current_ip";
+static CURRENT_IPV6: &str = "/var/lib/netdog/current_ipv6";
+static PRIMARY_INTERFACE: &str = "/var/lib/netdog/primary_interface";
+static WICKED_IFCONFIG_DIR: &str = "/etc/wicked/ifconfig";
+static RESOLV_CONF: &str = "/etc/resolv.conf";
+static DEFAULT_API_SOCKET: &str = "/run/api.sock";
+// The transaction netdog uses to report network state, so it doesn't commit changes pending from
+// other processes.
+static NETDOG_TRANSACTION: &str = "netdog";
+// Used when no interfaces are configured in settings.
+static DEFAULT_PRIMARY_INTERFACE: &str = "eth0";
 
 // Matches wicked's shell-like syntax for DHCP lease variables:
 //     FOO='BAR' -> key=FOO, val=BAR
@@ -49,7 +98,6 @@ mod error {
     use envy;
     use snafu::Snafu;
     use std::io;
-    use std::net::IpAddr;
     use std::path::PathBuf;
 
     #[derive(Debug, Snafu)]
@@ -62,29 +110,81 @@ mod error {
         #[snafu(display("Failed to parse lease data in '{}': {}", path.display(), source))]
         LeaseParseFailed { path: PathBuf, source: envy::Error },
 
-        #[snafu(display("Failed to build resolver configuration: {}", source))]
-        ResolvConfBuildFailed { source: std::fmt::Error },
-
-        #[snafu(display("Failed to write resolver configuration to '{}': {}", path.display(), source))]
-        ResolvConfWriteFailed { path: PathBuf, source: io::Error },
-
-        #[snafu(display("Failed to resolve '{}' to hostname: {}", ip, source))]
-        HostnameLookupFailed { ip: IpAddr, source: io::Error },
-
-        #[snafu(display("Failed to write hostname to '{}': {}", path.display(), source))]
-        HostnameWriteFailed { path: PathBuf, source: io::Error },
-
         #[snafu(display("Failed to write current IP to '{}': {}", path.display(), source))]
         CurrentIpWriteFailed { path: PathBuf, source: io::Error },
 
         #[snafu(display("Failed to read current IP data in '{}': {}", path.display(), source))]
         CurrentIpReadFailed { path: PathBuf, source: io::Error },
 
+        #[snafu(display("Invalid current IP '{}' in '{}': {}", ip, path.display(), source))]
+        CurrentIpParseFailed {
+            path: PathBuf,
+            ip: String,
+            source: std::net::AddrParseError,
+        },
+
         #[snafu(display("Error serializing to JSON: '{}': {}", output, source))]
         JsonSerialize {
             output: String,
             source: serde_json::error::Error,
         },
+
+        #[snafu(display("Error {}ing '{}': {}", method, uri, source))]
+        ApiRequestFailed {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when {}ing '{}': {}", code, method, uri, response_body))]
+        ApiResponseFailed {
+            method: String,
+            uri: String,
+            code: http::StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display("Error serializing network state: {}", source))]
+        NetworkStateSerialize { source: serde_json::Error },
+
+        #[snafu(display("Error deserializing response from '{}': {}", uri, source))]
+        ApiResponseParseFailed {
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Invalid CIDR '{}': {}", input, source))]
+        CidrParseFailed {
+            input: String,
+            source: ipnet::AddrParseError,
+        },
+
+        #[snafu(display("Invalid route for interface '{}': {}", name, msg))]
+        InvalidRoute { name: String, msg: String },
+
+        #[snafu(display("Failed to build configuration for interface '{}': {}", name, source))]
+        InterfaceConfigBuildFailed {
+            name: String,
+            source: std::fmt::Error,
+        },
+
+        #[snafu(display("Failed to list interface configuration in '{}': {}", path.display(), source))]
+        InterfaceConfigListFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to remove interface configuration '{}': {}", path.display(), source))]
+        InterfaceConfigRemoveFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to write interface configuration to '{}': {}", path.display(), source))]
+        InterfaceConfigWriteFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to write primary interface to '{}': {}", path.display(), source))]
+        PrimaryInterfaceWriteFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to build resolver configuration: {}", source))]
+        ResolvConfBuildFailed { source: std::fmt::Error },
+
+        #[snafu(display("Failed to write resolver configuration to '{}': {}", path.display(), source))]
+        ResolvConfWriteFailed { path: PathBuf, source: io::Error },
     }
 }
 
@@ -96,6 +196,8 @@ enum SubCommand {
     Install,
     Remove,
     NodeIp,
+    GenerateNetConfig,
+    SetHostname,
 }
 
 impl fmt::Display for SubCommand {
@@ -104,16 +206,12 @@ impl fmt::Display for SubCommand {
             SubCommand::Install => write!(f, "install"),
             SubCommand::Remove => write!(f, "remove"),
             SubCommand::NodeIp => write!(f, "node-ip"),
+            SubCommand::GenerateNetConfig => write!(f, "generate-net-config"),
+            SubCommand::SetHostname => write!(f, "set-hostname"),
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-enum InterfaceName {
-    Eth0,
-}
-
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 enum InterfaceType {
@@ -130,7 +228,7 @@ enum InterfaceFamily {
 /// Stores user-supplied arguments.
 #[derive(Debug)]
 struct Args {
-    interface_name: InterfaceName,
+    interface_name: String,
     interface_type: InterfaceType,
     interface_family: InterfaceFamily,
     data_file: PathBuf,
@@ -141,10 +239,9 @@ struct Args {
 struct LeaseInfo {
     #[serde(rename = "ipaddr")]
     ip_address: IpNet,
-    #[serde(rename = "dnsservers")]
+    // DHCPv6 leases may not include name servers.
+    #[serde(rename = "dnsservers", default)]
     dns_servers: BTreeSet<IpAddr>,
-    #[serde(rename = "dnsdomain")]
-    dns_domain: Option<String>,
     #[serde(rename = "dnssearch")]
     dns_search: Option<Vec<String>>,
 }
@@ -154,7 +251,7 @@ fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
     eprintln!(
         r"Usage: {}
-            [ node-ip | install | remove ]
+            [ node-ip | generate-net-config | set-hostname | install | remove ]
 
             Required for 'install' and 'remove' subcommands:
               -i INTERFACE_NAME
@@ -181,8 +278,12 @@ fn parse_args(args: env::Args) -> Result<(SubCommand, Option<Args>)> {
     let sub_command = serde_plain::from_str::<SubCommand>(&value)
         .unwrap_or_else(|_| usage_msg(format!("Unknown command {}", value)));
 
-    // The `node-ip` subcommand doesn't require any arguments
-    if sub_command == SubCommand::NodeIp {
+    // The `node-ip`, `generate-net-config`, and `set-hostname` subcommands don't require any
+    // arguments
+    if sub_command == SubCommand::NodeIp
+        || sub_command == SubCommand::GenerateNetConfig
+        || sub_command == SubCommand::SetHostname
+    {
         return Ok((sub_command, None));
     };
 
@@ -194,12 +295,9 @@ fn parse_args(args: env::Args) -> Result<(SubCommand, Option<Args>)> {
     while let Some(arg) = iter.next() {
         match arg.as_ref() {
             "-i" => {
-                let value = iter
-                    .next()
-                    .unwrap_or_else(|| usage_msg("Did not give argument to -i"));
                 interface_name = Some(
-                    serde_plain::from_str::<InterfaceName>(&value)
-                        .unwrap_or_else(|_| usage_msg(format!("Unknown interface name {}", value))),
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to -i")),
                 );
             }
 
@@ -274,86 +372,496 @@ where
         .context(error::LeaseParseFailed { path: lease_file })?)
 }
 
-/// Write resolver configuration for libc.
-fn write_resolv_conf(dns_servers: &[&IpAddr], dns_search: &Option<Vec<String>>) -> Result<()> {
-    let mut output = String::new();
-
-    if let Some(s) = dns_search {
-        writeln!(output, "search {}", s.join(" ")).context(error::ResolvConfBuildFailed)?;
-    }
-
-    for n in dns_servers {
-        writeln!(output, "nameserver {}", n).context(error::ResolvConfBuildFailed)?;
+/// Resolve assigned IP address to a hostname.  Returns None if there's no valid hostname for it,
+/// since that shouldn't keep the rest of the network state from being reported.
+fn lookup_hostname(ip: &IpAddr) -> Option<ValidLinuxHostname> {
+    let host = match lookup_addr(ip) {
+        Ok(host) => host,
+        Err(e) => {
+            eprintln!("Failed to resolve '{}' to hostname: {}", ip, e);
+            return None;
+        }
+    };
+    match ValidLinuxHostname::try_from(host.as_str()) {
+        Ok(hostname) => Some(hostname),
+        Err(e) => {
+            eprintln!("Ignoring hostname for '{}': {}", ip, e);
+            None
+        }
     }
-
-    fs::write(RESOLV_CONF, output).context(error::ResolvConfWriteFailed { path: RESOLV_CONF })?;
-    Ok(())
 }
 
-/// Resolve assigned IP address and persist the result as hostname.
-fn update_hostname(ip: &IpNet) -> Result<()> {
-    let host =
-        lookup_addr(&ip.addr()).with_context(|| error::HostnameLookupFailed { ip: ip.addr() })?;
-    fs::write(KERNEL_HOSTNAME, host).context(error::HostnameWriteFailed {
-        path: KERNEL_HOSTNAME,
-    })?;
-    Ok(())
+/// Persist the current IP address to file, separately for IPv4 and IPv6.
+fn write_current_ip(ip: &IpAddr) -> Result<()> {
+    let path = match ip {
+        IpAddr::V4(_) => CURRENT_IP,
+        IpAddr::V6(_) => CURRENT_IPV6,
+    };
+    fs::write(path, ip.to_string()).context(error::CurrentIpWriteFailed { path })
 }
 
-/// Persist the current IP address to file
-fn write_current_ip(ip: &IpAddr) -> Result<()> {
-    fs::write(CURRENT_IP, ip.to_string()).context(error::CurrentIpWriteFailed { path: CURRENT_IP })
+/// Returns the name of the interface whose address is the node's IP, as recorded by
+/// `generate-net-config`.
+fn primary_interface() -> String {
+    fs::read_to_string(PRIMARY_INTERFACE)
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| DEFAULT_PRIMARY_INTERFACE.to_string())
 }
 
-fn install(args: &Args) -> Result<()> {
-    match (
-        &args.interface_name,
-        &args.interface_type,
-        &args.interface_family,
-    ) {
-        (InterfaceName::Eth0, InterfaceType::Dhcp, InterfaceFamily::Ipv4) => {
+async fn install(args: &Args) -> Result<()> {
+    // Leases for other interfaces only matter to wicked.
+    if args.interface_name != primary_interface() {
+        return Ok(());
+    }
+
+    match (&args.interface_type, &args.interface_family) {
+        (InterfaceType::Dhcp, family) => {
             let info = parse_lease_info(&args.data_file)?;
-            // Randomize name server order, for libc implementations like musl that send
-            // queries to the first N servers.
-            let mut dns_servers: Vec<_> = info.dns_servers.iter().collect();
-            dns_servers.shuffle(&mut thread_rng());
-            write_resolv_conf(&dns_servers, &info.dns_search)?;
-            write_current_ip(&info.ip_address.addr())?;
-            update_hostname(&info.ip_address)?;
+            let ip_address = info.ip_address.addr();
+            write_current_ip(&ip_address)?;
+            // IPv4 is preferred; an IPv6 lease only matters on IPv6-only networks.
+            if let InterfaceFamily::Ipv6 = family {
+                if Path::new(CURRENT_IP).exists() {
+                    return Ok(());
+                }
+            }
+
+            let settings = match get_settings().await {
+                Ok(settings) => settings,
+                Err(e) => {
+                    // Name resolution shouldn't depend on the API, so write resolv.conf directly
+                    // from the lease until it's next rendered from settings.
+                    eprintln!("Writing {} from the lease: {}", RESOLV_CONF, e);
+                    return write_resolv_conf(&info);
+                }
+            };
+            let current = settings.network.as_ref().and_then(|n| n.current.as_ref());
+            let mut state = NetworkState {
+                ip_address: Some(ip_address),
+                hostname: lookup_hostname(&ip_address),
+                name_servers: None,
+                search_list: None,
+            };
+
+            // Name servers and search domains given for interfaces are used instead of those from
+            // the lease.
+            let (name_servers, search_list) = static_dns(&settings);
+            if let Some(name_servers) = name_servers {
+                state.name_servers = Some(name_servers);
+            } else {
+                // Keep the current order if the name servers haven't changed; otherwise,
+                // randomize their order, for libc implementations like musl that send queries to
+                // the first N servers.
+                let current_name_servers: HashSet<&IpAddr> = current
+                    .and_then(|c| c.name_servers.as_ref())
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                if !info.dns_servers.is_empty()
+                    && current_name_servers != info.dns_servers.iter().collect()
+                {
+                    let mut name_servers: Vec<_> = info.dns_servers.iter().cloned().collect();
+                    name_servers.shuffle(&mut thread_rng());
+                    state.name_servers = Some(name_servers);
+                }
+            }
+
+            if let Some(search_list) = search_list {
+                state.search_list = Some(search_list);
+            } else {
+                let search_list = info
+                    .dns_search
+                    .iter()
+                    .flatten()
+                    .filter_map(|domain| match DNSDomain::try_from(domain.as_str()) {
+                        Ok(domain) => Some(domain),
+                        Err(e) => {
+                            eprintln!("Ignoring search domain from lease: {}", e);
+                            None
+                        }
+                    })
+                    .collect();
+                state.search_list = Some(search_list);
+            }
+
+            update_network_state(current, state).await?;
         }
-        _ => eprintln!("Unhandled 'install' command: {:?}", &args),
     }
     Ok(())
 }
 
+/// Writes resolver configuration for libc from a lease, for when it can't be rendered from
+/// settings.
+fn write_resolv_conf(info: &LeaseInfo) -> Result<()> {
+    let mut output = String::new();
+    (|| -> std::result::Result<(), std::fmt::Error> {
+        if let Some(search) = &info.dns_search {
+            writeln!(output, "search {}", search.join(" "))?;
+        }
+        for name_server in &info.dns_servers {
+            writeln!(output, "nameserver {}", name_server)?;
+        }
+        Ok(())
+    })()
+    .context(error::ResolvConfBuildFailed)?;
+    fs::write(RESOLV_CONF, output).context(error::ResolvConfWriteFailed { path: RESOLV_CONF })
+}
+
 fn remove(args: &Args) -> Result<()> {
-    match (
-        &args.interface_name,
-        &args.interface_type,
-        &args.interface_family,
-    ) {
+    match (&args.interface_type, &args.interface_family) {
         _ => eprintln!("The 'remove' command is not implemented."),
     }
     Ok(())
 }
 
-/// Return the current IP address as JSON (intended for use as a settings generator)
+/// Return the current IP address as JSON (intended for use as a settings generator).  The IPv4
+/// address is preferred, if there is one.
 fn node_ip() -> Result<()> {
-    let ip =
-        fs::read_to_string(CURRENT_IP).context(error::CurrentIpReadFailed { path: CURRENT_IP })?;
+    let ip = match fs::read_to_string(CURRENT_IP) {
+        Ok(ip) => ip,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => fs::read_to_string(CURRENT_IPV6)
+            .context(error::CurrentIpReadFailed { path: CURRENT_IPV6 })?,
+        Err(e) => return Err(e).context(error::CurrentIpReadFailed { path: CURRENT_IP }),
+    };
     // sundog expects JSON-serialized output
     let output = serde_json::to_string(&ip).context(error::JsonSerialize { output: ip })?;
     println!("{}", output);
     Ok(())
 }
 
-fn run() -> Result<()> {
+/// Makes a request to the API, returning the response body if it was successful.
+async fn api_request(uri: &str, method: &str, data: Option<String>) -> Result<String> {
+    let (code, response_body) = apiclient::raw_request(DEFAULT_API_SOCKET, uri, method, data)
+        .await
+        .context(error::ApiRequestFailed { method, uri })?;
+    ensure!(
+        code.is_success(),
+        error::ApiResponseFailed {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+    );
+    Ok(response_body)
+}
+
+/// Retrieve the settings from the API.
+async fn get_settings() -> Result<model::Settings> {
+    let uri = "/settings";
+    let response_body = api_request(uri, "GET", None).await?;
+    serde_json::from_str(&response_body).context(error::ApiResponseParseFailed { uri })
+}
+
+/// Returns the name servers and search domains given for interfaces in settings, which are
+/// reported instead of any from a lease.  None means no name servers were given, so those from a
+/// lease should be used.
+fn static_dns(settings: &model::Settings) -> (Option<Vec<IpAddr>>, Option<Vec<DNSDomain>>) {
+    let network = match &settings.network {
+        Some(network) => network,
+        None => return (None, None),
+    };
+    let (name_servers, search_list) = interface_dns(network);
+    if name_servers.is_empty() {
+        return (None, None);
+    }
+    (Some(name_servers), Some(search_list))
+}
+
+/// Returns the name servers and search domains given for interfaces in settings, in a consistent
+/// order, without duplicates.
+fn interface_dns(network: &NetworkSettings) -> (Vec<IpAddr>, Vec<DNSDomain>) {
+    // Interfaces are sorted by name so that name servers are reported in a consistent order.
+    let sorted: BTreeMap<&str, &NetworkInterface> = network
+        .interfaces
+        .iter()
+        .flatten()
+        .map(|(name, interface)| (name.as_ref(), interface))
+        .collect();
+
+    let mut name_servers = Vec::new();
+    let mut search_list = Vec::new();
+    for interface in sorted.values() {
+        for name_server in interface.nameservers.iter().flatten() {
+            if !name_servers.contains(name_server) {
+                name_servers.push(*name_server);
+            }
+        }
+        for domain in interface.search.iter().flatten() {
+            if !search_list.contains(domain) {
+                search_list.push(domain.clone());
+            }
+        }
+    }
+    (name_servers, search_list)
+}
+
+/// Reports network state to the API in `settings.network.current`, then commits and applies it so
+/// that resolv.conf and the hostname are updated.  Fields that match the current state are left
+/// out, so services only restart for real changes.
+async fn update_network_state(
+    current: Option<&NetworkState>,
+    mut state: NetworkState,
+) -> Result<()> {
+    if let Some(current) = current {
+        if state.ip_address == current.ip_address {
+            state.ip_address = None;
+        }
+        if state.hostname == current.hostname {
+            state.hostname = None;
+        }
+        if state.name_servers == current.name_servers {
+            state.name_servers = None;
+        }
+        if state.search_list == current.search_list {
+            state.search_list = None;
+        }
+    }
+    if state.ip_address.is_none()
+        && state.hostname.is_none()
+        && state.name_servers.is_none()
+        && state.search_list.is_none()
+    {
+        return Ok(());
+    }
+
+    // The current state is read-only through /settings; it has its own endpoint.
+    let uri = format!("/network/current?tx={}", NETDOG_TRANSACTION);
+    let data = serde_json::to_string(&state).context(error::NetworkStateSerialize)?;
+    api_request(&uri, "PATCH", Some(data)).await?;
+
+    let uri = format!("/tx/commit_and_apply?tx={}", NETDOG_TRANSACTION);
+    api_request(&uri, "POST", None).await?;
+    Ok(())
+}
+
+/// Replace wicked's interface configuration with the interfaces configured in settings, if any,
+/// record which interface is primary, and report any static addresses and name servers to the
+/// API, since they won't come from a lease.
+async fn generate_net_config() -> Result<()> {
+    // Addresses from a previous boot shouldn't keep a lease for the other IP family from being
+    // used.
+    for path in &[CURRENT_IP, CURRENT_IPV6] {
+        remove_if_exists(path).context(error::CurrentIpWriteFailed { path: *path })?;
+    }
+
+    // Networking shouldn't depend on the API, or there'd be no way to reach the host to recover
+    // it, so if the settings aren't available we leave the default configuration in place.
+    let settings = match get_settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Using the default network configuration: {}", e);
+            return remove_if_exists(PRIMARY_INTERFACE).context(
+                error::PrimaryInterfaceWriteFailed {
+                    path: PRIMARY_INTERFACE,
+                },
+            );
+        }
+    };
+    let interfaces = match settings
+        .network
+        .as_ref()
+        .and_then(|n| n.interfaces.as_ref())
+    {
+        Some(interfaces) if !interfaces.is_empty() => interfaces,
+        _ => {
+            // Use the default configuration, and make sure nothing is left from a previous boot.
+            return remove_if_exists(PRIMARY_INTERFACE).context(
+                error::PrimaryInterfaceWriteFailed {
+                    path: PRIMARY_INTERFACE,
+                },
+            );
+        }
+    };
+
+    let configs = wicked::interface_configs(interfaces)?;
+    let ifconfig_dir = Path::new(WICKED_IFCONFIG_DIR);
+    let entries = fs::read_dir(ifconfig_dir)
+        .context(error::InterfaceConfigListFailed { path: ifconfig_dir })?;
+    for entry in entries {
+        let path = entry
+            .context(error::InterfaceConfigListFailed { path: ifconfig_dir })?
+            .path();
+        if path.extension() == Some("xml".as_ref()) {
+            fs::remove_file(&path).context(error::InterfaceConfigRemoveFailed { path: &path })?;
+        }
+    }
+    for (name, config) in configs {
+        let path = ifconfig_dir.join(format!("{}.xml", name));
+        fs::write(&path, config).context(error::InterfaceConfigWriteFailed { path: &path })?;
+    }
+
+    let mut state = NetworkState {
+        ip_address: None,
+        hostname: None,
+        name_servers: None,
+        search_list: None,
+    };
+
+    match choose_primary(interfaces) {
+        Some((name, interface)) => {
+            fs::write(PRIMARY_INTERFACE, name.as_bytes()).context(
+                error::PrimaryInterfaceWriteFailed {
+                    path: PRIMARY_INTERFACE,
+                },
+            )?;
+            // A static address won't get a lease, so record it now.
+            if let Some(address) = interface.addresses.iter().flatten().next() {
+                let address: IpNet = address.parse().context(error::CidrParseFailed {
+                    input: address.to_string(),
+                })?;
+                let ip_address = address.addr();
+                write_current_ip(&ip_address)?;
+                // The network isn't up yet, so we can't look up a hostname for it; `set-hostname`
+                // does that later.
+                state.ip_address = Some(ip_address);
+            }
+        }
+        None => {
+            remove_if_exists(PRIMARY_INTERFACE).context(error::PrimaryInterfaceWriteFailed {
+                path: PRIMARY_INTERFACE,
+            })?
+        }
+    }
+
+    let (name_servers, search_list) = static_dns(&settings);
+    state.name_servers = name_servers;
+    state.search_list = search_list;
+
+    let current = settings.network.as_ref().and_then(|n| n.current.as_ref());
+    update_network_state(current, state).await
+}
+
+/// Chooses the interface whose address is the node's IP: the one marked `primary`, or else the
+/// first interface that uses DHCP, or else the first with a static address.  Members of a bond
+/// have no address of their own, so they're never chosen.  Interfaces are sorted by name so that
+/// the choice is consistent.
+fn choose_primary(
+    interfaces: &HashMap<NetworkInterfaceName, NetworkInterface>,
+) -> Option<(&str, &NetworkInterface)> {
+    let sorted: BTreeMap<&str, &NetworkInterface> = interfaces
+        .iter()
+        .map(|(name, interface)| (name.as_ref(), interface))
+        .collect();
+    if let Some((name, interface)) = sorted
+        .iter()
+        .find(|(_, interface)| interface.primary == Some(true))
+    {
+        return Some((*name, *interface));
+    }
+
+    let members: HashSet<&str> = interfaces
+        .values()
+        .filter_map(|interface| interface.bond.as_ref())
+        .filter_map(|bond| bond.interfaces.as_ref())
+        .flatten()
+        .map(|member| member.as_ref())
+        .collect();
+    let candidates: Vec<(&str, &NetworkInterface)> = sorted
+        .into_iter()
+        .filter(|(name, _)| !members.contains(name))
+        .collect();
+    candidates
+        .iter()
+        .find(|(_, interface)| uses_dhcp(interface))
+        .or_else(|| {
+            candidates
+                .iter()
+                .find(|(_, interface)| interface.addresses.iter().flatten().next().is_some())
+        })
+        .copied()
+}
+
+/// Whether the interface gets an address from DHCP.
+fn uses_dhcp(interface: &NetworkInterface) -> bool {
+    interface.dhcp4 == Some(true) || interface.dhcp6 == Some(true)
+}
+
+/// Reports the hostname for a statically addressed primary interface, which doesn't get a lease
+/// to trigger `install`.  Runs once the network is up, so that the hostname can be found by
+/// reverse DNS lookup of the interface's address, like it is for a lease.
+async fn set_hostname() -> Result<()> {
+    let settings = get_settings().await?;
+    let network = match &settings.network {
+        Some(network) => network,
+        None => return Ok(()),
+    };
+    if !primary_is_static(network, &primary_interface()) {
+        return Ok(());
+    }
+    let ip_address = match current_ip()? {
+        Some(ip) => ip,
+        None => return Ok(()),
+    };
+
+    let state = NetworkState {
+        ip_address: None,
+        hostname: lookup_hostname(&ip_address),
+        name_servers: None,
+        search_list: None,
+    };
+    update_network_state(network.current.as_ref(), state).await
+}
+
+/// Whether the named interface is configured in settings with only static addresses, so it won't
+/// get a lease.
+fn primary_is_static(network: &NetworkSettings, primary: &str) -> bool {
+    let interface = network
+        .interfaces
+        .iter()
+        .flatten()
+        .find(|(name, _)| name.as_ref() == primary)
+        .map(|(_, interface)| interface);
+    match interface {
+        Some(interface) => {
+            interface.addresses.iter().flatten().next().is_some() && !uses_dhcp(interface)
+        }
+        None => false,
+    }
+}
+
+/// Returns the current IP address recorded by `install` or `generate-net-config`, preferring
+/// IPv4, or None if there isn't one.
+fn current_ip() -> Result<Option<IpAddr>> {
+    for path in &[CURRENT_IP, CURRENT_IPV6] {
+        match fs::read_to_string(path) {
+            Ok(ip) => {
+                let ip = ip.trim();
+                return ip
+                    .parse()
+                    .map(Some)
+                    .context(error::CurrentIpParseFailed { path: *path, ip });
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).context(error::CurrentIpReadFailed { path: *path }),
+        }
+    }
+    Ok(None)
+}
+
+/// Removes the given file, if it exists.
+fn remove_if_exists<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+async fn run() -> Result<()> {
     match parse_args(env::args())? {
         (SubCommand::NodeIp, None) => node_ip()?,
         (SubCommand::NodeIp, Some(_)) => {
             usage_msg("Subcommand 'node-ip' doesn't support arguments")
         }
-        (SubCommand::Install, Some(args)) => install(&args)?,
+        (SubCommand::GenerateNetConfig, None) => generate_net_config().await?,
+        (SubCommand::GenerateNetConfig, Some(_)) => {
+            usage_msg("Subcommand 'generate-net-config' doesn't support arguments")
+        }
+        (SubCommand::SetHostname, None) => set_hostname().await?,
+        (SubCommand::SetHostname, Some(_)) => {
+            usage_msg("Subcommand 'set-hostname' doesn't support arguments")
+        }
+        (SubCommand::Install, Some(args)) => install(&args).await?,
         (SubCommand::Remove, Some(args)) => remove(&args)?,
         (subcommand, None) => usage_msg(format!("Subcommand '{}' requires arguments", subcommand)),
     }
@@ -363,9 +871,161 @@ fn run() -> Result<()> {
 // Returning a Result from main makes it print a Debug representation of the error, but with Snafu
 // we have nice Display representations of the error, so we wrap "main" (run) and print any error.
 // https://github.com/shepmaster/snafu/issues/110
-fn main() {
-    if let Err(e) = run() {
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
         eprintln!("{}", e);
         process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(toml: &str) -> model::Settings {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn static_primary() {
+        let settings = settings(
+            r#"
+            [network.interfaces.eth0]
+            addresses = ["192.168.1.10/24"]
+            [network.interfaces.eth1]
+            addresses = ["192.168.2.10/24"]
+            dhcp6 = true
+            [network.interfaces.eth2]
+            dhcp4 = true
+            "#,
+        );
+        let network = settings.network.unwrap();
+        assert!(primary_is_static(&network, "eth0"));
+        assert!(!primary_is_static(&network, "eth1"));
+        assert!(!primary_is_static(&network, "eth2"));
+        assert!(!primary_is_static(&network, "eth3"));
+    }
+
+    fn primary_name(toml: &str) -> Option<String> {
+        let network = settings(toml).network.unwrap();
+        choose_primary(network.interfaces.as_ref().unwrap()).map(|(name, _)| name.to_string())
+    }
+
+    #[test]
+    fn explicit_primary() {
+        let primary = primary_name(
+            r#"
+            [network.interfaces.eth0]
+            dhcp4 = true
+            [network.interfaces.eth1]
+            addresses = ["192.168.1.10/24"]
+            primary = true
+            "#,
+        );
+        assert_eq!(primary.as_deref(), Some("eth1"));
+    }
+
+    #[test]
+    fn bond_without_primary() {
+        let primary = primary_name(
+            r#"
+            [network.interfaces.bond0]
+            dhcp4 = true
+            [network.interfaces.bond0.bond]
+            mode = "active-backup"
+            interfaces = ["eth0", "eth1"]
+            [network.interfaces.eth0]
+            mtu = 9001
+            "#,
+        );
+        assert_eq!(primary.as_deref(), Some("bond0"));
+    }
+
+    #[test]
+    fn dhcp_preferred_for_primary() {
+        let primary = primary_name(
+            r#"
+            [network.interfaces.eth0]
+            addresses = ["192.168.1.10/24"]
+            [network.interfaces."eth0.100"]
+            dhcp6 = true
+            [network.interfaces."eth0.100".vlan]
+            device = "eth0"
+            id = 100
+            "#,
+        );
+        assert_eq!(primary.as_deref(), Some("eth0.100"));
+    }
+
+    #[test]
+    fn static_fallback_primary() {
+        let primary = primary_name(
+            r#"
+            [network.interfaces.eth1]
+            addresses = ["192.168.2.10/24"]
+            [network.interfaces.eth0]
+            addresses = ["192.168.1.10/24"]
+            "#,
+        );
+        assert_eq!(primary.as_deref(), Some("eth0"));
+    }
+
+    #[test]
+    fn no_static_dns() {
+        let (name_servers, search_list) = static_dns(&model::Settings::default());
+        assert!(name_servers.is_none());
+        assert!(search_list.is_none());
+    }
+
+    #[test]
+    fn interface_dns_used() {
+        let settings = settings(
+            r#"
+            [network.interfaces.eth1]
+            nameservers = ["192.168.1.2", "192.168.1.3"]
+            search = ["example.com"]
+            [network.interfaces.eth0]
+            nameservers = ["192.168.1.3", "192.168.1.1"]
+            "#,
+        );
+        let (name_servers, search_list) = static_dns(&settings);
+        let name_servers: Vec<String> = name_servers
+            .unwrap()
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        assert_eq!(
+            name_servers,
+            vec!["192.168.1.3", "192.168.1.1", "192.168.1.2"]
+        );
+        assert_eq!(
+            search_list.unwrap(),
+            vec![DNSDomain::try_from("example.com").unwrap()]
+        );
+    }
+
+    #[test]
+    fn dns_settings_not_reported() {
+        let settings = settings(
+            r#"
+            [dns]
+            search-list = ["example.org"]
+            [network.interfaces.eth0]
+            nameservers = ["192.168.1.1"]
+            search = ["example.com"]
+            "#,
+        );
+        // The resolv-conf template gives settings.dns precedence; only what the interfaces use
+        // is reported.
+        let (name_servers, search_list) = static_dns(&settings);
+        assert_eq!(
+            name_servers.unwrap(),
+            vec!["192.168.1.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            search_list.unwrap(),
+            vec![DNSDomain::try_from("example.com").unwrap()]
+        );
+    }
+}
@@ -0,0 +1,502 @@
+//! This module generates wicked interface configuration ("ifconfig") XML from the network
+//! interface settings in the API.  Each configured interface gets its own file, named after the
+//! interface, and members of a bond get a file that attaches them to the bond even if they aren't
+//! otherwise configured.
+//!
+//! Every value we write comes from a modeled type that only allows interface names, IP
+//! addresses, numbers, and known keywords, so there's nothing that needs XML escaping.
+
+use crate::error;
+use crate::Result;
+use ipnet::IpNet;
+use model::modeled_types::{IpCidr, NetworkInterfaceName};
+use model::{NetworkBond, NetworkInterface, NetworkRoute, NetworkVlan};
+use snafu::{ensure, ResultExt};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Returns the ifconfig XML for each of the given interfaces, keyed by interface name.
+pub(crate) fn interface_configs(
+    interfaces: &HashMap<NetworkInterfaceName, NetworkInterface>,
+) -> Result<BTreeMap<String, String>> {
+    // Find the bond, if any, that each interface belongs to, so the member can refer to it.
+    let mut masters = BTreeMap::new();
+    for (name, interface) in interfaces {
+        let members = interface
+            .bond
+            .as_ref()
+            .and_then(|bond| bond.interfaces.as_ref());
+        for member in members.into_iter().flatten() {
+            masters.insert(member.to_string(), name.to_string());
+        }
+    }
+
+    let mut configs = BTreeMap::new();
+    for (name, interface) in interfaces {
+        let master = masters.get(name.as_ref()).map(String::as_str);
+        configs.insert(name.to_string(), interface_config(name, interface, master)?);
+    }
+
+    // Members of a bond don't have to be configured themselves, but they still need to be
+    // attached to the bond.
+    for (member, master) in &masters {
+        if !configs.contains_key(member) {
+            let config = member_config(member, master)
+                .context(error::InterfaceConfigBuildFailed { name: member })?;
+            configs.insert(member.clone(), config);
+        }
+    }
+
+    Ok(configs)
+}
+
+/// Builds the ifconfig XML for a single configured interface.
+fn interface_config(
+    name: &str,
+    interface: &NetworkInterface,
+    master: Option<&str>,
+) -> Result<String> {
+    let mut ipv4_addresses = Vec::new();
+    let mut ipv6_addresses = Vec::new();
+    for address in interface.addresses.iter().flatten() {
+        let net = parse_cidr(address)?;
+        match net {
+            IpNet::V4(_) => ipv4_addresses.push(net),
+            IpNet::V6(_) => ipv6_addresses.push(net),
+        }
+    }
+
+    let mut ipv4_routes = Vec::new();
+    let mut ipv6_routes = Vec::new();
+    for route in interface.routes.iter().flatten() {
+        let (to, via) = match (&route.to, &route.via) {
+            (Some(to), Some(via)) => (parse_cidr(to)?, via),
+            _ => {
+                return error::InvalidRoute {
+                    name,
+                    msg: "routes need both 'to' and 'via'",
+                }
+                .fail()
+            }
+        };
+        ensure!(
+            to.addr().is_ipv4() == via.is_ipv4(),
+            error::InvalidRoute {
+                name,
+                msg: format!("route to {} can't use gateway {}", to, via),
+            }
+        );
+        match to {
+            IpNet::V4(_) => ipv4_routes.push((to, route)),
+            IpNet::V6(_) => ipv6_routes.push((to, route)),
+        }
+    }
+
+    let xml = (|| -> std::result::Result<String, std::fmt::Error> {
+        let mut xml = String::new();
+        writeln!(xml, "<interface>")?;
+        writeln!(xml, "  <name>{}</name>", name)?;
+        writeln!(xml)?;
+
+        // Members of a bond are brought up by the bond, and have no addresses of their own.
+        if master.is_some() {
+            write_member_control(&mut xml)?;
+        } else {
+            write_control(
+                &mut xml,
+                interface.bond.is_none() && interface.vlan.is_none(),
+            )?;
+        }
+        write_link(&mut xml, interface.mtu, master)?;
+
+        if let Some(bond) = &interface.bond {
+            writeln!(xml)?;
+            write_bond(&mut xml, bond)?;
+        }
+        if let Some(vlan) = &interface.vlan {
+            writeln!(xml)?;
+            write_vlan(&mut xml, vlan)?;
+        }
+
+        if master.is_none() {
+            let dhcp4 = interface.dhcp4 == Some(true);
+            let dhcp6 = interface.dhcp6 == Some(true);
+            if dhcp4 || !ipv4_addresses.is_empty() {
+                writeln!(xml)?;
+                writeln!(xml, "  <ipv4>")?;
+                writeln!(xml, "    <arp-verify>false</arp-verify>")?;
+                writeln!(xml, "    <arp-notify>false</arp-notify>")?;
+                writeln!(xml, "  </ipv4>")?;
+            }
+            if dhcp4 {
+                writeln!(xml)?;
+                writeln!(xml, "  <ipv4:dhcp>")?;
+                writeln!(xml, "    <enabled>true</enabled>")?;
+                writeln!(xml, "  </ipv4:dhcp>")?;
+            }
+            write_static(&mut xml, "ipv4", &ipv4_addresses, &ipv4_routes)?;
+            if dhcp6 {
+                writeln!(xml)?;
+                writeln!(xml, "  <ipv6:dhcp>")?;
+                writeln!(xml, "    <enabled>true</enabled>")?;
+                writeln!(xml, "    <defer-timeout>1</defer-timeout>")?;
+                writeln!(xml, "    <flags>")?;
+                writeln!(xml, "      <optional />")?;
+                writeln!(xml, "    </flags>")?;
+                writeln!(xml, "  </ipv6:dhcp>")?;
+            }
+            write_static(&mut xml, "ipv6", &ipv6_addresses, &ipv6_routes)?;
+        }
+
+        writeln!(xml, "</interface>")?;
+        Ok(xml)
+    })()
+    .context(error::InterfaceConfigBuildFailed { name })?;
+
+    Ok(xml)
+}
+
+/// Builds the ifconfig XML for a member of a bond that isn't otherwise configured.
+fn member_config(name: &str, master: &str) -> std::result::Result<String, std::fmt::Error> {
+    let mut xml = String::new();
+    writeln!(xml, "<interface>")?;
+    writeln!(xml, "  <name>{}</name>", name)?;
+    writeln!(xml)?;
+    write_member_control(&mut xml)?;
+    write_link(&mut xml, None, Some(master))?;
+    writeln!(xml, "</interface>")?;
+    Ok(xml)
+}
+
+/// Parses a modeled CIDR into an IpNet so we can tell which address family it belongs to.
+fn parse_cidr(cidr: &IpCidr) -> Result<IpNet> {
+    IpNet::from_str(cidr).context(error::CidrParseFailed {
+        input: cidr.to_string(),
+    })
+}
+
+/// Interfaces are brought up at boot.  Devices wait for a link; virtual interfaces, like bonds and
+/// VLANs, have one as soon as they're created.
+fn write_control(xml: &mut String, require_link: bool) -> std::fmt::Result {
+    writeln!(xml, "  <control>")?;
+    writeln!(xml, "    <mode>boot</mode>")?;
+    if require_link {
+        writeln!(xml, "    <link-detection>")?;
+        writeln!(xml, "      <require-link />")?;
+        writeln!(xml, "    </link-detection>")?;
+    }
+    writeln!(xml, "  </control>")
+}
+
+/// Members of a bond are brought up when they appear, so the bond can use them.
+fn write_member_control(xml: &mut String) -> std::fmt::Result {
+    writeln!(xml, "  <control>")?;
+    writeln!(xml, "    <mode>hotplug</mode>")?;
+    writeln!(xml, "  </control>")
+}
+
+/// Writes link-level settings, if there are any.
+fn write_link(xml: &mut String, mtu: Option<u32>, master: Option<&str>) -> std::fmt::Result {
+    if mtu.is_none() && master.is_none() {
+        return Ok(());
+    }
+
+    writeln!(xml)?;
+    writeln!(xml, "  <link>")?;
+    if let Some(mtu) = mtu {
+        writeln!(xml, "    <mtu>{}</mtu>", mtu)?;
+    }
+    if let Some(master) = master {
+        writeln!(xml, "    <master>{}</master>", master)?;
+    }
+    writeln!(xml, "  </link>")
+}
+
+fn write_bond(xml: &mut String, bond: &NetworkBond) -> std::fmt::Result {
+    writeln!(xml, "  <bond>")?;
+    if let Some(mode) = &bond.mode {
+        writeln!(xml, "    <mode>{}</mode>", mode)?;
+    }
+    if let Some(miimon) = bond.miimon {
+        writeln!(xml, "    <miimon>")?;
+        writeln!(xml, "      <frequency>{}</frequency>", miimon)?;
+        writeln!(xml, "    </miimon>")?;
+    }
+    writeln!(xml, "    <slaves>")?;
+    for member in bond.interfaces.iter().flatten() {
+        writeln!(xml, "      <slave>")?;
+        writeln!(xml, "        <device>{}</device>", member)?;
+        writeln!(xml, "      </slave>")?;
+    }
+    writeln!(xml, "    </slaves>")?;
+    writeln!(xml, "  </bond>")
+}
+
+fn write_vlan(xml: &mut String, vlan: &NetworkVlan) -> std::fmt::Result {
+    writeln!(xml, "  <vlan>")?;
+    if let Some(device) = &vlan.device {
+        writeln!(xml, "    <device>{}</device>", device)?;
+    }
+    if let Some(id) = vlan.id {
+        writeln!(xml, "    <tag>{}</tag>", id)?;
+    }
+    writeln!(xml, "  </vlan>")
+}
+
+/// Writes the static addresses and routes for one address family, if there are any.
+fn write_static(
+    xml: &mut String,
+    family: &str,
+    addresses: &[IpNet],
+    routes: &[(IpNet, &NetworkRoute)],
+) -> std::fmt::Result {
+    if addresses.is_empty() && routes.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(xml)?;
+    writeln!(xml, "  <{}:static>", family)?;
+    for address in addresses {
+        writeln!(xml, "    <address>")?;
+        writeln!(xml, "      <local>{}</local>", address)?;
+        writeln!(xml, "    </address>")?;
+    }
+    for (to, route) in routes {
+        writeln!(xml, "    <route>")?;
+        writeln!(xml, "      <destination>{}</destination>", to)?;
+        if let Some(via) = &route.via {
+            writeln!(xml, "      <nexthop>")?;
+            writeln!(xml, "        <gateway>{}</gateway>", via)?;
+            writeln!(xml, "      </nexthop>")?;
+        }
+        if let Some(metric) = route.metric {
+            writeln!(xml, "      <priority>{}</priority>", metric)?;
+        }
+        writeln!(xml, "    </route>")?;
+    }
+    writeln!(xml, "  </{}:static>", family)
+}
+
+#[cfg(test)]
+mod test {
+    use super::interface_configs;
+    use model::NetworkSettings;
+
+    fn configs(toml_str: &str) -> std::collections::BTreeMap<String, String> {
+        let network: NetworkSettings = toml::from_str(toml_str).unwrap();
+        interface_configs(&network.interfaces.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn dhcp_matches_default() {
+        let configs = configs(
+            r#"
+            [interfaces.eth0]
+            dhcp4 = true
+            dhcp6 = true
+            "#,
+        );
+        let expected = r#"<interface>
+  <name>eth0</name>
+
+  <control>
+    <mode>boot</mode>
+    <link-detection>
+      <require-link />
+    </link-detection>
+  </control>
+
+  <ipv4>
+    <arp-verify>false</arp-verify>
+    <arp-notify>false</arp-notify>
+  </ipv4>
+
+  <ipv4:dhcp>
+    <enabled>true</enabled>
+  </ipv4:dhcp>
+
+  <ipv6:dhcp>
+    <enabled>true</enabled>
+    <defer-timeout>1</defer-timeout>
+    <flags>
+      <optional />
+    </flags>
+  </ipv6:dhcp>
+</interface>
+"#;
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs["eth0"], expected);
+    }
+
+    #[test]
+    fn static_addresses_and_routes() {
+        let configs = configs(
+            r#"
+            [interfaces.eth0]
+            addresses = ["192.168.1.10/24", "2001:db8::10/64"]
+            mtu = 9000
+            [[interfaces.eth0.routes]]
+            to = "0.0.0.0/0"
+            via = "192.168.1.1"
+            metric = 100
+            [[interfaces.eth0.routes]]
+            to = "::/0"
+            via = "2001:db8::1"
+            "#,
+        );
+        let expected = r#"<interface>
+  <name>eth0</name>
+
+  <control>
+    <mode>boot</mode>
+    <link-detection>
+      <require-link />
+    </link-detection>
+  </control>
+
+  <link>
+    <mtu>9000</mtu>
+  </link>
+
+  <ipv4>
+    <arp-verify>false</arp-verify>
+    <arp-notify>false</arp-notify>
+  </ipv4>
+
+  <ipv4:static>
+    <address>
+      <local>192.168.1.10/24</local>
+    </address>
+    <route>
+      <destination>0.0.0.0/0</destination>
+      <nexthop>
+        <gateway>192.168.1.1</gateway>
+      </nexthop>
+      <priority>100</priority>
+    </route>
+  </ipv4:static>
+
+  <ipv6:static>
+    <address>
+      <local>2001:db8::10/64</local>
+    </address>
+    <route>
+      <destination>::/0</destination>
+      <nexthop>
+        <gateway>2001:db8::1</gateway>
+      </nexthop>
+    </route>
+  </ipv6:static>
+</interface>
+"#;
+        assert_eq!(configs["eth0"], expected);
+    }
+
+    #[test]
+    fn bond_with_vlan() {
+        let configs = configs(
+            r#"
+            [interfaces.bond0]
+            [interfaces.bond0.bond]
+            mode = "active-backup"
+            interfaces = ["eth0", "eth1"]
+            miimon = 100
+            [interfaces.eth1]
+            mtu = 9000
+            [interfaces."bond0.100"]
+            addresses = ["10.0.100.5/24"]
+            primary = true
+            [interfaces."bond0.100".vlan]
+            device = "bond0"
+            id = 100
+            "#,
+        );
+        let names: Vec<_> = configs.keys().map(String::as_str).collect();
+        assert_eq!(names, vec!["bond0", "bond0.100", "eth0", "eth1"]);
+
+        let expected_bond = r#"<interface>
+  <name>bond0</name>
+
+  <control>
+    <mode>boot</mode>
+  </control>
+
+  <bond>
+    <mode>active-backup</mode>
+    <miimon>
+      <frequency>100</frequency>
+    </miimon>
+    <slaves>
+      <slave>
+        <device>eth0</device>
+      </slave>
+      <slave>
+        <device>eth1</device>
+      </slave>
+    </slaves>
+  </bond>
+</interface>
+"#;
+        assert_eq!(configs["bond0"], expected_bond);
+
+        let expected_vlan = r#"<interface>
+  <name>bond0.100</name>
+
+  <control>
+    <mode>boot</mode>
+  </control>
+
+  <vlan>
+    <device>bond0</device>
+    <tag>100</tag>
+  </vlan>
+
+  <ipv4>
+    <arp-verify>false</arp-verify>
+    <arp-notify>false</arp-notify>
+  </ipv4>
+
+  <ipv4:static>
+    <address>
+      <local>10.0.100.5/24</local>
+    </address>
+  </ipv4:static>
+</interface>
+"#;
+        assert_eq!(configs["bond0.100"], expected_vlan);
+
+        let expected_member = r#"<interface>
+  <name>eth0</name>
+
+  <control>
+    <mode>hotplug</mode>
+  </control>
+
+  <link>
+    <master>bond0</master>
+  </link>
+</interface>
+"#;
+        assert_eq!(configs["eth0"], expected_member);
+
+        // A configured member keeps its own link settings and is attached to the bond.
+        assert!(configs["eth1"].contains("<master>bond0</master>"));
+        assert!(configs["eth1"].contains("<mtu>9000</mtu>"));
+    }
+
+    #[test]
+    fn route_family_mismatch() {
+        let network: model::NetworkSettings = toml::from_str(
+            r#"
+            [interfaces.eth0]
+            addresses = ["192.168.1.10/24"]
+            [[interfaces.eth0.routes]]
+            to = "0.0.0.0/0"
+            via = "2001:db8::1"
+            "#,
+        )
+        .unwrap();
+        interface_configs(&network.interfaces.unwrap()).unwrap_err();
+    }
+}
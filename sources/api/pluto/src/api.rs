@@ -1,4 +1,4 @@
-pub(super) use inner::{get_aws_k8s_info, Error};
+pub(super) use inner::{get_aws_k8s_info, get_service_ipv6_cidr, Error};
 
 /// The result type for the [`api`] module.
 pub(super) type Result<T> = std::result::Result<T, Error>;
@@ -68,6 +68,15 @@ mod inner {
                 .into(),
         })
     }
+
+    /// Gets the service IPv6 CIDR from the Bottlerocket API; it's only set for IPv6 clusters.
+    pub(crate) async fn get_service_ipv6_cidr() -> Result<Option<String>> {
+        let settings = get_settings().await?;
+        Ok(settings
+            .kubernetes
+            .and_then(|k8s| k8s.service_ipv6_cidr)
+            .map(|cidr| cidr.to_string()))
+    }
 }
 
 /// This dummy code is compiled when the `sources` workspace is being compiled for non `aws-k8s-*`
@@ -80,13 +89,15 @@ mod inner {
 
     #[derive(Debug, Snafu)]
     pub(crate) enum Error {
-        #[snafu(display(
-            "The get_aws_k8s_info function is only compatible with aws-k8s variants"
-        ))]
+        #[snafu(display("The api functions are only compatible with aws-k8s variants"))]
         WrongVariant,
     }
 
     pub(crate) async fn get_aws_k8s_info() -> Result<AwsK8sInfo> {
         WrongVariant.fail()
     }
+
+    pub(crate) async fn get_service_ipv6_cidr() -> Result<Option<String>> {
+        WrongVariant.fail()
+    }
 }
@@ -20,6 +20,11 @@ It uses the Bottlerocket API to get information such as:
 
 - Kubernetes Cluster Name
 - AWS Region
+- Service IPV6 CIDR
+
+For IPv6 clusters, `settings.kubernetes.service-ipv6-cidr` should be set.
+The node IP is then the first IPv6 address of the primary network interface, and the cluster DNS IP is computed from the service IPv6 CIDR.
+Otherwise, the node IP is the local IPv4 address, or the IPv6 address if the node has no IPv4 address.
 
 # Interface
 
@@ -40,6 +45,7 @@ use imdsclient::ImdsClient;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::net::Ipv6Addr;
 use std::string::String;
 use std::{env, process};
 
@@ -51,7 +57,7 @@ const DEFAULT_10_RANGE_DNS_CLUSTER_IP: &str = "172.20.0.10";
 const ENI_MAX_PODS_PATH: &str = "/usr/share/eks/eni-max-pods";
 
 mod error {
-    use crate::eks;
+    use crate::{api, eks};
     use snafu::Snafu;
 
     #[derive(Debug, Snafu)]
@@ -88,6 +94,9 @@ mod error {
         #[snafu(display("{}", source))]
         EksError { source: eks::Error },
 
+        #[snafu(display("{}", source))]
+        ApiError { source: api::Error },
+
         #[snafu(display("Failed to open eni-max-pods file at {}: {}", path, source))]
         EniMaxPodsFile {
             path: &'static str,
@@ -144,11 +153,20 @@ async fn get_max_pods(client: &mut ImdsClient) -> Result<String> {
     error::NoInstanceTypeMaxPods { instance_type }.fail()
 }
 
-/// Returns the cluster's DNS IPV4 address. First it attempts to call EKS describe-cluster to find
+/// Returns the cluster's DNS IP address.  For IPv6 clusters, it's computed from the service IPv6
+/// CIDR in settings.  Otherwise, it first attempts to call EKS describe-cluster to find
 /// the `serviceIPv4CIDR`. If that works, it returns the expected cluster DNS IP address which is
 /// obtained by substituting `10` for the last octet. If the EKS call is not successful, it falls
 /// back to using IMDS MAC CIDR blocks to return one of two default addresses.
 async fn get_cluster_dns_ip(client: &mut ImdsClient) -> Result<String> {
+    // IPv6 clusters use an address in the service IPv6 CIDR given in settings.
+    if let Some(cidr) = api::get_service_ipv6_cidr()
+        .await
+        .context(error::ApiError)?
+    {
+        return get_dns_from_cidr(&cidr);
+    }
+
     // try calling eks describe-cluster to figure out the dns cluster ip
     if let Some(dns_ip) = get_dns_from_eks().await {
         // we were able to calculate the dns ip from the cidr range we received from eks
@@ -188,7 +206,13 @@ async fn get_dns_from_eks() -> Option<String> {
 /// DNS_CLUSTER_IP=${SERVICE_IPV4_CIDR%.*}.10
 /// ```
 /// [this]: https://github.com/awslabs/amazon-eks-ami/blob/732b6b2/files/bootstrap.sh#L335
+///
+/// For IPv6 CIDRs, it replicates the EKS AMI's `${SERVICE_IPV6_CIDR%/*}a`, using the address with
+/// `a` in the host part of the network.
 fn get_dns_from_cidr(cidr: &str) -> Result<String> {
+    if cidr.contains(':') {
+        return get_ipv6_dns_from_cidr(cidr);
+    }
     let mut split: Vec<&str> = cidr.split('.').collect();
     ensure!(
         split.len() == 4,
@@ -201,6 +225,43 @@ fn get_dns_from_cidr(cidr: &str) -> Result<String> {
     Ok(split.join("."))
 }
 
+fn get_ipv6_dns_from_cidr(cidr: &str) -> Result<String> {
+    let mut split = cidr.splitn(2, '/');
+    let address = split.next().unwrap_or_default();
+    let prefix = split.next().context(error::CidrParse {
+        cidr,
+        reason: "expected address/prefix",
+    })?;
+    let address: Ipv6Addr = address.parse().or_else(|e| {
+        error::CidrParse {
+            cidr,
+            reason: format!("invalid address: {}", e),
+        }
+        .fail()
+    })?;
+    let prefix: u32 = prefix.parse().or_else(|e| {
+        error::CidrParse {
+            cidr,
+            reason: format!("invalid prefix: {}", e),
+        }
+        .fail()
+    })?;
+    ensure!(
+        prefix <= 124,
+        error::CidrParse {
+            cidr,
+            reason: format!("prefix {} leaves no room for host addresses", prefix)
+        }
+    );
+    let mask = if prefix == 0 {
+        0
+    } else {
+        u128::MAX << (128 - prefix)
+    };
+    let dns = (u128::from(address) & mask) | 0xa;
+    Ok(Ipv6Addr::from(dns).to_string())
+}
+
 /// Gets gets the the first VPC IPV4 CIDR block from IMDS. If it starts with `10`, returns
 /// `10.100.0.10`, otherwise returns `172.20.0.10`
 async fn get_cluster_dns_from_imds_mac(client: &mut ImdsClient) -> Result<String> {
@@ -242,12 +303,29 @@ async fn get_cluster_dns_from_imds_mac(client: &mut ImdsClient) -> Result<String
     Ok(dns)
 }
 
+/// Returns the node's IP address: the local IPv4 address, or the primary IPv6 address if the node
+/// doesn't have an IPv4 address.  Only a node with both asks the API whether it's in an IPv6
+/// cluster, which uses the IPv6 address.
 async fn get_node_ip(client: &mut ImdsClient) -> Result<String> {
-    client
+    let ipv6 = client
+        .fetch_primary_ipv6_address()
+        .await
+        .context(error::ImdsRequest)?;
+    let ipv4 = client
         .fetch_local_ipv4_address()
         .await
-        .context(error::ImdsRequest)?
-        .context(error::ImdsNone { what: "node ip" })
+        .context(error::ImdsRequest)?;
+    match (ipv4, ipv6) {
+        (Some(ipv4), Some(ipv6)) => {
+            let ipv6_cluster = api::get_service_ipv6_cidr()
+                .await
+                .context(error::ApiError)?
+                .is_some();
+            Ok(if ipv6_cluster { ipv6 } else { ipv4 })
+        }
+        (Some(ip), None) | (None, Some(ip)) => Ok(ip),
+        (None, None) => error::ImdsNone { what: "node ip" }.fail(),
+    }
 }
 
 /// Print usage message.
@@ -320,6 +398,25 @@ fn test_get_dns_from_cidr_ok() {
     assert_eq!(expected, actual);
 }
 
+#[test]
+fn test_get_dns_from_ipv6_cidr_ok() {
+    let input = "fd30:1c53:5f8a::/108";
+    let expected = "fd30:1c53:5f8a::a";
+    let actual = get_dns_from_cidr(input).unwrap();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_get_dns_from_ipv6_cidr_err() {
+    for input in &[
+        "fd30:1c53:5f8a::",
+        "fd30:1c53:5f8a::/128",
+        "fd30:1c53:zzzz::/108",
+    ] {
+        assert!(get_dns_from_cidr(input).is_err());
+    }
+}
+
 #[test]
 fn test_get_dns_from_cidr_err() {
     let input = "123_456_789_0/123";
@@ -0,0 +1,448 @@
+//! The ecs module drains the container instance through the ECS API, finding out which container
+//! instance we are from the ECS agent's introspection API.
+
+use log::{debug, info, trace};
+use rusoto_core::region::ParseRegionError;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{Region, RusotoError};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+pub(crate) const ACTIVE: &str = "ACTIVE";
+pub(crate) const DRAINING: &str = "DRAINING";
+
+const AGENT_INTROSPECTION_URL: &str = "http://localhost:51678";
+const TARGET_PREFIX: &str = "AmazonEC2ContainerServiceV20141113";
+// ECS sets `startedBy` to this prefix for tasks it starts on behalf of a service.
+const SERVICE_TASK_PREFIX: &str = "ecs-svc/";
+// DescribeTasks accepts at most this many tasks at once.
+const DESCRIBE_TASKS_LIMIT: usize = 100;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Snafu)]
+pub(crate) enum Error {
+    #[snafu(display("Error querying ECS agent at {}: {}", uri, source))]
+    AgentRequest { uri: String, source: reqwest::Error },
+
+    #[snafu(display("Unable to parse response from ECS agent at {}: {}", uri, source))]
+    AgentResponseJson {
+        uri: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error calling ECS {}: {}", action, response_body))]
+    ApiResponse {
+        action: String,
+        response_body: String,
+    },
+
+    #[snafu(display("Error sending ECS {} request: {}", action, source))]
+    Dispatch {
+        action: String,
+        // We only ever see credential and HTTP errors here; service errors are in the body.
+        source: RusotoError<Infallible>,
+    },
+
+    #[snafu(display("The '{}' setting is missing", setting))]
+    Missing { setting: &'static str },
+
+    #[snafu(display("Container instance '{}' not found", container_instance))]
+    MissingContainerInstance { container_instance: String },
+
+    #[snafu(display("Unable to parse '{}' as a region: {}", region, source))]
+    RegionParse {
+        region: String,
+        source: ParseRegionError,
+    },
+
+    #[snafu(display("Unable to parse response to ECS {}: {}", action, source))]
+    ResponseJson {
+        action: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Timed out after {}s waiting for service tasks to stop: {}",
+        timeout.as_secs(),
+        tasks.join(", ")
+    ))]
+    Timeout {
+        timeout: Duration,
+        tasks: Vec<String>,
+    },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The container instance the ECS agent registered, from its metadata introspection endpoint.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ContainerInstance {
+    pub(crate) cluster: String,
+    pub(crate) container_instance_arn: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DescribeContainerInstancesResponse {
+    container_instances: Vec<ContainerInstanceStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContainerInstanceStatus {
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListTasksResponse {
+    task_arns: Vec<String>,
+    next_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DescribeTasksResponse {
+    tasks: Vec<Task>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Task {
+    task_arn: String,
+    started_by: Option<String>,
+}
+
+/// A minimal client for the ECS API calls needed to drain a container instance.
+pub(crate) struct EcsClient {
+    client: rusoto_core::Client,
+    region: Region,
+    agent_url: String,
+}
+
+impl EcsClient {
+    pub(crate) fn new<S>(client: rusoto_core::Client, region: Region, agent_url: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            client,
+            region,
+            agent_url: agent_url.into(),
+        }
+    }
+
+    /// Creates a client for the host's region using the default AWS credentials.
+    pub(crate) fn from_settings(settings: &crate::Settings) -> Result<Self> {
+        let region = settings
+            .aws
+            .as_ref()
+            .and_then(|aws| aws.region.as_ref())
+            .context(Missing {
+                setting: "aws.region",
+            })?;
+        let region = Region::from_str(region).context(RegionParse {
+            region: region.as_ref(),
+        })?;
+        Ok(Self::new(
+            rusoto_core::Client::shared(),
+            region,
+            AGENT_INTROSPECTION_URL,
+        ))
+    }
+
+    /// Asks the ECS agent which container instance we are.
+    pub(crate) async fn agent_metadata(&self) -> Result<ContainerInstance> {
+        let uri = format!("{}/v1/metadata", self.agent_url);
+        trace!("GETing {}", uri);
+        let response_body = reqwest::get(&uri)
+            .await
+            .and_then(|response| response.error_for_status())
+            .context(AgentRequest { uri: &uri })?
+            .text()
+            .await
+            .context(AgentRequest { uri: &uri })?;
+        serde_json::from_str(&response_body).context(AgentResponseJson { uri })
+    }
+
+    /// Calls an action of the ECS JSON API, returning the deserialized response.
+    async fn call<T>(&self, action: &str, body: serde_json::Value) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        trace!("Calling ECS {}: {}", action, body);
+        let mut request = SignedRequest::new("POST", "ecs", &self.region, "/");
+        request.set_content_type("application/x-amz-json-1.1".to_string());
+        request.add_header("x-amz-target", &format!("{}.{}", TARGET_PREFIX, action));
+        request.set_payload(Some(body.to_string()));
+
+        let mut response = self
+            .client
+            .sign_and_dispatch(request)
+            .await
+            .map_err(RusotoError::from)
+            .context(Dispatch { action })?;
+        let response = response
+            .buffer()
+            .await
+            .map_err(RusotoError::from)
+            .context(Dispatch { action })?;
+        ensure!(
+            response.status.is_success(),
+            ApiResponse {
+                action,
+                response_body: String::from_utf8_lossy(&response.body),
+            }
+        );
+        serde_json::from_slice(&response.body).context(ResponseJson { action })
+    }
+
+    /// Returns the status of the container instance, like ACTIVE or DRAINING.
+    pub(crate) async fn status(&self, cluster: &str, container_instance: &str) -> Result<String> {
+        let response: DescribeContainerInstancesResponse = self
+            .call(
+                "DescribeContainerInstances",
+                json!({ "cluster": cluster, "containerInstances": [container_instance] }),
+            )
+            .await?;
+        response
+            .container_instances
+            .into_iter()
+            .next()
+            .map(|instance| instance.status)
+            .context(MissingContainerInstance { container_instance })
+    }
+
+    /// Sets the status of the container instance, like ACTIVE or DRAINING.
+    pub(crate) async fn update_state(
+        &self,
+        cluster: &str,
+        container_instance: &str,
+        status: &str,
+    ) -> Result<()> {
+        debug!("Setting container instance status to {}", status);
+        let _: serde_json::Value = self
+            .call(
+                "UpdateContainerInstancesState",
+                json!({
+                    "cluster": cluster,
+                    "containerInstances": [container_instance],
+                    "status": status,
+                }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Lists the running tasks on the container instance that were started by a service.  ECS
+    /// moves these elsewhere when the instance is draining, but leaves standalone tasks alone, so
+    /// there's no point waiting for the latter.
+    async fn service_tasks(&self, cluster: &str, container_instance: &str) -> Result<Vec<String>> {
+        let mut task_arns = Vec::new();
+        let mut next_token = None;
+        loop {
+            let mut request = json!({
+                "cluster": cluster,
+                "containerInstance": container_instance,
+                "desiredStatus": "RUNNING",
+            });
+            if let Some(token) = next_token {
+                request["nextToken"] = json!(token);
+            }
+            let response: ListTasksResponse = self.call("ListTasks", request).await?;
+            task_arns.extend(response.task_arns);
+            next_token = response.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        let mut service_tasks = Vec::new();
+        for chunk in task_arns.chunks(DESCRIBE_TASKS_LIMIT) {
+            let response: DescribeTasksResponse = self
+                .call(
+                    "DescribeTasks",
+                    json!({ "cluster": cluster, "tasks": chunk }),
+                )
+                .await?;
+            service_tasks.extend(
+                response
+                    .tasks
+                    .into_iter()
+                    .filter(|task| {
+                        matches!(task.started_by.as_deref(),
+                            Some(started_by) if started_by.starts_with(SERVICE_TASK_PREFIX))
+                    })
+                    .map(|task| task.task_arn),
+            );
+        }
+        Ok(service_tasks)
+    }
+
+    /// Waits for the service tasks on the container instance to stop, up to the given timeout.
+    pub(crate) async fn wait_for_service_tasks(
+        &self,
+        cluster: &str,
+        container_instance: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let tasks = self.service_tasks(cluster, container_instance).await?;
+            if tasks.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Timeout { timeout, tasks }.fail();
+            }
+            info!("Waiting for {} service tasks to stop", tasks.len());
+            tokio::time::sleep(POLL_INTERVAL.min(timeout)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+    use rusoto_core::credential::StaticProvider;
+    use rusoto_core::HttpClient;
+
+    const CLUSTER: &str = "test-cluster";
+    const INSTANCE: &str = "arn:aws:ecs:us-west-2:123456789012:container-instance/test-cluster/abc";
+
+    fn client(server: &Server) -> EcsClient {
+        let url = format!("http://localhost:{}", server.addr().port());
+        let client = rusoto_core::Client::new_with(
+            StaticProvider::new_minimal("key".to_string(), "secret".to_string()),
+            HttpClient::new().unwrap(),
+        );
+        let region = Region::Custom {
+            name: "us-west-2".to_string(),
+            endpoint: url.clone(),
+        };
+        EcsClient::new(client, region, url)
+    }
+
+    fn action(name: &str) -> impl Matcher<httptest::http::Request<httptest::bytes::Bytes>> {
+        all_of![
+            request::method_path("POST", "/"),
+            request::headers(contains((
+                "x-amz-target",
+                format!("{}.{}", TARGET_PREFIX, name)
+            ))),
+        ]
+    }
+
+    #[tokio::test]
+    async fn agent_metadata() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/v1/metadata"))
+                .times(1)
+                .respond_with(json_encoded(json!({
+                    "Cluster": CLUSTER,
+                    "ContainerInstanceArn": INSTANCE,
+                    "Version": "Amazon ECS Agent - v1.52.2",
+                }))),
+        );
+        let instance = client(&server).agent_metadata().await.unwrap();
+        assert_eq!(instance.cluster, CLUSTER);
+        assert_eq!(instance.container_instance_arn, INSTANCE);
+    }
+
+    #[tokio::test]
+    async fn update_state() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                action("UpdateContainerInstancesState"),
+                request::headers(contains(key("authorization"))),
+                request::body(json_decoded(eq(json!({
+                    "cluster": CLUSTER,
+                    "containerInstances": [INSTANCE],
+                    "status": DRAINING,
+                })))),
+            ])
+            .times(1)
+            .respond_with(json_encoded(
+                json!({ "containerInstances": [], "failures": [] }),
+            )),
+        );
+        client(&server)
+            .update_state(CLUSTER, INSTANCE, DRAINING)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn update_state_error() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(action("UpdateContainerInstancesState"))
+                .times(1)
+                .respond_with(status_code(400).body(
+                    r#"{"__type":"ClusterNotFoundException","message":"Cluster not found."}"#,
+                )),
+        );
+        let result = client(&server)
+            .update_state(CLUSTER, INSTANCE, DRAINING)
+            .await;
+        assert!(matches!(result, Err(Error::ApiResponse { .. })));
+    }
+
+    #[tokio::test]
+    async fn wait_for_service_tasks() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(action("ListTasks"))
+                .times(2)
+                .respond_with(cycle![
+                    json_encoded(json!({ "taskArns": ["service-task", "standalone-task"] })),
+                    json_encoded(json!({ "taskArns": ["standalone-task"] })),
+                ]),
+        );
+        server.expect(
+            Expectation::matching(action("DescribeTasks"))
+                .times(2)
+                .respond_with(cycle![
+                    json_encoded(json!({ "tasks": [
+                        { "taskArn": "service-task", "startedBy": "ecs-svc/123" },
+                        { "taskArn": "standalone-task", "startedBy": "someone" },
+                    ]})),
+                    json_encoded(json!({ "tasks": [
+                        { "taskArn": "standalone-task", "startedBy": "someone" },
+                    ]})),
+                ]),
+        );
+        client(&server)
+            .wait_for_service_tasks(CLUSTER, INSTANCE, Duration::from_millis(100))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn wait_for_service_tasks_timeout() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(action("ListTasks"))
+                .times(2)
+                .respond_with(json_encoded(json!({ "taskArns": ["service-task"] }))),
+        );
+        server.expect(
+            Expectation::matching(action("DescribeTasks"))
+                .times(2)
+                .respond_with(json_encoded(json!({ "tasks": [
+                    { "taskArn": "service-task", "startedBy": "ecs-svc/123" },
+                ]}))),
+        );
+        let result = client(&server)
+            .wait_for_service_tasks(CLUSTER, INSTANCE, Duration::from_millis(100))
+            .await;
+        assert!(matches!(result, Err(Error::Timeout { .. })));
+    }
+}
@@ -0,0 +1,790 @@
+//! The kubernetes module cordons and drains the node through the Kubernetes API server, using the
+//! same credentials as kubelet.  It can also check that the node has registered.
+
+use log::{debug, info, trace};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Certificate, Client, Identity, Method, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const CLUSTER_CA: &str = "/etc/kubernetes/pki/ca.crt";
+const KUBELET_CLIENT_CERT: &str = "/var/lib/kubelet/pki/kubelet-client-current.pem";
+const AWS_IAM_AUTHENTICATOR: &str = "/usr/bin/aws-iam-authenticator";
+const MIRROR_POD_ANNOTATION: &str = "kubernetes.io/config.mirror";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Snafu)]
+pub(crate) enum Error {
+    #[snafu(display("Unable to run {}: {}", AWS_IAM_AUTHENTICATOR, source))]
+    AuthenticatorCommand { source: std::io::Error },
+
+    #[snafu(display("{} failed: {}", AWS_IAM_AUTHENTICATOR, stderr))]
+    AuthenticatorFailure { stderr: String },
+
+    #[snafu(display("Unable to parse {} output: {}", AWS_IAM_AUTHENTICATOR, source))]
+    AuthenticatorOutput { source: serde_json::Error },
+
+    #[snafu(display("Unable to create HTTP client: {}", source))]
+    ClientBuild { source: reqwest::Error },
+
+    #[snafu(display("Unable to read '{}': {}", path, source))]
+    FileRead {
+        path: &'static str,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid certificate in '{}': {}", path, source))]
+    InvalidCertificate {
+        path: &'static str,
+        source: reqwest::Error,
+    },
+
+    #[snafu(display("The '{}' setting is missing", setting))]
+    Missing { setting: &'static str },
+
+    #[snafu(display("No Kubernetes node has the internal IP {}", ip))]
+    NodeNotFound { ip: IpAddr },
+
+    #[snafu(display("Error {}ing {}: {}", method, uri, source))]
+    Request {
+        method: Method,
+        uri: String,
+        source: reqwest::Error,
+    },
+
+    #[snafu(display("Error {} when {}ing {}: {}", code, method, uri, response_body))]
+    Response {
+        method: Method,
+        uri: String,
+        code: StatusCode,
+        response_body: String,
+    },
+
+    #[snafu(display(
+        "Pods not managed by a controller would be lost, set drain-force to evict them anyway: {}",
+        pods.join(", ")
+    ))]
+    Unmanaged { pods: Vec<String> },
+
+    #[snafu(display("Unable to parse response from {}: {}", uri, source))]
+    ResponseJson {
+        uri: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Timed out after {}s waiting for pods to be evicted: {}",
+        timeout.as_secs(),
+        pods.join(", ")
+    ))]
+    Timeout {
+        timeout: Duration,
+        pods: Vec<String>,
+    },
+
+    #[snafu(display("Unsupported authentication mode '{}'", mode))]
+    UnsupportedAuthenticationMode { mode: String },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The parts of the Kubernetes API objects that we look at.
+#[derive(Debug, Deserialize)]
+struct NodeList {
+    items: Vec<Node>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    #[serde(default)]
+    metadata: NodeMetadata,
+    #[serde(default)]
+    spec: NodeSpec,
+    #[serde(default)]
+    status: NodeStatus,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NodeMetadata {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NodeSpec {
+    #[serde(default)]
+    unschedulable: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NodeStatus {
+    #[serde(default)]
+    conditions: Vec<NodeCondition>,
+    #[serde(default)]
+    addresses: Vec<NodeAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeAddress {
+    #[serde(rename = "type")]
+    address_type: String,
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeCondition {
+    #[serde(rename = "type")]
+    condition_type: String,
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PodList {
+    items: Vec<Pod>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pod {
+    metadata: PodMetadata,
+    #[serde(default)]
+    status: PodStatus,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PodMetadata {
+    name: String,
+    namespace: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(default)]
+    owner_references: Vec<OwnerReference>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OwnerReference {
+    kind: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PodStatus {
+    phase: Option<String>,
+}
+
+impl Pod {
+    /// Pods managed by a DaemonSet would be recreated on the node right away, and static pods
+    /// can't be evicted through the API at all, so we leave both behind.  Pods that have already
+    /// finished don't need to go anywhere.
+    fn needs_eviction(&self) -> bool {
+        let finished = matches!(
+            self.status.phase.as_deref(),
+            Some("Succeeded") | Some("Failed")
+        );
+        let daemonset = self
+            .metadata
+            .owner_references
+            .iter()
+            .any(|owner| owner.kind == "DaemonSet");
+        let mirror = self
+            .metadata
+            .annotations
+            .contains_key(MIRROR_POD_ANNOTATION);
+        !(finished || daemonset || mirror)
+    }
+
+    /// Pods without an owner aren't recreated anywhere once they're evicted, so like `kubectl
+    /// drain`, we only evict them if forced to.
+    fn unmanaged(&self) -> bool {
+        self.metadata.owner_references.is_empty()
+    }
+
+    fn id(&self) -> String {
+        format!("{}/{}", self.metadata.namespace, self.metadata.name)
+    }
+}
+
+/// The subset of kubelet's ExecCredential output we need.
+#[derive(Debug, Deserialize)]
+struct ExecCredential {
+    status: ExecCredentialStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExecCredentialStatus {
+    token: String,
+}
+
+/// How we prove who we are to the API server.
+pub(crate) enum Credentials {
+    /// A bearer token, as generated by aws-iam-authenticator.
+    Token(String),
+    /// A PEM file containing a client certificate and its key.
+    ClientCertificate(Vec<u8>),
+}
+
+/// A minimal client for the Kubernetes API calls needed to drain a node.
+pub(crate) struct KubeClient {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl KubeClient {
+    /// Creates a client for the given API server, trusting the given PEM-encoded CA certificate
+    /// in addition to the usual roots.
+    pub(crate) fn new<S>(
+        base_url: S,
+        ca_certificate: Option<&[u8]>,
+        credentials: Credentials,
+    ) -> Result<Self>
+    where
+        S: Into<String>,
+    {
+        let mut builder = Client::builder();
+        if let Some(pem) = ca_certificate {
+            let certificate =
+                Certificate::from_pem(pem).context(InvalidCertificate { path: CLUSTER_CA })?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        let token = match credentials {
+            Credentials::Token(token) => Some(token),
+            Credentials::ClientCertificate(pem) => {
+                let identity = Identity::from_pem(&pem).context(InvalidCertificate {
+                    path: KUBELET_CLIENT_CERT,
+                })?;
+                builder = builder.identity(identity);
+                None
+            }
+        };
+        let client = builder.build().context(ClientBuild)?;
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Ok(Self {
+            client,
+            base_url,
+            token,
+        })
+    }
+
+    /// Creates a client that authenticates the same way as kubelet's kubeconfig.
+    pub(crate) fn from_settings(k8s: &model::KubernetesSettings) -> Result<Self> {
+        let api_server = k8s.api_server.as_ref().context(Missing {
+            setting: "api-server",
+        })?;
+        let mode = k8s.authentication_mode.as_deref().unwrap_or("aws");
+        let credentials = match mode {
+            "aws" => {
+                let cluster_name = k8s.cluster_name.as_ref().context(Missing {
+                    setting: "cluster-name",
+                })?;
+                Credentials::Token(aws_iam_token(cluster_name)?)
+            }
+            "tls" => {
+                Credentials::ClientCertificate(fs::read(KUBELET_CLIENT_CERT).context(FileRead {
+                    path: KUBELET_CLIENT_CERT,
+                })?)
+            }
+            _ => {
+                return UnsupportedAuthenticationMode {
+                    mode: mode.to_string(),
+                }
+                .fail()
+            }
+        };
+        let ca_certificate = fs::read(CLUSTER_CA).context(FileRead { path: CLUSTER_CA })?;
+        Self::new(api_server.to_string(), Some(&ca_certificate), credentials)
+    }
+
+    /// Sends a request to the API server, returning the status code and body.  Failures other
+    /// than the listed acceptable status codes are returned as errors.
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        content_type: &str,
+        body: Option<serde_json::Value>,
+        acceptable: &[StatusCode],
+    ) -> Result<(StatusCode, String)> {
+        let uri = format!("{}{}", self.base_url, path);
+        trace!("{}ing {}", method, uri);
+        let mut request = self.client.request(method.clone(), &uri);
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, content_type)
+                .body(body.to_string());
+        }
+        let response = request.send().await.context(Request {
+            method: method.clone(),
+            uri: &uri,
+        })?;
+        let code = response.status();
+        let response_body = response.text().await.context(Request {
+            method: method.clone(),
+            uri: &uri,
+        })?;
+        ensure!(
+            code.is_success() || acceptable.contains(&code),
+            Response {
+                method,
+                uri,
+                code,
+                response_body,
+            }
+        );
+        Ok((code, response_body))
+    }
+
+    /// Finds the name of the node with the given internal IP.  Kubelet is given the node IP, but
+    /// the name it registers under depends on the cloud provider, like the private DNS name on
+    /// AWS, and on the hostname when kubelet started, so the IP is what reliably identifies it.
+    pub(crate) async fn node_name(&self, node_ip: &IpAddr) -> Result<String> {
+        let path = "/api/v1/nodes";
+        let (_, body) = self.request(Method::GET, path, "", None, &[]).await?;
+        let nodes: NodeList = serde_json::from_str(&body).context(ResponseJson { uri: path })?;
+        nodes
+            .items
+            .into_iter()
+            .find(|node| {
+                node.status.addresses.iter().any(|address| {
+                    address.address_type == "InternalIP"
+                        && address.address.parse::<IpAddr>().ok().as_ref() == Some(node_ip)
+                })
+            })
+            .map(|node| node.metadata.name)
+            .context(NodeNotFound { ip: *node_ip })
+    }
+
+    async fn get_node(&self, node: &str) -> Result<Node> {
+        let path = format!("/api/v1/nodes/{}", node);
+        let (_, body) = self.request(Method::GET, &path, "", None, &[]).await?;
+        serde_json::from_str(&body).context(ResponseJson { uri: path })
+    }
+
+    async fn set_unschedulable(&self, node: &str, unschedulable: bool) -> Result<()> {
+        let path = format!("/api/v1/nodes/{}", node);
+        let patch = json!({ "spec": { "unschedulable": unschedulable } });
+        self.request(
+            Method::PATCH,
+            &path,
+            "application/strategic-merge-patch+json",
+            Some(patch),
+            &[],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Marks the node unschedulable.  Returns false if it already was, so the caller knows
+    /// somebody else wanted it that way.
+    pub(crate) async fn cordon(&self, node: &str) -> Result<bool> {
+        if self.get_node(node).await?.spec.unschedulable {
+            return Ok(false);
+        }
+        debug!("Cordoning node '{}'", node);
+        self.set_unschedulable(node, true).await?;
+        Ok(true)
+    }
+
+    /// Returns whether the node has registered with the API server and is ready for pods.
+    pub(crate) async fn node_ready(&self, node: &str) -> Result<bool> {
+        let node = self.get_node(node).await?;
+        Ok(node
+            .status
+            .conditions
+            .iter()
+            .any(|condition| condition.condition_type == "Ready" && condition.status == "True"))
+    }
+
+    /// Marks the node schedulable again.
+    pub(crate) async fn uncordon(&self, node: &str) -> Result<()> {
+        debug!("Uncordoning node '{}'", node);
+        self.set_unschedulable(node, false).await
+    }
+
+    /// Lists the pods on the node that still need to be evicted.
+    async fn pods_to_evict(&self, node: &str) -> Result<Vec<Pod>> {
+        let path = format!("/api/v1/pods?fieldSelector=spec.nodeName%3D{}", node);
+        let (_, body) = self.request(Method::GET, &path, "", None, &[]).await?;
+        let pods: PodList = serde_json::from_str(&body).context(ResponseJson { uri: path })?;
+        Ok(pods
+            .items
+            .into_iter()
+            .filter(|pod| pod.needs_eviction())
+            .collect())
+    }
+
+    /// Asks the API server to evict the pod.  Returns false if the eviction is blocked for now,
+    /// usually by a PodDisruptionBudget, and should be retried.
+    async fn evict(&self, pod: &Pod) -> Result<bool> {
+        let path = format!(
+            "/api/v1/namespaces/{}/pods/{}/eviction",
+            pod.metadata.namespace, pod.metadata.name
+        );
+        let eviction = json!({
+            "apiVersion": "policy/v1beta1",
+            "kind": "Eviction",
+            "metadata": {
+                "name": pod.metadata.name,
+                "namespace": pod.metadata.namespace,
+            },
+        });
+        let (code, _) = self
+            .request(
+                Method::POST,
+                &path,
+                "application/json",
+                Some(eviction),
+                &[StatusCode::NOT_FOUND, StatusCode::TOO_MANY_REQUESTS],
+            )
+            .await?;
+        Ok(code != StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    /// Evicts the pods on the node and waits for them to be gone, up to the given timeout.  Unless
+    /// `force` is set, nothing is evicted if any of the pods aren't managed by a controller.
+    pub(crate) async fn evict_pods(
+        &self,
+        node: &str,
+        timeout: Duration,
+        force: bool,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let pods = self.pods_to_evict(node).await?;
+            if pods.is_empty() {
+                return Ok(());
+            }
+            if !force {
+                let unmanaged: Vec<_> = pods.iter().filter(|pod| pod.unmanaged()).collect();
+                ensure!(
+                    unmanaged.is_empty(),
+                    Unmanaged {
+                        pods: unmanaged.into_iter().map(Pod::id).collect::<Vec<_>>(),
+                    }
+                );
+            }
+            if Instant::now() >= deadline {
+                return Timeout {
+                    timeout,
+                    pods: pods.iter().map(Pod::id).collect::<Vec<_>>(),
+                }
+                .fail();
+            }
+
+            // Evicting a pod that's already terminating is harmless, so we don't track which ones
+            // we've asked for; we just keep asking until they're gone.
+            for pod in &pods {
+                if self.evict(pod).await? {
+                    debug!("Requested eviction of pod '{}'", pod.id());
+                } else {
+                    info!("Eviction of pod '{}' blocked, will retry", pod.id());
+                }
+            }
+            tokio::time::sleep(POLL_INTERVAL.min(timeout)).await;
+        }
+    }
+}
+
+/// Generates a token for the cluster the same way kubelet's kubeconfig does.
+fn aws_iam_token(cluster_name: &str) -> Result<String> {
+    let output = Command::new(AWS_IAM_AUTHENTICATOR)
+        .args(["token", "-i", cluster_name])
+        .output()
+        .context(AuthenticatorCommand)?;
+    ensure!(
+        output.status.success(),
+        AuthenticatorFailure {
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+    );
+    let credential: ExecCredential =
+        serde_json::from_slice(&output.stdout).context(AuthenticatorOutput)?;
+    Ok(credential.status.token)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    fn client(server: &Server) -> KubeClient {
+        let base_url = format!("http://localhost:{}", server.addr().port());
+        KubeClient::new(base_url, None, Credentials::Token("sometoken".to_string())).unwrap()
+    }
+
+    fn pod(name: &str, owner: &str) -> serde_json::Value {
+        json!({
+            "metadata": {
+                "name": name,
+                "namespace": "default",
+                "ownerReferences": [{ "kind": owner, "name": "owner" }],
+            },
+            "status": { "phase": "Running" },
+        })
+    }
+
+    #[tokio::test]
+    async fn cordon_node() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("GET", "/api/v1/nodes/node1"),
+                request::headers(contains(("authorization", "Bearer sometoken"))),
+            ])
+            .times(1)
+            .respond_with(json_encoded(json!({ "spec": {} }))),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("PATCH", "/api/v1/nodes/node1"),
+                request::headers(contains((
+                    "content-type",
+                    "application/strategic-merge-patch+json"
+                ))),
+                request::body(json_decoded(eq(
+                    json!({ "spec": { "unschedulable": true } })
+                ))),
+            ])
+            .times(1)
+            .respond_with(status_code(200)),
+        );
+        assert!(client(&server).cordon("node1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn node_name_from_internal_ip() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/nodes"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "items": [
+                    {
+                        "metadata": { "name": "myhost" },
+                        "status": { "addresses": [
+                            { "type": "InternalIP", "address": "10.0.0.2" },
+                            { "type": "Hostname", "address": "myhost" },
+                        ]},
+                    },
+                    {
+                        "metadata": { "name": "ip-10-0-0-1.us-west-2.compute.internal" },
+                        "status": { "addresses": [
+                            { "type": "InternalIP", "address": "10.0.0.1" },
+                            { "type": "Hostname", "address": "myhost" },
+                        ]},
+                    },
+                ]}))),
+        );
+        let node = client(&server)
+            .node_name(&"10.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(node, "ip-10-0-0-1.us-west-2.compute.internal");
+    }
+
+    #[tokio::test]
+    async fn node_name_not_found() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/nodes"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "items": [
+                    {
+                        "metadata": { "name": "other" },
+                        "status": { "addresses": [
+                            { "type": "ExternalIP", "address": "10.0.0.1" },
+                        ]},
+                    },
+                ]}))),
+        );
+        assert!(client(&server)
+            .node_name(&"10.0.0.1".parse().unwrap())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn node_ready() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/nodes/node1"))
+                .times(1)
+                .respond_with(json_encoded(json!({
+                    "spec": {},
+                    "status": { "conditions": [
+                        { "type": "MemoryPressure", "status": "False" },
+                        { "type": "Ready", "status": "True" },
+                    ]},
+                }))),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/nodes/node2"))
+                .times(1)
+                .respond_with(json_encoded(json!({
+                    "spec": {},
+                    "status": { "conditions": [{ "type": "Ready", "status": "Unknown" }] },
+                }))),
+        );
+        let client = client(&server);
+        assert!(client.node_ready("node1").await.unwrap());
+        assert!(!client.node_ready("node2").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn cordon_already_cordoned() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/nodes/node1"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "spec": { "unschedulable": true } }))),
+        );
+        assert!(!client(&server).cordon("node1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn evict_pods() {
+        let server = Server::run();
+        let pods = json!({
+            "items": [
+                pod("web", "ReplicaSet"),
+                pod("proxy", "DaemonSet"),
+                {
+                    "metadata": {
+                        "name": "static",
+                        "namespace": "kube-system",
+                        "annotations": { "kubernetes.io/config.mirror": "hash" },
+                    },
+                },
+            ]
+        });
+        let remaining = json!({ "items": [pod("proxy", "DaemonSet")] });
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("GET", "/api/v1/pods"),
+                request::query(url_decoded(contains((
+                    "fieldSelector",
+                    "spec.nodeName=node1"
+                )))),
+            ])
+            .times(2)
+            .respond_with(cycle![json_encoded(pods), json_encoded(remaining)]),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/api/v1/namespaces/default/pods/web/eviction"),
+                request::body(json_decoded(eq(json!({
+                    "apiVersion": "policy/v1beta1",
+                    "kind": "Eviction",
+                    "metadata": { "name": "web", "namespace": "default" },
+                })))),
+            ])
+            .times(1)
+            .respond_with(status_code(201)),
+        );
+        client(&server)
+            .evict_pods("node1", Duration::from_millis(100), false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn evict_pods_timeout() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/pods"))
+                .times(2)
+                .respond_with(json_encoded(json!({ "items": [pod("web", "ReplicaSet")] }))),
+        );
+        // Blocked by a PodDisruptionBudget.
+        server.expect(
+            Expectation::matching(request::method_path(
+                "POST",
+                "/api/v1/namespaces/default/pods/web/eviction",
+            ))
+            .times(1)
+            .respond_with(status_code(429)),
+        );
+        let result = client(&server)
+            .evict_pods("node1", Duration::from_millis(100), false)
+            .await;
+        assert!(matches!(result, Err(Error::Timeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn evict_forbidden() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/pods"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "items": [pod("web", "ReplicaSet")] }))),
+        );
+        server.expect(
+            Expectation::matching(request::method_path(
+                "POST",
+                "/api/v1/namespaces/default/pods/web/eviction",
+            ))
+            .times(1)
+            .respond_with(status_code(403)),
+        );
+        let result = client(&server)
+            .evict_pods("node1", Duration::from_secs(10), false)
+            .await;
+        assert!(matches!(result, Err(Error::Response { .. })));
+    }
+
+    #[tokio::test]
+    async fn evict_unmanaged() {
+        let server = Server::run();
+        let bare = json!({
+            "metadata": { "name": "bare", "namespace": "default" },
+            "status": { "phase": "Running" },
+        });
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/pods"))
+                .times(1)
+                .respond_with(json_encoded(
+                    json!({ "items": [pod("web", "ReplicaSet"), bare] }),
+                )),
+        );
+        let result = client(&server)
+            .evict_pods("node1", Duration::from_secs(10), false)
+            .await;
+        assert!(matches!(result, Err(Error::Unmanaged { pods }) if pods == ["default/bare"]));
+    }
+
+    #[tokio::test]
+    async fn evict_unmanaged_forced() {
+        let server = Server::run();
+        let bare = json!({
+            "metadata": { "name": "bare", "namespace": "default" },
+            "status": { "phase": "Running" },
+        });
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/pods"))
+                .times(2)
+                .respond_with(cycle![
+                    json_encoded(json!({ "items": [bare] })),
+                    json_encoded(json!({ "items": [] })),
+                ]),
+        );
+        server.expect(
+            Expectation::matching(request::method_path(
+                "POST",
+                "/api/v1/namespaces/default/pods/bare/eviction",
+            ))
+            .times(1)
+            .respond_with(status_code(201)),
+        );
+        client(&server)
+            .evict_pods("node1", Duration::from_millis(100), true)
+            .await
+            .unwrap();
+    }
+}
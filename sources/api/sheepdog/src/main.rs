@@ -0,0 +1,704 @@
+/*!
+# Introduction
+
+sheepdog herds workloads off of the host before it reboots, and lets them back in afterward.
+
+`sheepdog drain` is called by the API server before it reboots the host.
+It does nothing unless draining has been requested for the orchestrator the host is part of:
+
+* If `settings.kubernetes.drain-before-reboot` is true and the host has joined a cluster, the node is cordoned through the Kubernetes API server, using the same credentials as kubelet, and its pods are evicted.
+  DaemonSet pods and static pods are left alone, since they'd come right back.
+  Like `kubectl drain`, sheepdog won't evict pods that aren't managed by a controller, since they'd be lost, unless `settings.kubernetes.drain-force` is true.
+  The node is found by its internal IP, `settings.kubernetes.node-ip`, since the name kubelet registers it under depends on the cloud provider and on the hostname when kubelet started.
+* If `settings.ecs.drain-before-reboot` is true, the container instance is set to `DRAINING` and sheepdog waits for the tasks started by ECS services to be moved elsewhere.
+
+In both cases, sheepdog waits up to `drain-timeout` seconds (default 300) for the workloads to go away.
+If draining fails or times out, the node is returned to its previous state and sheepdog exits with an error, so that the reboot doesn't happen.
+
+`sheepdog restore` runs at boot.
+If the last `sheepdog drain` cordoned the node or set the container instance to `DRAINING`, it's made schedulable again.
+Nodes that were already cordoned by someone else before the reboot are left cordoned.
+
+`sheepdog registered` exits successfully if the host has joined its orchestrator's cluster, meaning the Kubernetes node is registered and `Ready`, or the ECS agent has registered a container instance.
+Hosts that aren't part of a cluster are considered registered.
+update-verifier uses this to check the health of the host after an update.
+
+## Kubernetes permissions
+
+Kubelet's credentials can cordon its own node, but evicting pods also requires permission to create `pods/eviction`.
+If you use drain-before-reboot, grant that to nodes with a `ClusterRole` like this, bound to the `system:nodes` group:
+
+```yaml
+apiVersion: rbac.authorization.k8s.io/v1
+kind: ClusterRole
+metadata:
+  name: bottlerocket-drain
+rules:
+- apiGroups: [""]
+  resources: ["pods/eviction"]
+  verbs: ["create"]
+```
+*/
+
+#![deny(rust_2018_idioms)]
+
+mod ecs;
+mod kubernetes;
+
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, process};
+
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const DRAIN_MARKER: &str = "/var/lib/sheepdog/drained";
+const DEFAULT_DRAIN_TIMEOUT: u32 = 300;
+
+/// Store the args we receive on the command line.
+struct Args {
+    subcommand: String,
+    log_level: LevelFilter,
+    socket_path: String,
+}
+
+/// The settings sheepdog cares about.  Not every variant has every orchestrator, so we pick them
+/// out of the response rather than using the variant's `Settings`.
+#[derive(Debug, Default, Deserialize)]
+struct Settings {
+    aws: Option<model::AwsSettings>,
+    kubernetes: Option<model::KubernetesSettings>,
+    ecs: Option<model::ECSSettings>,
+}
+
+/// Records what `drain` changed, so that `restore` only undoes our own work.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "orchestrator", rename_all = "kebab-case")]
+enum Drained {
+    Kubernetes {
+        node: String,
+    },
+    Ecs {
+        cluster: String,
+        #[serde(rename = "container-instance")]
+        container_instance: String,
+    },
+}
+
+/// Main entry point.
+async fn run() -> Result<()> {
+    let args = parse_args(env::args());
+
+    // SimpleLogger will send errors to stderr and anything less to stdout.
+    SimpleLogger::init(args.log_level, LogConfig::default()).context(error::Logger)?;
+
+    let settings = get_settings(&args.socket_path).await?;
+    match args.subcommand.as_ref() {
+        "drain" => drain(&settings).await,
+        "restore" => restore(&settings).await,
+        "registered" => registered(&settings).await,
+        _ => usage_msg(format!("Unknown subcommand '{}'", args.subcommand)), // should be unreachable
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Moves workloads off of the host, if the user asked for it.
+async fn drain(settings: &Settings) -> Result<()> {
+    if Path::new(DRAIN_MARKER).exists() {
+        // A previous drain didn't reboot, for example because shutdown failed; we'll still need
+        // to restore the original state after the next boot.
+        info!("Host was already drained by sheepdog");
+        return Ok(());
+    }
+
+    if let Some(k8s) = settings
+        .kubernetes
+        .as_ref()
+        .filter(|k8s| k8s.drain_before_reboot == Some(true))
+    {
+        if k8s.api_server.is_none() || k8s.standalone_mode == Some(true) {
+            debug!("Not part of a Kubernetes cluster, nothing to drain");
+            return Ok(());
+        }
+        let client = kubernetes::KubeClient::from_settings(k8s).context(error::Kubernetes)?;
+        let node_ip = k8s.node_ip.as_ref().context(error::MissingNodeIp)?;
+        let node = client.node_name(node_ip).await.context(error::Kubernetes)?;
+        let timeout = drain_timeout(k8s.drain_timeout);
+        let force = k8s.drain_force == Some(true);
+        return drain_kubernetes(&client, &node, timeout, force, DRAIN_MARKER).await;
+    }
+
+    if let Some(ecs) = settings
+        .ecs
+        .as_ref()
+        .filter(|ecs| ecs.drain_before_reboot == Some(true))
+    {
+        let client = ecs::EcsClient::from_settings(settings).context(error::Ecs)?;
+        let timeout = drain_timeout(ecs.drain_timeout);
+        return drain_ecs(&client, timeout, DRAIN_MARKER).await;
+    }
+
+    debug!("Draining before reboot is not enabled");
+    Ok(())
+}
+
+/// Cordons the node and evicts its pods.  If anything goes wrong, the node is uncordoned again.
+async fn drain_kubernetes<P>(
+    client: &kubernetes::KubeClient,
+    node: &str,
+    timeout: Duration,
+    force: bool,
+    marker: P,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let marker = marker.as_ref();
+    info!("Draining Kubernetes node '{}'", node);
+    let cordoned = client.cordon(node).await.context(error::Kubernetes)?;
+    if !cordoned {
+        info!(
+            "Node '{}' was already cordoned, it won't be uncordoned after boot",
+            node
+        );
+    } else {
+        write_marker(
+            marker,
+            &Drained::Kubernetes {
+                node: node.to_string(),
+            },
+        )?;
+    }
+
+    if let Err(e) = client.evict_pods(node, timeout, force).await {
+        if cordoned {
+            warn!("Failed to drain node, uncordoning it");
+            if let Err(e) = client.uncordon(node).await {
+                error!("Failed to uncordon node '{}': {}", node, e);
+            } else {
+                remove_marker(marker)?;
+            }
+        }
+        return Err(e).context(error::Kubernetes);
+    }
+
+    info!("Drained Kubernetes node '{}'", node);
+    Ok(())
+}
+
+/// Sets the container instance to DRAINING and waits for service tasks to move.  If anything goes
+/// wrong, the container instance is set back to ACTIVE, unless it was already draining.
+async fn drain_ecs<P>(client: &ecs::EcsClient, timeout: Duration, marker: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let marker = marker.as_ref();
+    let instance = client.agent_metadata().await.context(error::Ecs)?;
+    let cluster = &instance.cluster;
+    let container_instance = &instance.container_instance_arn;
+    info!(
+        "Draining ECS container instance '{}' in cluster '{}'",
+        container_instance, cluster
+    );
+    let status = client
+        .status(cluster, container_instance)
+        .await
+        .context(error::Ecs)?;
+    let set_draining = status != ecs::DRAINING;
+    if !set_draining {
+        info!("Container instance was already draining, it won't be activated after boot");
+    } else {
+        client
+            .update_state(cluster, container_instance, ecs::DRAINING)
+            .await
+            .context(error::Ecs)?;
+        write_marker(
+            marker,
+            &Drained::Ecs {
+                cluster: cluster.clone(),
+                container_instance: container_instance.clone(),
+            },
+        )?;
+    }
+
+    if let Err(e) = client
+        .wait_for_service_tasks(cluster, container_instance, timeout)
+        .await
+    {
+        if set_draining {
+            warn!("Failed to drain container instance, setting it back to active");
+            if let Err(e) = client
+                .update_state(cluster, container_instance, ecs::ACTIVE)
+                .await
+            {
+                error!("Failed to reactivate container instance: {}", e);
+            } else {
+                remove_marker(marker)?;
+            }
+        }
+        return Err(e).context(error::Ecs);
+    }
+
+    info!("Drained ECS container instance");
+    Ok(())
+}
+
+/// Undoes whatever the last `drain` recorded in the marker file.
+async fn restore(settings: &Settings) -> Result<()> {
+    let drained = match read_marker(DRAIN_MARKER)? {
+        Some(drained) => drained,
+        None => {
+            debug!("Host was not drained by sheepdog, nothing to restore");
+            return Ok(());
+        }
+    };
+
+    match &drained {
+        Drained::Kubernetes { node } => {
+            let k8s = settings
+                .kubernetes
+                .as_ref()
+                .context(error::MissingSettings {
+                    orchestrator: "kubernetes",
+                })?;
+            let client = kubernetes::KubeClient::from_settings(k8s).context(error::Kubernetes)?;
+            info!("Uncordoning Kubernetes node '{}'", node);
+            client.uncordon(node).await.context(error::Kubernetes)?;
+        }
+        Drained::Ecs {
+            cluster,
+            container_instance,
+        } => {
+            let client = ecs::EcsClient::from_settings(settings).context(error::Ecs)?;
+            info!(
+                "Setting ECS container instance '{}' to active",
+                container_instance
+            );
+            client
+                .update_state(cluster, container_instance, ecs::ACTIVE)
+                .await
+                .context(error::Ecs)?;
+        }
+    }
+
+    remove_marker(DRAIN_MARKER)
+}
+
+/// Succeeds if the host has joined its orchestrator's cluster, so callers can tell whether the
+/// host is able to run workloads.
+async fn registered(settings: &Settings) -> Result<()> {
+    if let Some(k8s) = &settings.kubernetes {
+        if k8s.api_server.is_none() || k8s.standalone_mode == Some(true) {
+            debug!("Not part of a Kubernetes cluster, nothing to register with");
+            return Ok(());
+        }
+        let client =
+            kubernetes::KubeClient::from_settings(k8s).context(error::KubernetesRegistration)?;
+        let node_ip = k8s.node_ip.as_ref().context(error::MissingNodeIp)?;
+        let node = client
+            .node_name(node_ip)
+            .await
+            .context(error::KubernetesRegistration)?;
+        let ready = client
+            .node_ready(&node)
+            .await
+            .context(error::KubernetesRegistration)?;
+        ensure!(ready, error::NodeNotReady { node });
+        info!("Kubernetes node '{}' is ready", node);
+        return Ok(());
+    }
+
+    if settings.ecs.is_some() {
+        let client = ecs::EcsClient::from_settings(settings).context(error::EcsRegistration)?;
+        let instance = client
+            .agent_metadata()
+            .await
+            .context(error::EcsRegistration)?;
+        info!(
+            "Registered as ECS container instance '{}' in cluster '{}'",
+            instance.container_instance_arn, instance.cluster
+        );
+        return Ok(());
+    }
+
+    debug!("Not part of a cluster, nothing to register with");
+    Ok(())
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Retrieve the current settings from the API.
+async fn get_settings<P>(socket_path: P) -> Result<Settings>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/settings";
+    let method = "GET";
+    trace!("{}ing from {}", method, uri);
+    let (code, response_body) = apiclient::raw_request(socket_path, &uri, method, None)
+        .await
+        .context(error::APIRequest { method, uri })?;
+
+    if !code.is_success() {
+        return error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+        .fail();
+    }
+    trace!("JSON response: {}", response_body);
+
+    serde_json::from_str(&response_body).context(error::ResponseJson { method, uri })
+}
+
+fn drain_timeout(setting: Option<u32>) -> Duration {
+    Duration::from_secs(setting.unwrap_or(DEFAULT_DRAIN_TIMEOUT).into())
+}
+
+fn write_marker(path: &Path, drained: &Drained) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(error::MarkerWrite { path })?;
+    }
+    let data = serde_json::to_string(drained).context(error::MarkerSerialize)?;
+    fs::write(path, data).context(error::MarkerWrite { path })
+}
+
+fn read_marker(path: &str) -> Result<Option<Drained>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(error::MarkerRead { path }),
+    };
+    serde_json::from_str(&data)
+        .map(Some)
+        .context(error::MarkerParse { path })
+}
+
+fn remove_marker(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).context(error::MarkerRemove { path }),
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Print a usage message in the event a bad argument is given.
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {} SUBCOMMAND [ ARGUMENTS... ]
+
+    Subcommands:
+        drain
+        restore
+        registered
+
+    Global arguments:
+        --socket-path PATH
+        --log-level trace|debug|info|warn|error
+
+    Socket path defaults to {}",
+        program_name, DEFAULT_API_SOCKET,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses the arguments to the program and return a representative `Args`.
+fn parse_args(args: env::Args) -> Args {
+    let mut log_level = None;
+    let mut socket_path = None;
+    let mut subcommand = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            "drain" | "restore" | "registered" => subcommand = Some(arg),
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        subcommand: subcommand.unwrap_or_else(|| usage_msg("Must specify a subcommand.")),
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+    }
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+mod error {
+    use crate::{ecs, kubernetes};
+    use http::StatusCode;
+    use snafu::Snafu;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Error {}ing to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when {}ing to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display("Failed to drain ECS container instance: {}", source))]
+        Ecs { source: ecs::Error },
+
+        #[snafu(display("ECS agent has not registered a container instance: {}", source))]
+        EcsRegistration { source: ecs::Error },
+
+        #[snafu(display("Failed to drain Kubernetes node: {}", source))]
+        Kubernetes { source: kubernetes::Error },
+
+        #[snafu(display("Unable to check Kubernetes node registration: {}", source))]
+        KubernetesRegistration { source: kubernetes::Error },
+
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Unable to parse drain marker '{}': {}", path, source))]
+        MarkerParse {
+            path: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Unable to read drain marker '{}': {}", path, source))]
+        MarkerRead { path: String, source: io::Error },
+
+        #[snafu(display("Unable to remove drain marker '{}': {}", path.display(), source))]
+        MarkerRemove { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Unable to serialize drain marker: {}", source))]
+        MarkerSerialize { source: serde_json::Error },
+
+        #[snafu(display("Unable to write drain marker '{}': {}", path.display(), source))]
+        MarkerWrite { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Kubernetes node '{}' is not ready", node))]
+        NodeNotReady { node: String },
+
+        #[snafu(display(
+            "Host was drained from {}, but {} settings are missing",
+            orchestrator,
+            orchestrator
+        ))]
+        MissingSettings { orchestrator: &'static str },
+
+        #[snafu(display("The Kubernetes node IP setting is missing"))]
+        MissingNodeIp,
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to '{}': {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+    use serde_json::json;
+
+    fn kube_client(server: &Server) -> kubernetes::KubeClient {
+        let base_url = format!("http://localhost:{}", server.addr().port());
+        kubernetes::KubeClient::new(
+            base_url,
+            None,
+            kubernetes::Credentials::Token("sometoken".to_string()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn marker_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sheepdog").join("drained");
+        let drained = Drained::Ecs {
+            cluster: "default".to_string(),
+            container_instance: "arn".to_string(),
+        };
+        write_marker(&path, &drained).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            r#"{"orchestrator":"ecs","cluster":"default","container-instance":"arn"}"#
+        );
+        let read = read_marker(path.to_str().unwrap()).unwrap();
+        assert_eq!(read, Some(drained));
+        remove_marker(&path).unwrap();
+        assert_eq!(read_marker(path.to_str().unwrap()).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn drain_kubernetes_writes_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("drained");
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/nodes/node1"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "spec": {} }))),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("PATCH", "/api/v1/nodes/node1"))
+                .times(1)
+                .respond_with(status_code(200)),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/pods"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "items": [] }))),
+        );
+        drain_kubernetes(
+            &kube_client(&server),
+            "node1",
+            Duration::from_secs(1),
+            false,
+            &marker,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            read_marker(marker.to_str().unwrap()).unwrap(),
+            Some(Drained::Kubernetes {
+                node: "node1".to_string()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn drain_kubernetes_failure_uncordons() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("drained");
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/nodes/node1"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "spec": {} }))),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("PATCH", "/api/v1/nodes/node1"),
+                request::body(json_decoded(eq(
+                    json!({ "spec": { "unschedulable": true } })
+                ))),
+            ])
+            .times(1)
+            .respond_with(status_code(200)),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/pods"))
+                .times(1)
+                .respond_with(status_code(403)),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("PATCH", "/api/v1/nodes/node1"),
+                request::body(json_decoded(eq(
+                    json!({ "spec": { "unschedulable": false } })
+                ))),
+            ])
+            .times(1)
+            .respond_with(status_code(200)),
+        );
+        let result = drain_kubernetes(
+            &kube_client(&server),
+            "node1",
+            Duration::from_secs(1),
+            false,
+            &marker,
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn drain_kubernetes_already_cordoned() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("drained");
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/nodes/node1"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "spec": { "unschedulable": true } }))),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/v1/pods"))
+                .times(1)
+                .respond_with(json_encoded(json!({ "items": [] }))),
+        );
+        drain_kubernetes(
+            &kube_client(&server),
+            "node1",
+            Duration::from_secs(1),
+            false,
+            &marker,
+        )
+        .await
+        .unwrap();
+        assert!(!marker.exists());
+    }
+}
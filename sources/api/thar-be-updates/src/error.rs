@@ -24,6 +24,39 @@ pub enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display("Failed to remove download progress file '{}': {}", path.display(), source))]
+    RemoveProgressFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to parse rollback record '{}': {}", path.display(), source))]
+    RollbackParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to read rollback record '{}': {}", path.display(), source))]
+    RollbackRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to remove rollback record '{}': {}", path.display(), source))]
+    RollbackRemove {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to serialize rollback record: {}", source))]
+    RollbackSerialize { source: serde_json::Error },
+
+    #[snafu(display("Failed to write rollback record '{}': {}", path.display(), source))]
+    RollbackWrite {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Failed to parse update status file '{}': {}", path.display(), source))]
     StatusParse {
         path: PathBuf,
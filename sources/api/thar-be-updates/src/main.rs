@@ -23,7 +23,8 @@ use num_traits::cast::ToPrimitive;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use snafu::ensure;
 use snafu::{OptionExt, ResultExt};
-use std::fs::File;
+use std::fs::{self, File};
+use std::io;
 use std::path::Path;
 use std::process::{exit, Command};
 use std::str::FromStr;
@@ -33,7 +34,7 @@ use thar_be_updates::error;
 use thar_be_updates::error::{Error, Result, TbuErrorStatus};
 use thar_be_updates::status::{
     get_update_status, UpdateCommand, UpdateState, UpdateStatus, UPDATE_LOCKFILE,
-    UPDATE_STATUS_FILE,
+    UPDATE_PROGRESS_FILE, UPDATE_STATUS_FILE,
 };
 
 // FIXME Get this from configuration in the future
@@ -137,6 +138,11 @@ fn initialize_update_status() -> Result<()> {
     let mut new_status = UpdateStatus::new();
     // Initialize active partition set information
     new_status.update_active_partition_info()?;
+    // Report any rollback from before the most recent reboot; failing to read it shouldn't stop
+    // updates from working.
+    if let Err(e) = new_status.load_most_recent_rollback() {
+        warn!("{}", e);
+    }
     write_update_status(&new_status)
 }
 
@@ -190,6 +196,7 @@ fn refresh(status: &mut UpdateStatus, socket_path: &str) -> Result<bool> {
             .output()
             .context(error::Updog)?;
         status.set_recent_command_info(UpdateCommand::Refresh, &output);
+        status.record_recent_command(None);
         if !output.status.success() {
             warn!("Failed to check for updates with updog");
             return Ok(false);
@@ -208,11 +215,19 @@ fn prepare(status: &mut UpdateStatus) -> Result<()> {
             .chosen_update()
             .context(error::UpdateDoesNotExist)?
             .clone();
+        // Start from a clean slate; updog writes its progress to the file as it downloads.
+        status.clear_download_progress();
+        remove_progress_file()?;
         let output = Command::new("updog")
-            .arg("update-image")
+            .args(["update-image", "--progress-file", UPDATE_PROGRESS_FILE])
             .output()
             .context(error::Updog)?;
         status.set_recent_command_info(UpdateCommand::Prepare, &output);
+        status.record_recent_command(Some(chosen_update.version()));
+        // Keep the final progress in the status, and remove the file so it isn't mistaken for a
+        // download in progress.
+        status.load_download_progress();
+        remove_progress_file()?;
         if !output.status.success() {
             warn!("Failed to prepare the update with updog");
             return error::PrepareUpdate.fail();
@@ -222,6 +237,18 @@ fn prepare(status: &mut UpdateStatus) -> Result<()> {
     })
 }
 
+/// Removes the download progress file updog writes while preparing an update
+fn remove_progress_file() -> Result<()> {
+    match fs::remove_file(UPDATE_PROGRESS_FILE) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).context(error::RemoveProgressFile {
+                path: UPDATE_PROGRESS_FILE,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
 /// "Activates" the staged update by letting updog set up the appropriate boot flags
 fn activate(status: &mut UpdateStatus) -> Result<()> {
     fork_and_return!({
@@ -231,6 +258,11 @@ fn activate(status: &mut UpdateStatus) -> Result<()> {
             .output()
             .context(error::Updog)?;
         status.set_recent_command_info(UpdateCommand::Activate, &output);
+        status.record_recent_command(
+            status
+                .staging_partition()
+                .map(|staged| staged.image().version()),
+        );
         if !output.status.success() {
             warn!("Failed to activate the update with updog");
             return error::ActivateUpdate.fail();
@@ -248,6 +280,11 @@ fn deactivate(status: &mut UpdateStatus) -> Result<()> {
             .output()
             .context(error::Updog)?;
         status.set_recent_command_info(UpdateCommand::Deactivate, &output);
+        status.record_recent_command(
+            status
+                .staging_partition()
+                .map(|staged| staged.image().version()),
+        );
         if !output.status.success() {
             warn!("Failed to deactivate the update with updog");
             return error::DeactivateUpdate.fail();
@@ -2,18 +2,27 @@ use crate::error;
 use crate::error::Result;
 use bottlerocket_release::BottlerocketRelease;
 use chrono::{DateTime, Utc};
+use log::warn;
 use model::modeled_types::FriendlyVersion;
 use serde::{Deserialize, Serialize};
 use signpost::State;
 use snafu::{OptionExt, ResultExt};
 use std::convert::TryInto;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io;
 use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
 use std::process::Output;
 use tokio::runtime::Runtime;
+use update_history::{Event, EventType, HISTORY_FILE};
+use update_metadata::ReleaseInfo;
 
 pub const UPDATE_LOCKFILE: &str = "/run/lock/thar-be-updates.lock";
 pub const UPDATE_STATUS_FILE: &str = "/run/cache/thar-be-updates/status.json";
+pub const UPDATE_PROGRESS_FILE: &str = "/run/cache/thar-be-updates/progress.json";
+/// Where update-verifier records an automatic rollback; unlike the rest of the update status, this
+/// needs to survive the reboot that follows.
+pub const ROLLBACK_FILE: &str = "/var/lib/thar-be-updates/rollback.json";
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum UpdateState {
@@ -29,6 +38,9 @@ pub struct UpdateImage {
     arch: String,
     version: semver::Version,
     variant: String,
+    /// What the update contains, if the manifest says
+    #[serde(flatten)]
+    release_info: ReleaseInfo,
 }
 
 impl UpdateImage {
@@ -46,12 +58,67 @@ pub struct StagedImage {
 }
 
 impl StagedImage {
+    pub fn image(&self) -> &UpdateImage {
+        &self.image
+    }
+
     pub(crate) fn set_next_to_boot(&mut self, next_to_boot: bool) {
         self.next_to_boot = next_to_boot
     }
 }
 
+/// DownloadProgress represents how much of an update updog has downloaded while preparing it
 #[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DownloadProgress {
+    bytes_downloaded: u64,
+    bytes_total: u64,
+}
+
+/// Rollback represents an automatic rollback after a new version failed its health checks
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Rollback {
+    pub from_version: semver::Version,
+    pub timestamp: DateTime<Utc>,
+    pub reason: String,
+}
+
+impl Rollback {
+    /// Loads the recorded rollback, if there is one
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(error::RollbackRead { path }),
+        };
+        serde_json::from_str(&data)
+            .map(Some)
+            .context(error::RollbackParse { path })
+    }
+
+    /// Records the rollback so it can be reported after rebooting
+    pub fn record<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(error::RollbackWrite { path })?;
+        }
+        let data = serde_json::to_string(self).context(error::RollbackSerialize)?;
+        fs::write(path, data).context(error::RollbackWrite { path })
+    }
+
+    /// Forgets the recorded rollback, once a newer version has passed its health checks
+    pub fn clear<P: AsRef<Path>>(path: P) -> Result<()> {
+        let path = path.as_ref();
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).context(error::RollbackRemove { path })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum CommandStatus {
     Success,
     Failed,
@@ -85,6 +152,8 @@ pub struct UpdateStatus {
     active_partition: Option<StagedImage>,
     staging_partition: Option<StagedImage>,
     most_recent_command: Option<CommandResult>,
+    download_progress: Option<DownloadProgress>,
+    most_recent_rollback: Option<Rollback>,
 }
 
 impl Default for UpdateStatus {
@@ -100,11 +169,13 @@ pub fn get_update_status(_lockfile: &File) -> Result<UpdateStatus> {
     let status_file = File::open(UPDATE_STATUS_FILE).context(error::NoStatusFile {
         path: UPDATE_STATUS_FILE,
     })?;
-    Ok(
+    let mut update_status: UpdateStatus =
         serde_json::from_reader(status_file).context(error::StatusParse {
             path: UPDATE_STATUS_FILE,
-        })?,
-    )
+        })?;
+    // While an update is being prepared, updog reports its progress in a separate file.
+    update_status.load_download_progress();
+    Ok(update_status)
 }
 
 /// Retrieves settings from the API.
@@ -136,6 +207,8 @@ impl UpdateStatus {
             active_partition: None,
             staging_partition: None,
             most_recent_command: None,
+            download_progress: None,
+            most_recent_rollback: None,
         }
     }
 
@@ -169,6 +242,7 @@ impl UpdateStatus {
             arch: os_info.arch,
             version: os_info.version_id,
             variant: os_info.variant_id,
+            release_info: ReleaseInfo::default(),
         };
 
         // Get partition set information. We can infer the version of the image in the active
@@ -183,6 +257,12 @@ impl UpdateStatus {
         Ok(())
     }
 
+    /// Loads the most recent automatic rollback, if update-verifier has recorded one
+    pub fn load_most_recent_rollback(&mut self) -> Result<()> {
+        self.most_recent_rollback = Rollback::load(ROLLBACK_FILE)?;
+        Ok(())
+    }
+
     /// Sets the staging partition image information
     pub fn set_staging_partition_image_info(&mut self, image: UpdateImage) {
         self.staging_partition = Some(StagedImage {
@@ -221,6 +301,20 @@ impl UpdateStatus {
         Ok(())
     }
 
+    /// Picks up the download progress updog has reported, if there is any
+    pub fn load_download_progress(&mut self) {
+        if let Ok(progress_file) = File::open(UPDATE_PROGRESS_FILE) {
+            if let Ok(progress) = serde_json::from_reader(progress_file) {
+                self.download_progress = Some(progress);
+            }
+        }
+    }
+
+    /// Clears the download progress of any previous update
+    pub fn clear_download_progress(&mut self) {
+        self.download_progress = None;
+    }
+
     /// Sets information regarding the latest command invocation
     /// Derive success/failure status from exit status when possible.
     pub fn set_recent_command_info(&mut self, cmd_type: UpdateCommand, cmd_output: &Output) {
@@ -242,6 +336,40 @@ impl UpdateStatus {
         self.most_recent_command = Some(command_result);
     }
 
+    /// Records the latest command invocation in the persistent update history, along with the
+    /// version it targeted, if any.  The history is informational, so failures are only logged.
+    pub fn record_recent_command(&self, target_version: Option<&semver::Version>) {
+        let command = match &self.most_recent_command {
+            Some(command) => command,
+            None => return,
+        };
+        let event_type = match command.cmd_type {
+            UpdateCommand::Refresh => EventType::Refresh,
+            UpdateCommand::Prepare => EventType::Prepare,
+            UpdateCommand::Activate => EventType::Activate,
+            UpdateCommand::Deactivate => EventType::Deactivate,
+        };
+        let success = command.cmd_status == CommandStatus::Success;
+        let event = Event {
+            timestamp: command.timestamp,
+            version: self
+                .active_partition
+                .as_ref()
+                .map(|active| active.image.version.clone()),
+            target_version: target_version.cloned(),
+            // Only keep updog's output when it explains a failure.
+            message: if success {
+                None
+            } else {
+                command.stderr.clone()
+            },
+            ..Event::new(event_type, success)
+        };
+        if let Err(e) = update_history::append(HISTORY_FILE, &event) {
+            warn!("Failed to record update history: {}", e);
+        }
+    }
+
     /// Returns the update information of the 'latest' available update
     pub fn get_latest_update(
         updates: Vec<update_metadata::Update>,
@@ -260,7 +388,9 @@ impl UpdateStatus {
     }
 
     /// Checks the list of updates to for an available update.
-    /// If the 'version-lock'ed version is available returns true. Otherwise returns false
+    /// If the 'version-lock'ed version is available returns true. Otherwise returns false.
+    /// In 'managed' mode, 'latest' never chooses an update, and in 'disabled' mode nothing is
+    /// chosen at all; updog has already left denied versions out of the list.
     pub fn update_available_updates(
         &mut self,
         socket_path: &str,
@@ -278,13 +408,25 @@ impl UpdateStatus {
             setting: "/settings/updates/version-lock",
         })?;
 
+        // The mode is optional in settings; updog treats a missing mode as 'automatic'.
+        let mode = settings["updates"]["mode"].as_str().unwrap_or("automatic");
+        if mode == "disabled" {
+            self.chosen_update = None;
+            return Ok(false);
+        }
+
         if String::from(locked_version.to_owned()) == "latest" {
+            if mode == "managed" {
+                self.chosen_update = None;
+                return Ok(false);
+            }
             // Set chosen_update to the latest version available
             if let Some(latest_update) = UpdateStatus::get_latest_update(updates)? {
                 self.chosen_update = Some(UpdateImage {
                     arch: latest_update.arch,
                     version: latest_update.version,
                     variant: latest_update.variant,
+                    release_info: latest_update.release_info,
                 });
                 return Ok(true);
             }
@@ -301,6 +443,7 @@ impl UpdateStatus {
                             arch: update.arch.clone(),
                             version: chosen_version,
                             variant: update.variant.clone(),
+                            release_info: update.release_info.clone(),
                         });
                         return Ok(true);
                     }
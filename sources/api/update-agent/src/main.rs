@@ -0,0 +1,685 @@
+/*!
+# Introduction
+
+update-agent applies updates on its own, but only during a maintenance window that you choose.
+
+Without it, someone has to call `apiclient update apply --reboot` to update the host.
+update-agent is run periodically by `update-agent.timer`, and does nothing unless `settings.updates.maintenance-window.schedule` is set.
+When it is, and the current time falls within a window, update-agent drives the update API the same way `apiclient` would:
+
+* It refreshes the list of updates, which lets thar-be-updates choose an update based on `settings.updates.version-lock`, `mode`, and `deny-versions`.
+* If an update was chosen, and this host's wave for that update has started, it prepares and activates the update.
+* It reboots into the update, if the window hasn't closed in the meantime.
+  If it has, the update stays activated and the reboot happens in the next window.
+
+Nothing happens while `settings.updates.mode` is `disabled`.
+Waves are respected unless `settings.updates.ignore-waves` is true; hosts whose `settings.updates.wave-labels` match a targeted wave can update once that wave starts.
+
+## Maintenance windows
+
+Windows are given by three settings under `settings.updates.maintenance-window`:
+
+* `schedule`: When windows start, in the cron-like form `"minute hour day-of-month month day-of-week"`, for example `"0 2 * * Sat,Sun"` for 02:00 on weekends.
+* `duration`: How long each window lasts, like `"3 hours"`.  Defaults to 3 hours.
+* `timezone`: The timezone the schedule is interpreted in, `"UTC"`, an IANA timezone name like `"America/New_York"`, or a fixed offset like `"-05:00"`.  Defaults to UTC.
+
+Windows should be longer than the interval of `update-agent.timer` (5 minutes), or they could be missed entirely.
+*/
+
+#![deny(rust_2018_idioms)]
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, trace};
+use parse_datetime::MaintenanceWindow;
+use serde::Deserialize;
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use snafu::{OptionExt, ResultExt};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::{env, process};
+use thar_be_updates::status::{UpdateState, UpdateStatus};
+use update_metadata::{Update, DEFAULT_CHANNEL};
+
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const DEFAULT_WINDOW_DURATION: &str = "3 hours";
+const DEFAULT_WINDOW_TIMEZONE: &str = "UTC";
+
+/// Store the args we receive on the command line.
+struct Args {
+    log_level: LevelFilter,
+    socket_path: String,
+}
+
+/// The settings update-agent cares about.
+#[derive(Debug, Default, Deserialize)]
+struct Settings {
+    updates: Option<model::UpdatesSettings>,
+}
+
+/// What update-agent should do about updates right now.
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Nothing to do until something changes, like the window opening or the wave starting.
+    Wait,
+    /// Prepare and activate the chosen update, then reboot into it.
+    Apply,
+    /// An update was already activated; reboot into it.
+    Reboot,
+}
+
+/// Main entry point.
+async fn run() -> Result<()> {
+    let args = parse_args(env::args());
+
+    // SimpleLogger will send errors to stderr and anything less to stdout.
+    SimpleLogger::init(args.log_level, LogConfig::default()).context(error::Logger)?;
+
+    let settings = get_settings(&args.socket_path).await?;
+    let updates = settings
+        .updates
+        .context(error::MissingSetting { setting: "updates" })?;
+
+    let window = match maintenance_window(&updates)? {
+        Some(window) => window,
+        None => {
+            debug!("No maintenance window is configured, not updating automatically");
+            return Ok(());
+        }
+    };
+    if updates.mode.as_ref().map(|mode| mode.as_ref()) == Some("disabled") {
+        info!("Updates are disabled, not updating automatically");
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    if !window.contains(now) {
+        match window.next_start(now) {
+            Some(next) => info!(
+                "Outside of the maintenance window; the next starts at {}",
+                next
+            ),
+            None => info!("Outside of the maintenance window, and no upcoming window was found"),
+        }
+        return Ok(());
+    }
+
+    // Let thar-be-updates refresh the list of updates and choose one for us.
+    let status_body = apiclient::update::check(&args.socket_path)
+        .await
+        .context(error::Check)?;
+    let status: UpdateStatus = serde_json::from_str(&status_body).context(error::StatusJson)?;
+
+    // The update API only tells us the chosen version; we need updog's metadata to see when
+    // this host's wave starts.
+    let chosen = match status.chosen_update() {
+        Some(image) => find_update(image.version())?,
+        None => None,
+    };
+
+    let seed = updates
+        .seed
+        .context(error::MissingSetting { setting: "seed" })?;
+    let labels = wave_labels(&updates);
+    let ignore_waves = updates.ignore_waves.unwrap_or(false);
+    match decide(
+        now,
+        &window,
+        status.update_state(),
+        chosen.as_ref(),
+        seed,
+        &labels,
+        ignore_waves,
+    ) {
+        Action::Wait => Ok(()),
+        Action::Apply => {
+            // A rate-limited download can take longer than apiclient waits by default, so we
+            // keep waiting until the window closes.
+            let timeout = window
+                .current_end(now)
+                .and_then(|end| (end - now).to_std().ok())
+                .map_or(apiclient::update::DEFAULT_PREPARE_TIMEOUT, |remaining| {
+                    remaining.max(apiclient::update::DEFAULT_PREPARE_TIMEOUT)
+                });
+            apiclient::update::apply(&args.socket_path, timeout)
+                .await
+                .context(error::Apply)?;
+            // Preparing the update can take a while; don't reboot if the window closed meanwhile.
+            if window.contains(Utc::now()) {
+                reboot(&args.socket_path).await
+            } else {
+                info!("Maintenance window closed while applying the update; will reboot in the next window");
+                Ok(())
+            }
+        }
+        Action::Reboot => reboot(&args.socket_path).await,
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Builds the maintenance window from settings, if a schedule was given.
+fn maintenance_window(updates: &model::UpdatesSettings) -> Result<Option<MaintenanceWindow>> {
+    let settings = match &updates.maintenance_window {
+        Some(settings) => settings,
+        None => return Ok(None),
+    };
+    let schedule = match &settings.schedule {
+        Some(schedule) => schedule,
+        None => return Ok(None),
+    };
+    let duration = settings
+        .duration
+        .as_ref()
+        .map(|d| d.as_ref())
+        .unwrap_or(DEFAULT_WINDOW_DURATION);
+    let timezone = settings
+        .timezone
+        .as_ref()
+        .map(|t| t.as_ref())
+        .unwrap_or(DEFAULT_WINDOW_TIMEZONE);
+
+    MaintenanceWindow::new(schedule.as_ref(), duration, timezone)
+        .map(Some)
+        .context(error::Window)
+}
+
+/// Returns the labels that place this host in targeted update waves.  Like updog, the channel is
+/// included as the "channel" label, unless there's an explicit label by that name.
+fn wave_labels(updates: &model::UpdatesSettings) -> BTreeMap<String, String> {
+    let mut labels: BTreeMap<String, String> = updates
+        .wave_labels
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    let channel = updates
+        .channel
+        .as_ref()
+        .map(|c| c.as_ref())
+        .unwrap_or(DEFAULT_CHANNEL);
+    labels
+        .entry("channel".to_string())
+        .or_insert_with(|| channel.to_string());
+    labels
+}
+
+/// Decides what to do, given the current time and the state of the update API.  `chosen` is
+/// the update the API chose, if any, with its wave information.
+fn decide(
+    now: DateTime<Utc>,
+    window: &MaintenanceWindow,
+    state: &UpdateState,
+    chosen: Option<&Update>,
+    seed: u32,
+    labels: &BTreeMap<String, String>,
+    ignore_waves: bool,
+) -> Action {
+    if !window.contains(now) {
+        debug!("Outside of the maintenance window");
+        return Action::Wait;
+    }
+
+    match state {
+        UpdateState::Idle => {
+            info!("No updates available");
+            Action::Wait
+        }
+        UpdateState::Ready => {
+            info!("Update already activated, rebooting into it");
+            Action::Reboot
+        }
+        UpdateState::Available | UpdateState::Staged => match chosen {
+            Some(update) if ignore_waves || update.update_ready_for(seed, labels, now) => {
+                info!("Applying update to {}", update.version);
+                Action::Apply
+            }
+            Some(update) => {
+                info!(
+                    "Update to {} is available, but this host's wave hasn't started yet",
+                    update.version
+                );
+                Action::Wait
+            }
+            None => {
+                info!("Chosen update was not found in the update metadata");
+                Action::Wait
+            }
+        },
+    }
+}
+
+/// Finds the given version in updog's list of updates, ignoring waves so that we can check them
+/// ourselves.
+fn find_update(version: &semver::Version) -> Result<Option<Update>> {
+    debug!("Spawning 'updog whats --all --json --ignore-waves'");
+    let output = Command::new("updog")
+        .args(["whats", "--all", "--json", "--ignore-waves"])
+        .output()
+        .context(error::Updog)?;
+    if !output.status.success() {
+        return error::UpdogStatus {
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+        .fail();
+    }
+    let updates: Vec<Update> = serde_json::from_slice(&output.stdout).context(error::UpdogJson)?;
+    Ok(updates.into_iter().find(|u| u.version == *version))
+}
+
+/// Reboots through the API, which drains the host first if requested.
+async fn reboot<P>(socket_path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    apiclient::reboot::reboot(socket_path)
+        .await
+        .context(error::Reboot)
+}
+
+/// Retrieves the current settings from the API.
+async fn get_settings<P>(socket_path: P) -> Result<Settings>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/settings";
+    let method = "GET";
+    trace!("{}ing from {}", method, uri);
+    let (code, response_body) = apiclient::raw_request(socket_path, &uri, method, None)
+        .await
+        .context(error::APIRequest { method, uri })?;
+
+    if !code.is_success() {
+        return error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+        .fail();
+    }
+    trace!("JSON response: {}", response_body);
+
+    serde_json::from_str(&response_body).context(error::ResponseJson { method, uri })
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --socket-path PATH ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Socket path defaults to {}",
+        program_name, DEFAULT_API_SOCKET,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses the arguments to the program and return a representative `Args`.
+fn parse_args(args: env::Args) -> Args {
+    let mut log_level = None;
+    let mut socket_path = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+    }
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+mod error {
+    use http::StatusCode;
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Error {}ing to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when {}ing to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display("Failed to apply update: {}", source))]
+        Apply { source: apiclient::update::Error },
+
+        #[snafu(display("Failed to check for updates: {}", source))]
+        Check { source: apiclient::update::Error },
+
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Missing setting 'settings.updates.{}'", setting))]
+        MissingSetting { setting: &'static str },
+
+        #[snafu(display("Failed to reboot: {}", source))]
+        Reboot { source: apiclient::reboot::Error },
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to '{}': {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Unable to parse update status: {}", source))]
+        StatusJson { source: serde_json::Error },
+
+        #[snafu(display("Failed to run updog: {}", source))]
+        Updog { source: std::io::Error },
+
+        #[snafu(display("Unable to parse list of updates from updog: {}", source))]
+        UpdogJson { source: serde_json::Error },
+
+        #[snafu(display("updog failed to list updates: {}", stderr))]
+        UpdogStatus { stderr: String },
+
+        #[snafu(display("Invalid maintenance window: {}", source))]
+        Window { source: parse_datetime::Error },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use semver::Version;
+    use update_metadata::{Images, TargetedWave};
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().into()
+    }
+
+    /// Weekend windows from 02:00 to 05:00 UTC; 2021-06-05 is a Saturday.
+    fn window() -> MaintenanceWindow {
+        MaintenanceWindow::new("0 2 * * Sat,Sun", "3 hours", "UTC").unwrap()
+    }
+
+    fn update(waves: BTreeMap<u32, DateTime<Utc>>) -> Update {
+        Update {
+            variant: "aws-k8s-1.21".to_string(),
+            arch: "x86_64".to_string(),
+            version: Version::parse("1.1.0").unwrap(),
+            max_version: Version::parse("1.1.0").unwrap(),
+            waves,
+            targeted_waves: Vec::new(),
+            images: Images {
+                boot: "boot".to_string(),
+                root: "root".to_string(),
+                hash: "hash".to_string(),
+            },
+            deltas: Vec::new(),
+            release_info: update_metadata::ReleaseInfo::default(),
+            channels: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn waits_outside_window() {
+        let update = update(BTreeMap::new());
+        for now in &[
+            "2021-06-04T03:00:00Z",
+            "2021-06-05T01:59:00Z",
+            "2021-06-05T05:00:00Z",
+        ] {
+            for state in &[UpdateState::Available, UpdateState::Ready] {
+                assert_eq!(
+                    decide(
+                        utc(now),
+                        &window(),
+                        state,
+                        Some(&update),
+                        0,
+                        &BTreeMap::new(),
+                        false
+                    ),
+                    Action::Wait
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn acts_inside_window() {
+        let update = update(BTreeMap::new());
+        let now = utc("2021-06-05T03:00:00Z");
+        let cases = vec![
+            (UpdateState::Idle, Action::Wait),
+            (UpdateState::Available, Action::Apply),
+            (UpdateState::Staged, Action::Apply),
+            (UpdateState::Ready, Action::Reboot),
+        ];
+        for (state, action) in cases {
+            assert_eq!(
+                decide(
+                    now,
+                    &window(),
+                    &state,
+                    Some(&update),
+                    0,
+                    &BTreeMap::new(),
+                    false
+                ),
+                action
+            );
+        }
+    }
+
+    #[test]
+    fn waits_without_update_metadata() {
+        let now = utc("2021-06-05T03:00:00Z");
+        assert_eq!(
+            decide(
+                now,
+                &window(),
+                &UpdateState::Available,
+                None,
+                0,
+                &BTreeMap::new(),
+                false
+            ),
+            Action::Wait
+        );
+    }
+
+    #[test]
+    fn respects_waves() {
+        // The last wave, for seeds from 1024 on, starts Sunday at 04:00, so those hosts can only
+        // update in the last hour of Sunday's window, or in a later window.
+        let saturday = utc("2021-06-05T04:00:00Z");
+        let mut waves = BTreeMap::new();
+        waves.insert(512, saturday);
+        waves.insert(1024, saturday + Duration::days(1));
+        let update = update(waves);
+        let seed = 1500;
+
+        let cases = vec![
+            ("2021-06-05T02:30:00Z", Action::Wait),
+            ("2021-06-05T04:30:00Z", Action::Wait),
+            ("2021-06-06T02:30:00Z", Action::Wait),
+            ("2021-06-06T04:30:00Z", Action::Apply),
+            ("2021-06-07T04:30:00Z", Action::Wait),
+            ("2021-06-12T02:30:00Z", Action::Apply),
+        ];
+        for (now, action) in cases {
+            assert_eq!(
+                decide(
+                    utc(now),
+                    &window(),
+                    &UpdateState::Available,
+                    Some(&update),
+                    seed,
+                    &BTreeMap::new(),
+                    false
+                ),
+                action,
+                "{}",
+                now
+            );
+        }
+
+        // Unless we've been asked to ignore waves.
+        assert_eq!(
+            decide(
+                utc("2021-06-05T02:30:00Z"),
+                &window(),
+                &UpdateState::Available,
+                Some(&update),
+                seed,
+                &BTreeMap::new(),
+                true
+            ),
+            Action::Apply
+        );
+    }
+
+    #[test]
+    fn respects_targeted_waves() {
+        // Canaries can update in Saturday's window, long before their seed's wave starts.
+        let saturday = utc("2021-06-05T02:00:00Z");
+        let mut waves = BTreeMap::new();
+        waves.insert(1024, saturday + Duration::days(7));
+        let mut update = update(waves);
+        let mut canary = BTreeMap::new();
+        canary.insert("pool".to_string(), "canary".to_string());
+        update.targeted_waves.push(TargetedWave {
+            labels: canary.clone(),
+            start_time: saturday,
+        });
+        let now = utc("2021-06-05T03:00:00Z");
+
+        let decide_for = |labels: &BTreeMap<String, String>| {
+            decide(
+                now,
+                &window(),
+                &UpdateState::Available,
+                Some(&update),
+                1500,
+                labels,
+                false,
+            )
+        };
+        assert_eq!(decide_for(&canary), Action::Apply);
+        assert_eq!(decide_for(&BTreeMap::new()), Action::Wait);
+
+        // A held-back pool waits for its targeted wave, even once every seed's wave has passed.
+        let mut waves = BTreeMap::new();
+        waves.insert(2048, saturday - Duration::days(1));
+        let mut update = self::update(waves);
+        let mut held = BTreeMap::new();
+        held.insert("pool".to_string(), "held".to_string());
+        update.targeted_waves.push(TargetedWave {
+            labels: held.clone(),
+            start_time: saturday + Duration::days(14),
+        });
+        let decide_for = |labels: &BTreeMap<String, String>| {
+            decide(
+                now,
+                &window(),
+                &UpdateState::Available,
+                Some(&update),
+                1500,
+                labels,
+                false,
+            )
+        };
+        assert_eq!(decide_for(&BTreeMap::new()), Action::Apply);
+        assert_eq!(decide_for(&held), Action::Wait);
+
+        let updates: model::UpdatesSettings =
+            serde_json::from_str(r#"{"wave-labels": {"pool": "canary"}, "channel": "beta"}"#)
+                .unwrap();
+        let mut expected = canary;
+        expected.insert("channel".to_string(), "beta".to_string());
+        assert_eq!(wave_labels(&updates), expected);
+    }
+
+    #[test]
+    fn window_from_settings() {
+        let updates: model::UpdatesSettings = serde_json::from_str("{}").unwrap();
+        assert!(maintenance_window(&updates).unwrap().is_none());
+
+        // Duration and timezone have defaults, but there's no window without a schedule.
+        let updates: model::UpdatesSettings = serde_json::from_str(
+            r#"{"maintenance-window": {"duration": "1 hour", "timezone": "+02:00"}}"#,
+        )
+        .unwrap();
+        assert!(maintenance_window(&updates).unwrap().is_none());
+
+        let updates: model::UpdatesSettings = serde_json::from_str(
+            r#"{"maintenance-window": {"schedule": "0 4 * * Sat", "duration": "1 hour", "timezone": "+02:00"}}"#,
+        )
+        .unwrap();
+        let window = maintenance_window(&updates).unwrap().unwrap();
+        // 04:00 at UTC+02:00 is 02:00 UTC, and the window is an hour long.
+        assert!(window.contains(utc("2021-06-05T02:30:00Z")));
+        assert!(!window.contains(utc("2021-06-05T03:30:00Z")));
+
+        let updates: model::UpdatesSettings =
+            serde_json::from_str(r#"{"maintenance-window": {"schedule": "0 4 * * Sat"}}"#).unwrap();
+        let window = maintenance_window(&updates).unwrap().unwrap();
+        assert!(window.contains(utc("2021-06-05T06:59:00Z")));
+        assert!(!window.contains(utc("2021-06-05T07:00:00Z")));
+    }
+}
@@ -0,0 +1,851 @@
+/*!
+# Introduction
+
+update-verifier checks that the host is healthy after booting into a new version, and rolls back to the previous version if it isn't.
+
+The partition table only protects against versions that can't boot at all; signpost marks the boot successful as soon as the host is configured.
+update-verifier runs at every boot, but only does something the first time a version boots, and only if there's a previous version to go back to.
+It runs the checks listed under `settings.updates.health-checks` until they all pass:
+
+* `services`: systemd units that must be active, like `["containerd", "kubelet"]`.
+* `registration`: if true, the host must have joined its orchestrator's cluster; see `sheepdog registered`.
+* `commands`: commands that must exit successfully.
+  They aren't run through a shell, so there are no pipes, redirects, or variables, but they're split into arguments with shell quoting rules.
+  Single or double quotes keep an argument with spaces together, and a backslash escapes the next character, so `sh -c "systemctl is-active my-app"` runs `sh` with the arguments `-c` and `systemctl is-active my-app`.
+  Commands with unbalanced quotes are rejected when they're set.
+  A command that's still running at the deadline is killed, along with anything it started, and counts as failed.
+
+If the checks haven't all passed within `timeout` seconds of boot (default 600), update-verifier records the reason, marks the previous version's partitions to boot, and reboots.
+The reason is reported in the `most_recent_rollback` field of the update status until a later version passes its checks.
+
+Each boot of a new version, successful or not, is also recorded in the update history, as is a boot where the partition table fell back to this version because the activated update failed to boot.
+
+No checks are configured by default, in which case every version is accepted.
+*/
+
+#![deny(rust_2018_idioms)]
+
+use bottlerocket_release::BottlerocketRelease;
+use chrono::Utc;
+use log::{debug, error, info, trace, warn};
+use metricdog::service_check::{ServiceCheck, SystemdCheck};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{setpgid, Pid};
+use semver::Version;
+use serde::Deserialize;
+use signpost::State;
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use snafu::{OptionExt, ResultExt};
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, process};
+use thar_be_updates::status::{Rollback, ROLLBACK_FILE};
+use update_history::{Event, EventType, HISTORY_FILE};
+
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const VERIFIED_VERSION_FILE: &str = "/var/lib/update-verifier/verified-version";
+const UPTIME_FILE: &str = "/proc/uptime";
+const DEFAULT_TIMEOUT: u32 = 600;
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// The least time a check is given, so checks still get a fair try when we start close to, or
+/// after, the deadline.
+const MIN_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often we check whether a command has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long we wait for the rest of a command's error output once it has finished or been stopped.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Store the args we receive on the command line.
+struct Args {
+    log_level: LevelFilter,
+    socket_path: String,
+}
+
+/// The settings update-verifier cares about.
+#[derive(Debug, Default, Deserialize)]
+struct Settings {
+    updates: Option<model::UpdatesSettings>,
+}
+
+/// Something that must be true for the host to be considered healthy.
+#[derive(Debug, Clone, PartialEq)]
+enum Check {
+    /// A systemd unit that must be active.
+    Service(String),
+    /// The host must have joined its orchestrator's cluster.
+    Registration,
+    /// A command that must exit successfully, given as the program and its arguments.
+    Command(Vec<String>),
+}
+
+/// The outcome of running the checks.
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Healthy,
+    /// The checks were still failing at the deadline, for the given reason.
+    Unhealthy(String),
+}
+
+/// Runs individual checks; split out so the verification logic can be tested.
+trait CheckRunner {
+    /// Returns why the check failed, if it did.  Checks that run commands fail if they haven't
+    /// finished within `timeout`.
+    fn run(&self, check: &Check, timeout: Duration) -> std::result::Result<(), String>;
+}
+
+/// Runs checks against the real host.
+struct HostChecks {
+    services: Box<dyn ServiceCheck>,
+}
+
+impl CheckRunner for HostChecks {
+    fn run(&self, check: &Check, timeout: Duration) -> std::result::Result<(), String> {
+        match check {
+            Check::Service(name) => {
+                let health = self
+                    .services
+                    .check(name)
+                    .map_err(|e| format!("unable to check service '{}': {}", name, e))?;
+                if health.is_healthy {
+                    Ok(())
+                } else {
+                    Err(match health.exit_code {
+                        Some(code) => format!("service '{}' failed with exit code {}", name, code),
+                        None => format!("service '{}' is not running", name),
+                    })
+                }
+            }
+            Check::Registration => {
+                run_command(&["sheepdog".to_string(), "registered".to_string()], timeout)
+            }
+            Check::Command(args) => run_command(args, timeout),
+        }
+    }
+}
+
+/// Runs a command, returning its error output if it fails.  The command runs in its own process
+/// group, and is killed along with anything it started if it runs longer than `timeout`.
+/// Anything the command leaves running in the background once it exits is stopped too.
+fn run_command(args: &[String], timeout: Duration) -> std::result::Result<(), String> {
+    let (program, program_args) = args.split_first().ok_or("empty command")?;
+    let command = args.join(" ");
+    debug!("Running '{}'", command);
+    let mut cmd = Command::new(program);
+    cmd.args(program_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    // Safety: the closure only makes a system call, which is safe to do between fork and exec.
+    unsafe {
+        cmd.pre_exec(|| {
+            setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(|_| io::Error::last_os_error())
+        });
+    }
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("unable to run '{}': {}", command, e))?;
+
+    // The error output is read as it's written, so a chatty command can't fill the pipe and stall.
+    // The reading thread sends what it read when it's done, so we can stop waiting for it.
+    let (done_tx, done_rx) = mpsc::channel();
+    if let Some(mut stderr) = child.stderr.take() {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = stderr.read_to_end(&mut output);
+            // The receiver is gone if we stopped waiting, and then the output isn't needed.
+            let _ = done_tx.send(output);
+        });
+    }
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if start.elapsed() >= timeout => break None,
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => {
+                stop_process_group(&child);
+                let _ = child.wait();
+                return Err(format!("unable to wait for '{}': {}", command, e));
+            }
+        }
+    };
+    stop_process_group(&child);
+    let status = match status {
+        Some(status) => status,
+        None => {
+            let _ = child.wait();
+            return Err(format!(
+                "'{}' didn't finish within {} seconds",
+                command,
+                timeout.as_secs()
+            ));
+        }
+    };
+
+    // A process that left the command's process group could keep the output open indefinitely, so
+    // we only wait a little while for the rest of it.
+    let stderr = done_rx.recv_timeout(OUTPUT_GRACE).unwrap_or_default();
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "'{}' failed: {}",
+            command,
+            String::from_utf8_lossy(&stderr).trim()
+        ))
+    }
+}
+
+/// Stops every process in the process group led by `child`, which includes anything the command
+/// started.
+fn stop_process_group(child: &Child) {
+    // The processes may have exited already, so failing to signal them isn't a problem.
+    let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+}
+
+/// Main entry point.
+async fn run() -> Result<()> {
+    let args = parse_args(env::args());
+
+    // SimpleLogger will send errors to stderr and anything less to stdout.
+    SimpleLogger::init(args.log_level, LogConfig::default()).context(error::Logger)?;
+
+    let release = BottlerocketRelease::new().context(error::ReleaseVersion)?;
+    let version = release.version_id;
+    if let Some(event) = boot_fallback(HISTORY_FILE, &version) {
+        warn!("{}", event.message.as_deref().unwrap_or_default());
+        record_history(&event);
+    }
+    if read_verified_version(VERIFIED_VERSION_FILE)?.as_ref() == Some(&version) {
+        debug!("Version {} has already been verified", version);
+        return Ok(());
+    }
+    if !can_roll_back()? {
+        info!(
+            "No previous version to roll back to, accepting version {}",
+            version
+        );
+        return accept(&version);
+    }
+
+    let settings = get_settings(&args.socket_path).await?;
+    let health_checks = settings
+        .updates
+        .and_then(|updates| updates.health_checks)
+        .unwrap_or_else(no_health_checks);
+    let checks = checks(&health_checks)?;
+    if checks.is_empty() {
+        debug!("No health checks configured, accepting version {}", version);
+        return accept(&version);
+    }
+
+    let timeout = health_checks.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    info!(
+        "Checking the health of version {}, with a deadline of {}s after boot",
+        version, timeout
+    );
+    let runner = HostChecks {
+        services: Box::new(SystemdCheck {}),
+    };
+    let deadline = Duration::from_secs(timeout.into());
+    match verify(&checks, &runner, deadline, uptime, CHECK_INTERVAL)? {
+        Verdict::Healthy => {
+            info!("Version {} is healthy", version);
+            accept(&version)?;
+            Rollback::clear(ROLLBACK_FILE).context(error::Rollback)
+        }
+        Verdict::Unhealthy(reason) => {
+            error!("Version {} is unhealthy, rolling back: {}", version, reason);
+            record_history(&Event {
+                version: Some(version.clone()),
+                message: Some(reason.clone()),
+                ..Event::new(EventType::Boot, false)
+            });
+            roll_back(version, reason)
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+fn no_health_checks() -> model::HealthChecks {
+    model::HealthChecks {
+        timeout: None,
+        services: None,
+        registration: None,
+        commands: None,
+    }
+}
+
+/// Turns the health-check settings into the list of checks to run.  Commands are split into
+/// arguments with shell quoting rules.
+fn checks(settings: &model::HealthChecks) -> Result<Vec<Check>> {
+    let mut checks = Vec::new();
+    for service in settings.services.iter().flatten() {
+        checks.push(Check::Service(service.to_string()));
+    }
+    if settings.registration == Some(true) {
+        checks.push(Check::Registration);
+    }
+    for command in settings.commands.iter().flatten() {
+        let args = shell_words::split(command).context(error::CommandParse {
+            command: command.as_ref(),
+        })?;
+        if !args.is_empty() {
+            checks.push(Check::Command(args));
+        }
+    }
+    Ok(checks)
+}
+
+/// Runs the checks until they all pass, or until `deadline` has passed since boot, as measured by
+/// `uptime`.  If they're failing at the deadline, returns the first failure as the reason.  Each
+/// check is given the time left before the deadline, but at least `MIN_CHECK_TIMEOUT`.
+fn verify<R, U>(
+    checks: &[Check],
+    runner: &R,
+    deadline: Duration,
+    uptime: U,
+    interval: Duration,
+) -> Result<Verdict>
+where
+    R: CheckRunner,
+    U: Fn() -> Result<Duration>,
+{
+    loop {
+        let round_uptime = uptime()?;
+        let round_start = Instant::now();
+        let failure = checks.iter().find_map(|check| {
+            let elapsed = round_uptime + round_start.elapsed();
+            let timeout = deadline.saturating_sub(elapsed).max(MIN_CHECK_TIMEOUT);
+            runner.run(check, timeout).err()
+        });
+        let reason = match failure {
+            None => return Ok(Verdict::Healthy),
+            Some(reason) => reason,
+        };
+        if round_uptime + round_start.elapsed() >= deadline {
+            return Ok(Verdict::Unhealthy(reason));
+        }
+        debug!("Not healthy yet: {}", reason);
+        thread::sleep(interval);
+    }
+}
+
+/// Returns whether the previous version's partitions can be booted, without changing anything.
+fn can_roll_back() -> Result<bool> {
+    let mut state = State::load().context(error::PartitionTableRead)?;
+    Ok(state.rollback_to_inactive().is_ok())
+}
+
+/// Records why we're rolling back, switches to the previous version's partitions, and reboots.
+fn roll_back(version: Version, reason: String) -> Result<()> {
+    let rollback = Rollback {
+        from_version: version.clone(),
+        timestamp: Utc::now(),
+        reason,
+    };
+    // The rollback is only reported if this works, but not rolling back would be worse.
+    if let Err(e) = rollback.record(ROLLBACK_FILE) {
+        warn!("{}", e);
+    }
+
+    let mut state = State::load().context(error::PartitionTableRead)?;
+    state
+        .rollback_to_inactive()
+        .context(error::RollbackToInactive)?;
+    state.write().context(error::PartitionTableWrite)?;
+    record_history(&Event {
+        version: Some(version),
+        ..Event::new(EventType::Rollback, true)
+    });
+
+    // Don't go through the API to reboot; it may be part of what's broken.
+    info!("Rebooting into the previous version");
+    let status = Command::new("shutdown")
+        .arg("-r")
+        .status()
+        .context(error::Reboot)?;
+    snafu::ensure!(status.success(), error::RebootFailed { status });
+    Ok(())
+}
+
+/// Marks the running version as verified, so it isn't checked again, and records its successful
+/// boot in the update history.
+fn accept(version: &Version) -> Result<()> {
+    write_verified_version(VERIFIED_VERSION_FILE, version)?;
+    record_history(&Event {
+        version: Some(version.clone()),
+        ..Event::new(EventType::Boot, true)
+    });
+    Ok(())
+}
+
+/// Checks whether the host fell back to the running version because an activated update failed to
+/// boot, in which case the partition table, rather than us, chose the version.  If the last thing
+/// in the history is a successful activation of some other version, returns a boot failure event
+/// to record, which also keeps us from reporting it again next boot.
+fn boot_fallback<P: AsRef<Path>>(history_path: P, version: &Version) -> Option<Event> {
+    let events = match update_history::load(history_path) {
+        Ok(events) => events,
+        Err(e) => {
+            warn!("{}", e);
+            return None;
+        }
+    };
+    let last = events.last()?;
+    let target = last.target_version.as_ref()?;
+    if last.event_type != EventType::Activate || !last.success || target == version {
+        return None;
+    }
+    Some(Event {
+        version: Some(version.clone()),
+        target_version: Some(target.clone()),
+        message: Some(format!(
+            "Version {} failed to boot; the host fell back to version {}",
+            target, version
+        )),
+        ..Event::new(EventType::Boot, false)
+    })
+}
+
+/// Adds an event to the update history.  The history is informational, so failures are only
+/// logged.
+fn record_history(event: &Event) {
+    if let Err(e) = update_history::append(HISTORY_FILE, event) {
+        warn!("Failed to record update history: {}", e);
+    }
+}
+
+/// How long it's been since the host booted.
+fn uptime() -> Result<Duration> {
+    let contents = fs::read_to_string(UPTIME_FILE).context(error::Uptime)?;
+    let seconds = contents
+        .split_whitespace()
+        .next()
+        .and_then(|s| s.parse::<f64>().ok())
+        .context(error::UptimeParse {
+            contents: &contents,
+        })?;
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn read_verified_version<P: AsRef<Path>>(path: P) -> Result<Option<Version>> {
+    let path = path.as_ref();
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(error::VerifiedRead { path }),
+    };
+    // An unreadable record just means we verify again.
+    Ok(Version::parse(data.trim()).ok())
+}
+
+fn write_verified_version<P: AsRef<Path>>(path: P, version: &Version) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(error::VerifiedWrite { path })?;
+    }
+    fs::write(path, version.to_string()).context(error::VerifiedWrite { path })
+}
+
+/// Retrieves the current settings from the API.
+async fn get_settings<P>(socket_path: P) -> Result<Settings>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/settings";
+    let method = "GET";
+    trace!("{}ing from {}", method, uri);
+    let (code, response_body) = apiclient::raw_request(socket_path, &uri, method, None)
+        .await
+        .context(error::APIRequest { method, uri })?;
+
+    if !code.is_success() {
+        return error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+        .fail();
+    }
+    trace!("JSON response: {}", response_body);
+
+    serde_json::from_str(&response_body).context(error::ResponseJson { method, uri })
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --socket-path PATH ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Socket path defaults to {}",
+        program_name, DEFAULT_API_SOCKET,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses the arguments to the program and return a representative `Args`.
+fn parse_args(args: env::Args) -> Args {
+    let mut log_level = None;
+    let mut socket_path = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+    }
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+mod error {
+    use http::StatusCode;
+    use snafu::Snafu;
+    use std::path::PathBuf;
+    use std::process::ExitStatus;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Error {}ing to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when {}ing to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display("Unable to parse health-check command '{}': {}", command, source))]
+        CommandParse {
+            command: String,
+            source: shell_words::ParseError,
+        },
+
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Unable to read partition table: {}", source))]
+        PartitionTableRead { source: signpost::Error },
+
+        #[snafu(display("Unable to write partition table: {}", source))]
+        PartitionTableWrite { source: signpost::Error },
+
+        #[snafu(display("Failed to reboot: {}", source))]
+        Reboot { source: std::io::Error },
+
+        #[snafu(display("Reboot command failed: {}", status))]
+        RebootFailed { status: ExitStatus },
+
+        #[snafu(display("Unable to get OS version: {}", source))]
+        ReleaseVersion { source: bottlerocket_release::Error },
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to '{}': {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("{}", source))]
+        Rollback {
+            source: thar_be_updates::error::Error,
+        },
+
+        #[snafu(display("Unable to roll back to the previous version: {}", source))]
+        RollbackToInactive { source: signpost::Error },
+
+        #[snafu(display("Unable to read uptime: {}", source))]
+        Uptime { source: std::io::Error },
+
+        #[snafu(display("Unable to parse uptime from '{}'", contents))]
+        UptimeParse { contents: String },
+
+        #[snafu(display("Unable to read verified version '{}': {}", path.display(), source))]
+        VerifiedRead {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Unable to write verified version '{}': {}", path.display(), source))]
+        VerifiedWrite {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Fails the given check until it has been run `passes_after` times.
+    struct FakeChecks {
+        failing: Check,
+        passes_after: u32,
+        runs: Cell<u32>,
+    }
+
+    impl CheckRunner for FakeChecks {
+        fn run(&self, check: &Check, _timeout: Duration) -> std::result::Result<(), String> {
+            if *check != self.failing {
+                return Ok(());
+            }
+            self.runs.set(self.runs.get() + 1);
+            if self.runs.get() > self.passes_after {
+                Ok(())
+            } else {
+                Err("not yet".to_string())
+            }
+        }
+    }
+
+    /// An uptime that advances a minute each time it's checked.
+    fn fake_uptime() -> impl Fn() -> Result<Duration> {
+        let minutes = Cell::new(0);
+        move || {
+            minutes.set(minutes.get() + 1);
+            Ok(Duration::from_secs(minutes.get() * 60))
+        }
+    }
+
+    fn all_checks() -> Vec<Check> {
+        vec![
+            Check::Service("containerd".to_string()),
+            Check::Registration,
+            Check::Command(vec!["/usr/bin/true".to_string()]),
+        ]
+    }
+
+    #[test]
+    fn checks_from_settings() {
+        let settings: model::HealthChecks = serde_json::from_str(
+            r#"{
+                "timeout": 60,
+                "services": ["containerd"],
+                "registration": true,
+                "commands": ["/usr/bin/true"]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(checks(&settings).unwrap(), all_checks());
+        assert!(checks(&no_health_checks()).unwrap().is_empty());
+
+        // Quoted arguments stay together
+        let settings: model::HealthChecks =
+            serde_json::from_str(r#"{"commands": ["sh -c \"systemctl is-active foo\""]}"#).unwrap();
+        assert_eq!(
+            checks(&settings).unwrap(),
+            vec![Check::Command(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "systemctl is-active foo".to_string(),
+            ])]
+        );
+    }
+
+    #[test]
+    fn healthy_eventually() {
+        let runner = FakeChecks {
+            failing: Check::Registration,
+            passes_after: 3,
+            runs: Cell::new(0),
+        };
+        let verdict = verify(
+            &all_checks(),
+            &runner,
+            Duration::from_secs(600),
+            fake_uptime(),
+            Duration::from_secs(0),
+        )
+        .unwrap();
+        assert_eq!(verdict, Verdict::Healthy);
+        assert_eq!(runner.runs.get(), 4);
+    }
+
+    #[test]
+    fn unhealthy_at_deadline() {
+        let runner = FakeChecks {
+            failing: Check::Service("containerd".to_string()),
+            passes_after: u32::MAX,
+            runs: Cell::new(0),
+        };
+        let verdict = verify(
+            &all_checks(),
+            &runner,
+            Duration::from_secs(300),
+            fake_uptime(),
+            Duration::from_secs(0),
+        )
+        .unwrap();
+        assert_eq!(verdict, Verdict::Unhealthy("not yet".to_string()));
+        // Checked at 1 through 5 minutes of uptime.
+        assert_eq!(runner.runs.get(), 5);
+    }
+
+    fn command(line: &str) -> Vec<String> {
+        shell_words::split(line).unwrap()
+    }
+
+    #[test]
+    fn failed_command() {
+        let timeout = Duration::from_secs(10);
+        assert!(run_command(&command("true"), timeout).is_ok());
+        assert!(run_command(&command("false"), timeout).is_err());
+        assert!(run_command(&[], timeout).is_err());
+        assert_eq!(
+            run_command(&command("sh -c 'echo oops >&2; exit 1'"), timeout),
+            Err("'sh -c echo oops >&2; exit 1' failed: oops".to_string())
+        );
+    }
+
+    #[test]
+    fn command_timeout() {
+        // The command never exits, and starts a background process that would outlive it if only
+        // the command were stopped.
+        let dir = tempfile::tempdir().unwrap();
+        let pid_path = dir.path().join("pid");
+        let line = format!(
+            "sh -c 'sleep 30 & echo $! > {}; while true; do sleep 1; done'",
+            pid_path.display()
+        );
+        let result = run_command(&command(&line), Duration::from_millis(500));
+        assert!(result.unwrap_err().contains("didn't finish"));
+
+        let pid = fs::read_to_string(&pid_path).unwrap();
+        // Once killed, the sleep is gone, or a zombie if nothing has reaped it yet; it can take a
+        // moment to finish exiting.
+        let start = Instant::now();
+        loop {
+            let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+            if stat.is_empty() || stat.contains(") Z ") {
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "sleep still running: {}",
+                stat
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn verified_version_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("update-verifier").join("verified-version");
+        assert_eq!(read_verified_version(&path).unwrap(), None);
+        let version = Version::parse("1.2.3").unwrap();
+        write_verified_version(&path, &version).unwrap();
+        assert_eq!(read_verified_version(&path).unwrap(), Some(version));
+    }
+
+    #[test]
+    fn detects_boot_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.json");
+        let old = Version::parse("1.1.0").unwrap();
+        let new = Version::parse("1.2.0").unwrap();
+        assert!(boot_fallback(&path, &old).is_none());
+
+        let activate = Event {
+            version: Some(old.clone()),
+            target_version: Some(new.clone()),
+            ..Event::new(EventType::Activate, true)
+        };
+        update_history::append(&path, &activate).unwrap();
+        // Booting the activated version is fine; booting anything else means it fell back.
+        assert!(boot_fallback(&path, &new).is_none());
+        let event = boot_fallback(&path, &old).unwrap();
+        assert_eq!(event.event_type, EventType::Boot);
+        assert!(!event.success);
+        assert_eq!(event.target_version, Some(new));
+
+        // Once recorded, it isn't reported again.
+        update_history::append(&path, &event).unwrap();
+        assert!(boot_fallback(&path, &old).is_none());
+    }
+
+    #[test]
+    fn rollback_record_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("thar-be-updates").join("rollback.json");
+        assert_eq!(Rollback::load(&path).unwrap(), None);
+        let rollback = Rollback {
+            from_version: Version::parse("1.2.3").unwrap(),
+            timestamp: Utc::now(),
+            reason: "service 'kubelet' is not running".to_string(),
+        };
+        rollback.record(&path).unwrap();
+        assert_eq!(Rollback::load(&path).unwrap(), Some(rollback));
+        Rollback::clear(&path).unwrap();
+        assert_eq!(Rollback::load(&path).unwrap(), None);
+    }
+}
@@ -103,6 +103,27 @@ impl ImdsClient {
         self.fetch_string(&node_ip_target).await
     }
 
+    /// Gets the list of IPv6 addresses for a given network interface `mac` address.
+    pub async fn fetch_ipv6_addresses_for_mac(&mut self, mac: &str) -> Result<Option<Vec<String>>> {
+        let mac_ipv6s_target = format!("meta-data/network/interfaces/macs/{}/ipv6s", mac);
+        let ipv6s = self
+            .fetch_string(&mac_ipv6s_target)
+            .await?
+            .map(|ipv6s| ipv6s.lines().map(|s| s.to_string()).collect());
+        Ok(ipv6s)
+    }
+
+    /// Gets the first IPv6 address of the primary network interface from instance metadata.
+    pub async fn fetch_primary_ipv6_address(&mut self) -> Result<Option<String>> {
+        let mac_target = "meta-data/mac";
+        let mac = match self.fetch_string(&mac_target).await? {
+            Some(mac) => mac,
+            None => return Ok(None),
+        };
+        let ipv6s = self.fetch_ipv6_addresses_for_mac(mac.trim()).await?;
+        Ok(ipv6s.and_then(|ipv6s| ipv6s.into_iter().next()))
+    }
+
     /// Gets the instance-type from instance metadata.
     pub async fn fetch_instance_type(&mut self) -> Result<Option<String>> {
         let instance_type_target = "meta-data/instance-type";
@@ -691,6 +712,54 @@ mod test {
         assert_eq!(imds_data, Some(response_body.as_bytes().to_vec()));
     }
 
+    #[tokio::test]
+    async fn fetch_primary_ipv6_address() {
+        let server = Server::run();
+        let port = server.addr().port();
+        let base_uri = format!("http://localhost:{}", port);
+        let token = "some+token";
+        let mac = "0e:a1:b2:c3:d4:e5";
+        server.expect(
+            Expectation::matching(request::method_path("PUT", "/latest/api/token"))
+                .times(1)
+                .respond_with(
+                    status_code(200)
+                        .append_header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+                        .body(token),
+                ),
+        );
+        server.expect(
+            Expectation::matching(request::method_path(
+                "GET",
+                format!("/{}/meta-data/mac", PINNED_SCHEMA),
+            ))
+            .times(1)
+            .respond_with(
+                status_code(200)
+                    .append_header("X-aws-ec2-metadata-token", token)
+                    .body(mac),
+            ),
+        );
+        server.expect(
+            Expectation::matching(request::method_path(
+                "GET",
+                format!(
+                    "/{}/meta-data/network/interfaces/macs/{}/ipv6s",
+                    PINNED_SCHEMA, mac
+                ),
+            ))
+            .times(1)
+            .respond_with(
+                status_code(200)
+                    .append_header("X-aws-ec2-metadata-token", token)
+                    .body("2600:1f14:abc:de00::1\n2600:1f14:abc:de00::2"),
+            ),
+        );
+        let mut imds_client = ImdsClient::new_impl(base_uri).await.unwrap();
+        let imds_data = imds_client.fetch_primary_ipv6_address().await.unwrap();
+        assert_eq!(imds_data, Some("2600:1f14:abc:de00::1".to_string()));
+    }
+
     #[test]
     fn printable_string_short() {
         let input = "Hello".as_bytes();
@@ -3,18 +3,14 @@
 use std::io;
 use std::path::PathBuf;
 
-use reqwest::Url;
+use reqwest::{StatusCode, Url};
 use snafu::{Backtrace, Snafu};
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub(crate)")]
 pub(crate) enum Error {
-    #[snafu(display("Error creating the command stderr file '{}': {}", path.display(), source))]
-    CommandErrFile {
-        source: io::Error,
-        path: PathBuf,
-        backtrace: Backtrace,
-    },
+    #[snafu(display("API request to '{}' failed: {}", uri, stderr))]
+    ApiStatus { uri: String, stderr: String },
 
     #[snafu(display("Error completing command '{}': {}", command, source))]
     CommandFinish {
@@ -29,13 +25,20 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
-    #[snafu(display("Error creating the command stdout file '{}': {}", path.display(), source))]
+    #[snafu(display("Error writing the command output file '{}': {}", path.display(), source))]
     CommandOutputFile {
         source: io::Error,
         path: PathBuf,
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Error reading the output of command '{}': {}", command, source))]
+    CommandOutputRead {
+        command: String,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Error parsing command '{}': {}", command, source))]
     CommandParse {
         source: shell_words::ParseError,
@@ -43,6 +46,13 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Error creating the output pipe for command '{}': {}", command, source))]
+    CommandPipe {
+        command: String,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Error starting command '{}': {}", command, source))]
     CommandSpawn {
         command: String,
@@ -82,9 +92,6 @@ pub(crate) enum Error {
     #[snafu(display("No file to copy from given for request '{}'", request))]
     FileFromEmpty { request: String },
 
-    #[snafu(display("Output filename is missing in request: '{}'", request))]
-    FilenameMissing { request: String },
-
     #[snafu(display("Unable to create HTTP client for '{}': {}", url, source))]
     HttpClient { url: Url, source: reqwest::Error },
 
@@ -94,12 +101,6 @@ pub(crate) enum Error {
     #[snafu(display("HTTP error for '{}': {}", url, source))]
     HttpResponse { url: Url, source: reqwest::Error },
 
-    #[snafu(display("HTTP response body for '{}' could not be read: {}", request, source))]
-    HttpResponseBytes {
-        request: String,
-        source: reqwest::Error,
-    },
-
     #[snafu(display("Unable to send HTTP request to '{}': {}", url, source))]
     HttpSend { url: Url, source: reqwest::Error },
 
@@ -121,8 +122,11 @@ pub(crate) enum Error {
         source: std::io::Error,
     },
 
-    #[snafu(display("Empty command."))]
-    ModeMissing {},
+    #[snafu(display("Error serializing the manifest: {}", source))]
+    ManifestSerialize { source: serde_json::Error },
+
+    #[snafu(display("Error writing the manifest '{}': {}", path.display(), source))]
+    ManifestWrite { path: PathBuf, source: io::Error },
 
     #[snafu(display("Error parsing glob pattern '{}': {}", pattern, source))]
     ParseGlobPattern {
@@ -130,8 +134,31 @@ pub(crate) enum Error {
         source: glob::PatternError,
     },
 
-    #[snafu(display("The logdog configuration has a 'glob' line with no glob instructions."))]
-    PatternMissing {},
+    #[snafu(display("{}", source))]
+    RequestInvalid {
+        source: model::modeled_types::error::Error,
+    },
+
+    #[snafu(display("Sensitive pattern '{}' is invalid: {}", pattern, source))]
+    RedactPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+
+    #[snafu(display("Error reading '{}' to redact it: {}", path.display(), source))]
+    RedactRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error writing redacted '{}': {}", path.display(), source))]
+    RedactWrite { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Log request '{}' panicked", request))]
+    RequestPanic { request: String },
+
+    #[snafu(display("Error reading log request directory '{}': {}", path.display(), source))]
+    RequestsDirRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error reading log request file '{}': {}", path.display(), source))]
+    RequestsFileRead { path: PathBuf, source: io::Error },
 
     #[snafu(display("Cannot write to / as a file."))]
     RootAsFile { backtrace: Backtrace },
@@ -161,6 +188,12 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Sensitive settings from the API are not valid JSON: {}", source))]
+    SensitiveSettingsParse { source: serde_json::Error },
+
+    #[snafu(display("Settings from the API are not valid JSON: {}", source))]
+    SettingsParse { source: serde_json::Error },
+
     #[snafu(display("Error writing to the tarball '{}': {}", path.display(), source))]
     TarballWrite {
         source: io::Error,
@@ -176,6 +209,33 @@ pub(crate) enum Error {
 
     #[snafu(display("Unknown request type '{}' in '{}'", mode, request))]
     UnhandledRequest { mode: String, request: String },
+
+    #[snafu(display("Unable to create HTTP client to upload to '{}': {}", url, source))]
+    UploadClient { url: String, source: reqwest::Error },
+
+    #[snafu(display("Error reading '{}' to upload it: {}", path.display(), source))]
+    UploadRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display(
+        "Failed to upload to '{}' after {} attempts: {}",
+        url,
+        attempts,
+        message
+    ))]
+    UploadRetries {
+        url: String,
+        attempts: u32,
+        message: String,
+    },
+
+    #[snafu(display("Upload URL '{}' must use HTTPS", url))]
+    UploadScheme { url: String },
+
+    #[snafu(display("Upload to '{}' was refused: {}", url, status))]
+    UploadStatus { url: String, status: StatusCode },
+
+    #[snafu(display("No upload URL; give one with --url or set settings.logdog.upload-url"))]
+    UploadUrlMissing,
 }
 
 pub(crate) type Result<T> = std::result::Result<T, Error>;
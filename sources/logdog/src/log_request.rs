@@ -7,16 +7,41 @@
 //! file which points to the log requests for the current variant. This file is named `logdog.conf`.
 //! We load `logdog.conf` and `logdog.common.conf` files into static strings at compile time, and
 //! these provide the list of log requests that `logdog` will run.
+//!
+//! # Runtime Log Requests
+//!
+//! Requests can also be added without rebuilding `logdog`, by listing them in
+//! `settings.logdog.requests`, or in files ending in `.conf` in the `REQUESTS_DIR` directory.
+//! They use the same format as the static requests, and are run after them.
+//!
+//! # Limits
+//!
+//! Each request is stopped if it runs longer than its `Limits` allow, and each file it writes is
+//! cut down to a maximum size, keeping the end, where the most recent logs are.  The outcome of
+//! each request is returned so it can be recorded.
 
 use crate::error::{self, Result};
 use glob::glob;
+use nix::fcntl::OFlag;
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{pipe2, setpgid, Pid};
 use reqwest::blocking::{Client, Response};
+use serde_json::Value;
 use snafu::{ensure, OptionExt, ResultExt};
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
 use std::fs;
 use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 use url::Url;
 use walkdir::WalkDir;
 
@@ -25,17 +50,158 @@ const COMMON_REQUESTS: &str = include_str!("../conf/logdog.common.conf");
 /// The `logdog` log requests that are specific to the current variant.
 const VARIANT_REQUESTS: &str = include_str!("../conf/current/logdog.conf");
 
+/// Where users can add log requests at runtime, in files ending in `.conf`.  This is on the
+/// persistent local filesystem so the requests survive reboots.
+pub(crate) const REQUESTS_DIR: &str = "/local/logdog.d";
+/// The modes a log request can have.
+const MODES: &[&str] = &["exec", "http", "https", "file", "glob"];
+/// How long a request can run by default.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+/// How large each output file can be by default.
+pub(crate) const DEFAULT_MAX_OUTPUT_SIZE: u64 = 64 * 1024 * 1024;
+/// How often we check whether a command has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long we wait for the rest of a command's output once it has finished or been stopped.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Limits on how long a log request can run and how much output it can keep.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    /// How long a command or HTTP request can run before it's stopped.
+    pub(crate) timeout: Duration,
+    /// How many bytes each output file can have; beyond this, the start of the file is dropped.
+    pub(crate) max_output_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            max_output_size: DEFAULT_MAX_OUTPUT_SIZE,
+        }
+    }
+}
+
+/// What happened when a log request ran.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Outcome {
+    /// The exit status of an `exec` request's command, if it exited normally.
+    pub(crate) exit_status: Option<i32>,
+    /// Whether the request was stopped for running longer than its timeout.
+    pub(crate) timed_out: bool,
+    /// Whether any output was dropped for being larger than the maximum size.
+    pub(crate) truncated: bool,
+}
+
 /// Returns the list of log requests to run by combining `VARIANT_REQUESTS` and `COMMON_REQUESTS`.
 /// These are read at compile time from files named `logdog.conf` and `logdog.common.conf`
 /// respectively.
 pub(crate) fn log_requests() -> Vec<&'static str> {
-    COMMON_REQUESTS
-        .lines()
-        .chain(VARIANT_REQUESTS.lines())
-        .filter(|&command| !command.is_empty() && !command.trim_start().starts_with('#'))
+    request_lines(COMMON_REQUESTS)
+        .chain(request_lines(VARIANT_REQUESTS))
+        .collect()
+}
+
+/// Returns the log requests in the text of a request file, skipping blank lines and comments.
+fn request_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .filter(|&command| !command.trim().is_empty() && !command.trim_start().starts_with('#'))
+}
+
+/// Returns the log requests added at runtime, from files in `REQUESTS_DIR` and from
+/// `settings.logdog.requests`, which is read through `api`.  Requests that can't be parsed are
+/// skipped, and failing to load either source only prints a warning, so the static requests still
+/// run.
+pub(crate) fn user_log_requests(api: &dyn ApiClient) -> Vec<String> {
+    let mut requests = Vec::new();
+    match drop_in_requests(REQUESTS_DIR) {
+        Ok(found) => requests.extend(found),
+        Err(e) => eprintln!("Unable to load log requests from '{}': {}", REQUESTS_DIR, e),
+    }
+    match settings_requests(api) {
+        Ok(found) => requests.extend(found),
+        Err(e) => eprintln!("Unable to load log requests from settings: {}", e),
+    }
+    requests
+        .into_iter()
+        .filter(|request| match LogRequest::parse(request) {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("Skipping invalid log request: {}", e);
+                false
+            }
+        })
         .collect()
 }
 
+/// Reads the log requests from files ending in `.conf` in `dir`, in order of filename.  A missing
+/// directory has no requests.
+fn drop_in_requests<P: AsRef<Path>>(dir: P) -> Result<Vec<String>> {
+    let dir = dir.as_ref();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(error::RequestsDirRead { path: dir }),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry.context(error::RequestsDirRead { path: dir })?.path();
+        if path.is_file() && path.extension() == Some(OsStr::new("conf")) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut requests = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(&path).context(error::RequestsFileRead { path: &path })?;
+        requests.extend(request_lines(&text).map(String::from));
+    }
+    Ok(requests)
+}
+
+/// Gets the log requests listed in `settings.logdog.requests`.
+fn settings_requests(api: &dyn ApiClient) -> Result<Vec<String>> {
+    let response = api.get("/settings?keys=settings.logdog.requests")?;
+    let settings: Value = serde_json::from_slice(&response).context(error::SettingsParse)?;
+    Ok(settings
+        .pointer("/logdog/requests")
+        .and_then(|requests| requests.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|request| request.as_str())
+        .map(String::from)
+        .collect())
+}
+
+/// Makes GET requests to the Bottlerocket API.
+pub(crate) trait ApiClient {
+    /// Returns the body of the response to a GET request for `uri`.
+    fn get(&self, uri: &str) -> Result<Vec<u8>>;
+}
+
+/// Makes API requests by calling `apiclient`.
+pub(crate) struct CommandApiClient {}
+
+impl ApiClient for CommandApiClient {
+    fn get(&self, uri: &str) -> Result<Vec<u8>> {
+        let output = Command::new("apiclient")
+            .args(["--method", "GET", "--uri", uri])
+            .output()
+            .context(error::CommandSpawn {
+                command: format!("apiclient --method GET --uri {}", uri),
+            })?;
+        ensure!(
+            output.status.success(),
+            error::ApiStatus {
+                uri,
+                stderr: String::from_utf8_lossy(&output.stderr),
+            }
+        );
+        Ok(output.stdout)
+    }
+}
+
 /// A logdog `LogRequest` represents a line from the config file. It starts with a "mode" that
 /// specifies what type of request it is, e.g. `exec ` for a command or `http` for an HTTP get
 /// request. Some modes then require a `filename` that determines where the data will be saved in
@@ -95,49 +261,143 @@ impl ToString for LogRequest<'_> {
     }
 }
 
-/// Runs a `LogRequest` and writes its output to a file in `tempdir`.
-pub(crate) fn handle_log_request<S, P>(request: S, tempdir: P) -> Result<()>
+impl<'a> LogRequest<'a> {
+    /// Parses a line from a config file into a `LogRequest`, checking that it has a known mode,
+    /// and the fields that mode needs.  The API checks requests in settings with the same parser.
+    fn parse(request: &'a str) -> Result<Self> {
+        let parts =
+            model::modeled_types::LogRequest::parse(request).context(error::RequestInvalid)?;
+        Ok(LogRequest {
+            mode: parts.mode,
+            filename: parts.filename,
+            instructions: parts.instructions,
+        })
+    }
+}
+
+/// Runs a `LogRequest` within the given limits and writes its output to a file in `tempdir`.
+pub(crate) fn handle_log_request<S, P>(request: S, tempdir: P, limits: &Limits) -> Result<Outcome>
 where
     S: AsRef<str>,
     P: AsRef<Path>,
 {
     let request = request.as_ref();
-    let mut iter = request.splitn(3, ' ');
-    let mode = iter.next().context(error::ModeMissing)?;
-    let req = if mode == "glob" {
-        // for glob request format is "glob <pattern>"
-        LogRequest {
-            mode,
-            filename: "",
-            instructions: iter.next().context(error::PatternMissing)?,
-        }
-    } else {
-        // Get the second token (output filename) and put the remainder of the
-        // log request into the instructions field (or default to an empty string).
-        LogRequest {
-            mode,
-            filename: iter.next().context(error::FilenameMissing { request })?,
-            instructions: iter.next().unwrap_or(""),
-        }
-    };
+    let req = LogRequest::parse(request)?;
     // execute the log request with the correct handler based on the mode field.
-    match req.mode {
-        "exec" => handle_exec_request(&req, tempdir)?,
-        "http" | "https" => handle_http_request(&req, tempdir)?,
-        "file" => handle_file_request(&req, tempdir)?,
-        "glob" => handle_glob_request(&req, tempdir)?,
+    let outcome = match req.mode {
+        "exec" => handle_exec_request(&req, tempdir, limits)?,
+        "http" | "https" => handle_http_request(&req, tempdir, limits)?,
+        "file" => handle_file_request(&req, tempdir, limits)?,
+        "glob" => handle_glob_request(&req, tempdir, limits)?,
         unmatched => {
             return Err(error::Error::UnhandledRequest {
                 mode: unmatched.into(),
                 request: request.into(),
             })
         }
+    };
+    Ok(outcome)
+}
+
+/// Keeps the last `max_size` bytes written to it, where the most recent logs are, so output is
+/// capped as it's read instead of after all of it has been stored.
+struct OutputTail {
+    kept: VecDeque<u8>,
+    max_size: usize,
+    dropped: u64,
+}
+
+impl OutputTail {
+    fn new(max_size: u64) -> Self {
+        Self {
+            kept: VecDeque::new(),
+            max_size: usize::try_from(max_size).unwrap_or(usize::MAX),
+            dropped: 0,
+        }
     }
-    Ok(())
+
+    /// Writes the kept output to a file at `path`, noting how much was dropped.  Returns whether
+    /// any output was dropped.
+    fn save(&self, path: &Path) -> io::Result<bool> {
+        let mut file = File::create(path)?;
+        if self.dropped > 0 {
+            writeln!(
+                file,
+                "[logdog: dropped the first {} bytes of this output to stay under {} bytes]",
+                self.dropped, self.max_size
+            )?;
+        }
+        let (front, back) = self.kept.as_slices();
+        file.write_all(front)?;
+        file.write_all(back)?;
+        Ok(self.dropped > 0)
+    }
+}
+
+impl Write for OutputTail {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // Only the end of the data can be kept if it's larger than the limit by itself.
+        let keep = &data[data.len().saturating_sub(self.max_size)..];
+        let overflow = (self.kept.len() + keep.len()).saturating_sub(self.max_size);
+        self.kept.drain(..overflow);
+        self.kept.extend(keep);
+        self.dropped += (overflow + data.len() - keep.len()) as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An `OutputTail` shared with the thread reading a command's output.
+struct SharedTail(Arc<Mutex<OutputTail>>);
+
+impl Write for SharedTail {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        lock(&self.0).write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Locks `tail`; a thread that panicked while holding it can't have left it inconsistent.
+fn lock(tail: &Mutex<OutputTail>) -> MutexGuard<'_, OutputTail> {
+    tail.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Copies everything from `reader` to a file at `path`, keeping at most the last `max_size`
+/// bytes.  Returns whether any output was dropped.
+fn copy_output<R: Read>(reader: &mut R, path: &Path, max_size: u64) -> io::Result<bool> {
+    let mut tail = OutputTail::new(max_size);
+    io::copy(reader, &mut tail)?;
+    tail.save(path)
+}
+
+/// Creates a pipe for a command's output, returning its read and write ends.  Both ends are
+/// closed on exec, so commands running in parallel don't hold each other's output open; the
+/// command's own copies are duplicated onto its stdout and stderr, which stay open.
+fn output_pipe() -> io::Result<(File, File)> {
+    let (read_fd, write_fd) =
+        pipe2(OFlag::O_CLOEXEC).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    // Safety: the descriptors were just created and nothing else owns them.
+    unsafe { Ok((File::from_raw_fd(read_fd), File::from_raw_fd(write_fd))) }
+}
+
+/// Stops every process in the process group led by `child`, which includes anything the command
+/// started.
+fn stop_process_group(child: &Child) {
+    // The processes may have exited already, so failing to signal them isn't a problem.
+    let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
 }
 
-/// Runs an `exec` `LogRequest`'s `instructions` and writes its output to to `tempdir`.
-fn handle_exec_request<P>(request: &LogRequest<'_>, tempdir: P) -> Result<()>
+/// Runs an `exec` `LogRequest`'s `instructions` and writes its output to to `tempdir`.  The command
+/// runs in its own process group, and is killed along with anything it started if it runs longer
+/// than the timeout; whatever it wrote so far is kept.  Anything the command leaves running in the
+/// background once it exits is stopped too.
+fn handle_exec_request<P>(request: &LogRequest<'_>, tempdir: P, limits: &Limits) -> Result<Outcome>
 where
     P: AsRef<Path>,
 {
@@ -148,27 +408,91 @@ where
         request: request.to_string(),
     })?;
     let outpath = tempdir.as_ref().join(request.filename);
-    let ofile = File::create(&outpath).context(error::CommandOutputFile { path: &outpath })?;
-    let stderr_file = ofile
-        .try_clone()
-        .context(error::CommandErrFile { path: &outpath })?;
-    Command::new(command)
-        .args(args)
-        .stdout(Stdio::from(ofile))
-        .stderr(Stdio::from(stderr_file))
-        .spawn()
-        .with_context(|| error::CommandSpawn {
-            command: request.to_string(),
-        })?
-        .wait_with_output()
-        .with_context(|| error::CommandFinish {
-            command: request.to_string(),
+    let request_text = request.to_string();
+    let (mut reader, writer) = output_pipe().context(error::CommandPipe {
+        command: &request_text,
+    })?;
+    let stderr_writer = writer.try_clone().context(error::CommandPipe {
+        command: &request_text,
+    })?;
+    // The command is dropped once it's spawned, closing our copies of the pipe's write end, so
+    // reading ends when the command and anything it started have exited.  It runs in its own
+    // process group so it can be stopped along with anything it starts.
+    let mut cmd = Command::new(command);
+    cmd.args(args).stdout(writer).stderr(stderr_writer);
+    // Safety: the closure only makes a system call, which is safe to do between fork and exec.
+    unsafe {
+        cmd.pre_exec(|| {
+            setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(|_| io::Error::last_os_error())
+        });
+    }
+    let mut child = cmd.spawn().with_context(|| error::CommandSpawn {
+        command: request.to_string(),
+    })?;
+    drop(cmd);
+
+    // The output is read as it's written so it's capped without storing all of it.  The reading
+    // thread sends its result when it's done, so we can stop waiting for it.
+    let tail = Arc::new(Mutex::new(OutputTail::new(limits.max_output_size)));
+    let (done_tx, done_rx) = mpsc::channel();
+    {
+        let mut shared = SharedTail(Arc::clone(&tail));
+        thread::spawn(move || {
+            // The receiver is gone if we stopped waiting, and then the result isn't needed.
+            let _ = done_tx.send(io::copy(&mut reader, &mut shared));
+        });
+    }
+
+    let start = Instant::now();
+    let mut outcome = Outcome::default();
+    loop {
+        let finished = child.try_wait().context(error::CommandFinish {
+            command: &request_text,
         })?;
-    Ok(())
+        if let Some(status) = finished {
+            outcome.exit_status = status.code();
+            break;
+        }
+        if start.elapsed() >= limits.timeout {
+            stop_process_group(&child);
+            child.wait().context(error::CommandFinish {
+                command: &request_text,
+            })?;
+            outcome.timed_out = true;
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    stop_process_group(&child);
+
+    // A process that left the command's process group could keep the output open indefinitely, so
+    // we only wait a little while for the rest of the output, and keep what we have.
+    if let Ok(Err(e)) = done_rx.recv_timeout(OUTPUT_GRACE) {
+        return Err(e).context(error::CommandOutputRead {
+            command: &request_text,
+        });
+    }
+    outcome.truncated = lock(&tail)
+        .save(&outpath)
+        .context(error::CommandOutputFile { path: &outpath })?;
+
+    if outcome.timed_out {
+        let mut ofile = fs::OpenOptions::new()
+            .append(true)
+            .open(&outpath)
+            .context(error::CommandOutputFile { path: &outpath })?;
+        writeln!(
+            ofile,
+            "\n[logdog: stopped the command after {} seconds]",
+            limits.timeout.as_secs()
+        )
+        .context(error::CommandOutputFile { path: &outpath })?;
+    }
+    Ok(outcome)
 }
 
 /// Executes an `http` `LogRequest` and writes the response body to a file in `tempdir`.
-fn handle_http_request<P>(request: &LogRequest<'_>, tempdir: P) -> Result<()>
+fn handle_http_request<P>(request: &LogRequest<'_>, tempdir: P, limits: &Limits) -> Result<Outcome>
 where
     P: AsRef<Path>,
 {
@@ -179,21 +503,26 @@ where
         }
     );
     let outpath = tempdir.as_ref().join(request.filename);
-    let response = send_get_request(request.instructions)?;
-    let data = response.bytes().with_context(|| error::HttpResponseBytes {
-        request: request.to_string(),
-    })?;
-    fs::write(&outpath, &data).with_context(|| error::HttpWriteBytes {
-        request: request.to_string(),
-        path: &outpath,
-    })?;
-    Ok(())
+    let mut response = send_get_request(request.instructions, limits.timeout)?;
+    let truncated =
+        copy_output(&mut response, &outpath, limits.max_output_size).with_context(|| {
+            error::HttpWriteBytes {
+                request: request.to_string(),
+                path: &outpath,
+            }
+        })?;
+    Ok(Outcome {
+        truncated,
+        ..Outcome::default()
+    })
 }
 
-/// Uses the reqwest library to send a GET request to `URL` and returns the response.
-fn send_get_request(url: &str) -> Result<Response> {
+/// Uses the reqwest library to send a GET request to `URL` and returns the response.  The whole
+/// request, including reading the body, must finish within `timeout`.
+fn send_get_request(url: &str, timeout: Duration) -> Result<Response> {
     let url = Url::parse(&url).context(error::HttpUrlParse { url })?;
     let client = Client::builder()
+        .timeout(timeout)
         .build()
         .with_context(|| error::HttpClient { url: url.clone() })?;
     let response = client
@@ -208,7 +537,7 @@ fn send_get_request(url: &str) -> Result<Response> {
 
 /// Copies a file from the path given by `request.instructions` to the tempdir with filename given
 /// by `request.filename`.
-fn handle_file_request<P>(request: &LogRequest<'_>, tempdir: P) -> Result<()>
+fn handle_file_request<P>(request: &LogRequest<'_>, tempdir: P, limits: &Limits) -> Result<Outcome>
 where
     P: AsRef<Path>,
 {
@@ -219,17 +548,22 @@ where
         }
     );
     let dest = tempdir.as_ref().join(request.filename);
-    let _ = fs::copy(&request.instructions, &dest).with_context(|| error::FileCopy {
-        request: request.to_string(),
-        from: request.instructions,
-        to: &dest,
-    })?;
-    Ok(())
+    let truncated = File::open(request.instructions)
+        .and_then(|mut file| copy_output(&mut file, &dest, limits.max_output_size))
+        .with_context(|| error::FileCopy {
+            request: request.to_string(),
+            from: request.instructions,
+            to: &dest,
+        })?;
+    Ok(Outcome {
+        truncated,
+        ..Outcome::default()
+    })
 }
 
 /// Copies all files matching the glob pattern given by `request.instructions` to the tempdir with filename and path
 /// same as source file.
-fn handle_glob_request<P>(request: &LogRequest<'_>, tempdir: P) -> Result<()>
+fn handle_glob_request<P>(request: &LogRequest<'_>, tempdir: P, limits: &Limits) -> Result<Outcome>
 where
     P: AsRef<Path>,
 {
@@ -253,6 +587,7 @@ where
             }
         }
     }
+    let mut outcome = Outcome::default();
     for src_filepath in &files {
         // with glob pattern there are chances of multiple targets with same name, therefore
         // we maintain source file path and name in destination directory.
@@ -266,21 +601,27 @@ where
         fs::create_dir_all(dest_dir_path).context(error::CreateOutputDirectory {
             path: dest_dir_path,
         })?;
-        let _ = fs::copy(&src_filepath, &dest_filepath).with_context(|| error::FileCopy {
-            request: request.to_string(),
-            from: src_filepath.to_str().unwrap_or("<unknown>"),
-            to: &dest_filepath,
-        })?;
+        outcome.truncated |= File::open(src_filepath)
+            .and_then(|mut file| copy_output(&mut file, &dest_filepath, limits.max_output_size))
+            .with_context(|| error::FileCopy {
+                request: request.to_string(),
+                from: src_filepath.to_str().unwrap_or("<unknown>"),
+                to: &dest_filepath,
+            })?;
     }
-    Ok(())
+    Ok(outcome)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::log_request::handle_log_request;
+    use crate::log_request::{
+        drop_in_requests, handle_log_request, log_requests, Limits, LogRequest,
+    };
+    use std::convert::TryFrom;
     use std::fs;
     use std::fs::write;
     use std::path::PathBuf;
+    use std::time::{Duration, Instant};
     use tempfile::TempDir;
 
     // adds a sub directory and some files to temp directory for file request tests
@@ -335,7 +676,7 @@ mod test {
         write(&source_filepath, want).unwrap();
         let request = format!("file foo-bar {}", source_filepath.display());
         let outdir = TempDir::new().unwrap();
-        handle_log_request(&request, outdir.path()).unwrap();
+        handle_log_request(&request, outdir.path(), &Limits::default()).unwrap();
         let outfile = outdir.path().join("foo-bar");
         let got = std::fs::read_to_string(&outfile).unwrap();
         assert_eq!(got, want);
@@ -346,7 +687,7 @@ mod test {
         let want = "hello world! \"quoted\"\n";
         let request = r#"exec output-file.txt echo 'hello' "world!" "\"quoted\"""#;
         let outdir = TempDir::new().unwrap();
-        handle_log_request(&request, outdir.path()).unwrap();
+        handle_log_request(&request, outdir.path(), &Limits::default()).unwrap();
         let outfile = outdir.path().join("output-file.txt");
         let got = std::fs::read_to_string(&outfile).unwrap();
         assert_eq!(got, want);
@@ -359,7 +700,7 @@ mod test {
         create_source_dir(&source_dir);
         let outdir = TempDir::new().unwrap();
         let request = format!("glob {}/foo.source", source_dir.path().display());
-        handle_log_request(&request, outdir.path()).unwrap();
+        handle_log_request(&request, outdir.path(), &Limits::default()).unwrap();
         assert_file_match(&outdir, get_dest_filepath(&source_dir, "foo.source"), "1");
     }
 
@@ -370,7 +711,7 @@ mod test {
         create_source_dir(&source_dir);
         let outdir = TempDir::new().unwrap();
         let request = format!("glob {}/*.source", source_dir.path().display());
-        handle_log_request(&request, outdir.path()).unwrap();
+        handle_log_request(&request, outdir.path(), &Limits::default()).unwrap();
         assert_file_match(&outdir, get_dest_filepath(&source_dir, "foo.source"), "1");
         assert_file_match(&outdir, get_dest_filepath(&source_dir, "bar.source"), "2");
     }
@@ -382,7 +723,7 @@ mod test {
         create_source_dir(&source_dir);
         let outdir = TempDir::new().unwrap();
         let request = format!("glob {}/**/*.source", source_dir.path().display());
-        handle_log_request(&request, outdir.path()).unwrap();
+        handle_log_request(&request, outdir.path(), &Limits::default()).unwrap();
         assert_file_match(&outdir, get_dest_filepath(&source_dir, "foo.source"), "1");
         assert_file_match(&outdir, get_dest_filepath(&source_dir, "bar.source"), "2");
         assert_file_match(
@@ -414,7 +755,7 @@ mod test {
         create_source_dir(&source_dir);
         let outdir = TempDir::new().unwrap();
         let request = format!("glob {}/**/", source_dir.path().display());
-        handle_log_request(&request, outdir.path()).unwrap();
+        handle_log_request(&request, outdir.path(), &Limits::default()).unwrap();
         assert_file_match(
             &outdir,
             get_dest_filepath(&source_dir, "depth1/foo.source"),
@@ -452,7 +793,173 @@ mod test {
     fn glob_empty_pattern_request() {
         let outdir = TempDir::new().unwrap();
         let request = "glob";
-        let err = handle_log_request(&request, outdir.path()).unwrap_err();
-        assert!(matches!(err, crate::error::Error::PatternMissing {}));
+        let err = handle_log_request(&request, outdir.path(), &Limits::default()).unwrap_err();
+        assert!(matches!(err, crate::error::Error::RequestInvalid { .. }));
+    }
+
+    #[test]
+    fn exec_request_timeout() {
+        let limits = Limits {
+            timeout: Duration::from_millis(200),
+            ..Limits::default()
+        };
+        let outdir = TempDir::new().unwrap();
+        let start = Instant::now();
+        let outcome = handle_log_request(
+            "exec slow.txt sh -c 'echo started; sleep 10'",
+            outdir.path(),
+            &limits,
+        )
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(outcome.timed_out);
+        assert_eq!(outcome.exit_status, None);
+        let got = std::fs::read_to_string(outdir.path().join("slow.txt")).unwrap();
+        assert!(got.starts_with("started\n"));
+        assert!(got.contains("stopped the command"));
+    }
+
+    #[test]
+    fn exec_request_timeout_stops_process_group() {
+        let limits = Limits {
+            timeout: Duration::from_millis(200),
+            ..Limits::default()
+        };
+        let outdir = TempDir::new().unwrap();
+        handle_log_request(
+            "exec slow.txt sh -c 'sleep 30 & echo $!; wait'",
+            outdir.path(),
+            &limits,
+        )
+        .unwrap();
+        let got = std::fs::read_to_string(outdir.path().join("slow.txt")).unwrap();
+        let pid = got.lines().next().unwrap();
+        // Once killed, the sleep is gone, or a zombie if nothing has reaped it yet; it can take a
+        // moment to finish exiting.
+        let start = Instant::now();
+        loop {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+            if stat.is_empty() || stat.contains(") Z ") {
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "sleep still running: {}",
+                stat
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn file_request_truncated() {
+        let limits = Limits {
+            max_output_size: 10,
+            ..Limits::default()
+        };
+        let srcdir = TempDir::new().unwrap();
+        let src = srcdir.path().join("big.log");
+        write(&src, "0123456789abcdefghij\n").unwrap();
+        let outdir = TempDir::new().unwrap();
+        let outcome = handle_log_request(
+            format!("file big.txt {}", src.display()),
+            outdir.path(),
+            &limits,
+        )
+        .unwrap();
+        assert!(outcome.truncated);
+        let got = std::fs::read_to_string(outdir.path().join("big.txt")).unwrap();
+        assert!(got.starts_with("[logdog: dropped the first 11 bytes"));
+        assert!(got.ends_with("bytes]\nbcdefghij\n"));
+    }
+
+    #[test]
+    fn exec_request_truncated() {
+        let limits = Limits {
+            max_output_size: 10,
+            ..Limits::default()
+        };
+        let outdir = TempDir::new().unwrap();
+        let outcome = handle_log_request(
+            "exec big.txt sh -c 'echo 0123456789abcdefghij; exit 3'",
+            outdir.path(),
+            &limits,
+        )
+        .unwrap();
+        assert_eq!(outcome.exit_status, Some(3));
+        assert!(outcome.truncated);
+        let got = std::fs::read_to_string(outdir.path().join("big.txt")).unwrap();
+        assert!(got.starts_with("[logdog: dropped the first 11 bytes"));
+        assert!(got.ends_with("bytes]\nbcdefghij\n"));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_requests() {
+        assert!(LogRequest::parse("exec hello.txt echo hello").is_ok());
+        assert!(LogRequest::parse("glob /var/log/*.log").is_ok());
+        for request in &[
+            "fetch hello.txt /etc/hello",
+            "file ../escape /etc/hello",
+            "file sub/dir /etc/hello",
+            "exec .. ls",
+        ] {
+            assert!(matches!(
+                LogRequest::parse(request).unwrap_err(),
+                crate::error::Error::RequestInvalid { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_matches_settings_validation() {
+        let requests = log_requests().into_iter().chain(vec![
+            "",
+            "exec hello.txt echo hello",
+            "exec hello.txt",
+            "exec hello.txt  ",
+            "exec hello.txt echo 'unclosed",
+            "exec .. ls",
+            "http status.json",
+            "https status.json https://example.com/status",
+            "file my-app.log /local/my-app/app.log",
+            "file sub/dir /etc/hello",
+            "glob /var/log/*.log",
+            "glob",
+            "fetch hello.txt /etc/hello",
+        ]);
+        for request in requests {
+            assert_eq!(
+                LogRequest::parse(request).is_ok(),
+                model::modeled_types::LogRequest::try_from(request).is_ok(),
+                "{}",
+                request
+            );
+        }
+    }
+
+    #[test]
+    fn static_requests_valid() {
+        for request in log_requests() {
+            LogRequest::parse(request).unwrap();
+        }
+    }
+
+    #[test]
+    fn drop_in_requests_read_in_order() {
+        let dir = TempDir::new().unwrap();
+        assert!(drop_in_requests(dir.path().join("missing"))
+            .unwrap()
+            .is_empty());
+        write(dir.path().join("20-second.conf"), "exec b.txt echo b\n").unwrap();
+        write(
+            dir.path().join("10-first.conf"),
+            "# comment\n\nexec a.txt echo a\n",
+        )
+        .unwrap();
+        write(dir.path().join("ignored.txt"), "exec c.txt echo c\n").unwrap();
+        assert_eq!(
+            drop_in_requests(dir.path()).unwrap(),
+            vec!["exec a.txt echo a", "exec b.txt echo b"]
+        );
     }
 }
@@ -19,6 +19,56 @@ For the log requests used to gather logs, please see the following:
 * And the variant-specific files in [conf](conf/), one of which is selected by [build.rs](build.rs)
 based on the value of the `VARIANT` environment variable at build time.
 
+You can add your own log requests without rebuilding, for example to collect logs from your own
+host containers.  List them in `settings.logdog.requests`, or in files ending in `.conf` in
+`/local/logdog.d`, using the same format as the files above.
+
+# Redaction
+
+Before the logs are archived, secrets are removed from them so the archive can be shared, for
+example with support.  The values of sensitive settings, like `settings.kubernetes.bootstrap-token`
+and host container user data, are replaced with `REDACTED` wherever they appear, as is anything
+that looks like a credential, like a bootstrap token, a password in a URL, or a private key.
+Settings are sensitive if they're marked `sensitive` in the API's metadata.  Host container and
+bootstrap container user data are always redacted, because users name those containers themselves
+and they can't be marked ahead of time.
+
+If you need the logs exactly as collected, and won't be sharing them, you can pass `--no-redact`.
+
+# Limits
+
+Requests run several at a time, 4 by default, or as many as given by `--parallel`.
+Each request is stopped after 120 seconds, or the number of seconds given by `--timeout`, and each
+output file is limited to 64 MiB, or the number of bytes given by `--max-output-size`.  When a file
+is too large, the start of it is dropped as it's collected, keeping the most recent logs, and a
+note at the top of the file says how much was dropped.  Commands run in their own process group;
+when one finishes or is stopped, anything it started is stopped too.
+
+The archive includes `logdog.manifest.json`, which lists each request along with how long it took,
+its exit status if it ran a command, and whether it timed out or was truncated.
+
+# Upload
+
+Rather than fetching the archive through the admin container, you can have logdog send it
+somewhere with `logdog upload`.  It collects the logs as usual, keeping the archive on local disk,
+then sends it with an HTTP PUT to the URL given by `--url`, or to `settings.logdog.upload-url` if
+no URL is given.  This works with a pre-signed S3 URL, or the equivalent for an S3-compatible
+store.  Only pre-signed URLs are supported: logdog doesn't have credentials for the store and
+doesn't sign requests with SigV4, so the URL has to be pre-signed for a PUT before it's given to
+logdog.
+
+```
+$ logdog upload --url 'https://my-bucket.s3.amazonaws.com/host-logs.tar.gz?X-Amz-...'
+logs are at: /tmp/bottlerocket-logs.tar.gz
+logs were uploaded to: https://my-bucket.s3.amazonaws.com/host-logs.tar.gz
+```
+
+The URL must use HTTPS, unless it's for a loopback address.  The archive's MD5 digest is sent in
+the `Content-MD5` header, so the store can reject an archive that was corrupted on the way.
+Failed attempts are retried a few times if the failure may be temporary, like a dropped connection
+or a server error.  Pre-signed URLs contain credentials, so logdog only prints the URL without its
+query string.
+
 */
 
 #![deny(rust_2018_idioms)]
@@ -26,18 +76,34 @@ based on the value of the `VARIANT` environment variable at build time.
 mod create_tarball;
 mod error;
 mod log_request;
+mod redact;
+mod upload;
 
 use create_tarball::create_tarball;
 use error::Result;
-use log_request::{handle_log_request, log_requests};
+use log_request::{
+    handle_log_request, log_requests, user_log_requests, ApiClient, CommandApiClient, Limits,
+};
+use redact::redact_logs;
+use serde::Serialize;
 use snafu::{ErrorCompat, ResultExt};
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{env, process};
 use tempfile::TempDir;
+use upload::{display_url, resolve_upload_url, upload};
 
 const ERROR_FILENAME: &str = "logdog.errors";
+const MANIFEST_FILENAME: &str = "logdog.manifest.json";
+/// How many requests run at the same time by default.
+const DEFAULT_PARALLEL: usize = 4;
 const OUTPUT_FILENAME: &str = "bottlerocket-logs.tar.gz";
 const TARBALL_DIRNAME: &str = "bottlerocket-logs";
 
@@ -45,10 +111,20 @@ const TARBALL_DIRNAME: &str = "bottlerocket-logs";
 fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
     eprintln!(
-        r"Usage: {}
+        r"Usage: {} [ upload [ --url URL ] ]
+            [ upload ]              send the archive somewhere after writing it
+            [ --url URL ]           where to upload; default settings.logdog.upload-url
             [ --output PATH ]       where to write archived logs
+            [ --no-redact ]         don't remove secrets from the logs
+            [ --timeout SECONDS ]   stop each request after this long; default {}
+            [ --max-output-size BYTES ]
+                                    keep at most this much of each output file; default {}
+            [ --parallel COUNT ]    how many requests to run at once; default {}
 ",
         program_name,
+        log_request::DEFAULT_TIMEOUT.as_secs(),
+        log_request::DEFAULT_MAX_OUTPUT_SIZE,
+        DEFAULT_PARALLEL,
     );
     process::exit(2);
 }
@@ -59,33 +135,105 @@ fn usage_msg(msg: &str) -> ! {
     usage();
 }
 
+/// Stores the command line arguments.
+struct Args {
+    outpath: PathBuf,
+    redact: bool,
+    limits: Limits,
+    parallel: usize,
+    upload: bool,
+    upload_url: Option<String>,
+}
+
+/// Parses a numeric argument, exiting with usage if it's missing or invalid.
+fn parse_number<T: FromStr>(arg: &str, value: Option<String>) -> T {
+    value
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| usage_msg(&format!("Did not give a valid number to {}", arg)))
+}
+
 /// Parses the command line arguments.
-fn parse_args(args: env::Args) -> PathBuf {
+fn parse_args(args: env::Args) -> Args {
     let mut output_arg = None;
-    let mut iter = args.skip(1);
+    let mut redact = true;
+    let mut limits = Limits::default();
+    let mut parallel = DEFAULT_PARALLEL;
+    let mut upload = false;
+    let mut upload_url = None;
+    let mut iter = args.skip(1).peekable();
+    if iter.peek().map(String::as_str) == Some("upload") {
+        upload = true;
+        iter.next();
+    }
     while let Some(arg) = iter.next() {
         match arg.as_ref() {
+            "--url" if upload => {
+                upload_url = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --url")),
+                )
+            }
             "--output" => {
                 output_arg = Some(
                     iter.next()
                         .unwrap_or_else(|| usage_msg("Did not give argument to --output")),
                 )
             }
+            "--no-redact" => redact = false,
+            "--timeout" => {
+                limits.timeout = Duration::from_secs(parse_number(&arg, iter.next()));
+            }
+            "--max-output-size" => limits.max_output_size = parse_number(&arg, iter.next()),
+            "--parallel" => {
+                parallel = parse_number(&arg, iter.next());
+                if parallel == 0 {
+                    usage_msg("--parallel must be at least 1");
+                }
+            }
             _ => usage(),
         }
     }
 
-    match output_arg {
+    let outpath = match output_arg {
         Some(path) => PathBuf::from(path),
         None => env::temp_dir().as_path().join(OUTPUT_FILENAME),
+    };
+    Args {
+        outpath,
+        redact,
+        limits,
+        parallel,
+        upload,
+        upload_url,
     }
 }
 
-/// Runs a list of log requests and writes their output into files in `outdir`. Any failures are
-/// noted in the file named by `ERROR_FILENAME`. Note: In the case of `exec` log requests, non-zero
-/// exit codes are not considered errors and the command's stdout and stderr will be still be
-/// written.
-pub(crate) fn collect_logs<P: AsRef<Path>>(log_requests: &[&str], outdir: P) -> Result<()> {
+/// A record of how one log request went, written to the file named by `MANIFEST_FILENAME`.
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    request: String,
+    duration_secs: f64,
+    exit_status: Option<i32>,
+    timed_out: bool,
+    truncated: bool,
+    error: Option<String>,
+}
+
+/// Runs a list of log requests, up to `parallel` at a time, and writes their output into files in
+/// `outdir`. Any failures are noted in the file named by `ERROR_FILENAME`, and the outcome of each
+/// request is listed in the file named by `MANIFEST_FILENAME`. Note: In the case of `exec` log
+/// requests, non-zero exit codes are not considered errors and the command's stdout and stderr
+/// will be still be written.
+pub(crate) fn collect_logs<S, P>(
+    log_requests: &[S],
+    outdir: P,
+    limits: Limits,
+    parallel: usize,
+) -> Result<()>
+where
+    S: AsRef<str>,
+    P: AsRef<Path>,
+{
     // if a command fails, we will pipe its error here and continue.
     let outdir = outdir.as_ref();
     let error_path = outdir.join(crate::ERROR_FILENAME);
@@ -93,37 +241,120 @@ pub(crate) fn collect_logs<P: AsRef<Path>>(log_requests: &[&str], outdir: P) ->
         path: error_path.clone(),
     })?;
 
-    for &log_request in log_requests {
-        // show the user what command we are running
-        println!("Running: {}", log_request);
-        if let Err(e) = handle_log_request(log_request, &outdir) {
-            // ignore the error, but make note of it in the error file.
-            write!(
-                &mut error_file,
-                "Error running command '{}': '{}'\n",
-                log_request, e
-            )
-            .context(error::ErrorWrite {
-                path: error_path.clone(),
-            })?;
+    // Workers take the next request from the shared list until there are none left, and send
+    // back how it went along with its index, so we can report in the original order.
+    let requests: Arc<Vec<String>> = Arc::new(
+        log_requests
+            .iter()
+            .map(|request| request.as_ref().to_string())
+            .collect(),
+    );
+    let next = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+    let mut workers = Vec::new();
+    for _ in 0..parallel.max(1).min(requests.len()) {
+        let requests = Arc::clone(&requests);
+        let next = Arc::clone(&next);
+        let tx = tx.clone();
+        let outdir = outdir.to_path_buf();
+        workers.push(thread::spawn(move || loop {
+            let index = next.fetch_add(1, Ordering::SeqCst);
+            let log_request = match requests.get(index) {
+                Some(log_request) => log_request,
+                None => break,
+            };
+            // show the user what command we are running
+            println!("Running: {}", log_request);
+            let start = Instant::now();
+            // A request that panics is reported as failed, and the worker goes on to the next.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                handle_log_request(log_request, &outdir, &limits)
+            }))
+            .unwrap_or_else(|_| {
+                error::RequestPanic {
+                    request: log_request.as_str(),
+                }
+                .fail()
+            });
+            let _ = tx.send((index, start.elapsed(), result));
+        }));
+    }
+    drop(tx);
+    let mut results: Vec<_> = rx.iter().collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    results.sort_by_key(|(index, _, _)| *index);
+
+    let mut manifest = Vec::new();
+    for (index, duration, result) in results {
+        let log_request = &requests[index];
+        let mut entry = ManifestEntry {
+            request: log_request.clone(),
+            duration_secs: duration.as_secs_f64(),
+            exit_status: None,
+            timed_out: false,
+            truncated: false,
+            error: None,
+        };
+        match result {
+            Ok(outcome) => {
+                entry.exit_status = outcome.exit_status;
+                entry.timed_out = outcome.timed_out;
+                entry.truncated = outcome.truncated;
+            }
+            Err(e) => {
+                // ignore the error, but make note of it in the error file.
+                writeln!(
+                    &mut error_file,
+                    "Error running command '{}': '{}'",
+                    log_request, e
+                )
+                .context(error::ErrorWrite {
+                    path: error_path.clone(),
+                })?;
+                entry.error = Some(e.to_string());
+            }
         }
+        manifest.push(entry);
     }
+
+    let manifest_path = outdir.join(MANIFEST_FILENAME);
+    let manifest = serde_json::to_vec_pretty(&manifest).context(error::ManifestSerialize)?;
+    fs::write(&manifest_path, manifest).context(error::ManifestWrite {
+        path: &manifest_path,
+    })?;
     Ok(())
 }
 
-/// Runs the bulk of the program's logic, main wraps this.
-fn run(outfile: &Path, commands: &[&str]) -> Result<()> {
+/// Runs the bulk of the program's logic, main wraps this.  Settings are read through `api`.
+fn run<S: AsRef<str>>(args: &Args, commands: &[S], api: &dyn ApiClient) -> Result<()> {
+    // Find out where to upload first, so a missing or invalid URL doesn't waste a collection.
+    let upload_url = if args.upload {
+        Some(resolve_upload_url(args.upload_url.as_deref(), api)?)
+    } else {
+        None
+    };
     let temp_dir = TempDir::new().context(error::TempDirCreate)?;
-    collect_logs(&commands, &temp_dir.path().to_path_buf())?;
-    create_tarball(&temp_dir.path().to_path_buf(), &outfile)?;
-    println!("logs are at: {}", outfile.display());
+    collect_logs(commands, temp_dir.path(), args.limits, args.parallel)?;
+    if args.redact {
+        redact_logs(temp_dir.path(), api)?;
+    }
+    create_tarball(temp_dir.path(), &args.outpath)?;
+    println!("logs are at: {}", args.outpath.display());
+    if let Some(upload_url) = upload_url {
+        upload(&args.outpath, &upload_url)?;
+        println!("logs were uploaded to: {}", display_url(&upload_url));
+    }
     Ok(())
 }
 
 fn main() -> ! {
-    let outpath = parse_args(env::args());
-    let log_requests = log_requests();
-    process::exit(match run(&outpath, &log_requests) {
+    let args = parse_args(env::args());
+    let api = CommandApiClient {};
+    let mut log_requests: Vec<String> = log_requests().into_iter().map(String::from).collect();
+    log_requests.extend(user_log_requests(&api));
+    process::exit(match run(&args, &log_requests, &api) {
         Ok(()) => 0,
         Err(err) => {
             eprintln!("{}", err);
@@ -146,6 +377,19 @@ mod tests {
     use std::fs::File;
     use tar::Archive;
 
+    /// Stands in for the API when it isn't available, so nothing depends on the host.
+    struct UnavailableApi {}
+
+    impl ApiClient for UnavailableApi {
+        fn get(&self, uri: &str) -> Result<Vec<u8>> {
+            error::ApiStatus {
+                uri,
+                stderr: "unavailable",
+            }
+            .fail()
+        }
+    }
+
     #[test]
     fn test_program() {
         let output_tempdir = TempDir::new().unwrap();
@@ -153,7 +397,15 @@ mod tests {
 
         // we assume that `echo` will not do something unexpected on the machine running this test.
         let commands = vec!["exec hello.txt echo hello world"];
-        run(&outfile, &commands).unwrap();
+        let args = Args {
+            outpath: outfile.clone(),
+            redact: true,
+            limits: Limits::default(),
+            parallel: DEFAULT_PARALLEL,
+            upload: false,
+            upload_url: None,
+        };
+        run(&args, &commands, &UnavailableApi {}).unwrap();
 
         // this function will panic if the given path is not found in the tarball.
         let find = |path_to_find: &PathBuf| {
@@ -174,5 +426,6 @@ mod tests {
         // assert that the expected paths exist in the tarball
         find(&PathBuf::from(TARBALL_DIRNAME));
         find(&PathBuf::from(TARBALL_DIRNAME).join("hello.txt"));
+        find(&PathBuf::from(TARBALL_DIRNAME).join(MANIFEST_FILENAME));
     }
 }
@@ -1,4 +1,5 @@
 use log::LevelFilter;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -24,4 +25,10 @@ pub(crate) enum Command {
     SendBootSuccess,
     /// check services and report their health.
     SendHealthPing,
+    /// serve host health metrics in the Prometheus text format.
+    Serve {
+        /// Address to listen on [default: 127.0.0.1 and `exporter_port` from the config]
+        #[structopt(long = "listen")]
+        listen: Option<SocketAddr>,
+    },
 }
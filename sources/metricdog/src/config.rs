@@ -1,4 +1,5 @@
 use crate::error::{self, Result};
+use crate::exporter::DEFAULT_EXPORTER_PORT;
 use serde::Deserialize;
 use snafu::ResultExt;
 use std::fs;
@@ -15,6 +16,16 @@ pub(crate) struct Config {
     pub(crate) seed: u32,
     pub(crate) version_lock: String,
     pub(crate) ignore_waves: bool,
+    /// Whether `metricdog serve` should serve metrics; unlike `send_metrics`, this only exposes
+    /// them locally.
+    #[serde(default)]
+    pub(crate) exporter_enabled: bool,
+    #[serde(default = "default_exporter_port")]
+    pub(crate) exporter_port: u16,
+}
+
+fn default_exporter_port() -> u16 {
+    DEFAULT_EXPORTER_PORT
 }
 
 impl Config {
@@ -73,6 +84,8 @@ mod test {
         assert_eq!(1234, config.seed);
         assert_eq!("v0.1.2", config.version_lock);
         assert!(!config.ignore_waves);
+        assert!(!config.exporter_enabled);
+        assert_eq!(crate::exporter::DEFAULT_EXPORTER_PORT, config.exporter_port);
     }
 
     #[test]
@@ -1,12 +1,22 @@
 //! Provides the list of errors for `metricdog`.
 
 use snafu::Snafu;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use url::Url;
 
 #[derive(Debug, Snafu)]
-#[snafu(visibility = "pub(crate)")]
-pub(crate) enum Error {
+#[snafu(visibility = "pub")]
+pub enum Error {
+    #[snafu(display("API request to '{}' failed: {}", uri, stderr))]
+    ApiRequest { uri: String, stderr: String },
+
+    #[snafu(display("Unable to parse API response from '{}': {}", uri, source))]
+    ApiResponseParse {
+        uri: String,
+        source: serde_json::Error,
+    },
+
     #[snafu(display("Unable to load Bottlerocket release info: '{}'", source))]
     BottlerocketRelease { source: bottlerocket_release::Error },
 
@@ -38,6 +48,12 @@ pub(crate) enum Error {
     #[snafu(display("Error receiving HTTP response {}: {}", url.as_str(), source))]
     HttpResponse { url: Url, source: reqwest::Error },
 
+    #[snafu(display("Unable to serve metrics on {}: {}", addr, source))]
+    Listen {
+        addr: SocketAddr,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Unable to parse URL {}: {}", url, source))]
     UrlParse {
         url: String,
@@ -45,4 +61,4 @@ pub(crate) enum Error {
     },
 }
 
-pub(crate) type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,384 @@
+use crate::error::{self, Result};
+use crate::service_check::ServiceCheck;
+use bottlerocket_release::BottlerocketRelease;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use snafu::{ensure, ResultExt};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::Command;
+use std::time::Duration;
+
+/// The port the exporter listens on, on the loopback address, unless configured otherwise.
+pub(crate) const DEFAULT_EXPORTER_PORT: u16 = 9101;
+
+/// The content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// How long to wait for a scraper to send its request before giving up on it, so that a stuck
+/// connection can't block other scrapers.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The update states reported by thar-be-updates; each gets a sample so that queries can tell a
+/// state that isn't current from one that's missing.
+const UPDATE_STATES: &[&str] = &["Idle", "Available", "Staged", "Ready"];
+
+/// Makes GET requests to the Bottlerocket API.
+pub(crate) trait ApiClient {
+    /// Returns the body of the response to a GET request for `uri`.
+    fn get(&self, uri: &str) -> Result<Vec<u8>>;
+}
+
+/// Makes API requests by calling `apiclient`.
+pub(crate) struct CommandApiClient {}
+
+impl ApiClient for CommandApiClient {
+    fn get(&self, uri: &str) -> Result<Vec<u8>> {
+        let args = ["--method", "GET", "--uri", uri];
+        let output = Command::new("apiclient")
+            .args(args)
+            .output()
+            .with_context(|| error::Command {
+                command: "apiclient",
+                args: args.iter().map(|&s| s.to_owned()).collect::<Vec<String>>(),
+            })?;
+        ensure!(
+            output.status.success(),
+            error::ApiRequest {
+                uri,
+                stderr: String::from_utf8_lossy(&output.stderr),
+            }
+        );
+        Ok(output.stdout)
+    }
+}
+
+/// The parts of the thar-be-updates status that we report.
+#[derive(Debug, Deserialize)]
+struct UpdateStatus {
+    update_state: String,
+    available_updates: Vec<String>,
+    most_recent_command: Option<CommandResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandResult {
+    cmd_type: String,
+    cmd_status: String,
+    timestamp: DateTime<Utc>,
+}
+
+/// The parts of a settings generation, from `/settings/history`, that we report.
+#[derive(Debug, Deserialize)]
+struct Generation {
+    generation: u64,
+    timestamp: DateTime<Utc>,
+}
+
+/// Serves the health of the host in the Prometheus text format, so that node monitoring can
+/// scrape it.  Metrics are gathered fresh for each scrape.
+pub(crate) struct Exporter {
+    /// The services to check, from `service_checks` in the config.
+    service_checks: Vec<String>,
+    /// Information about the Bottlerocket release, e.g. from `os-release`
+    os_release: BottlerocketRelease,
+    /// Checks whether each service is healthy.
+    healthcheck: Box<dyn ServiceCheck>,
+    /// Reads update status and settings history from the API.
+    api: Box<dyn ApiClient>,
+}
+
+impl Exporter {
+    pub(crate) fn from_parts(
+        service_checks: Vec<String>,
+        os_release: BottlerocketRelease,
+        healthcheck: Box<dyn ServiceCheck>,
+        api: Box<dyn ApiClient>,
+    ) -> Self {
+        Self {
+            service_checks,
+            os_release,
+            healthcheck,
+            api,
+        }
+    }
+
+    /// Listens on `addr` and answers scrapes, one at a time, until an error stops the listener.
+    pub(crate) fn serve(&self, addr: SocketAddr) -> Result<()> {
+        let listener = TcpListener::bind(addr).context(error::Listen { addr })?;
+        debug!("serving metrics on {}", addr);
+        for stream in listener.incoming() {
+            let stream = stream.context(error::Listen { addr })?;
+            // A problem with one scraper's connection shouldn't stop us serving the next.
+            if let Err(e) = self.handle(stream) {
+                warn!("Error answering metrics request: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads an HTTP request from `stream` and responds with the metrics if it's a GET of
+    /// `/metrics`.
+    pub(crate) fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Read the rest of the headers so the client isn't cut off mid-request.
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), Some(_)) => ("404 Not Found", "Metrics are at /metrics\n".to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                "Only GET is supported\n".to_string(),
+            ),
+        };
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            CONTENT_TYPE,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+
+    /// Gathers every metric and returns them in the Prometheus text format.  A source that can't
+    /// be read is reported as down through `bottlerocket_metrics_source_up`, rather than failing
+    /// the whole scrape.
+    pub(crate) fn render(&self) -> String {
+        let mut out = Metrics::default();
+        self.release_metrics(&mut out);
+
+        let services = self.service_metrics(&mut out);
+        let updates = self.update_metrics(&mut out);
+        let settings = self.settings_metrics(&mut out);
+
+        out.header(
+            "bottlerocket_metrics_source_up",
+            "Whether each source of metrics could be read.",
+            "gauge",
+        );
+        for (source, result) in &[
+            ("services", services),
+            ("updates", updates),
+            ("settings", settings),
+        ] {
+            if let Err(e) = result {
+                warn!("Unable to read {} metrics: {}", source, e);
+            }
+            out.sample(
+                "bottlerocket_metrics_source_up",
+                &[("source", *source)],
+                result.is_ok() as u8,
+            );
+        }
+        out.text
+    }
+
+    fn release_metrics(&self, out: &mut Metrics) {
+        out.header(
+            "bottlerocket_release_info",
+            "The running Bottlerocket release; the value is always 1.",
+            "gauge",
+        );
+        out.sample(
+            "bottlerocket_release_info",
+            &[
+                ("version", &self.os_release.version_id.to_string()),
+                ("variant", &self.os_release.variant_id),
+                ("arch", &self.os_release.arch),
+                ("build_id", &self.os_release.build_id),
+            ],
+            1,
+        );
+
+        if let Some(boot_time) = boot_time() {
+            out.header(
+                "bottlerocket_boot_time_seconds",
+                "When the host booted, in seconds since the Unix epoch.",
+                "gauge",
+            );
+            out.sample("bottlerocket_boot_time_seconds", &[], boot_time);
+        }
+    }
+
+    fn service_metrics(&self, out: &mut Metrics) -> Result<()> {
+        let mut healths = Vec::new();
+        for service in &self.service_checks {
+            healths.push((service, self.healthcheck.check(service)?));
+        }
+
+        out.header(
+            "bottlerocket_service_healthy",
+            "Whether each critical service is running (1) or has failed (0).",
+            "gauge",
+        );
+        for (service, health) in &healths {
+            out.sample(
+                "bottlerocket_service_healthy",
+                &[("service", service.as_str())],
+                health.is_healthy as u8,
+            );
+        }
+        out.header(
+            "bottlerocket_service_exit_code",
+            "The exit code of each failed critical service, if known.",
+            "gauge",
+        );
+        for (service, health) in &healths {
+            if let Some(exit_code) = health.exit_code {
+                out.sample(
+                    "bottlerocket_service_exit_code",
+                    &[("service", service.as_str())],
+                    exit_code,
+                );
+            }
+        }
+        out.header(
+            "bottlerocket_healthy",
+            "Whether all critical services are healthy.",
+            "gauge",
+        );
+        out.sample(
+            "bottlerocket_healthy",
+            &[],
+            healths.iter().all(|(_, health)| health.is_healthy) as u8,
+        );
+        Ok(())
+    }
+
+    fn update_metrics(&self, out: &mut Metrics) -> Result<()> {
+        let status: UpdateStatus = self.api_get("/updates/status")?;
+
+        out.header(
+            "bottlerocket_update_state",
+            "The state of updates, as reported by the update API; the current state is 1.",
+            "gauge",
+        );
+        for state in UPDATE_STATES {
+            out.sample(
+                "bottlerocket_update_state",
+                &[("state", *state)],
+                (status.update_state == *state) as u8,
+            );
+        }
+        out.header(
+            "bottlerocket_update_available_count",
+            "How many updates are available to this host.",
+            "gauge",
+        );
+        out.sample(
+            "bottlerocket_update_available_count",
+            &[],
+            status.available_updates.len(),
+        );
+        if let Some(command) = status.most_recent_command {
+            out.header(
+                "bottlerocket_update_last_command_timestamp_seconds",
+                "When the most recent update command ran, labeled by command and result.",
+                "gauge",
+            );
+            out.sample(
+                "bottlerocket_update_last_command_timestamp_seconds",
+                &[
+                    ("command", &command.cmd_type),
+                    ("status", &command.cmd_status.to_lowercase()),
+                ],
+                command.timestamp.timestamp(),
+            );
+        }
+        Ok(())
+    }
+
+    fn settings_metrics(&self, out: &mut Metrics) -> Result<()> {
+        let history: Vec<Generation> = self.api_get("/settings/history")?;
+
+        out.header(
+            "bottlerocket_settings_commits_total",
+            "How many changes have been committed to settings, including rollbacks.",
+            "counter",
+        );
+        out.sample(
+            "bottlerocket_settings_commits_total",
+            &[],
+            history.last().map(|g| g.generation).unwrap_or(0),
+        );
+        if let Some(latest) = history.last() {
+            out.header(
+                "bottlerocket_settings_last_commit_timestamp_seconds",
+                "When settings were last changed, in seconds since the Unix epoch.",
+                "gauge",
+            );
+            out.sample(
+                "bottlerocket_settings_last_commit_timestamp_seconds",
+                &[],
+                latest.timestamp.timestamp(),
+            );
+        }
+        Ok(())
+    }
+
+    fn api_get<T: DeserializeOwned>(&self, uri: &str) -> Result<T> {
+        let response = self.api.get(uri)?;
+        serde_json::from_slice(&response).context(error::ApiResponseParse { uri })
+    }
+}
+
+/// Returns when the host booted, in seconds since the Unix epoch, from `/proc/stat`.
+fn boot_time() -> Option<u64> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    stat.lines()
+        .find(|line| line.starts_with("btime "))
+        .and_then(|line| line["btime ".len()..].trim().parse().ok())
+}
+
+/// Builds up metrics in the Prometheus text format.
+#[derive(Debug, Default)]
+struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    /// Adds the HELP and TYPE lines that describe a metric; call this before its samples.
+    fn header(&mut self, name: &str, help: &str, metric_type: &str) {
+        // Writing to a String can't fail.
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, metric_type);
+    }
+
+    /// Adds one sample of a metric with the given labels.
+    fn sample<V: std::fmt::Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+/// Escapes a label value as the Prometheus text format requires.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,184 @@
+use crate::error::{self, Result};
+use crate::exporter::{ApiClient, Exporter};
+use crate::service_check::{ServiceCheck, ServiceHealth};
+use bottlerocket_release::BottlerocketRelease;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use tempfile::TempDir;
+
+const OS_RELEASE: &str = r#"NAME=Bottlerocket
+ID=bottlerocket
+PRETTY_NAME="Bottlerocket OS 0.4.0"
+VARIANT_ID=aws-k8s-1.16
+VERSION_ID=0.4.0
+BUILD_ID=7303622
+"#;
+
+const UPDATE_STATUS: &str = r#"{
+  "update_state": "Available",
+  "available_updates": ["0.4.1", "0.4.2"],
+  "chosen_update": null,
+  "active_partition": null,
+  "staging_partition": null,
+  "most_recent_command": {
+    "cmd_type": "refresh",
+    "cmd_status": "Success",
+    "timestamp": "2021-07-01T12:00:00Z",
+    "exit_status": 0,
+    "stderr": ""
+  }
+}"#;
+
+const SETTINGS_HISTORY: &str = r#"[
+  {
+    "generation": 6,
+    "timestamp": "2021-07-01T11:00:00Z",
+    "source": {"commit": {"transaction": "default"}},
+    "previous": {}
+  },
+  {
+    "generation": 7,
+    "timestamp": "2021-07-01T11:30:00Z",
+    "source": {"rollback": {"generation": 5}},
+    "previous": {}
+  }
+]"#;
+
+fn os_release() -> BottlerocketRelease {
+    let td = TempDir::new().unwrap();
+    let path = td.path().join("os-release");
+    std::fs::write(&path, OS_RELEASE).unwrap();
+    BottlerocketRelease::from_file(&path).unwrap()
+}
+
+struct MockCheck {}
+
+impl ServiceCheck for MockCheck {
+    fn check(&self, service_name: &str) -> Result<ServiceHealth> {
+        if service_name.ends_with("fail1") {
+            Ok(ServiceHealth {
+                is_healthy: false,
+                exit_code: Some(1),
+            })
+        } else {
+            Ok(ServiceHealth {
+                is_healthy: true,
+                exit_code: None,
+            })
+        }
+    }
+}
+
+/// Answers API requests with canned responses, or fails if `available` is false.
+struct MockApi {
+    available: bool,
+}
+
+impl ApiClient for MockApi {
+    fn get(&self, uri: &str) -> Result<Vec<u8>> {
+        let response = match uri {
+            _ if !self.available => "",
+            "/updates/status" => UPDATE_STATUS,
+            "/settings/history" => SETTINGS_HISTORY,
+            _ => "",
+        };
+        if response.is_empty() {
+            return error::ApiRequest {
+                uri,
+                stderr: "unavailable",
+            }
+            .fail();
+        }
+        Ok(response.as_bytes().to_vec())
+    }
+}
+
+fn exporter(services: &[&str], api_available: bool) -> Exporter {
+    Exporter::from_parts(
+        services.iter().map(|&s| s.to_owned()).collect(),
+        os_release(),
+        Box::new(MockCheck {}),
+        Box::new(MockApi {
+            available: api_available,
+        }),
+    )
+}
+
+#[test]
+fn render_metrics() {
+    let got = exporter(&["service_a", "service_fail1"], true).render();
+    let lines: Vec<&str> = got.lines().collect();
+    for want in &[
+        "# TYPE bottlerocket_release_info gauge",
+        r#"bottlerocket_release_info{version="0.4.0",variant="aws-k8s-1.16",arch="x86_64",build_id="7303622"} 1"#,
+        r#"bottlerocket_service_healthy{service="service_a"} 1"#,
+        r#"bottlerocket_service_healthy{service="service_fail1"} 0"#,
+        r#"bottlerocket_service_exit_code{service="service_fail1"} 1"#,
+        "bottlerocket_healthy 0",
+        r#"bottlerocket_update_state{state="Idle"} 0"#,
+        r#"bottlerocket_update_state{state="Available"} 1"#,
+        "bottlerocket_update_available_count 2",
+        r#"bottlerocket_update_last_command_timestamp_seconds{command="refresh",status="success"} 1625140800"#,
+        "# TYPE bottlerocket_settings_commits_total counter",
+        "bottlerocket_settings_commits_total 7",
+        "bottlerocket_settings_last_commit_timestamp_seconds 1625139000",
+        r#"bottlerocket_metrics_source_up{source="services"} 1"#,
+        r#"bottlerocket_metrics_source_up{source="updates"} 1"#,
+        r#"bottlerocket_metrics_source_up{source="settings"} 1"#,
+    ] {
+        assert!(lines.contains(want), "missing '{}' in:\n{}", want, got);
+    }
+    assert!(!got.contains(r#"bottlerocket_service_exit_code{service="service_a"}"#));
+}
+
+#[test]
+fn render_without_api() {
+    let got = exporter(&["service_a"], false).render();
+    let lines: Vec<&str> = got.lines().collect();
+    assert!(lines.contains(&"bottlerocket_healthy 1"));
+    assert!(lines.contains(&r#"bottlerocket_metrics_source_up{source="services"} 1"#));
+    assert!(lines.contains(&r#"bottlerocket_metrics_source_up{source="updates"} 0"#));
+    assert!(lines.contains(&r#"bottlerocket_metrics_source_up{source="settings"} 0"#));
+    assert!(!got.contains("bottlerocket_update_state"));
+    assert!(!got.contains("bottlerocket_settings_commits_total"));
+}
+
+#[test]
+fn render_escapes_labels() {
+    let got = exporter(&["odd\"name\\"], true).render();
+    assert!(got.contains(r#"bottlerocket_service_healthy{service="odd\"name\\"} 1"#));
+}
+
+/// Sends `request` to a connection handled by the exporter and returns the response.
+fn http_request(request: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    });
+    let (stream, _) = listener.accept().unwrap();
+    exporter(&["service_a"], true).handle(stream).unwrap();
+    client.join().unwrap()
+}
+
+#[test]
+fn serve_metrics() {
+    let response = http_request("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+    assert!(response.contains("\r\n\r\n# HELP bottlerocket_release_info"));
+    assert!(response.contains("bottlerocket_healthy 1\n"));
+}
+
+#[test]
+fn serve_other_paths() {
+    let response = http_request("GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    let response = http_request("POST /metrics HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+}
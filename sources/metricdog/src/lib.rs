@@ -0,0 +1,7 @@
+//! The parts of metricdog that are useful to other programs, like checking whether systemd
+//! services are healthy.
+
+#![deny(rust_2018_idioms)]
+
+pub mod error;
+pub mod service_check;
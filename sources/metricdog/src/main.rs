@@ -54,30 +54,63 @@ seed = 1234
 version_lock = "latest"
 # whether bottlerocket should ignore update roll-out timing
 ignore_waves = false
+# whether `metricdog serve` serves metrics locally; this is separate from send_metrics
+exporter_enabled = true
+# the loopback port that `metricdog serve` listens on
+exporter_port = 9101
 ```
+
+# Local Metrics
+
+`metricdog serve` runs an exporter that node monitoring, like Prometheus, can scrape.
+It listens on `127.0.0.1` at `exporter_port`, or the address given with `--listen`, and serves
+metrics in the Prometheus text format at `/metrics`.
+Metrics are gathered fresh for each scrape and are never sent anywhere, so they're available even
+if you've opted out of sending health pings with `send_metrics`.
+If `exporter_enabled` is false, `metricdog serve` exits right away.
+
+* `bottlerocket_release_info`: the version, variant, arch, and build ID as labels, with a value of 1.
+* `bottlerocket_boot_time_seconds`: when the host booted.
+* `bottlerocket_service_healthy`: 1 or 0 for each service in `service_checks`.
+* `bottlerocket_service_exit_code`: the exit code of each failed service, if known.
+* `bottlerocket_healthy`: 1 if all services in `service_checks` are healthy.
+* `bottlerocket_update_state`: 1 for the current update state, and 0 for the others.
+* `bottlerocket_update_available_count`: how many updates are available.
+* `bottlerocket_update_last_command_timestamp_seconds`: when the last update command ran, with the
+  command and its result as labels.
+* `bottlerocket_settings_commits_total`: how many changes have been made to settings.
+* `bottlerocket_settings_last_commit_timestamp_seconds`: when settings last changed.
+* `bottlerocket_metrics_source_up`: whether the services, updates, and settings could be read.
+  If a source can't be read, its metrics are left out, but the rest are still served.
 */
 
 #![deny(rust_2018_idioms)]
 
 mod args;
 mod config;
-mod error;
+mod exporter;
+#[cfg(test)]
+mod exporter_test;
 #[cfg(test)]
 mod main_test;
 mod metricdog;
 #[cfg(test)]
 mod metricdog_test;
-mod service_check;
+
+// The service checks are shared with other programs through our library.
+use ::metricdog::{error, service_check};
 
 use crate::args::{Arguments, Command};
 use crate::config::Config;
 use crate::error::Result;
+use crate::exporter::{CommandApiClient, Exporter};
 use crate::metricdog::Metricdog;
 use crate::service_check::{ServiceCheck, SystemdCheck};
 use bottlerocket_release::BottlerocketRelease;
 use log::error;
 use simplelog::{Config as LogConfig, SimpleLogger};
 use snafu::ResultExt;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::process;
 use structopt::StructOpt;
 
@@ -101,8 +134,14 @@ pub(crate) fn main_inner(arguments: Arguments, service_check: Box<dyn ServiceChe
         Some(filepath) => Config::from_file(filepath)?,
     };
 
+    // the exporter only serves metrics locally, so it has its own flag
+    let enabled = match arguments.command {
+        Command::Serve { .. } => config.exporter_enabled,
+        _ => config.send_metrics,
+    };
+
     // exit early with no error if the opt-out flag is set
-    if !config.send_metrics {
+    if !enabled {
         return Ok(());
     }
 
@@ -114,6 +153,18 @@ pub(crate) fn main_inner(arguments: Arguments, service_check: Box<dyn ServiceChe
     }
     .context(error::BottlerocketRelease)?;
 
+    if let Command::Serve { listen } = arguments.command {
+        let addr =
+            listen.unwrap_or_else(|| SocketAddr::from((Ipv4Addr::LOCALHOST, config.exporter_port)));
+        let exporter = Exporter::from_parts(
+            config.service_checks,
+            os_release,
+            service_check,
+            Box::new(CommandApiClient {}),
+        );
+        return exporter.serve(addr);
+    }
+
     // instantiate the metricdog object
     let metricdog = Metricdog::from_parts(config, os_release, service_check)?;
 
@@ -129,6 +180,8 @@ pub(crate) fn main_inner(arguments: Arguments, service_check: Box<dyn ServiceChe
         Command::SendHealthPing => {
             metricdog.send_health_ping()?;
         }
+        // handled above, since it doesn't send anything
+        Command::Serve { .. } => {}
     }
     Ok(())
 }
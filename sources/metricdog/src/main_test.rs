@@ -173,3 +173,16 @@ fn send_health_ping() {
     };
     main_inner(args, Box::new(MockCheck {})).unwrap();
 }
+
+#[test]
+/// assert that `serve` exits right away, without listening, when the exporter isn't enabled
+fn serve_disabled() {
+    let tempdir = create_test_files(0, &["a"], true);
+    let args = Arguments {
+        config: Some(config_path(&tempdir)),
+        log_level: LevelFilter::Off,
+        os_release: Some(os_release_path(&tempdir)),
+        command: Command::Serve { listen: None },
+    };
+    main_inner(args, Box::new(MockCheck {})).unwrap();
+}
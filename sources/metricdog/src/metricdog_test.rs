@@ -74,6 +74,8 @@ fn send_healthy_ping() {
             seed: 2041,
             version_lock: String::from("latest"),
             ignore_waves: false,
+            exporter_enabled: false,
+            exporter_port: 9101,
         },
         os_release(),
         Box::new(MockCheck {}),
@@ -117,6 +119,8 @@ fn send_unhealthy_ping() {
             seed: 2041,
             version_lock: String::from("latest"),
             ignore_waves: false,
+            exporter_enabled: false,
+            exporter_port: 9101,
         },
         os_release(),
         Box::new(MockCheck {}),
@@ -153,6 +157,8 @@ fn send_boot_success() {
             seed: 2041,
             version_lock: String::from("latest"),
             ignore_waves: false,
+            exporter_enabled: false,
+            exporter_port: 9101,
         },
         os_release(),
         Box::new(MockCheck {}),
@@ -35,16 +35,25 @@ This can be disabled by specifying the argument `add_option = false`.
 
 Fields are all wrapped in `Option<...>`.
 Similar to the `serde` attribute added to fields, this is because we don't want users to have to specify fields they aren't changing, and can be disabled the same way, by specifying `add_option = false`.
+
+## Validation
+
+`Validate` is added to the derives, using the `Validate` derive macro from this crate.
+The derived implementation validates each field, which recursively checks nested structures, and then runs any checks named in `#[validate(with = "function")]` attributes on the struct.
+The function is given a reference to the struct and returns a `validation::Result<()>` from the models crate.
+Errors from fields have the (kebab-case) field name added to the name of the invalid field, so users see the full path to it.
 */
 
 extern crate proc_macro;
 
 use darling::FromMeta;
 use proc_macro::TokenStream;
-use quote::ToTokens;
+use quote::{quote, ToTokens};
+use syn::ext::IdentExt;
 use syn::visit_mut::{self, VisitMut};
 use syn::{
-    parse_macro_input, parse_quote, Attribute, AttributeArgs, Field, ItemStruct, Visibility,
+    parse_macro_input, parse_quote, Attribute, AttributeArgs, Field, ItemStruct, Meta, NestedMeta,
+    Path, Visibility,
 };
 
 /// Define a `#[model]` attribute that can be placed on structs to be used in an API model.
@@ -126,9 +135,9 @@ impl VisitMut for ModelHelper {
         if !is_attr_set("derive", &node.attrs) {
             // Derive Default, if the user requested
             let attr = if self.impl_default {
-                parse_quote!(#[derive(Debug, Default, PartialEq, Serialize, Deserialize)])
+                parse_quote!(#[derive(Debug, Default, PartialEq, Serialize, Deserialize, model_derive::Validate)])
             } else {
-                parse_quote!(#[derive(Debug, PartialEq, Serialize, Deserialize)])
+                parse_quote!(#[derive(Debug, PartialEq, Serialize, Deserialize, model_derive::Validate)])
             };
             // Rust 1.52 added a legacy_derive_helpers warning (soon to be an error) that yells if
             // you use an attribute macro before the derive macro that introduces it.  We should
@@ -167,6 +176,63 @@ impl VisitMut for ModelHelper {
     }
 }
 
+/// Define a `Validate` derive macro for model structs.  The generated implementation validates
+/// each field, then calls the functions given in any `#[validate(with = "function")]` attributes.
+/// Only usable inside the models crate, because it refers to the trait as `crate::validation`.
+#[proc_macro_derive(Validate, attributes(validate))]
+pub fn derive_validate(input: TokenStream) -> TokenStream {
+    let ast: ItemStruct = syn::parse(input)
+        .expect("Unable to parse item `Validate` was derived for - is it a struct?");
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    // Check each named field, adding the field name to any error so the user can find it.  Field
+    // names are kebab-case in the API, like serde's rename_all in the #[model] attribute.
+    let field_checks = ast
+        .fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .map(|ident| {
+            let segment = ident.unraw().to_string().replace('_', "-");
+            quote! {
+                crate::validation::Validate::validate(&self.#ident).map_err(|e| e.within(#segment))?;
+            }
+        });
+
+    // Then run any struct-level checks the user requested.
+    let struct_checks = ast
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("validate"))
+        .map(|attr| {
+            let nested = match attr.parse_meta() {
+                Ok(Meta::List(list)) => list.nested.into_iter().collect::<Vec<NestedMeta>>(),
+                _ => panic!("Expected `validate` attribute like #[validate(with = \"function\")]"),
+            };
+            let args = ValidateArgs::from_list(&nested)
+                .expect("Unable to parse arguments to `validate` attribute");
+            syn::parse_str::<Path>(&args.with)
+                .expect("Argument `with` of `validate` attribute should be a function path")
+        });
+
+    let output = quote! {
+        impl #impl_generics crate::validation::Validate for #name #ty_generics #where_clause {
+            fn validate(&self) -> crate::validation::Result<()> {
+                #(#field_checks)*
+                #(#struct_checks(self)?;)*
+                Ok(())
+            }
+        }
+    };
+    output.into()
+}
+
+/// Store the args given by the user inside `#[validate(...)]`.
+#[derive(Debug, FromMeta)]
+struct ValidateArgs {
+    with: String,
+}
+
 /// Checks whether an attribute named `attr_name` (e.g. "serde") is set in the given list of
 /// `syn::Attribute`s.
 fn is_attr_set(attr_name: &'static str, attrs: &[Attribute]) -> bool {
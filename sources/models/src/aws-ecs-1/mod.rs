@@ -4,8 +4,8 @@ use std::collections::HashMap;
 
 use crate::modeled_types::Identifier;
 use crate::{
-    AwsSettings, BootstrapContainer, ECSSettings, HostContainer, KernelSettings, MetricsSettings,
-    NetworkSettings, NtpSettings, UpdatesSettings,
+    AwsSettings, BootstrapContainer, DnsSettings, ECSSettings, HostContainer, KernelSettings,
+    LogdogSettings, MetricsSettings, NetworkSettings, NtpSettings, UpdatesSettings,
 };
 
 // Note: we have to use 'rename' here because the top-level Settings structure is the only one
@@ -18,8 +18,10 @@ struct Settings {
     bootstrap_containers: HashMap<Identifier, BootstrapContainer>,
     ntp: NtpSettings,
     network: NetworkSettings,
+    dns: DnsSettings,
     kernel: KernelSettings,
     aws: AwsSettings,
     ecs: ECSSettings,
     metrics: MetricsSettings,
+    logdog: LogdogSettings,
 }
@@ -19,6 +19,10 @@ Entries are sorted by filename, and later entries take precedence.
 
 The `#[model]` attribute on Settings and its sub-structs reduces duplication and adds some required metadata; see [its docs](model-derive/) for details.
 
+Some rules span more than one field, for example a setting that's required when another has a particular value.
+These are checked by the `Validate` trait, which `#[model]` derives for each struct.
+Add a check by placing `#[validate(with = "function")]` on the struct; the API server runs these checks against the complete settings before committing a transaction.
+
 ## aws-k8s-1.16: Kubernetes 1.16 (deprecated)
 
 * [Model](src/aws-k8s-1.19/mod.rs)
@@ -98,6 +102,10 @@ Note: all models share the same `Cargo.toml`.
 // "Modeled types" are types with special ser/de behavior used for validation.
 pub mod modeled_types;
 
+// Validation of invariants that span more than one field; see the Validate trait.
+pub mod validation;
+pub use validation::Validate;
+
 // The "variant" module is just a directory where we symlink in the user's requested build
 // variant; each variant defines a top-level Settings structure and we re-export the current one.
 mod variant;
@@ -109,16 +117,19 @@ pub use variant::*;
 
 use model_derive::model;
 use serde::{Deserialize, Serialize};
+use snafu::ensure;
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 
 use crate::modeled_types::{
-    BootstrapContainerMode, DNSDomain, ECSAgentLogLevel, ECSAttributeKey, ECSAttributeValue,
-    FriendlyVersion, Identifier, KubernetesAuthenticationMode, KubernetesBootstrapToken,
-    KubernetesCloudProvider, KubernetesClusterName, KubernetesEvictionHardKey, KubernetesLabelKey,
-    KubernetesLabelValue, KubernetesQuantityValue, KubernetesReservedResourceKey,
-    KubernetesTaintValue, KubernetesThresholdValue, Lockdown, SingleLineString, SysctlKey, Url,
-    ValidBase64,
+    BootstrapContainerMode, CronSchedule, DNSDomain, DownloadRate, ECSAgentLogLevel,
+    ECSAttributeKey, ECSAttributeValue, FriendlyVersion, HealthCheckCommand, Identifier, IpCidr,
+    KubernetesAuthenticationMode, KubernetesBootstrapToken, KubernetesCloudProvider,
+    KubernetesClusterName, KubernetesEvictionHardKey, KubernetesLabelKey, KubernetesLabelValue,
+    KubernetesQuantityValue, KubernetesReservedResourceKey, KubernetesTaintValue,
+    KubernetesThresholdValue, Lockdown, LogRequest, NetworkBondMode, NetworkInterfaceName,
+    SingleLineString, SysctlKey, Timezone, UpdatesMode, Url, ValidBase64, ValidLinuxHostname,
+    WindowDuration,
 };
 
 // Kubernetes static pod manifest settings
@@ -131,6 +142,7 @@ struct StaticPod {
 // Kubernetes related settings. The dynamic settings are retrieved from
 // IMDS via Sundog's child "Pluto".
 #[model]
+#[validate(with = "validate_kubernetes_authentication")]
 struct KubernetesSettings {
     // Settings that must be specified via user data or through API requests.  Not all settings are
     // useful for all modes. For example, in standalone mode the user does not need to specify any
@@ -158,16 +170,39 @@ struct KubernetesSettings {
     kube_api_burst: i32,
     container_log_max_size: KubernetesQuantityValue,
     container_log_max_files: i32,
+    // Set for IPv6 clusters, so that the node IP and cluster DNS IP are generated as IPv6.
+    service_ipv6_cidr: IpCidr,
+    // Whether to cordon and drain the node through the API server before rebooting, and how long
+    // to wait (in seconds) for pods to be evicted.
+    drain_before_reboot: bool,
+    drain_timeout: u32,
+    // Whether to also evict pods that aren't managed by a controller, like `kubectl drain --force`.
+    drain_force: bool,
 
     // Settings where we generate a value based on the runtime environment.  The user can specify a
     // value to override the generated one, but typically would not.
     max_pods: u32,
-    cluster_dns_ip: Ipv4Addr,
+    cluster_dns_ip: IpAddr,
     cluster_domain: DNSDomain,
-    node_ip: Ipv4Addr,
+    node_ip: IpAddr,
     pod_infra_container_image: SingleLineString,
 }
 
+/// In TLS authentication mode, kubelet needs a bootstrap token to join the cluster; without one,
+/// the problem isn't found until kubelet fails to start.  Standalone mode doesn't join a cluster.
+fn validate_kubernetes_authentication(k8s: &KubernetesSettings) -> validation::Result<()> {
+    let joining_cluster = k8s.api_server.is_some() && k8s.standalone_mode != Some(true);
+    let tls = k8s.authentication_mode.as_deref() == Some("tls");
+    ensure!(
+        !(joining_cluster && tls) || k8s.bootstrap_token.is_some(),
+        validation::error::Invalid {
+            field: "bootstrap-token",
+            msg: "required when authentication-mode is 'tls'",
+        }
+    );
+    Ok(())
+}
+
 // ECS settings.
 #[model]
 struct ECSSettings {
@@ -177,11 +212,16 @@ struct ECSSettings {
     logging_drivers: Vec<SingleLineString>,
     loglevel: ECSAgentLogLevel,
     enable_spot_instance_draining: bool,
+    // Whether to set the container instance to DRAINING before rebooting, and how long to wait
+    // (in seconds) for its tasks to stop.
+    drain_before_reboot: bool,
+    drain_timeout: u32,
 }
 
 // Update settings. Taken from userdata. The 'seed' setting is generated
 // by the "Bork" settings generator at runtime.
 #[model]
+#[validate(with = "validate_deny_versions")]
 struct UpdatesSettings {
     metadata_base_url: Url,
     targets_base_url: Url,
@@ -189,6 +229,60 @@ struct UpdatesSettings {
     // Version to update to when updating via the API.
     version_lock: FriendlyVersion,
     ignore_waves: bool,
+    // Whether updog picks updates on its own ('automatic'), only updates to an explicitly chosen
+    // version ('managed'), or doesn't update at all ('disabled').
+    mode: UpdatesMode,
+    // Versions that should never be installed, for example a release found to be bad.
+    deny_versions: Vec<FriendlyVersion>,
+    // When set, update-agent applies updates and reboots on its own during this window.
+    maintenance_window: MaintenanceWindow,
+    // Limits how fast updog downloads update images, e.g. "10MiB".
+    max_download_rate: DownloadRate,
+    // Checks that must pass after booting a new version, or the host rolls back.
+    health_checks: HealthChecks,
+    // Labels like 'pool = "canary"' that place the host in targeted update waves.
+    wave_labels: HashMap<Identifier, Identifier>,
+    // The channel of updates to follow, like 'stable' (the default) or 'beta'.
+    channel: Identifier,
+}
+
+// A recurring window of time in which the host may update and reboot.  Windows start at the
+// times given by the cron-like 'schedule', interpreted in 'timezone', and last for 'duration'.
+#[model]
+struct MaintenanceWindow {
+    schedule: CronSchedule,
+    duration: WindowDuration,
+    timezone: Timezone,
+}
+
+// Checks that update-verifier runs after the host boots into a new version.  If they don't all
+// pass within 'timeout' seconds of boot, the host rolls back to the previous version.  'services'
+// are systemd units that must be running, 'registration' requires the host to have joined its
+// orchestrator's cluster, and 'commands' must exit successfully.
+#[model]
+struct HealthChecks {
+    timeout: u32,
+    services: Vec<SingleLineString>,
+    registration: bool,
+    commands: Vec<HealthCheckCommand>,
+}
+
+/// 'latest' is a valid FriendlyVersion for version-lock, but denying it would mean denying
+/// whatever happens to be newest, which is what 'mode' is for.
+fn validate_deny_versions(updates: &UpdatesSettings) -> validation::Result<()> {
+    let denies_latest = updates
+        .deny_versions
+        .iter()
+        .flatten()
+        .any(|version| version.as_ref() == "latest");
+    ensure!(
+        !denies_latest,
+        validation::error::Invalid {
+            field: "deny-versions",
+            msg: "must list specific versions, not 'latest'; use 'mode' to stop updates",
+        }
+    );
+    Ok(())
 }
 
 #[model]
@@ -201,10 +295,129 @@ struct HostContainer {
 
 // Network settings. These settings will affect host service components' network behavior
 #[model]
+#[validate(with = "validate_network_settings")]
 struct NetworkSettings {
     https_proxy: Url,
     // We allow some flexibility in NO_PROXY values because different services support different formats.
     no_proxy: Vec<SingleLineString>,
+    // Interface configuration, used by netdog to generate wicked configuration at boot.  If not
+    // given, the primary interface is configured with DHCP.
+    interfaces: HashMap<NetworkInterfaceName, NetworkInterface>,
+    // Used instead of the name found by reverse DNS lookup of the node's IP.
+    hostname: ValidLinuxHostname,
+    // Read-only through /settings; maintained by netdog.
+    current: NetworkState,
+}
+
+// The current state of the network, as discovered by netdog from DHCP leases and interface
+// configuration, with any overrides from settings.  These settings are read-only, and are used to
+// render /etc/resolv.conf and set the hostname.  The read-only flag is advisory: it only keeps them
+// out of /settings, and any API client can still report state through /network/current.
+#[model]
+struct NetworkState {
+    ip_address: IpAddr,
+    hostname: ValidLinuxHostname,
+    name_servers: Vec<IpAddr>,
+    search_list: Vec<DNSDomain>,
+}
+
+// Configuration of a single network interface.  An interface can use DHCP, static addresses, or
+// both; a member of a bond needs neither.
+#[model]
+#[validate(with = "validate_network_interface")]
+struct NetworkInterface {
+    dhcp4: bool,
+    dhcp6: bool,
+    // Static addresses with their prefix length, like "192.168.1.10/24".
+    addresses: Vec<IpCidr>,
+    routes: Vec<NetworkRoute>,
+    // Name servers and search domains for /etc/resolv.conf.
+    nameservers: Vec<IpAddr>,
+    search: Vec<DNSDomain>,
+    mtu: u32,
+    // The node's IP, as reported by `netdog node-ip`, is taken from the primary interface.
+    primary: bool,
+    // Set one of these to make a bond or VLAN interface, rather than configuring a device.
+    bond: NetworkBond,
+    vlan: NetworkVlan,
+}
+
+#[model]
+struct NetworkRoute {
+    // Use "0.0.0.0/0" or "::/0" for a default route.
+    to: IpCidr,
+    via: IpAddr,
+    metric: u32,
+}
+
+#[model]
+struct NetworkBond {
+    mode: NetworkBondMode,
+    interfaces: Vec<NetworkInterfaceName>,
+    // Link monitoring interval, in milliseconds.
+    miimon: u32,
+}
+
+#[model]
+struct NetworkVlan {
+    device: NetworkInterfaceName,
+    id: u16,
+}
+
+/// Catches interface configurations that netdog can't turn into something wicked understands.
+fn validate_network_interface(interface: &NetworkInterface) -> validation::Result<()> {
+    ensure!(
+        interface.bond.is_none() || interface.vlan.is_none(),
+        validation::error::Invalid {
+            field: "bond",
+            msg: "an interface can't be both a bond and a VLAN",
+        }
+    );
+    if let Some(bond) = &interface.bond {
+        ensure!(
+            matches!(&bond.interfaces, Some(members) if !members.is_empty()),
+            validation::error::Invalid {
+                field: "bond.interfaces",
+                msg: "a bond needs at least one member interface",
+            }
+        );
+    }
+    if let Some(id) = interface.vlan.as_ref().and_then(|vlan| vlan.id) {
+        ensure!(
+            (1..=4094).contains(&id),
+            validation::error::Invalid {
+                field: "vlan.id",
+                msg: "must be between 1 and 4094",
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Only one interface can be the source of the node's IP.
+fn validate_network_settings(network: &NetworkSettings) -> validation::Result<()> {
+    let primaries = network
+        .interfaces
+        .iter()
+        .flat_map(|interfaces| interfaces.values())
+        .filter(|interface| interface.primary == Some(true))
+        .count();
+    ensure!(
+        primaries <= 1,
+        validation::error::Invalid {
+            field: "interfaces",
+            msg: "at most one interface can be primary",
+        }
+    );
+    Ok(())
+}
+
+// DNS settings.  These take precedence over name servers and search domains from DHCP leases or
+// interface configuration.
+#[model]
+struct DnsSettings {
+    name_servers: Vec<IpAddr>,
+    search_list: Vec<DNSDomain>,
 }
 
 // NTP settings
@@ -233,6 +446,15 @@ struct MetricsSettings {
     metrics_url: Url,
     send_metrics: bool,
     service_checks: Vec<String>,
+    exporter_enabled: bool,
+    exporter_port: u16,
+}
+
+// Logdog settings
+#[model]
+struct LogdogSettings {
+    requests: Vec<LogRequest>,
+    upload_url: Url,
 }
 
 ///// Internal services
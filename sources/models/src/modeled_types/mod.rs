@@ -31,6 +31,15 @@ pub mod error {
         #[snafu(display("Invalid version string '{}'", input))]
         InvalidVersion { input: String },
 
+        #[snafu(display("Invalid updates mode '{}'", input))]
+        InvalidUpdatesMode { input: String },
+
+        #[snafu(display("Invalid download rate '{}': {}", input, msg))]
+        InvalidDownloadRate { input: String, msg: String },
+
+        #[snafu(display("{}", source))]
+        InvalidMaintenanceWindow { source: parse_datetime::Error },
+
         #[snafu(display("{} must match '{}', given: {}", thing, pattern, input))]
         Pattern {
             thing: String,
@@ -45,15 +54,30 @@ pub mod error {
         #[snafu(display("Invalid Kubernetes authentication mode '{}'", input))]
         InvalidAuthenticationMode { input: String },
 
+        #[snafu(display("Invalid network bond mode '{}'", input))]
+        InvalidBondMode { input: String },
+
         #[snafu(display("Invalid bootstrap container mode '{}'", input))]
         InvalidBootstrapContainerMode { input: String },
 
         #[snafu(display("Given invalid cluster name '{}': {}", name, msg))]
         InvalidClusterName { name: String, msg: String },
 
+        #[snafu(display("Invalid CIDR '{}': {}", input, msg))]
+        InvalidCidr { input: String, msg: String },
+
         #[snafu(display("Invalid domain name '{}': {}", input, msg))]
         InvalidDomainName { input: String, msg: String },
 
+        #[snafu(display("Invalid health-check command '{}': {}", input, msg))]
+        InvalidHealthCheckCommand { input: String, msg: String },
+
+        #[snafu(display("Invalid Linux hostname '{}': {}", input, msg))]
+        InvalidLinuxHostname { input: String, msg: String },
+
+        #[snafu(display("Invalid log request '{}': {}", input, msg))]
+        InvalidLogRequest { input: String, msg: String },
+
         #[snafu(display("Invalid Linux lockdown mode '{}'", input))]
         InvalidLockdown { input: String },
 
@@ -113,6 +137,9 @@ macro_rules! string_impls_for {
             }
         }
 
+        /// Modeled types are checked when they're created, so there's nothing left to validate.
+        impl $crate::validation::Validate for $for {}
+
         impl Deref for $for {
             type Target = str;
             fn deref(&self) -> &Self::Target {
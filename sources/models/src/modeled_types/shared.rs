@@ -5,10 +5,11 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use super::error;
 use semver::Version;
 use serde::de::Error as _;
-use snafu::{ensure, ResultExt};
+use snafu::{ensure, OptionExt, ResultExt};
 use std::borrow::Borrow;
 use std::convert::TryFrom;
 use std::fmt;
+use std::net::IpAddr;
 use std::ops::Deref;
 use std::str::FromStr;
 use url::Host;
@@ -366,6 +367,299 @@ mod test_version {
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+/// UpdatesMode represents a string that is a valid update policy for updog: 'automatic',
+/// 'managed', or 'disabled'.  It stores the original string and makes it accessible through
+/// standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UpdatesMode {
+    inner: String,
+}
+
+impl TryFrom<&str> for UpdatesMode {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            matches!(input, "automatic" | "managed" | "disabled"),
+            error::InvalidUpdatesMode { input }
+        );
+        Ok(UpdatesMode {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(UpdatesMode, "UpdatesMode");
+
+#[cfg(test)]
+mod test_updates_mode {
+    use super::UpdatesMode;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_modes() {
+        for ok in &["automatic", "managed", "disabled"] {
+            UpdatesMode::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_modes() {
+        for err in &["", "auto", "Disabled", "enabled"] {
+            UpdatesMode::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// CronSchedule represents a string that is a valid cron-like schedule of start times, like
+/// "0 2 * * Sat,Sun", with the fields 'minute hour day-of-month month day-of-week'.  It stores
+/// the original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CronSchedule {
+    inner: String,
+}
+
+impl TryFrom<&str> for CronSchedule {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        parse_datetime::Schedule::parse(input).context(error::InvalidMaintenanceWindow)?;
+        Ok(CronSchedule {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(CronSchedule, "CronSchedule");
+
+#[cfg(test)]
+mod test_cron_schedule {
+    use super::CronSchedule;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_schedules() {
+        for ok in &[
+            "* * * * *",
+            "0 2 * * Sat,Sun",
+            "*/30 22-23 1,15 Jan-Jun 1-5",
+        ] {
+            CronSchedule::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_schedules() {
+        for err in &[
+            "",
+            "0 2 * *",
+            "60 2 * * *",
+            "0 2 * * Caturday",
+            "0 0 31 2 *",
+        ] {
+            CronSchedule::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// WindowDuration represents a string that is a valid, non-zero length of time like "3 hours",
+/// in the shorthand accepted by parse-datetime.  It stores the original string and makes it
+/// accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct WindowDuration {
+    inner: String,
+}
+
+impl TryFrom<&str> for WindowDuration {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        parse_datetime::parse_window_duration(input).context(error::InvalidMaintenanceWindow)?;
+        Ok(WindowDuration {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(WindowDuration, "WindowDuration");
+
+#[cfg(test)]
+mod test_window_duration {
+    use super::WindowDuration;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_durations() {
+        for ok in &["1 hour", "3 hours", "2 days", "1 week"] {
+            WindowDuration::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_durations() {
+        for err in &["", "0 hours", "3", "hours", "3 fortnights", "-1 hours"] {
+            WindowDuration::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Timezone represents a string that is a valid timezone for a maintenance window: 'UTC', an IANA
+/// timezone name like "America/New_York", or a fixed offset from UTC like "+01:00" or "-05:30".
+/// It stores the original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Timezone {
+    inner: String,
+}
+
+impl TryFrom<&str> for Timezone {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        parse_datetime::parse_timezone(input).context(error::InvalidMaintenanceWindow)?;
+        Ok(Timezone {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(Timezone, "Timezone");
+
+#[cfg(test)]
+mod test_timezone {
+    use super::Timezone;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_offsets() {
+        for ok in &["UTC", "Z", "+00:00", "+01:00", "-05:30", "America/Chicago"] {
+            Timezone::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_offsets() {
+        for err in &["", "PST", "America/Springfield", "+1", "01:00", "+25:00"] {
+            Timezone::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// DownloadRate represents a string that is a valid, non-zero transfer rate in bytes per second,
+/// like "512KiB" or "10MB", with an optional "/s" suffix.  A bare number is taken to be bytes.
+/// It stores the original string and makes it accessible through standard traits; the parsed
+/// rate is available through `bytes_per_second`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DownloadRate {
+    inner: String,
+    bytes_per_second: u64,
+}
+
+impl DownloadRate {
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+}
+
+impl TryFrom<&str> for DownloadRate {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let rate = input.trim_end_matches("/s");
+        let unit_start = rate
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rate.len());
+        let (number, unit) = rate.split_at(unit_start);
+        let number: u64 = number.parse().ok().context(error::InvalidDownloadRate {
+            input,
+            msg: "must start with a number",
+        })?;
+        let multiplier: u64 = match unit.trim() {
+            "" | "B" => 1,
+            "KB" => 1000,
+            "KiB" => 1024,
+            "MB" => 1000 * 1000,
+            "MiB" => 1024 * 1024,
+            "GB" => 1000 * 1000 * 1000,
+            "GiB" => 1024 * 1024 * 1024,
+            _ => {
+                return error::InvalidDownloadRate {
+                    input,
+                    msg: "unit must be one of B, KB, KiB, MB, MiB, GB, or GiB",
+                }
+                .fail()
+            }
+        };
+        let bytes_per_second =
+            number
+                .checked_mul(multiplier)
+                .context(error::InvalidDownloadRate {
+                    input,
+                    msg: "rate is too large",
+                })?;
+        ensure!(
+            bytes_per_second > 0,
+            error::InvalidDownloadRate {
+                input,
+                msg: "rate must be greater than zero",
+            }
+        );
+        Ok(DownloadRate {
+            inner: input.to_string(),
+            bytes_per_second,
+        })
+    }
+}
+
+string_impls_for!(DownloadRate, "DownloadRate");
+
+#[cfg(test)]
+mod test_download_rate {
+    use super::DownloadRate;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_rates() {
+        for (ok, bytes) in &[
+            ("1", 1),
+            ("100B", 100),
+            ("512KiB", 512 * 1024),
+            ("10MB/s", 10_000_000),
+            ("2 GiB/s", 2 * 1024 * 1024 * 1024),
+        ] {
+            assert_eq!(
+                DownloadRate::try_from(*ok).unwrap().bytes_per_second(),
+                *bytes
+            );
+        }
+    }
+
+    #[test]
+    fn bad_rates() {
+        for err in &[
+            "",
+            "0",
+            "0MiB",
+            "MiB",
+            "10 mebibytes",
+            "-5KB",
+            "1.5MB",
+            "99999999999GiB",
+        ] {
+            DownloadRate::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
 /// DNSDomain represents a string that is a valid DNS domain. It stores the
 /// original string and makes it accessible through standard traits. Its purpose
 /// is input validation, for example validating the kubelet's "clusterDomain"
@@ -618,3 +912,490 @@ mod test_valid_container_mode {
         assert!(BootstrapContainerMode::try_from("invalid").is_err());
     }
 }
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// NetworkInterfaceName represents a string that is a valid Linux network interface name, like
+/// "eth0", "bond0", or "eth0.100" for a VLAN.  The kernel allows almost any character, but we
+/// restrict names to a conservative set so they're safe to use in file names and configuration
+/// files.  It stores the original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct NetworkInterfaceName {
+    inner: String,
+}
+
+lazy_static! {
+    /// Pattern matching a network interface name.  The kernel limits names to 15 bytes.
+    pub(crate) static ref NETWORK_INTERFACE_NAME: Regex =
+        Regex::new(r"^[a-zA-Z0-9_.-]{1,15}$").unwrap();
+}
+
+impl TryFrom<&str> for NetworkInterfaceName {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        // "." and ".." are the only names the kernel rejects that match our pattern.
+        ensure!(
+            NETWORK_INTERFACE_NAME.is_match(input) && input != "." && input != "..",
+            error::Pattern {
+                thing: "Network interface name",
+                pattern: NETWORK_INTERFACE_NAME.clone(),
+                input
+            }
+        );
+        Ok(NetworkInterfaceName {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(NetworkInterfaceName, "NetworkInterfaceName");
+
+#[cfg(test)]
+mod test_network_interface_name {
+    use super::NetworkInterfaceName;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_network_interface_name() {
+        for ok in &[
+            "eth0",
+            "ens5",
+            "bond0",
+            "eth0.100",
+            "br-ex",
+            "a",
+            "abcdefghijklmno",
+        ] {
+            NetworkInterfaceName::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_network_interface_name() {
+        for err in &[
+            "",
+            ".",
+            "..",
+            "abcdefghijklmnop",
+            "eth/0",
+            "eth 0",
+            "eth0:1",
+        ] {
+            NetworkInterfaceName::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// IpCidr represents an IPv4 or IPv6 address with a prefix length in CIDR notation, like
+/// "192.168.1.10/24" or "2001:db8::10/64".  It's used both for addresses assigned to an interface
+/// and for route destinations, so host bits are allowed to be set.  It stores the original string
+/// and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct IpCidr {
+    inner: String,
+}
+
+impl TryFrom<&str> for IpCidr {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        let mut parts = input.splitn(2, '/');
+        let address = parts.next().unwrap_or_default();
+        let prefix = parts.next().context(error::InvalidCidr {
+            input,
+            msg: "missing '/' and prefix length",
+        })?;
+
+        let address = IpAddr::from_str(address).or_else(|e| {
+            error::InvalidCidr {
+                input,
+                msg: e.to_string(),
+            }
+            .fail()
+        })?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = u8::from_str(prefix).or_else(|e| {
+            error::InvalidCidr {
+                input,
+                msg: format!("invalid prefix length: {}", e),
+            }
+            .fail()
+        })?;
+        ensure!(
+            prefix <= max_prefix,
+            error::InvalidCidr {
+                input,
+                msg: format!("prefix length must be at most {}", max_prefix),
+            }
+        );
+
+        Ok(IpCidr {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(IpCidr, "IpCidr");
+
+#[cfg(test)]
+mod test_ip_cidr {
+    use super::IpCidr;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_ip_cidr() {
+        for ok in &[
+            "192.168.1.10/24",
+            "0.0.0.0/0",
+            "10.0.0.1/32",
+            "2001:db8::10/64",
+            "::/0",
+            "fe80::1/128",
+        ] {
+            IpCidr::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_ip_cidr() {
+        for err in &[
+            "",
+            "192.168.1.10",
+            "192.168.1.10/",
+            "192.168.1.10/33",
+            "2001:db8::10/129",
+            "192.168.1/24",
+            "example.com/24",
+            "192.168.1.10/-1",
+            "192.168.1.10/24/8",
+        ] {
+            IpCidr::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// NetworkBondMode represents a string that is a valid Linux bonding driver mode name.  It stores
+/// the original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct NetworkBondMode {
+    inner: String,
+}
+
+impl TryFrom<&str> for NetworkBondMode {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        ensure!(
+            matches!(
+                input,
+                "balance-rr"
+                    | "active-backup"
+                    | "balance-xor"
+                    | "broadcast"
+                    | "802.3ad"
+                    | "balance-tlb"
+                    | "balance-alb"
+            ),
+            error::InvalidBondMode { input }
+        );
+        Ok(NetworkBondMode {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(NetworkBondMode, "NetworkBondMode");
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// ValidLinuxHostname represents a string that is a valid Linux hostname: dot-separated labels of
+/// ASCII alphanumerics and hyphens, where labels don't start or end with a hyphen, and no longer
+/// than the kernel's limit of 64 bytes.  It stores the original string and makes it accessible
+/// through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidLinuxHostname {
+    inner: String,
+}
+
+lazy_static! {
+    /// Pattern matching a hostname; length is checked separately.
+    pub(crate) static ref VALID_LINUX_HOSTNAME: Regex = Regex::new(
+        r"^[a-zA-Z0-9]([a-zA-Z0-9-]*[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9-]*[a-zA-Z0-9])?)*$"
+    )
+    .unwrap();
+}
+
+impl TryFrom<&str> for ValidLinuxHostname {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        ensure!(
+            input.len() <= 64,
+            error::InvalidLinuxHostname {
+                input,
+                msg: "must be no more than 64 characters",
+            }
+        );
+        ensure!(
+            VALID_LINUX_HOSTNAME.is_match(input),
+            error::InvalidLinuxHostname {
+                input,
+                msg: "must be dot-separated labels of letters, digits, and hyphens",
+            }
+        );
+        Ok(ValidLinuxHostname {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidLinuxHostname, "ValidLinuxHostname");
+
+#[cfg(test)]
+mod test_valid_linux_hostname {
+    use super::ValidLinuxHostname;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_linux_hostname() {
+        for ok in &[
+            "localhost",
+            "ip-10-0-0-1.us-west-2.compute.internal",
+            "node-1",
+            "a",
+            "1",
+            &"a".repeat(64),
+        ] {
+            ValidLinuxHostname::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_linux_hostname() {
+        for err in &[
+            "",
+            &"a".repeat(65),
+            "-node",
+            "node-",
+            "node..example",
+            ".node",
+            "node.",
+            "node_1",
+            "node 1",
+            "node\n1",
+        ] {
+            ValidLinuxHostname::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// LogRequest represents one of logdog's log requests: a mode, an output filename, and the mode's
+/// instructions, like `exec my-app.txt /usr/bin/my-app --status`, or `glob` and a pattern.  logdog
+/// parses requests with `LogRequest::parse`, so an invalid request is rejected when it's set rather
+/// than skipped when logs are collected.  It stores the original string and makes it accessible
+/// through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct LogRequest {
+    inner: String,
+}
+
+/// The fields of a log request, borrowed from its text.  `glob` requests have no filename.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LogRequestParts<'a> {
+    pub mode: &'a str,
+    pub filename: &'a str,
+    pub instructions: &'a str,
+}
+
+impl LogRequest {
+    /// Splits a log request into its fields, checking that it has a known mode and the fields that
+    /// mode needs.
+    pub fn parse(input: &str) -> Result<LogRequestParts<'_>, error::Error> {
+        SingleLineString::try_from(input)?;
+        let mut fields = input.splitn(3, ' ');
+        let mode = fields.next().unwrap_or("");
+        let (filename, instructions) = match mode {
+            "glob" => ("", fields.next().unwrap_or("")),
+            "exec" | "http" | "https" | "file" => {
+                (fields.next().unwrap_or(""), fields.next().unwrap_or(""))
+            }
+            _ => {
+                return error::InvalidLogRequest {
+                    input,
+                    msg: "mode must be one of exec, http, https, file, or glob",
+                }
+                .fail()
+            }
+        };
+        if mode != "glob" {
+            // The output must stay in the directory logdog archives.
+            ensure!(
+                !filename.is_empty()
+                    && !filename.contains('/')
+                    && filename != "."
+                    && filename != "..",
+                error::InvalidLogRequest {
+                    input,
+                    msg: "output filename must be a name without '/'",
+                }
+            );
+        }
+        ensure!(
+            !instructions.is_empty(),
+            error::InvalidLogRequest {
+                input,
+                msg: match mode {
+                    "exec" => "missing command",
+                    "http" | "https" => "missing URL",
+                    "file" => "missing source path",
+                    _ => "missing pattern",
+                },
+            }
+        );
+        if mode == "exec" {
+            let words =
+                shell_words::split(instructions).map_err(|e| error::Error::InvalidLogRequest {
+                    input: input.to_string(),
+                    msg: e.to_string(),
+                })?;
+            ensure!(
+                !words.is_empty(),
+                error::InvalidLogRequest {
+                    input,
+                    msg: "missing command",
+                }
+            );
+        }
+        Ok(LogRequestParts {
+            mode,
+            filename,
+            instructions,
+        })
+    }
+}
+
+impl TryFrom<&str> for LogRequest {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        Self::parse(input)?;
+        Ok(LogRequest {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(LogRequest, "LogRequest");
+
+#[cfg(test)]
+mod test_log_request {
+    use super::LogRequest;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_log_request() {
+        for ok in &[
+            "exec my-app.txt /usr/bin/my-app --status",
+            "exec status.txt sh -c 'echo \"hi\"'",
+            "http status.json http://localhost:8080/status",
+            "https status.json https://example.com/status",
+            "file my-app.log /local/my-app/app.log",
+            "glob /var/log/my-app.log*",
+        ] {
+            LogRequest::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_log_request() {
+        for err in &[
+            "",
+            "fetch hello.txt /etc/hello",
+            "exec hello.txt",
+            "exec hello.txt  ",
+            "exec hello.txt echo 'unclosed",
+            "file ../escape /etc/hello",
+            "file sub/dir /etc/hello",
+            "exec .. ls",
+            "http status.json",
+            "glob",
+            "exec a.txt echo a\nexec b.txt echo b",
+        ] {
+            LogRequest::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// HealthCheckCommand represents a command that update-verifier runs to check the health of a new
+/// OS version, like `systemctl is-active my-app` or `sh -c "curl -sf localhost:8080/ready"`.  It's
+/// split into arguments with shell quoting rules, so it must be a non-empty command with balanced
+/// quotes.  It stores the original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HealthCheckCommand {
+    inner: String,
+}
+
+impl TryFrom<&str> for HealthCheckCommand {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        SingleLineString::try_from(input)?;
+        let words =
+            shell_words::split(input).map_err(|e| error::Error::InvalidHealthCheckCommand {
+                input: input.to_string(),
+                msg: e.to_string(),
+            })?;
+        ensure!(
+            !words.is_empty(),
+            error::InvalidHealthCheckCommand {
+                input,
+                msg: "missing command",
+            }
+        );
+        Ok(HealthCheckCommand {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(HealthCheckCommand, "HealthCheckCommand");
+
+#[cfg(test)]
+mod test_health_check_command {
+    use super::HealthCheckCommand;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_health_check_command() {
+        for ok in &[
+            "/usr/bin/true",
+            "systemctl is-active my-app",
+            "sh -c \"systemctl is-active foo\"",
+            "sh -c 'test -e /run/ready'",
+        ] {
+            HealthCheckCommand::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_health_check_command() {
+        for err in &[
+            "",
+            "  ",
+            "sh -c \"unclosed",
+            "sh -c 'unclosed",
+            "true\nfalse",
+        ] {
+            HealthCheckCommand::try_from(*err).unwrap_err();
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! This module contains the `Validate` trait, which checks invariants of a model structure that
+//! span more than one field, like "this setting is required when that one has a given value."
+//! Single values are already checked when they're deserialized into modeled types, so this is
+//! only needed for rules that can't be expressed by a field's type.
+//!
+//! Model structures get an implementation automatically through the `#[model]` attribute, which
+//! derives `Validate`.  The derived implementation validates each field, so nested structures
+//! are checked recursively, and then calls any functions named in `#[validate(with = "...")]`
+//! attributes on the structure.  A function given this way takes a reference to the structure
+//! and returns `validation::Result<()>`.
+
+use bottlerocket_release::BottlerocketRelease;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+pub mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub")]
+    pub enum Error {
+        #[snafu(display("Invalid value for '{}': {}", field, msg))]
+        Invalid { field: String, msg: String },
+    }
+
+    impl Error {
+        /// Adds a name segment, like a field name or map key, to the beginning of the field named
+        /// in the error, so that errors from nested structures point to the full path.
+        pub fn within<S: AsRef<str>>(self, segment: S) -> Self {
+            match self {
+                Error::Invalid { field, msg } => Error::Invalid {
+                    field: format!("{}.{}", segment.as_ref(), field),
+                    msg,
+                },
+            }
+        }
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+/// Validate checks invariants of a structure that can't be expressed by the types of its fields.
+/// The default implementation accepts any value, which is right for scalar types and modeled
+/// types, since they were checked during deserialization.
+pub trait Validate {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Types that don't need any checks beyond their own deserialization.  (Modeled types get an
+// implementation from the string_impls_for macro.)
+impl Validate for String {}
+impl Validate for bool {}
+impl Validate for i32 {}
+impl Validate for u16 {}
+impl Validate for u32 {}
+impl Validate for IpAddr {}
+impl Validate for toml::Value {}
+impl Validate for BottlerocketRelease {}
+
+// Fields are generally Option so that API users can give a subset of settings; we can only check
+// what we were given.
+impl<T: Validate> Validate for Option<T> {
+    fn validate(&self) -> Result<()> {
+        match self {
+            Some(value) => value.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<K: fmt::Display, V: Validate> Validate for HashMap<K, V> {
+    fn validate(&self) -> Result<()> {
+        for (key, value) in self {
+            value.validate().map_err(|e| e.within(key.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Validate> Validate for Vec<T> {
+    fn validate(&self) -> Result<()> {
+        for (index, value) in self.iter().enumerate() {
+            value.validate().map_err(|e| e.within(index.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Validate;
+    use crate::{KubernetesSettings, NetworkSettings, UpdatesSettings};
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+
+    fn tls_settings() -> KubernetesSettings {
+        toml::from_str(
+            r#"
+            api-server = "https://example.com"
+            authentication-mode = "tls"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn tls_requires_bootstrap_token() {
+        let mut k8s = tls_settings();
+        let err = k8s.validate().unwrap_err();
+        assert!(err.to_string().contains("'bootstrap-token'"));
+
+        k8s.bootstrap_token = Some("abcdef.0123456789abcdef".try_into().unwrap());
+        k8s.validate().unwrap();
+    }
+
+    #[test]
+    fn standalone_doesnt_require_bootstrap_token() {
+        let mut k8s = tls_settings();
+        k8s.standalone_mode = Some(true);
+        k8s.validate().unwrap();
+    }
+
+    #[test]
+    fn errors_include_path() {
+        let mut map = HashMap::new();
+        map.insert("cluster".to_string(), Some(tls_settings()));
+        let err = map.validate().unwrap_err();
+        assert!(err.to_string().contains("'cluster.bootstrap-token'"));
+    }
+
+    #[test]
+    fn deny_versions_cant_include_latest() {
+        let updates: UpdatesSettings =
+            toml::from_str(r#"deny-versions = ["1.1.0", "latest"]"#).unwrap();
+        let err = updates.validate().unwrap_err();
+        assert!(err.to_string().contains("'deny-versions'"));
+
+        let updates: UpdatesSettings =
+            toml::from_str(r#"deny-versions = ["1.1.0", "v1.2.0"]"#).unwrap();
+        updates.validate().unwrap();
+    }
+
+    #[test]
+    fn interface_cant_be_bond_and_vlan() {
+        let network: NetworkSettings = toml::from_str(
+            r#"
+            [interfaces.bond0.bond]
+            mode = "active-backup"
+            interfaces = ["eth0", "eth1"]
+            [interfaces.bond0.vlan]
+            device = "eth0"
+            id = 100
+            "#,
+        )
+        .unwrap();
+        let err = network.validate().unwrap_err();
+        assert!(err.to_string().contains("'interfaces.bond0.bond'"));
+    }
+
+    #[test]
+    fn vlan_id_in_range() {
+        let network: NetworkSettings = toml::from_str(
+            r#"
+            [interfaces."eth0.4095".vlan]
+            device = "eth0"
+            id = 4095
+            "#,
+        )
+        .unwrap();
+        let err = network.validate().unwrap_err();
+        assert!(err.to_string().contains("'interfaces.eth0.4095.vlan.id'"));
+    }
+
+    #[test]
+    fn only_one_primary_interface() {
+        let mut network: NetworkSettings = toml::from_str(
+            r#"
+            [interfaces.eth0]
+            dhcp4 = true
+            primary = true
+            [interfaces.eth1]
+            addresses = ["192.168.1.10/24"]
+            primary = true
+            "#,
+        )
+        .unwrap();
+        network.validate().unwrap_err();
+
+        let interfaces = network.interfaces.as_mut().unwrap();
+        for interface in interfaces.values_mut() {
+            interface.primary = None;
+        }
+        network.validate().unwrap();
+    }
+}
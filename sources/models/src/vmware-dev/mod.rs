@@ -4,8 +4,8 @@ use std::collections::HashMap;
 
 use crate::modeled_types::Identifier;
 use crate::{
-    BootstrapContainer, HostContainer, KernelSettings, MetricsSettings, NetworkSettings,
-    NtpSettings, UpdatesSettings,
+    BootstrapContainer, DnsSettings, HostContainer, KernelSettings, LogdogSettings,
+    MetricsSettings, NetworkSettings, NtpSettings, UpdatesSettings,
 };
 
 // Note: we have to use 'rename' here because the top-level Settings structure is the only one
@@ -18,6 +18,8 @@ struct Settings {
     bootstrap_containers: HashMap<Identifier, BootstrapContainer>,
     ntp: NtpSettings,
     network: NetworkSettings,
+    dns: DnsSettings,
     kernel: KernelSettings,
     metrics: MetricsSettings,
+    logdog: LogdogSettings,
 }
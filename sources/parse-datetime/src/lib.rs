@@ -18,6 +18,16 @@ Examples:
 * `"in 2 weeks"`
 * `"1 hour"`
 * `"7 days"`
+
+# Maintenance windows
+
+The library also parses recurring maintenance windows with `MaintenanceWindow::new`, from:
+
+* a cron-like schedule of start times, `"minute hour day-of-month month day-of-week"`, like `"0 2 * * Sat,Sun"`
+* a duration in the form above, like `"3 hours"`
+* a timezone in which the schedule is interpreted: `"UTC"`, an IANA timezone name like `"America/New_York"`, or a fixed offset like `"-05:00"`
+
+A window can then tell you whether a given time falls within it, and when the next one starts.
 */
 
 use chrono::{DateTime, Duration, FixedOffset, Utc};
@@ -27,7 +37,7 @@ mod error {
     use snafu::Snafu;
 
     #[derive(Debug, Snafu)]
-    #[snafu(visibility = "pub(super)")]
+    #[snafu(visibility = "pub(crate)")]
     pub enum Error {
         #[snafu(display("Date argument '{}' is invalid: {}", input, msg))]
         DateArgInvalid { input: String, msg: &'static str },
@@ -41,11 +51,40 @@ mod error {
             input: String,
             source: std::num::ParseIntError,
         },
+
+        #[snafu(display("Schedule '{}' is invalid: {}", input, msg))]
+        ScheduleInvalid { input: String, msg: &'static str },
+
+        #[snafu(display("Schedule '{}' has invalid {} field: {}", input, field, msg))]
+        ScheduleField {
+            input: String,
+            field: &'static str,
+            msg: &'static str,
+        },
+
+        #[snafu(display(
+            "Timezone '{}' is invalid, expected 'UTC', a name like 'America/New_York', or an offset like '+01:00'",
+            input
+        ))]
+        TimezoneInvalid { input: String },
+
+        #[snafu(display("Window duration '{}' is invalid: {}", input, source))]
+        WindowDuration {
+            input: String,
+            #[snafu(source(from(Error, Box::new)))]
+            source: Box<Error>,
+        },
+
+        #[snafu(display("Window duration '{}' must be longer than zero", input))]
+        WindowDurationZero { input: String },
     }
 }
 pub use error::Error;
+pub use window::{parse_timezone, parse_window_duration, MaintenanceWindow, Schedule, Timezone};
 type Result<T> = std::result::Result<T, error::Error>;
 
+mod window;
+
 /// Parses a user-specified datetime, either in full RFC 3339 format, or a shorthand like "in 7
 /// days" that's taken as an offset from the time the function is run.
 pub fn parse_datetime(input: &str) -> Result<DateTime<Utc>> {
@@ -0,0 +1,653 @@
+//! Maintenance windows: recurring spans of time, given by a cron-like schedule of start times, a
+//! duration, and a timezone in which the schedule is interpreted.
+
+use crate::error;
+use crate::{parse_offset, Result};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Offset,
+    TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use snafu::{ensure, OptionExt, ResultExt};
+
+/// How far ahead we look for the next start of a window.  Eight years covers schedules that only
+/// match February 29th, even across a skipped leap year at the turn of a century.
+const MAX_SEARCH_DAYS: i64 = 8 * 366;
+
+const MONTH_NAMES: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const DAY_NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A set of start times, parsed from a cron-like expression with five space-separated fields:
+/// `minute hour day-of-month month day-of-week`.
+///
+/// Each field can be `*`, a number, a range like `1-5`, a step like `*/15` or `0-30/10`, or a
+/// comma-separated list of those.  Months and days of the week can also be given by their
+/// three-letter English names, like `Jan` or `Sat`; Sunday is both 0 and 7.  As with cron, if
+/// both day-of-month and day-of-week are restricted, a day matching either one is a match.  Only a
+/// bare `*` leaves a field unrestricted; `*/2` is a restriction like any other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+/// Describes one field of a schedule expression for parsing.
+struct Field {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+    // The number that the first entry of `names` stands for.
+    names_start: u32,
+}
+
+impl Schedule {
+    /// Parses a cron-like expression; see the type documentation for the format.
+    pub fn parse(input: &str) -> Result<Self> {
+        let fields: Vec<&str> = input.split_whitespace().collect();
+        ensure!(
+            fields.len() == 5,
+            error::ScheduleInvalid {
+                input,
+                msg: "expected five fields: minute hour day-of-month month day-of-week",
+            }
+        );
+
+        let minutes = parse_field(
+            input,
+            fields[0],
+            &Field {
+                name: "minute",
+                min: 0,
+                max: 59,
+                names: &[],
+                names_start: 0,
+            },
+        )?;
+        let hours = parse_field(
+            input,
+            fields[1],
+            &Field {
+                name: "hour",
+                min: 0,
+                max: 23,
+                names: &[],
+                names_start: 0,
+            },
+        )?;
+        let days_of_month = parse_field(
+            input,
+            fields[2],
+            &Field {
+                name: "day-of-month",
+                min: 1,
+                max: 31,
+                names: &[],
+                names_start: 0,
+            },
+        )?;
+        let months = parse_field(
+            input,
+            fields[3],
+            &Field {
+                name: "month",
+                min: 1,
+                max: 12,
+                names: MONTH_NAMES,
+                names_start: 1,
+            },
+        )?;
+        let mut days_of_week = parse_field(
+            input,
+            fields[4],
+            &Field {
+                name: "day-of-week",
+                min: 0,
+                max: 7,
+                names: DAY_NAMES,
+                names_start: 0,
+            },
+        )?;
+        // 7 is another name for Sunday; fold it into 0 so we only have to check one bit.
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+
+        let schedule = Self {
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            any_day_of_month: fields[2] == "*",
+            any_day_of_week: fields[4] == "*",
+        };
+
+        // Something like "0 0 31 2 *" is syntactically fine but would never start a window.
+        ensure!(
+            schedule.can_match_a_day(),
+            error::ScheduleInvalid {
+                input,
+                msg: "no day of the year matches the given day-of-month and month",
+            }
+        );
+
+        Ok(schedule)
+    }
+
+    /// Returns whether windows can start at some point during the given date.
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        if !has_bit(self.months, date.month()) {
+            return false;
+        }
+        let day_of_month = has_bit(self.days_of_month, date.day());
+        let day_of_week = has_bit(self.days_of_week, date.weekday().num_days_from_sunday());
+        // The bits of an unrestricted field are all set, so it only matters when both are
+        // restricted.
+        if self.any_day_of_month || self.any_day_of_week {
+            day_of_month && day_of_week
+        } else {
+            day_of_month || day_of_week
+        }
+    }
+
+    /// Returns the start times within any matching day, earliest first.
+    fn times(&self) -> Vec<NaiveTime> {
+        let mut times = Vec::new();
+        for hour in bits(self.hours) {
+            for minute in bits(self.minutes) {
+                if let Some(time) = NaiveTime::from_hms_opt(hour, minute, 0) {
+                    times.push(time);
+                }
+            }
+        }
+        times
+    }
+
+    /// Returns whether any day could ever match.  If day-of-week is restricted, some day of the
+    /// week always comes around, so we only need to check day-of-month against month lengths.
+    fn can_match_a_day(&self) -> bool {
+        if self.any_day_of_month || !self.any_day_of_week {
+            return true;
+        }
+        // February is considered to have 29 days so that "29 2" is allowed for leap years.
+        const MONTH_LENGTHS: [u32; 12] = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+        bits(self.months).any(|month| {
+            bits(self.days_of_month).any(|day| day <= MONTH_LENGTHS[month as usize - 1])
+        })
+    }
+}
+
+/// Parses one comma-separated field of a schedule into a bit set of the allowed values.
+fn parse_field(input: &str, field_str: &str, field: &Field) -> Result<u64> {
+    let mut set = 0;
+    for item in field_str.split(',') {
+        let mut parts = item.splitn(2, '/');
+        let range_str = parts.next().unwrap_or_default();
+        let step = match parts.next() {
+            Some(step_str) => {
+                let step: u32 = step_str.parse().ok().context(error::ScheduleField {
+                    input,
+                    field: field.name,
+                    msg: "step must be a number",
+                })?;
+                ensure!(
+                    step > 0,
+                    error::ScheduleField {
+                        input,
+                        field: field.name,
+                        msg: "step must be greater than zero",
+                    }
+                );
+                Some(step)
+            }
+            None => None,
+        };
+
+        let (start, end) = if range_str == "*" {
+            (field.min, field.max)
+        } else {
+            let mut bounds = range_str.splitn(2, '-');
+            let start = parse_value(input, bounds.next().unwrap_or_default(), field)?;
+            let end = match bounds.next() {
+                Some(end_str) => parse_value(input, end_str, field)?,
+                // "5/10" means every 10th value starting at 5, like "5-max/10".
+                None if step.is_some() => field.max,
+                None => start,
+            };
+            ensure!(
+                start <= end,
+                error::ScheduleField {
+                    input,
+                    field: field.name,
+                    msg: "range must not end before it starts",
+                }
+            );
+            (start, end)
+        };
+
+        let mut value = start;
+        while value <= end {
+            set |= 1 << value;
+            value += step.unwrap_or(1);
+        }
+    }
+    Ok(set)
+}
+
+/// Parses a single number, or a name if the field allows them.
+fn parse_value(input: &str, value_str: &str, field: &Field) -> Result<u32> {
+    let lower = value_str.to_lowercase();
+    let value = match field.names.iter().position(|name| *name == lower) {
+        Some(index) => index as u32 + field.names_start,
+        None => value_str.parse().ok().context(error::ScheduleField {
+            input,
+            field: field.name,
+            msg: "expected a number, a range, a step, or '*'",
+        })?,
+    };
+    ensure!(
+        value >= field.min && value <= field.max,
+        error::ScheduleField {
+            input,
+            field: field.name,
+            msg: "value out of range",
+        }
+    );
+    Ok(value)
+}
+
+fn has_bit(set: u64, bit: u32) -> bool {
+    set & (1 << bit) != 0
+}
+
+/// Iterates over the values in a bit set, lowest first.
+fn bits(set: u64) -> impl Iterator<Item = u32> {
+    (0..64).filter(move |bit| has_bit(set, *bit))
+}
+
+/// The timezone in which a schedule is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    /// A fixed offset from UTC, which never changes.
+    Fixed(FixedOffset),
+    /// An IANA zone like "America/New_York", whose offset changes with daylight saving time.
+    Named(Tz),
+}
+
+/// Parses an IANA timezone name like "America/New_York", or a fixed UTC offset like "+01:00" or
+/// "-05:30"; "UTC" and "Z" mean no offset.
+pub fn parse_timezone(input: &str) -> Result<Timezone> {
+    if input.eq_ignore_ascii_case("utc") || input == "Z" {
+        return Ok(Timezone::Fixed(FixedOffset::east(0)));
+    }
+
+    let invalid = || error::TimezoneInvalid { input }.build();
+    let sign = match input.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return input.parse().map(Timezone::Named).map_err(|_| invalid()),
+    };
+    // Hours and minutes are each exactly two digits, so there's no room for another sign.
+    let two_digits = |field: &str| -> Option<u32> {
+        if field.len() == 2 && field.bytes().all(|b| b.is_ascii_digit()) {
+            field.parse().ok()
+        } else {
+            None
+        }
+    };
+    let mut parts = input[1..].splitn(2, ':');
+    let hours = parts.next().and_then(two_digits).ok_or_else(invalid)?;
+    let minutes = parts.next().and_then(two_digits).ok_or_else(invalid)?;
+    ensure!(
+        hours <= 23 && minutes <= 59,
+        error::TimezoneInvalid { input }
+    );
+
+    Ok(Timezone::Fixed(FixedOffset::east(
+        sign * (hours * 3600 + minutes * 60) as i32,
+    )))
+}
+
+/// A recurring window of time in which maintenance, like updates, is allowed to happen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceWindow {
+    schedule: Schedule,
+    duration: Duration,
+    timezone: Timezone,
+}
+
+impl MaintenanceWindow {
+    /// Builds a window from a cron-like schedule of start times (see `Schedule`), a duration like
+    /// "3 hours" (see `parse_offset`), and a timezone like "UTC", "America/New_York" or "-05:00"
+    /// (see `parse_timezone`) in which the schedule is interpreted.
+    pub fn new(schedule: &str, duration: &str, timezone: &str) -> Result<Self> {
+        Ok(Self {
+            schedule: Schedule::parse(schedule)?,
+            duration: parse_window_duration(duration)?,
+            timezone: parse_timezone(timezone)?,
+        })
+    }
+
+    /// Returns whether the given time falls within a window.
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.current_start(time).is_some()
+    }
+
+    /// Returns the start of the window containing the given time, if it's within one.
+    pub fn current_start(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = self.local(time);
+        let earliest = local - self.duration;
+        let mut date = local.date();
+        // Walk back over each day that could hold the start of a window that's still open.
+        while date >= earliest.date() {
+            if self.schedule.matches_day(date) {
+                let latest_start = self
+                    .schedule
+                    .times()
+                    .into_iter()
+                    .rev()
+                    .map(|t| date.and_time(t))
+                    .find(|start| *start <= local && *start > earliest);
+                if let Some(start) = latest_start {
+                    return Some(self.utc(start));
+                }
+            }
+            date = date.pred();
+        }
+        None
+    }
+
+    /// Returns the start of the first window that starts after the given time.  This is `None`
+    /// only in the rare case that no window starts in the next several years.
+    pub fn next_start(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = self.local(after);
+        let times = self.schedule.times();
+        let mut date = local.date();
+        for _ in 0..MAX_SEARCH_DAYS {
+            if self.schedule.matches_day(date) {
+                let next = times
+                    .iter()
+                    .map(|t| date.and_time(*t))
+                    .find(|start| *start > local);
+                if let Some(start) = next {
+                    return Some(self.utc(start));
+                }
+            }
+            date = date.succ();
+        }
+        None
+    }
+
+    /// Returns the end of the window containing the given time, if it's within one.
+    pub fn current_end(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.current_start(time).map(|start| start + self.duration)
+    }
+
+    fn local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        match self.timezone {
+            Timezone::Fixed(offset) => time.with_timezone(&offset).naive_local(),
+            Timezone::Named(tz) => time.with_timezone(&tz).naive_local(),
+        }
+    }
+
+    fn utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let offset = match self.timezone {
+            // Fixed offsets never have ambiguous or missing local times.
+            Timezone::Fixed(offset) => offset,
+            // When clocks go back, a local time happens twice, and we start at the first.  When
+            // they go forward, a skipped local time is read with the offset from before the
+            // change, so the window starts as much later as the clocks moved, like it would with
+            // cron.  Changes are never a day apart, so the day before has the earlier offset.
+            Timezone::Named(tz) => tz
+                .offset_from_local_datetime(&local)
+                .earliest()
+                .or_else(|| {
+                    tz.offset_from_local_datetime(&(local - Duration::days(1)))
+                        .earliest()
+                })
+                .map(|offset| offset.fix())
+                .unwrap_or_else(|| FixedOffset::east(0)),
+        };
+        let offset = Duration::seconds(offset.local_minus_utc().into());
+        Utc.from_utc_datetime(&(local - offset))
+    }
+}
+
+/// Parses the length of a window, like "3 hours", which must be longer than zero.
+pub fn parse_window_duration(input: &str) -> Result<Duration> {
+    let duration = parse_offset(input).context(error::WindowDuration { input })?;
+    ensure!(
+        duration > Duration::zero(),
+        error::WindowDurationZero { input }
+    );
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().into()
+    }
+
+    #[test]
+    fn good_schedules() {
+        for input in &[
+            "* * * * *",
+            "0 2 * * *",
+            "30 1 * * Sat,Sun",
+            "0 */4 * * 1-5",
+            "0-30/10 22 1,15 * *",
+            "0 3 * Jan-Mar 0",
+            "0 3 29 2 *",
+            "0 3 31 2 Mon",
+            "5/20 3 * * 7",
+        ] {
+            assert!(Schedule::parse(input).is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn bad_schedules() {
+        for input in &[
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "* * * * Funday",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "a * * * *",
+            "0 0 31 2 *",
+            "0 0 30,31 Feb *",
+        ] {
+            assert!(Schedule::parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn timezones() {
+        let fixed = |offset| Timezone::Fixed(offset);
+        assert_eq!(parse_timezone("UTC").unwrap(), fixed(FixedOffset::east(0)));
+        assert_eq!(parse_timezone("Z").unwrap(), fixed(FixedOffset::east(0)));
+        assert_eq!(
+            parse_timezone("+01:00").unwrap(),
+            fixed(FixedOffset::east(3600))
+        );
+        assert_eq!(
+            parse_timezone("-05:30").unwrap(),
+            fixed(FixedOffset::west(5 * 3600 + 30 * 60))
+        );
+        assert_eq!(
+            parse_timezone("America/New_York").unwrap(),
+            Timezone::Named(Tz::America__New_York)
+        );
+        for input in &[
+            "",
+            "PST",
+            "+1",
+            "+01",
+            "01:00",
+            "+24:00",
+            "+01:60",
+            "+001:00",
+            "+-5:00",
+            "-+5:00",
+            "+05:-1",
+            "Mars/Olympus_Mons",
+        ] {
+            assert!(parse_timezone(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert!(MaintenanceWindow::new("0 2 * * *", "3 hours", "UTC").is_ok());
+        assert!(MaintenanceWindow::new("0 2 * * *", "0 hours", "UTC").is_err());
+        assert!(MaintenanceWindow::new("0 2 * * *", "3 months", "UTC").is_err());
+    }
+
+    #[test]
+    fn contains() {
+        // 2021-06-05 is a Saturday.
+        let window = MaintenanceWindow::new("0 2 * * Sat", "3 hours", "UTC").unwrap();
+        assert!(!window.contains(utc("2021-06-05T01:59:59Z")));
+        assert!(window.contains(utc("2021-06-05T02:00:00Z")));
+        assert!(window.contains(utc("2021-06-05T04:59:59Z")));
+        assert!(!window.contains(utc("2021-06-05T05:00:00Z")));
+        assert!(!window.contains(utc("2021-06-06T02:30:00Z")));
+        assert_eq!(
+            window.current_start(utc("2021-06-05T03:00:00Z")),
+            Some(utc("2021-06-05T02:00:00Z"))
+        );
+        assert_eq!(
+            window.current_end(utc("2021-06-05T03:00:00Z")),
+            Some(utc("2021-06-05T05:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn contains_across_midnight() {
+        let window = MaintenanceWindow::new("0 23 * * Fri", "4 hours", "UTC").unwrap();
+        // Friday night into Saturday morning.
+        assert!(window.contains(utc("2021-06-04T23:30:00Z")));
+        assert!(window.contains(utc("2021-06-05T02:59:00Z")));
+        assert!(!window.contains(utc("2021-06-05T03:00:00Z")));
+    }
+
+    #[test]
+    fn contains_with_timezone() {
+        // 02:00 at UTC-05:00 is 07:00 UTC.
+        let window = MaintenanceWindow::new("0 2 * * *", "1 hour", "-05:00").unwrap();
+        assert!(!window.contains(utc("2021-06-05T02:30:00Z")));
+        assert!(window.contains(utc("2021-06-05T07:30:00Z")));
+        // Saturday 01:00 at UTC+02:00 is Friday 23:00 UTC.
+        let window = MaintenanceWindow::new("0 1 * * Sat", "1 hour", "+02:00").unwrap();
+        assert!(window.contains(utc("2021-06-04T23:30:00Z")));
+        assert!(!window.contains(utc("2021-06-05T23:30:00Z")));
+    }
+
+    #[test]
+    fn contains_with_named_timezone() {
+        // 02:00 in New York is 07:00 UTC in winter, and 06:00 UTC in summer.
+        let window = MaintenanceWindow::new("0 2 * * *", "1 hour", "America/New_York").unwrap();
+        assert!(window.contains(utc("2021-01-05T07:30:00Z")));
+        assert!(!window.contains(utc("2021-01-05T06:30:00Z")));
+        assert!(window.contains(utc("2021-06-05T06:30:00Z")));
+        assert!(!window.contains(utc("2021-06-05T07:30:00Z")));
+    }
+
+    #[test]
+    fn daylight_saving_changes() {
+        let window = MaintenanceWindow::new("30 2 * * *", "1 hour", "America/New_York").unwrap();
+        // Clocks went forward from 02:00 to 03:00 on 2021-03-14, so 02:30 didn't happen; the
+        // window starts at 03:30 instead.
+        assert_eq!(
+            window.next_start(utc("2021-03-14T05:00:00Z")),
+            Some(utc("2021-03-14T07:30:00Z"))
+        );
+        // Clocks went back from 02:00 to 01:00 on 2021-11-07, so 01:30 happened twice; 02:30
+        // only happened once.
+        let window = MaintenanceWindow::new("30 1 * * *", "1 hour", "America/New_York").unwrap();
+        assert_eq!(
+            window.next_start(utc("2021-11-07T04:00:00Z")),
+            Some(utc("2021-11-07T05:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn overlapping_windows() {
+        // Windows start every hour and last two, so we're always in one.
+        let window = MaintenanceWindow::new("0 * * * *", "2 hours", "UTC").unwrap();
+        assert_eq!(
+            window.current_start(utc("2021-06-05T10:15:00Z")),
+            Some(utc("2021-06-05T10:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn next_start() {
+        let window = MaintenanceWindow::new("30 2 * * Sat,Sun", "1 hour", "UTC").unwrap();
+        // From Wednesday, the next window is Saturday.
+        assert_eq!(
+            window.next_start(utc("2021-06-02T12:00:00Z")),
+            Some(utc("2021-06-05T02:30:00Z"))
+        );
+        // From inside Saturday's window, the next is Sunday's.
+        assert_eq!(
+            window.next_start(utc("2021-06-05T02:30:00Z")),
+            Some(utc("2021-06-06T02:30:00Z"))
+        );
+
+        let window = MaintenanceWindow::new("0 0 29 2 *", "1 hour", "+01:00").unwrap();
+        assert_eq!(
+            window.next_start(utc("2021-06-01T00:00:00Z")),
+            Some(utc("2024-02-28T23:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // The 1st of the month, or any Monday.  2021-06-01 is a Tuesday.
+        let window = MaintenanceWindow::new("0 0 1 * Mon", "1 hour", "UTC").unwrap();
+        assert!(window.contains(utc("2021-06-01T00:30:00Z")));
+        assert!(!window.contains(utc("2021-06-02T00:30:00Z")));
+        assert!(window.contains(utc("2021-06-07T00:30:00Z")));
+    }
+
+    #[test]
+    fn day_steps() {
+        // Every other day of the week: Sunday, Tuesday, Thursday and Saturday.  2021-06-01 is a
+        // Tuesday.
+        let window = MaintenanceWindow::new("0 2 * * */2", "1 hour", "UTC").unwrap();
+        assert!(window.contains(utc("2021-06-01T02:30:00Z")));
+        assert!(!window.contains(utc("2021-06-02T02:30:00Z")));
+        assert!(window.contains(utc("2021-06-03T02:30:00Z")));
+
+        // Every other day of the month, starting with the 1st.
+        let window = MaintenanceWindow::new("0 2 */2 * *", "1 hour", "UTC").unwrap();
+        assert!(window.contains(utc("2021-06-01T02:30:00Z")));
+        assert!(!window.contains(utc("2021-06-02T02:30:00Z")));
+        assert!(window.contains(utc("2021-06-03T02:30:00Z")));
+
+        // A day-of-month step still counts when day-of-week is restricted: odd days, or Mondays.
+        // 2021-06-07 is a Monday.
+        let window = MaintenanceWindow::new("0 2 */2 * Mon", "1 hour", "UTC").unwrap();
+        assert!(window.contains(utc("2021-06-03T02:30:00Z")));
+        assert!(!window.contains(utc("2021-06-04T02:30:00Z")));
+        assert!(window.contains(utc("2021-06-07T02:30:00Z")));
+    }
+}
@@ -0,0 +1,354 @@
+/*!
+# Background
+
+This library keeps a persistent log of update events, so that administrators can see what
+happened to a host's updates after the fact.
+
+Each step of an update records an `Event`: refreshing the list of updates, preparing (downloading)
+an update, activating or deactivating it, the result of booting into a new version, datastore
+migrations, and automatic rollbacks.
+
+Events are stored as JSON lines in `HISTORY_FILE`, under `/var/lib` so that they survive reboots
+and updates.  The log is append-only from the point of view of callers, but it's bounded in size;
+once it grows past `MAX_FILE_SIZE`, the oldest events are dropped.  Writers take an exclusive
+lock on a sibling lock file, so events from different processes aren't lost or interleaved.
+
+Recording history should never stop an update, so callers are expected to log, rather than fail
+on, errors from `append`.
+*/
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use nix::fcntl::{flock, FlockArg};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(crate)")]
+    pub enum Error {
+        #[snafu(display("Failed to create directory '{}': {}", path.display(), source))]
+        CreateDir {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to lock update history '{}': {}", path.display(), source))]
+        Lock { path: PathBuf, source: nix::Error },
+
+        #[snafu(display("Failed to open update history lock '{}': {}", path.display(), source))]
+        LockOpen {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to serialize update event: {}", source))]
+        Serialize { source: serde_json::Error },
+
+        #[snafu(display("Failed to read update history '{}': {}", path.display(), source))]
+        ReadHistory {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to write update history '{}': {}", path.display(), source))]
+        WriteHistory {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+pub use error::Error;
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Where the update history is kept by default.
+pub const HISTORY_FILE: &str = "/var/lib/update-history/events.json";
+
+/// Once the history grows past this size, in bytes, the oldest events are dropped until it's
+/// half this size.
+pub const MAX_FILE_SIZE: u64 = 256 * 1024;
+
+/// Messages longer than this, in bytes, are truncated so one event can't crowd out the rest.
+/// The end of the message is kept, since that's usually where the underlying cause is.
+pub const MAX_MESSAGE_SIZE: usize = 2048;
+
+/// The step of the update process that an event describes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventType {
+    Refresh,
+    Prepare,
+    Activate,
+    Deactivate,
+    Boot,
+    Migration,
+    Rollback,
+}
+
+/// One entry in the update history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub timestamp: DateTime<Utc>,
+    pub event_type: EventType,
+    pub success: bool,
+    /// The version running when the event happened, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<Version>,
+    /// The version being updated to, if the event concerns one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_version: Option<Version>,
+    /// Further detail, like the error that caused a failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Event {
+    /// Creates an event happening now, without version or message details.
+    pub fn new(event_type: EventType, success: bool) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            event_type,
+            success,
+            version: None,
+            target_version: None,
+            message: None,
+        }
+    }
+}
+
+/// Adds an event to the end of the history at the given path, creating it if needed, and drops
+/// the oldest events if the history has grown too large.
+pub fn append<P: AsRef<Path>>(path: P, event: &Event) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(error::CreateDir { path: parent })?;
+    }
+
+    let mut event = event.clone();
+    if let Some(message) = &event.message {
+        event.message = Some(truncate_message(message));
+    }
+    let mut line = serde_json::to_vec(&event).context(error::Serialize)?;
+    line.push(b'\n');
+
+    // Held until we return, so compaction can't drop an event another process is appending.
+    let _lock = lock(path)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(error::WriteHistory { path })?;
+    file.write_all(&line)
+        .context(error::WriteHistory { path })?;
+    let size = file.metadata().context(error::WriteHistory { path })?.len();
+
+    if size > MAX_FILE_SIZE {
+        compact(path)?;
+    }
+    Ok(())
+}
+
+/// Returns the events in the history at the given path, oldest first.  A missing history is
+/// treated as empty, and lines that can't be parsed are skipped.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Event>> {
+    let path = path.as_ref();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(error::ReadHistory { path }),
+    };
+
+    let mut events = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.context(error::ReadHistory { path })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(event) => events.push(event),
+            // A partial write, for example from losing power, shouldn't hide the rest.
+            Err(e) => warn!(
+                "Skipping unreadable update event in '{}': {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(events)
+}
+
+/// Takes an exclusive lock for writing the history at the given path, waiting for other writers.
+/// The lock is released when the returned file is closed.
+fn lock(path: &Path) -> Result<File> {
+    let lock_path = sibling_path(path, "lock");
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&lock_path)
+        .context(error::LockOpen { path: &lock_path })?;
+    flock(lock_file.as_raw_fd(), FlockArg::LockExclusive)
+        .context(error::Lock { path: &lock_path })?;
+    Ok(lock_file)
+}
+
+/// Returns a path next to the given one, with the given suffix added to its file name.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Rewrites the history with only the newest lines that fit in half of `MAX_FILE_SIZE`.  The file
+/// is replaced atomically so readers never see a partial history.  The caller must hold the lock.
+fn compact(path: &Path) -> Result<()> {
+    let data = fs::read(path).context(error::ReadHistory { path })?;
+    let limit = (MAX_FILE_SIZE / 2) as usize;
+
+    let mut kept = Vec::new();
+    let mut kept_size = 0;
+    for line in data.split(|b| *b == b'\n').rev() {
+        if line.is_empty() {
+            continue;
+        }
+        if kept_size + line.len() + 1 > limit {
+            break;
+        }
+        kept_size += line.len() + 1;
+        kept.push(line);
+    }
+
+    let mut compacted = Vec::with_capacity(kept_size);
+    for line in kept.into_iter().rev() {
+        compacted.extend_from_slice(line);
+        compacted.push(b'\n');
+    }
+
+    let tmp_path = sibling_path(path, &format!("{}.tmp", process::id()));
+    fs::write(&tmp_path, compacted).context(error::WriteHistory { path: &tmp_path })?;
+    fs::rename(&tmp_path, path).context(error::WriteHistory { path })?;
+    Ok(())
+}
+
+/// Keeps the end of a message that's longer than `MAX_MESSAGE_SIZE`.
+fn truncate_message(message: &str) -> String {
+    if message.len() <= MAX_MESSAGE_SIZE {
+        return message.to_string();
+    }
+    let mut start = message.len() - MAX_MESSAGE_SIZE;
+    while !message.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("...{}", &message[start..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn append_and_load() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("history").join("events.json");
+        assert!(load(&path).unwrap().is_empty());
+
+        let refresh = Event::new(EventType::Refresh, true);
+        let prepare = Event {
+            target_version: Some(Version::parse("1.2.0").unwrap()),
+            message: Some("Failed to download".to_string()),
+            ..Event::new(EventType::Prepare, false)
+        };
+        append(&path, &refresh).unwrap();
+        append(&path, &prepare).unwrap();
+
+        assert_eq!(load(&path).unwrap(), vec![refresh, prepare]);
+    }
+
+    #[test]
+    fn skips_unreadable_lines() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.json");
+        let event = Event::new(EventType::Boot, true);
+        append(&path, &event).unwrap();
+        fs::write(
+            &path,
+            format!("{}{{\"timestamp\":", fs::read_to_string(&path).unwrap()),
+        )
+        .unwrap();
+
+        assert_eq!(load(&path).unwrap(), vec![event]);
+    }
+
+    #[test]
+    fn concurrent_appends() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.json");
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for seq in 0..300 {
+                        let event = Event {
+                            message: Some(format!("{} {} {}", "x".repeat(1000), writer, seq)),
+                            ..Event::new(EventType::Refresh, false)
+                        };
+                        append(&path, &event).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // Compaction ran while others were appending, but the history should still be the newest
+        // events, so each writer's remaining events run without gaps through its last one.
+        let mut seqs = vec![Vec::new(); 8];
+        for line in fs::read_to_string(&path).unwrap().lines() {
+            let event: Event = serde_json::from_str(line).unwrap();
+            let message = event.message.unwrap();
+            let mut fields = message.rsplit(' ');
+            let seq: usize = fields.next().unwrap().parse().unwrap();
+            let writer: usize = fields.next().unwrap().parse().unwrap();
+            seqs[writer].push(seq);
+        }
+        for writer_seqs in seqs.iter().filter(|s| !s.is_empty()) {
+            let first = writer_seqs[0];
+            assert_eq!(writer_seqs, &(first..300).collect::<Vec<_>>());
+        }
+        assert!(fs::metadata(&path).unwrap().len() <= MAX_FILE_SIZE);
+    }
+
+    #[test]
+    fn drops_oldest_events() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.json");
+        let event = Event {
+            message: Some("x".repeat(MAX_MESSAGE_SIZE * 2)),
+            ..Event::new(EventType::Refresh, false)
+        };
+        for _ in 0..200 {
+            append(&path, &event).unwrap();
+        }
+        let last = Event::new(EventType::Activate, true);
+        append(&path, &last).unwrap();
+
+        assert!(fs::metadata(&path).unwrap().len() <= MAX_FILE_SIZE);
+        let events = load(&path).unwrap();
+        assert!(events.len() < 201);
+        assert_eq!(events.last(), Some(&last));
+        let message = events[0].message.as_ref().unwrap();
+        assert!(message.len() <= MAX_MESSAGE_SIZE + 3);
+    }
+}
@@ -0,0 +1,447 @@
+//! Binary diffs between OS images, so that hosts can download only what changed in an update.
+//!
+//! A delta is a list of instructions for building the new image, either by copying a range of
+//! the old image, which updog reads from the active partition, or by writing bytes included in
+//! the delta itself.  The format is:
+//!
+//! * The 8-byte magic string `BRDELTA1`.
+//! * Any number of operations, each starting with a 1-byte code:
+//!   * `1`, copy: a big-endian `u64` offset into the old image and a `u64` length to copy.
+//!   * `2`, data: a `u64` length, followed by that many bytes to write.
+//! * An end marker: `0`, followed by the `u64` length of the new image.
+//!
+//! Like full images, deltas are compressed with LZ4 in the repository.
+
+use sha2::{Digest, Sha256};
+use snafu::{ensure, ResultExt};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+const MAGIC: &[u8; 8] = b"BRDELTA1";
+const OP_END: u8 = 0;
+const OP_COPY: u8 = 1;
+const OP_DATA: u8 = 2;
+
+/// The size of the blocks that `make` looks for in the old image.  Filesystems in our images are
+/// laid out in blocks of at least this size, so unchanged files tend to stay aligned to it.
+const BLOCK_SIZE: usize = 4096;
+
+/// How much new data `make` will collect before writing it out as one operation.
+const MAX_DATA_LEN: usize = 1024 * 1024;
+
+/// Builds a new image by applying the delta to the old image in `source`, writing the result to
+/// `output`.  Returns the SHA-256 digest of the new image, in hex, so the caller can confirm it
+/// matches what was expected.
+pub fn apply<D, S, W>(mut delta: D, mut source: S, output: W) -> Result<String>
+where
+    D: Read,
+    S: Read + Seek,
+    W: Write,
+{
+    let mut magic = [0; 8];
+    delta.read_exact(&mut magic).context(error::ReadDelta)?;
+    ensure!(&magic == MAGIC, error::BadMagic);
+
+    let mut output = HashingWriter::new(output);
+    loop {
+        let mut op = [0; 1];
+        delta.read_exact(&mut op).context(error::ReadDelta)?;
+        match op[0] {
+            OP_COPY => {
+                let offset = read_u64(&mut delta)?;
+                let len = read_u64(&mut delta)?;
+                source
+                    .seek(SeekFrom::Start(offset))
+                    .context(error::ReadSource)?;
+                let copied =
+                    io::copy(&mut (&mut source).take(len), &mut output).context(error::Copy)?;
+                ensure!(copied == len, error::SourceTooShort { offset, len });
+            }
+            OP_DATA => {
+                let len = read_u64(&mut delta)?;
+                let copied =
+                    io::copy(&mut (&mut delta).take(len), &mut output).context(error::Copy)?;
+                ensure!(copied == len, error::Truncated);
+            }
+            OP_END => {
+                let expected = read_u64(&mut delta)?;
+                ensure!(
+                    expected == output.written,
+                    error::LengthMismatch {
+                        expected,
+                        actual: output.written,
+                    }
+                );
+                break;
+            }
+            op => return error::UnknownOperation { op }.fail(),
+        }
+    }
+    output.flush().context(error::Write)?;
+
+    Ok(hex::encode(output.hasher.finalize()))
+}
+
+/// Writes a delta to `output` that builds the image in `target` from the one in `source`.
+/// Returns the SHA-256 digest of the target image, in hex, for the manifest.
+///
+/// The whole source image is read into memory; this is meant for build hosts, not Bottlerocket.
+pub fn make<S, T, W>(mut source: S, mut target: T, output: W) -> Result<String>
+where
+    S: Read,
+    T: Read,
+    W: Write,
+{
+    let mut old = Vec::new();
+    source.read_to_end(&mut old).context(error::ReadSource)?;
+
+    // Index every aligned block of the old image by a hash of its contents.
+    let mut index: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, block) in old.chunks_exact(BLOCK_SIZE).enumerate() {
+        index
+            .entry(block_hash(block))
+            .or_default()
+            .push(i * BLOCK_SIZE);
+    }
+
+    let mut writer = DeltaWriter::new(output)?;
+    let mut hasher = Sha256::new();
+    let mut total: u64 = 0;
+    let mut block = vec![0; BLOCK_SIZE];
+    loop {
+        let len = read_block(&mut target, &mut block)?;
+        if len == 0 {
+            break;
+        }
+        let block = &block[..len];
+        hasher.update(block);
+        total += len as u64;
+
+        // Prefer continuing the current copy, so that runs of unchanged blocks become one
+        // operation, then look for the block anywhere else in the old image.
+        let continued = writer
+            .copy_end()
+            .filter(|end| old.get(*end..end + len) == Some(block));
+        let found = continued.or_else(|| {
+            if len < BLOCK_SIZE {
+                return None;
+            }
+            index.get(&block_hash(block)).and_then(|offsets| {
+                offsets
+                    .iter()
+                    .copied()
+                    .find(|offset| &old[*offset..offset + len] == block)
+            })
+        });
+
+        match found {
+            Some(offset) => writer.copy(offset as u64, len as u64)?,
+            None => writer.data(block)?,
+        }
+    }
+    writer.finish(total)?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    block.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Fills `buf` from `reader` unless we reach the end of it, and returns how much was read.
+fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context(error::ReadTarget),
+        }
+    }
+    Ok(filled)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes).context(error::ReadDelta)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Passes writes through, keeping a digest and count of everything written.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    written: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Collects operations for `make`, merging adjacent copies and buffering new data.
+struct DeltaWriter<W> {
+    output: W,
+    copy: Option<(u64, u64)>,
+    data: Vec<u8>,
+}
+
+impl<W: Write> DeltaWriter<W> {
+    fn new(mut output: W) -> Result<Self> {
+        output.write_all(MAGIC).context(error::Write)?;
+        Ok(Self {
+            output,
+            copy: None,
+            data: Vec::new(),
+        })
+    }
+
+    /// Returns the offset in the old image just past the current copy, if we're in one.
+    fn copy_end(&self) -> Option<usize> {
+        self.copy
+            .and_then(|(offset, len)| usize::try_from(offset + len).ok())
+    }
+
+    fn copy(&mut self, offset: u64, len: u64) -> Result<()> {
+        self.flush_data()?;
+        match &mut self.copy {
+            Some((start, current_len)) if *start + *current_len == offset => *current_len += len,
+            _ => {
+                self.flush_copy()?;
+                self.copy = Some((offset, len));
+            }
+        }
+        Ok(())
+    }
+
+    fn data(&mut self, data: &[u8]) -> Result<()> {
+        self.flush_copy()?;
+        self.data.extend_from_slice(data);
+        if self.data.len() >= MAX_DATA_LEN {
+            self.flush_data()?;
+        }
+        Ok(())
+    }
+
+    fn flush_copy(&mut self) -> Result<()> {
+        if let Some((offset, len)) = self.copy.take() {
+            self.output.write_all(&[OP_COPY]).context(error::Write)?;
+            self.output
+                .write_all(&offset.to_be_bytes())
+                .context(error::Write)?;
+            self.output
+                .write_all(&len.to_be_bytes())
+                .context(error::Write)?;
+        }
+        Ok(())
+    }
+
+    fn flush_data(&mut self) -> Result<()> {
+        if !self.data.is_empty() {
+            self.output.write_all(&[OP_DATA]).context(error::Write)?;
+            self.output
+                .write_all(&(self.data.len() as u64).to_be_bytes())
+                .context(error::Write)?;
+            self.output.write_all(&self.data).context(error::Write)?;
+            self.data.clear();
+        }
+        Ok(())
+    }
+
+    fn finish(mut self, total: u64) -> Result<()> {
+        self.flush_copy()?;
+        self.flush_data()?;
+        self.output.write_all(&[OP_END]).context(error::Write)?;
+        self.output
+            .write_all(&total.to_be_bytes())
+            .context(error::Write)?;
+        self.output.flush().context(error::Write)
+    }
+}
+
+pub mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Delta does not start with the expected magic string"))]
+        BadMagic,
+
+        #[snafu(display("Failed to copy image data: {}", source))]
+        Copy { source: std::io::Error },
+
+        #[snafu(display(
+            "Delta produced {} bytes, but should have produced {}",
+            actual,
+            expected
+        ))]
+        LengthMismatch { expected: u64, actual: u64 },
+
+        #[snafu(display("Failed to read delta: {}", source))]
+        ReadDelta { source: std::io::Error },
+
+        #[snafu(display("Failed to read old image: {}", source))]
+        ReadSource { source: std::io::Error },
+
+        #[snafu(display("Failed to read new image: {}", source))]
+        ReadTarget { source: std::io::Error },
+
+        #[snafu(display("Old image is too short to copy {} bytes at offset {}", len, offset))]
+        SourceTooShort { offset: u64, len: u64 },
+
+        #[snafu(display("Delta ended in the middle of an operation"))]
+        Truncated,
+
+        #[snafu(display("Unknown operation {} in delta", op))]
+        UnknownOperation { op: u8 },
+
+        #[snafu(display("Failed to write delta: {}", source))]
+        Write { source: std::io::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Makes an "image" of the given number of blocks with recognizable contents.
+    fn image(blocks: usize, seed: u8) -> Vec<u8> {
+        (0..blocks * BLOCK_SIZE)
+            .map(|i| (i / 7) as u8 ^ (i / BLOCK_SIZE) as u8 ^ seed)
+            .collect()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    fn roundtrip(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let mut delta = Vec::new();
+        let digest = make(old, new, &mut delta).unwrap();
+        assert_eq!(digest, sha256(new));
+
+        let mut output = Vec::new();
+        let digest = apply(delta.as_slice(), Cursor::new(old), &mut output).unwrap();
+        assert_eq!(digest, sha256(new));
+        assert_eq!(output, new);
+        delta
+    }
+
+    #[test]
+    fn unchanged_image() {
+        let old = image(64, 0);
+        let delta = roundtrip(&old, &old);
+        // Magic, one copy, and the end marker.
+        assert_eq!(delta.len(), 8 + 17 + 9);
+    }
+
+    #[test]
+    fn changed_blocks() {
+        let old = image(64, 0);
+        let mut new = old.clone();
+        new[10 * BLOCK_SIZE + 5] ^= 0xff;
+        new[40 * BLOCK_SIZE..41 * BLOCK_SIZE].copy_from_slice(&image(1, 0x55));
+        let delta = roundtrip(&old, &new);
+        assert!(delta.len() < 3 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn moved_and_resized() {
+        let old = image(64, 0);
+        let mut new = old[32 * BLOCK_SIZE..].to_vec();
+        new.extend_from_slice(&image(3, 0xaa));
+        new.extend_from_slice(&old[..16 * BLOCK_SIZE]);
+        new.extend_from_slice(b"an unaligned tail");
+        let delta = roundtrip(&old, &new);
+        assert!(delta.len() < 4 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn empty_images() {
+        roundtrip(&[], &[]);
+        roundtrip(&[], &image(2, 0));
+        roundtrip(&image(2, 0), &[]);
+    }
+
+    #[test]
+    fn wrong_source() {
+        // The delta applies, but doesn't produce the image we expected.
+        let old = image(8, 0);
+        let mut new = old.clone();
+        new[..BLOCK_SIZE].copy_from_slice(&image(1, 1));
+        let mut delta = Vec::new();
+        make(old.as_slice(), new.as_slice(), &mut delta).unwrap();
+        let mut output = Vec::new();
+        let digest = apply(delta.as_slice(), Cursor::new(image(8, 2)), &mut output).unwrap();
+        assert_ne!(digest, sha256(&new));
+
+        // A source that's too short can't be copied from.
+        let mut delta = Vec::new();
+        make(old.as_slice(), old.as_slice(), &mut delta).unwrap();
+        let short = &old[..BLOCK_SIZE];
+        assert!(matches!(
+            apply(delta.as_slice(), Cursor::new(short), io::sink()),
+            Err(Error::SourceTooShort { .. })
+        ));
+    }
+
+    #[test]
+    fn bad_deltas() {
+        let old = image(4, 0);
+        let mut delta = Vec::new();
+        make(old.as_slice(), image(4, 1).as_slice(), &mut delta).unwrap();
+
+        let mut bad_magic = delta.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(
+            apply(bad_magic.as_slice(), Cursor::new(&old), io::sink()),
+            Err(Error::BadMagic)
+        ));
+
+        let truncated = &delta[..delta.len() - 100];
+        assert!(apply(truncated, Cursor::new(&old), io::sink()).is_err());
+
+        let mut bad_op = delta.clone();
+        bad_op[8] = 9;
+        assert!(matches!(
+            apply(bad_op.as_slice(), Cursor::new(&old), io::sink()),
+            Err(Error::UnknownOperation { op: 9 })
+        ));
+
+        let mut bad_length = delta;
+        let last = bad_length.len() - 1;
+        bad_length[last] ^= 1;
+        assert!(matches!(
+            apply(bad_length.as_slice(), Cursor::new(&old), io::sink()),
+            Err(Error::LengthMismatch { .. })
+        ));
+    }
+}
@@ -89,6 +89,18 @@ pub enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display(
+        "Invalid channel name '{}'; names may only contain ASCII letters, digits, and hyphens",
+        channel
+    ))]
+    InvalidChannel { channel: String },
+
+    #[snafu(display(
+        "Invalid CVE identifier '{}'; expected a format like 'CVE-2021-3156'",
+        cve
+    ))]
+    InvalidCve { cve: String },
+
     #[snafu(display("Invalid TOML in '{}': {}", path.display(), source))]
     InvalidToml {
         path: PathBuf,
@@ -131,6 +143,16 @@ pub enum Error {
         target: Version,
     },
 
+    #[snafu(display("Targeted waves must list at least one label"))]
+    TargetedWaveLabels,
+
+    #[snafu(display("No update found for {} {} {}", arch, variant, version))]
+    UpdateNotFound {
+        variant: String,
+        arch: String,
+        version: Version,
+    },
+
     #[snafu(display("Failed to serialize update information: {}", source))]
     UpdateSerialize {
         source: serde_json::Error,
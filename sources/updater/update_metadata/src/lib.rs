@@ -1,6 +1,7 @@
 #![warn(clippy::pedantic)]
 
 mod de;
+pub mod delta;
 pub mod error;
 mod se;
 
@@ -20,6 +21,9 @@ use std::path::Path;
 
 pub const MAX_SEED: u32 = 2048;
 
+/// The channel of updates that don't list any channels, which hosts follow by default.
+pub const DEFAULT_CHANNEL: &str = "stable";
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Wave {
     Initial {
@@ -59,7 +63,10 @@ impl Wave {
 /// update waves from TOML files
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateWaves {
+    #[serde(default)]
     pub waves: Vec<UpdateWave>,
+    #[serde(default)]
+    pub targeted_waves: Vec<TargetedUpdateWave>,
 }
 
 impl UpdateWaves {
@@ -80,6 +87,33 @@ pub struct UpdateWave {
     pub fleet_percentage: u32,
 }
 
+/// A wave for the hosts whose `wave-labels` setting includes all of `labels`, which can update
+/// after `start_after` whatever their seed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TargetedUpdateWave {
+    pub start_after: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Manifest form of `TargetedUpdateWave`, with an absolute start time.  Hosts with matching labels
+/// can update from `start_time`, and not before, so that pools like canaries are chosen
+/// deterministically rather than by seed; hosts that don't match still follow the seed waves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TargetedWave {
+    pub labels: BTreeMap<String, String>,
+    pub start_time: DateTime<Utc>,
+}
+
+impl TargetedWave {
+    /// Returns whether a host with the given labels belongs to this wave.
+    #[must_use]
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.labels
+            .iter()
+            .all(|(key, value)| labels.get(key) == Some(value))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Images {
     pub boot: String,
@@ -87,6 +121,67 @@ pub struct Images {
     pub hash: String,
 }
 
+/// Binary diffs that produce an update's images from the images of an earlier version.  Hosts
+/// running the `from` version can download these instead of the full images.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delta {
+    pub from: Version,
+    pub boot: DeltaImage,
+    pub root: DeltaImage,
+    pub hash: DeltaImage,
+}
+
+/// The target name of a binary diff, and the SHA-256 digest (in hex) of the image it produces, so
+/// the result can be checked before it's used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaImage {
+    pub target: String,
+    pub sha256: String,
+}
+
+/// Information about what an update contains, to help people decide whether to apply it.  It's
+/// all optional so that manifests written before it existed remain valid.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReleaseInfo {
+    /// The total size of the update's images, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_notes_url: Option<String>,
+    /// A short summary of the release notes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<String>,
+    /// CVE identifiers, like "CVE-2021-3156", for the vulnerabilities fixed in the update.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cves: Vec<String>,
+    /// Whether the update should be applied as soon as possible.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub urgent: bool,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // serde's skip_serializing_if passes a reference
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl ReleaseInfo {
+    /// Checks that CVE identifiers look like "CVE-<year>-<number>".
+    pub fn validate(&self) -> Result<()> {
+        for cve in &self.cves {
+            let parts: Vec<&str> = cve.split('-').collect();
+            let valid = parts.len() == 3
+                && parts[0] == "CVE"
+                && parts[1].len() == 4
+                && parts[2].len() >= 4
+                && parts[1..]
+                    .iter()
+                    .all(|p| p.chars().all(|c| c.is_ascii_digit()));
+            ensure!(valid, error::InvalidCve { cve });
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Update {
     pub variant: String,
@@ -95,15 +190,74 @@ pub struct Update {
     pub max_version: Version,
     #[serde(deserialize_with = "de::deserialize_bound")]
     pub waves: BTreeMap<u32, DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targeted_waves: Vec<TargetedWave>,
     pub images: Images,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deltas: Vec<Delta>,
+    #[serde(flatten)]
+    pub release_info: ReleaseInfo,
+    /// The channels the update is published to, like "stable" or "beta"; hosts only see updates
+    /// in the channel they follow.  If empty, the update is only in `DEFAULT_CHANNEL`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<String>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
+#[serde(from = "StoredManifest")]
 pub struct Manifest {
     pub updates: Vec<Update>,
+    pub migrations: BTreeMap<(Version, Version), Vec<String>>,
+}
+
+/// The manifest as it's written to the repository.  Versions of updog from before channels only
+/// read `updates` and ignore `channels`, so updates that aren't in `DEFAULT_CHANNEL` are kept in
+/// `channel_updates` instead, where those hosts won't see them.
+#[derive(Deserialize)]
+struct StoredManifest {
+    updates: Vec<Update>,
+    #[serde(default)]
+    channel_updates: Vec<Update>,
     #[serde(deserialize_with = "de::deserialize_migration")]
+    migrations: BTreeMap<(Version, Version), Vec<String>>,
+}
+
+impl From<StoredManifest> for Manifest {
+    fn from(stored: StoredManifest) -> Self {
+        let mut updates = stored.updates;
+        updates.extend(stored.channel_updates);
+        Manifest {
+            updates,
+            migrations: stored.migrations,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StoredManifestRef<'a> {
+    updates: Vec<&'a Update>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    channel_updates: Vec<&'a Update>,
     #[serde(serialize_with = "se::serialize_migration")]
-    pub migrations: BTreeMap<(Version, Version), Vec<String>>,
+    migrations: &'a BTreeMap<(Version, Version), Vec<String>>,
+}
+
+impl Serialize for Manifest {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let (updates, channel_updates) = self
+            .updates
+            .iter()
+            .partition(|update| update.in_channel(DEFAULT_CHANNEL));
+        StoredManifestRef {
+            updates,
+            channel_updates,
+            migrations: &self.migrations,
+        }
+        .serialize(serializer)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -171,6 +325,10 @@ impl Manifest {
             max_version: max_version.clone(),
             images,
             waves: BTreeMap::new(),
+            targeted_waves: Vec::new(),
+            deltas: Vec::new(),
+            release_info: ReleaseInfo::default(),
+            channels: Vec::new(),
         };
         self.update_max_version(
             &update.max_version,
@@ -273,6 +431,19 @@ impl Manifest {
 
         for update in matching {
             update.waves.clear();
+            update.targeted_waves.clear();
+
+            for wave in &waves.targeted_waves {
+                // A wave without labels would match every host.
+                ensure!(!wave.labels.is_empty(), error::TargetedWaveLabels);
+                let offset = parse_offset(&wave.start_after).context(error::BadOffset {
+                    offset: &wave.start_after,
+                })?;
+                update.targeted_waves.push(TargetedWave {
+                    labels: wave.labels.clone(),
+                    start_time: start_at + offset,
+                });
+            }
 
             // The first wave has a 0 seed
             let mut seed = 0;
@@ -299,9 +470,117 @@ impl Manifest {
         Self::validate_updates(&self.updates)?;
         Ok(num_matching)
     }
+
+    /// Adds a delta to the matching updates, replacing any existing delta from the same version.
+    /// Returns the number of matching updates.
+    pub fn add_delta(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        delta: &Delta,
+    ) -> Result<usize> {
+        let matching =
+            self.get_matching_updates(variant.clone(), arch.clone(), image_version.clone());
+        let num_matching = matching.len();
+        ensure!(
+            num_matching > 0,
+            error::UpdateNotFound {
+                variant,
+                arch,
+                version: image_version,
+            }
+        );
+
+        for update in matching {
+            update.deltas.retain(|d| d.from != delta.from);
+            update.deltas.push(delta.clone());
+        }
+        Ok(num_matching)
+    }
+
+    /// Sets the release information of the matching updates, replacing what was there.  Returns
+    /// the number of matching updates.
+    pub fn set_release_info(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        release_info: &ReleaseInfo,
+    ) -> Result<usize> {
+        release_info.validate()?;
+        let matching =
+            self.get_matching_updates(variant.clone(), arch.clone(), image_version.clone());
+        let num_matching = matching.len();
+        ensure!(
+            num_matching > 0,
+            error::UpdateNotFound {
+                variant,
+                arch,
+                version: image_version,
+            }
+        );
+
+        for update in matching {
+            update.release_info = release_info.clone();
+        }
+        Ok(num_matching)
+    }
+
+    /// Sets the channels the matching updates are published to, replacing what was there.  An
+    /// empty list puts them back in the default channel.  Returns the number of matching updates.
+    pub fn set_channels(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        channels: &[String],
+    ) -> Result<usize> {
+        for channel in channels {
+            ensure!(
+                !channel.is_empty()
+                    && channel
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-'),
+                error::InvalidChannel { channel }
+            );
+        }
+        let matching =
+            self.get_matching_updates(variant.clone(), arch.clone(), image_version.clone());
+        let num_matching = matching.len();
+        ensure!(
+            num_matching > 0,
+            error::UpdateNotFound {
+                variant,
+                arch,
+                version: image_version,
+            }
+        );
+
+        for update in matching {
+            update.channels = channels.to_vec();
+        }
+        Ok(num_matching)
+    }
 }
 
 impl Update {
+    /// Returns whether the update is published to the given channel.
+    #[must_use]
+    pub fn in_channel(&self, channel: &str) -> bool {
+        if self.channels.is_empty() {
+            channel == DEFAULT_CHANNEL
+        } else {
+            self.channels.iter().any(|c| c == channel)
+        }
+    }
+
+    /// Returns the delta that produces this update's images from the given version, if any.
+    #[must_use]
+    pub fn delta_from(&self, version: &Version) -> Option<&Delta> {
+        self.deltas.iter().find(|delta| delta.from == *version)
+    }
+
     /// Returns the update wave that Updog belongs to, based on the seed value.
     /// Depending on the waves described in the update, the possible results are
     /// - Some wave described by a start and end time, and the starting seed and ending seed.
@@ -338,6 +617,33 @@ impl Update {
         }
     }
 
+    /// Returns when a host with the given labels can update because it's in a targeted wave, if it
+    /// is in one.  If it's in several, the earliest start time wins.
+    #[must_use]
+    pub fn targeted_start(&self, labels: &BTreeMap<String, String>) -> Option<DateTime<Utc>> {
+        self.targeted_waves
+            .iter()
+            .filter(|wave| wave.matches(labels))
+            .map(|wave| wave.start_time)
+            .min()
+    }
+
+    /// Returns whether the update is available to a host with the given seed and labels.  Hosts in
+    /// a targeted wave are ready once it starts, and not before, whatever their seed; otherwise,
+    /// this is the same as `update_ready`.
+    #[must_use]
+    pub fn update_ready_for(
+        &self,
+        seed: u32,
+        labels: &BTreeMap<String, String>,
+        time: DateTime<Utc>,
+    ) -> bool {
+        match self.targeted_start(labels) {
+            Some(start_time) => start_time <= time,
+            None => self.update_ready(seed, time),
+        }
+    }
+
     /// Returns whether the update is available. An update is said to be 'ready/available' if the wave
     /// this host belongs to has fully passed, or if the host's position in the wave has passed, or
     /// if there are no waves.
@@ -470,11 +776,15 @@ mod tests {
             version: Version::parse("1.1.1").unwrap(),
             max_version: Version::parse("1.1.1").unwrap(),
             waves: BTreeMap::new(),
+            targeted_waves: Vec::new(),
             images: Images {
                 boot: String::from("boot"),
                 root: String::from("root"),
                 hash: String::from("hash"),
             },
+            deltas: Vec::new(),
+            release_info: ReleaseInfo::default(),
+            channels: Vec::new(),
         }
     }
 
@@ -635,11 +945,15 @@ mod tests {
             version: Version::parse("1.0.0").unwrap(),
             max_version: Version::parse("1.1.0").unwrap(),
             waves: BTreeMap::new(),
+            targeted_waves: Vec::new(),
             images: Images {
                 boot: String::from("boot"),
                 root: String::from("root"),
                 hash: String::from("hash"),
             },
+            deltas: Vec::new(),
+            release_info: ReleaseInfo::default(),
+            channels: Vec::new(),
         };
         let seed = 1024;
         // Construct a DateTime object for 1/1/2000 00:00:00
@@ -659,6 +973,138 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_channels() {
+        let mut manifest = Manifest::default();
+        manifest.updates.push(test_update());
+        let set = |manifest: &mut Manifest, channels: &[&str]| {
+            let channels: Vec<String> = channels.iter().map(|c| (*c).to_string()).collect();
+            manifest.set_channels(
+                "bottlerocket".to_string(),
+                "test".to_string(),
+                Version::parse("1.1.1").unwrap(),
+                &channels,
+            )
+        };
+
+        // Updates without channels are only in the default channel.
+        assert!(manifest.updates[0].in_channel(DEFAULT_CHANNEL));
+        assert!(!manifest.updates[0].in_channel("beta"));
+
+        set(&mut manifest, &["beta", "nightly"]).unwrap();
+        assert!(!manifest.updates[0].in_channel(DEFAULT_CHANNEL));
+        assert!(manifest.updates[0].in_channel("beta"));
+        assert!(manifest.updates[0].in_channel("nightly"));
+
+        set(&mut manifest, &[]).unwrap();
+        assert!(manifest.updates[0].in_channel(DEFAULT_CHANNEL));
+
+        assert!(set(&mut manifest, &["beta channel"]).is_err());
+        assert!(set(&mut manifest, &[""]).is_err());
+    }
+
+    #[test]
+    fn test_channel_updates_hidden_from_old_clients() {
+        let mut manifest = Manifest::default();
+        manifest.updates.push(test_update());
+        let mut beta = test_update();
+        beta.version = Version::parse("1.2.0").unwrap();
+        beta.channels = vec!["beta".to_string()];
+        manifest.updates.push(beta);
+
+        // Only the stable update is in the list older updog reads.
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["updates"].as_array().unwrap().len(), 1);
+        assert_eq!(json["updates"][0]["version"], "1.1.1");
+        assert_eq!(json["channel_updates"].as_array().unwrap().len(), 1);
+        assert_eq!(json["channel_updates"][0]["version"], "1.2.0");
+
+        // Both are loaded back into one list.
+        let manifest: Manifest = serde_json::from_value(json).unwrap();
+        assert_eq!(manifest.updates.len(), 2);
+        assert!(manifest.updates[1].in_channel("beta"));
+    }
+
+    #[test]
+    fn test_targeted_waves() {
+        let time = test_time();
+        let mut manifest = Manifest::default();
+        manifest.updates.push(test_update());
+        let waves: UpdateWaves = toml::from_str(
+            r#"
+            [[waves]]
+            start_after = "1 day"
+            fleet_percentage = 100
+
+            [[targeted_waves]]
+            start_after = "1 hour"
+            labels = { pool = "canary", zone = "a" }
+
+            [[targeted_waves]]
+            start_after = "2 hours"
+            labels = { pool = "canary" }
+            "#,
+        )
+        .unwrap();
+        manifest
+            .set_waves(
+                "bottlerocket".to_string(),
+                "test".to_string(),
+                Version::parse("1.1.1").unwrap(),
+                time,
+                &waves,
+            )
+            .unwrap();
+        let update = &manifest.updates[0];
+        assert_eq!(update.targeted_waves.len(), 2);
+
+        let labels = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect()
+        };
+        let zone_a = labels(&[("pool", "canary"), ("zone", "a")]);
+        let zone_b = labels(&[("pool", "canary"), ("zone", "b")]);
+        let other = labels(&[("pool", "general")]);
+
+        // Only the waves whose labels all match apply, and the earliest wins.
+        assert_eq!(
+            update.targeted_start(&zone_a),
+            Some(time + Duration::hours(1))
+        );
+        assert_eq!(
+            update.targeted_start(&zone_b),
+            Some(time + Duration::hours(2))
+        );
+        assert_eq!(update.targeted_start(&other), None);
+
+        let later = time + Duration::hours(3);
+        assert!(update.update_ready_for(MAX_SEED, &zone_b, later));
+        assert!(!update.update_ready_for(MAX_SEED, &other, later));
+        // Everyone is ready once the seed waves are done.
+        assert!(update.update_ready_for(MAX_SEED, &other, time + Duration::days(2)));
+
+        // A targeted wave has to say who it targets.
+        let waves: UpdateWaves = toml::from_str(
+            r#"
+            [[targeted_waves]]
+            start_after = "1 hour"
+            labels = {}
+            "#,
+        )
+        .unwrap();
+        assert!(manifest
+            .set_waves(
+                "bottlerocket".to_string(),
+                "test".to_string(),
+                Version::parse("1.1.1").unwrap(),
+                time,
+                &waves,
+            )
+            .is_err());
+    }
+
     #[test]
     fn test_migrations_forward() {
         // A manifest with four migration tuples starting at 1.0 and ending at 1.3.
@@ -691,4 +1137,60 @@ mod tests {
         assert!(i.next().unwrap() == "migration_1.1.0_b");
         assert!(i.next().unwrap() == "migration_1.1.0_a");
     }
+
+    #[test]
+    fn test_release_info() {
+        let mut manifest = Manifest::default();
+        manifest.updates.push(test_update());
+        let version = Version::parse("1.1.1").unwrap();
+        let release_info = ReleaseInfo {
+            size: Some(1_234_567),
+            release_notes_url: Some("https://example.com/release-notes".to_string()),
+            release_notes: Some("Fixes sudo".to_string()),
+            cves: vec!["CVE-2021-3156".to_string()],
+            urgent: true,
+        };
+        let matching = manifest
+            .set_release_info(
+                "bottlerocket".to_string(),
+                "test".to_string(),
+                version.clone(),
+                &release_info,
+            )
+            .unwrap();
+        assert_eq!(matching, 1);
+
+        // The release information survives a round trip through JSON, next to the other fields.
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["updates"][0]["cves"][0], "CVE-2021-3156");
+        let manifest: Manifest = serde_json::from_value(json).unwrap();
+        assert_eq!(manifest.updates[0].release_info, release_info);
+
+        let bad = ReleaseInfo {
+            cves: vec!["CVE-21-3156".to_string()],
+            ..ReleaseInfo::default()
+        };
+        let mut manifest = manifest;
+        assert!(manifest
+            .set_release_info(
+                "bottlerocket".to_string(),
+                "test".to_string(),
+                version,
+                &bad
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_release_info_optional() {
+        // Manifests written before release information existed have none.
+        let path = "./tests/data/migrations.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        for update in &manifest.updates {
+            assert_eq!(update.release_info, ReleaseInfo::default());
+        }
+        let json = serde_json::to_value(test_update()).unwrap();
+        assert!(json.get("urgent").is_none());
+        assert!(json.get("cves").is_none());
+    }
 }
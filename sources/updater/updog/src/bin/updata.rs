@@ -12,10 +12,14 @@ use chrono::{DateTime, Utc};
 use semver::Version;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use snafu::{ErrorCompat, OptionExt, ResultExt};
-use std::fs;
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
-use update_metadata::{Images, Manifest, Release, UpdateWaves};
+use update_metadata::{
+    delta, Delta, DeltaImage, Images, Manifest, Release, ReleaseInfo, UpdateWaves,
+};
 
 #[derive(Debug, StructOpt)]
 struct GeneralArgs {
@@ -212,6 +216,224 @@ impl MigrationArgs {
     }
 }
 
+#[derive(Debug, StructOpt)]
+struct MakeDeltaArgs {
+    // image from the version being updated from; decompressed first if it ends in .lz4
+    #[structopt(short = "s", long = "source")]
+    source: PathBuf,
+
+    // image from the version being updated to; decompressed first if it ends in .lz4
+    #[structopt(short = "t", long = "target")]
+    target: PathBuf,
+
+    // file to write the LZ4-compressed delta to
+    #[structopt(short = "o", long = "output")]
+    output: PathBuf,
+}
+
+impl MakeDeltaArgs {
+    fn run(self) -> Result<()> {
+        let source = open_image(&self.source)?;
+        let target = open_image(&self.target)?;
+        let file = File::create(&self.output).context(error::CreateDelta { path: &self.output })?;
+        let mut encoder = lz4::EncoderBuilder::new()
+            .build(BufWriter::new(file))
+            .context(error::CreateDelta { path: &self.output })?;
+        let digest = delta::make(source, target, &mut encoder).context(error::Delta {
+            target: self.output.display().to_string(),
+        })?;
+        let (_output, result) = encoder.finish();
+        result.context(error::CreateDelta { path: &self.output })?;
+
+        // Print the target's digest so it can be given to add-delta.
+        println!("{}", digest);
+        Ok(())
+    }
+}
+
+/// Opens an image for reading, decompressing it if it has an .lz4 extension.
+fn open_image(path: &Path) -> Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path).context(error::OpenImage { path })?);
+    if path.extension() == Some(OsStr::new("lz4")) {
+        let decoder = lz4::Decoder::new(file).context(error::OpenImage { path })?;
+        Ok(Box::new(decoder))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct AddDeltaArgs {
+    // metadata file to create/modify
+    file: PathBuf,
+
+    // image 'variant', eg. 'aws-ecs-1'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // version the delta updates from
+    #[structopt(short = "f", long = "from")]
+    from: Version,
+
+    // root image delta target name
+    #[structopt(short = "r", long = "root")]
+    root: String,
+
+    // SHA-256 of the root image the delta produces, as printed by make-delta
+    #[structopt(long = "root-sha256")]
+    root_sha256: String,
+
+    // boot image delta target name
+    #[structopt(short = "b", long = "boot")]
+    boot: String,
+
+    // SHA-256 of the boot image the delta produces, as printed by make-delta
+    #[structopt(long = "boot-sha256")]
+    boot_sha256: String,
+
+    // verity "hash" image delta target name
+    #[structopt(short = "h", long = "hash")]
+    hash: String,
+
+    // SHA-256 of the verity "hash" image the delta produces, as printed by make-delta
+    #[structopt(long = "hash-sha256")]
+    hash_sha256: String,
+}
+
+impl AddDeltaArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+        let delta = Delta {
+            from: self.from,
+            root: DeltaImage {
+                target: self.root,
+                sha256: self.root_sha256,
+            },
+            boot: DeltaImage {
+                target: self.boot,
+                sha256: self.boot_sha256,
+            },
+            hash: DeltaImage {
+                target: self.hash,
+                sha256: self.hash_sha256,
+            },
+        };
+        let num_matching =
+            manifest.add_delta(self.variant, self.arch, self.image_version, &delta)?;
+        if num_matching > 1 {
+            warn!("Multiple matching updates for delta - this is weird but not a disaster");
+        }
+        update_metadata::write_file(&self.file, &manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ReleaseInfoArgs {
+    // metadata file to create/modify
+    file: PathBuf,
+
+    // image 'variant', eg. 'aws-ecs-1'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // total size of the update's images, in bytes
+    #[structopt(short = "s", long = "size")]
+    size: Option<u64>,
+
+    // where to find the release notes
+    #[structopt(short = "u", long = "release-notes-url")]
+    release_notes_url: Option<String>,
+
+    // short summary of the release notes
+    #[structopt(short = "n", long = "release-notes")]
+    release_notes: Option<String>,
+
+    // CVE fixed by the update, eg. 'CVE-2021-3156'; may be given more than once
+    #[structopt(short = "c", long = "cve", number_of_values = 1)]
+    cves: Vec<String>,
+
+    // whether the update should be applied as soon as possible
+    #[structopt(long = "urgent")]
+    urgent: bool,
+}
+
+impl ReleaseInfoArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+        let release_info = ReleaseInfo {
+            size: self.size,
+            release_notes_url: self.release_notes_url,
+            release_notes: self.release_notes,
+            cves: self.cves,
+            urgent: self.urgent,
+        };
+        let num_matching = manifest.set_release_info(
+            self.variant,
+            self.arch,
+            self.image_version,
+            &release_info,
+        )?;
+        if num_matching > 1 {
+            warn!("Multiple matching updates for release information - this is weird but not a disaster");
+        }
+        update_metadata::write_file(&self.file, &manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ChannelArgs {
+    // metadata file to create/modify
+    file: PathBuf,
+
+    // image 'variant', eg. 'aws-ecs-1'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // channel to publish the update to, eg. 'beta'; may be given more than once, and if not
+    // given, the update is only in the default 'stable' channel
+    #[structopt(short = "c", long = "channel", number_of_values = 1)]
+    channels: Vec<String>,
+}
+
+impl ChannelArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+        let num_matching =
+            manifest.set_channels(self.variant, self.arch, self.image_version, &self.channels)?;
+        if num_matching > 1 {
+            warn!("Multiple matching updates for channels - this is weird but not a disaster");
+        }
+        update_metadata::write_file(&self.file, &manifest)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, StructOpt)]
 struct MaxVersionArgs {
     // metadata file to create/modify
@@ -240,6 +462,14 @@ enum Command {
     AddUpdate(AddUpdateArgs),
     /// Set waves for an update
     SetWaves(WaveArgs),
+    /// Create a delta that produces one image from another
+    MakeDelta(MakeDeltaArgs),
+    /// Add a delta from an earlier version to an update
+    AddDelta(AddDeltaArgs),
+    /// Set the release notes, size, and security information of an update
+    SetReleaseInfo(ReleaseInfoArgs),
+    /// Set the channels an update is published to
+    SetChannels(ChannelArgs),
     /// Set the global maximum image version
     SetMaxVersion(MaxVersionArgs),
     /// Remove an update from the manifest, including wave information
@@ -264,6 +494,10 @@ fn main_inner() -> Result<()> {
         }
         Command::AddUpdate(args) => args.run(),
         Command::SetWaves(args) => args.set(),
+        Command::MakeDelta(args) => args.run(),
+        Command::AddDelta(args) => args.run(),
+        Command::SetReleaseInfo(args) => args.run(),
+        Command::SetChannels(args) => args.run(),
         Command::SetMaxVersion(args) => args.run(),
         Command::RemoveUpdate(args) => args.run(),
         Command::SetMigrations(args) => args.set(),
@@ -39,6 +39,13 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to write delta '{}': {}", path.display(), source))]
+    CreateDelta {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to create metadata cache directory '{}': {}", path, source))]
     CreateMetadataCache {
         path: &'static str,
@@ -46,6 +53,24 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to apply delta {}: {}", target, source))]
+    Delta {
+        target: String,
+        source: update_metadata::delta::Error,
+    },
+
+    #[snafu(display(
+        "Delta {} produced an image with SHA-256 {}, expected {}",
+        target,
+        actual,
+        expected
+    ))]
+    DeltaHash {
+        target: String,
+        expected: String,
+        actual: String,
+    },
+
     #[snafu(display("Failed to create directory: {:?}", path))]
     DirCreate {
         backtrace: Backtrace,
@@ -94,6 +119,36 @@ pub(crate) enum Error {
         name: String,
     },
 
+    #[snafu(display("Failed to open image {}: {}", path.display(), source))]
+    OpenImage {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to find local repository '{}': {}", path.display(), source))]
+    LocalRepository {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Unable to form URL for local repository path '{}'", path.display()))]
+    LocalRepositoryUrl { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("Failed to serialize download progress: {}", source))]
+    ProgressSerialize {
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to write download progress to '{}': {}", path.display(), source))]
+    ProgressWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to open partition {}: {}", path.display(), source))]
     OpenPartition {
         path: PathBuf,
@@ -162,6 +217,9 @@ pub(crate) enum Error {
     #[snafu(display("No update available"))]
     UpdateNotAvailable { backtrace: Backtrace },
 
+    #[snafu(display("Updates are disabled by settings.updates.mode"))]
+    UpdatesDisabled { backtrace: Backtrace },
+
     #[snafu(display("Failed to serialize update information: {}", source))]
     UpdateSerialize {
         source: serde_json::Error,
@@ -2,30 +2,35 @@
 #![warn(clippy::pedantic)]
 
 mod error;
+mod progress;
 mod transport;
 
 use crate::error::Result;
+use crate::progress::Progress;
 use crate::transport::{HttpQueryTransport, QueryParams};
 use bottlerocket_release::BottlerocketRelease;
 use chrono::Utc;
-use log::debug;
-use model::modeled_types::FriendlyVersion;
+use log::{debug, warn};
+use model::modeled_types::{DownloadRate, FriendlyVersion};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use signal_hook::consts::SIGTERM;
 use signal_hook::iterator::Signals;
-use signpost::State;
+use signpost::{PartitionSet, State};
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
-use snafu::{ErrorCompat, OptionExt, ResultExt};
+use snafu::{ensure, ErrorCompat, OptionExt, ResultExt};
+use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
 use std::fs::{self, File, OpenOptions};
-use std::io;
-use std::path::Path;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 use std::thread;
 use tough::{Repository, RepositoryLoader};
-use update_metadata::{find_migrations, Manifest, Update};
+use update_metadata::{
+    delta, find_migrations, Delta, DeltaImage, Manifest, Update, DEFAULT_CHANNEL,
+};
 use url::Url;
 
 #[cfg(target_arch = "x86_64")]
@@ -63,9 +68,49 @@ struct Config {
     ignore_waves: bool,
     https_proxy: Option<String>,
     no_proxy: Option<Vec<String>>,
-    // TODO API sourced configuration, eg.
-    // blacklist: Option<Vec<Version>>,
-    // mode: Option<{Automatic, Managed, Disabled}>
+    #[serde(default)]
+    mode: UpdateMode,
+    #[serde(default)]
+    deny_versions: Vec<FriendlyVersion>,
+    max_download_rate: Option<DownloadRate>,
+    #[serde(default)]
+    wave_labels: BTreeMap<String, String>,
+    channel: Option<String>,
+}
+
+impl Config {
+    /// The channel of updates to follow.
+    fn channel(&self) -> &str {
+        self.channel.as_deref().unwrap_or(DEFAULT_CHANNEL)
+    }
+
+    /// The labels that place this host in targeted waves.  The channel is included as the
+    /// "channel" label, unless there's an explicit label by that name.
+    fn wave_labels(&self) -> BTreeMap<String, String> {
+        let mut labels = self.wave_labels.clone();
+        labels
+            .entry("channel".to_string())
+            .or_insert_with(|| self.channel().to_string());
+        labels
+    }
+}
+
+/// How updog chooses updates, from `settings.updates.mode`.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum UpdateMode {
+    /// Update to the newest applicable version, or to the version-lock version if one is set.
+    Automatic,
+    /// Only update to a version that was explicitly chosen, through version-lock or --image.
+    Managed,
+    /// Don't update at all.
+    Disabled,
+}
+
+impl Default for UpdateMode {
+    fn default() -> Self {
+        UpdateMode::Automatic
+    }
 }
 
 /// Prints a more specific message before exiting through usage().
@@ -97,6 +142,7 @@ SUBCOMMANDS:
         [ -i | --image version ]      Update to a specfic image version
         [ -n | --now ]                Update immediately, ignoring wave limits
         [ -t | --timestamp time ]     The timestamp to execute an update from
+        [ --progress-file path ]      Write download progress to this file as JSON
 
     update-apply            Update boot flags (after having called update-image)
         [ -r | --reboot ]             Reboot after updating boot flags
@@ -105,7 +151,10 @@ SUBCOMMANDS:
 
 GLOBAL OPTIONS:
     [ -j | --json ]               JSON-formatted output
-    [ --log-level trace|debug|info|warn|error ]  Set logging verbosity");
+    [ --log-level trace|debug|info|warn|error ]  Set logging verbosity
+    [ --repository path ]         Use the TUF repository in this directory, with
+                                  'metadata' and 'targets' subdirectories,
+                                  instead of the configured URLs");
     std::process::exit(1)
 }
 
@@ -116,6 +165,25 @@ fn load_config() -> Result<Config> {
     Ok(config)
 }
 
+/// Points the config at a TUF repository on local disk, for example on removable media, laid out
+/// with `metadata` and `targets` directories like the output of `tuftool create`.  Metadata is
+/// still checked against our trusted root, so this is as safe as a remote repository.
+fn use_local_repository(config: &mut Config, path: &Path) -> Result<()> {
+    let path = path
+        .canonicalize()
+        .context(error::LocalRepository { path })?;
+    let dir_url = |dir: &str| {
+        Url::from_directory_path(path.join(dir))
+            .ok()
+            .context(error::LocalRepositoryUrl {
+                path: path.join(dir),
+            })
+    };
+    config.metadata_base_url = dir_url("metadata")?.to_string();
+    config.targets_base_url = dir_url("targets")?.to_string();
+    Ok(())
+}
+
 fn load_repository(transport: HttpQueryTransport, config: &Config) -> Result<Repository> {
     fs::create_dir_all(METADATA_PATH).context(error::CreateMetadataCache {
         path: METADATA_PATH,
@@ -140,8 +208,11 @@ fn applicable_updates<'a>(
     manifest: &'a Manifest,
     variant: &str,
     ignore_waves: bool,
-    seed: u32,
+    config: &Config,
+    deny_versions: &[Version],
 ) -> Vec<&'a Update> {
+    let channel = config.channel();
+    let wave_labels = config.wave_labels();
     let mut updates: Vec<&Update> = manifest
         .updates
         .iter()
@@ -149,7 +220,9 @@ fn applicable_updates<'a>(
             u.variant == *variant
                 && u.arch == TARGET_ARCH
                 && u.version <= u.max_version
-                && (ignore_waves || u.update_ready(seed, Utc::now()))
+                && u.in_channel(channel)
+                && !deny_versions.contains(&u.version)
+                && (ignore_waves || u.update_ready_for(config.seed, &wave_labels, Utc::now()))
         })
         .collect();
     // sort descending
@@ -157,22 +230,36 @@ fn applicable_updates<'a>(
     updates
 }
 
-// TODO use config if there is api-sourced configuration that could affect this
-// TODO updog.toml may include settings that cause us to ignore/delay
-// certain/any updates;
-//  Ignore Specific Target Version
-//  Ignore Any Target
-//  ...
+/// Converts the configured deny-list into versions we can compare against the manifest.
+fn denied_versions(config: &Config) -> Result<Vec<Version>> {
+    config
+        .deny_versions
+        .iter()
+        .map(|v| {
+            v.clone().try_into().context(error::BadVersion {
+                version_str: v.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the update we should move to, if any, based on the update mode, deny-list, and
+/// version-lock in the config.
 fn update_required<'a>(
     manifest: &'a Manifest,
     version: &Version,
     variant: &str,
     ignore_waves: bool,
-    seed: u32,
-    version_lock: &str,
+    config: &Config,
     force_version: Option<Version>,
 ) -> Result<Option<&'a Update>> {
-    let updates = applicable_updates(manifest, variant, ignore_waves, seed);
+    if config.mode == UpdateMode::Disabled {
+        return Ok(None);
+    }
+
+    let deny_versions = denied_versions(config)?;
+    let updates = applicable_updates(manifest, variant, ignore_waves, config, &deny_versions);
+    let version_lock = config.version_lock.as_str();
 
     if let Some(forced_version) = force_version {
         return Ok(updates.into_iter().find(|u| u.version == forced_version));
@@ -200,6 +287,11 @@ fn update_required<'a>(
         };
     }
 
+    // Without a version-lock, there's no explicitly chosen version to move to.
+    if config.mode == UpdateMode::Managed {
+        return Ok(None);
+    }
+
     for update in updates {
         // If the current running version is greater than the max version ever published,
         // or moves us to a valid version <= the maximum version, update.
@@ -214,11 +306,13 @@ fn write_target_to_disk<P: AsRef<Path>>(
     repository: &Repository,
     target: &str,
     disk_path: P,
+    progress: &mut Progress,
 ) -> Result<()> {
     let reader = repository
         .read_target(target)
         .context(error::Metadata)?
         .context(error::TargetNotFound { target })?;
+    let reader = progress.reader(reader);
     // Note: the file extension for the compression type we're using should be removed in
     // retrieve_migrations below.
     let mut reader = lz4::Decoder::new(reader).context(error::Lz4Decode { target })?;
@@ -267,7 +361,97 @@ fn retrieve_migrations(
     Ok(())
 }
 
-fn update_image(update: &Update, repository: &Repository) -> Result<()> {
+/// Builds the target image by applying a delta to the same image in the active partition set,
+/// and checks that the result is what the manifest says it should be.
+fn write_delta_to_disk<P: AsRef<Path>>(
+    repository: &Repository,
+    image: &DeltaImage,
+    source_path: P,
+    disk_path: P,
+    progress: &mut Progress,
+) -> Result<()> {
+    let target = &image.target;
+    let reader = repository
+        .read_target(target)
+        .context(error::Metadata)?
+        .context(error::TargetNotFound { target })?;
+    let reader = progress.reader(reader);
+    let reader = lz4::Decoder::new(reader).context(error::Lz4Decode { target })?;
+    let source = File::open(source_path.as_ref()).context(error::OpenPartition {
+        path: source_path.as_ref(),
+    })?;
+    let f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(disk_path.as_ref())
+        .context(error::OpenPartition {
+            path: disk_path.as_ref(),
+        })?;
+    let digest =
+        delta::apply(reader, BufReader::new(source), f).context(error::Delta { target })?;
+    ensure!(
+        digest == image.sha256,
+        error::DeltaHash {
+            target,
+            expected: &image.sha256,
+            actual: digest,
+        }
+    );
+    Ok(())
+}
+
+/// Writes all of the update's images using the given delta against the active partition set.
+fn write_delta_images(
+    repository: &Repository,
+    delta: &Delta,
+    active: &PartitionSet,
+    inactive: &PartitionSet,
+    progress: &mut Progress,
+) -> Result<()> {
+    progress.reset(targets_length(
+        repository,
+        &[&delta.root.target, &delta.boot.target, &delta.hash.target],
+    ));
+    write_delta_to_disk(
+        repository,
+        &delta.root,
+        &active.root,
+        &inactive.root,
+        progress,
+    )?;
+    write_delta_to_disk(
+        repository,
+        &delta.boot,
+        &active.boot,
+        &inactive.boot,
+        progress,
+    )?;
+    write_delta_to_disk(
+        repository,
+        &delta.hash,
+        &active.hash,
+        &inactive.hash,
+        progress,
+    )?;
+    Ok(())
+}
+
+/// Returns the total size of the given targets, as listed in the repository's metadata.
+fn targets_length(repository: &Repository, targets: &[&str]) -> u64 {
+    let listed = &repository.targets().signed.targets;
+    targets
+        .iter()
+        .filter_map(|target| listed.get(*target))
+        .map(|target| target.length)
+        .sum()
+}
+
+fn update_image(
+    update: &Update,
+    repository: &Repository,
+    current_version: &Version,
+    progress: &mut Progress,
+) -> Result<()> {
     let mut gpt_state = State::load().context(error::PartitionTableRead)?;
     gpt_state.clear_inactive();
     // Write out the clearing of the inactive partition immediately, because we're about to
@@ -275,12 +459,38 @@ fn update_image(update: &Update, repository: &Repository) -> Result<()> {
     // know we're done with all components.
     gpt_state.write().context(error::PartitionTableWrite)?;
 
+    let active = gpt_state.active_set();
     let inactive = gpt_state.inactive_set();
 
+    // If there's a delta from the running version, it's much less to download.  Anything going
+    // wrong with it, including the result not matching the expected hash, means we fall back to
+    // the full images, which overwrite whatever the delta wrote.
+    let wrote_delta = match update.delta_from(current_version) {
+        Some(delta) => match write_delta_images(repository, delta, active, inactive, progress) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    "Unable to update using delta from {}, downloading full images: {}",
+                    current_version, e
+                );
+                false
+            }
+        },
+        None => false,
+    };
+
     // TODO Do we want to recover the inactive side on an error?
-    write_target_to_disk(repository, &update.images.root, &inactive.root)?;
-    write_target_to_disk(repository, &update.images.boot, &inactive.boot)?;
-    write_target_to_disk(repository, &update.images.hash, &inactive.hash)?;
+    if !wrote_delta {
+        let images = &update.images;
+        progress.reset(targets_length(
+            repository,
+            &[&images.root, &images.boot, &images.hash],
+        ));
+        write_target_to_disk(repository, &images.root, &inactive.root, progress)?;
+        write_target_to_disk(repository, &images.boot, &inactive.boot, progress)?;
+        write_target_to_disk(repository, &images.hash, &inactive.hash, progress)?;
+    }
+    progress.finish();
 
     gpt_state.mark_inactive_valid();
     gpt_state.write().context(error::PartitionTableWrite)?;
@@ -320,9 +530,14 @@ fn list_updates(
     variant: &str,
     json: bool,
     ignore_waves: bool,
-    seed: u32,
+    config: &Config,
 ) -> Result<()> {
-    let updates = applicable_updates(manifest, variant, ignore_waves, seed);
+    let updates = if config.mode == UpdateMode::Disabled {
+        Vec::new()
+    } else {
+        let deny_versions = denied_versions(config)?;
+        applicable_updates(manifest, variant, ignore_waves, config, &deny_versions)
+    };
     if json {
         println!(
             "{}",
@@ -346,6 +561,8 @@ struct Arguments {
     all: bool,
     reboot: bool,
     variant: Option<String>,
+    progress_file: Option<PathBuf>,
+    repository: Option<PathBuf>,
 }
 
 /// Parse the command line arguments to get the user-specified values
@@ -358,6 +575,8 @@ fn parse_args(args: std::env::Args) -> Arguments {
     let mut all = false;
     let mut reboot = false;
     let mut variant = None;
+    let mut progress_file = None;
+    let mut repository = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -383,6 +602,18 @@ fn parse_args(args: std::env::Args) -> Arguments {
                         .unwrap_or_else(|| usage_msg("Did not give argument to --variant")),
                 );
             }
+            "--progress-file" => {
+                progress_file =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --progress-file")
+                    })));
+            }
+            "--repository" => {
+                repository =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --repository")
+                    })));
+            }
             "-n" | "--now" | "--ignore-waves" => {
                 ignore_waves = true;
             }
@@ -415,6 +646,8 @@ fn parse_args(args: std::env::Args) -> Arguments {
         all,
         reboot,
         variant,
+        progress_file,
+        repository,
     }
 }
 
@@ -493,11 +726,17 @@ fn main_inner() -> Result<()> {
     let command =
         serde_plain::from_str::<Command>(&arguments.subcommand).unwrap_or_else(|_| usage());
 
-    let config = load_config()?;
+    let mut config = load_config()?;
+    if let Some(path) = &arguments.repository {
+        use_local_repository(&mut config, path)?;
+    }
     set_https_proxy_environment_variables(&config.https_proxy, &config.no_proxy)?;
     let current_release = BottlerocketRelease::new().context(error::ReleaseVersion)?;
     let variant = arguments.variant.unwrap_or(current_release.variant_id);
-    let transport = HttpQueryTransport::new();
+    let mut transport = HttpQueryTransport::new();
+    if let Some(rate) = &config.max_download_rate {
+        transport = transport.with_max_download_rate(rate.bytes_per_second());
+    }
     // get a shared pointer to the transport's query_params so we can add metrics information to
     // the transport's HTTP calls.
     let mut query_params = transport.query_params();
@@ -508,13 +747,7 @@ fn main_inner() -> Result<()> {
     match command {
         Command::CheckUpdate | Command::Whats => {
             if arguments.all {
-                return list_updates(
-                    &manifest,
-                    &variant,
-                    arguments.json,
-                    ignore_waves,
-                    config.seed,
-                );
+                return list_updates(&manifest, &variant, arguments.json, ignore_waves, &config);
             }
 
             let update = update_required(
@@ -522,8 +755,7 @@ fn main_inner() -> Result<()> {
                 &current_release.version_id,
                 &variant,
                 ignore_waves,
-                config.seed,
-                &config.version_lock,
+                &config,
                 arguments.force_version,
             )?
             .context(error::UpdateNotAvailable)?;
@@ -531,13 +763,13 @@ fn main_inner() -> Result<()> {
             output(arguments.json, &update, &fmt_full_version(&update))?;
         }
         Command::Update | Command::UpdateImage => {
+            ensure!(config.mode != UpdateMode::Disabled, error::UpdatesDisabled);
             if let Some(u) = update_required(
                 &manifest,
                 &current_release.version_id,
                 &variant,
                 ignore_waves,
-                config.seed,
-                &config.version_lock,
+                &config,
                 arguments.force_version,
             )? {
                 eprintln!("Starting update to {}", u.version);
@@ -549,7 +781,8 @@ fn main_inner() -> Result<()> {
                     u,
                     &current_release.version_id,
                 )?;
-                update_image(u, &repository)?;
+                let mut progress = Progress::new(arguments.progress_file);
+                update_image(u, &repository, &current_release.version_id, &mut progress)?;
                 if command == Command::Update {
                     update_flags()?;
                     if arguments.reboot {
@@ -566,6 +799,7 @@ fn main_inner() -> Result<()> {
             }
         }
         Command::UpdateApply => {
+            ensure!(config.mode != UpdateMode::Disabled, error::UpdatesDisabled);
             update_flags()?;
             if arguments.reboot {
                 initiate_reboot()?;
@@ -614,7 +848,6 @@ fn main() -> ! {
 mod tests {
     use super::*;
     use chrono::Duration as TestDuration;
-    use std::collections::BTreeMap;
     use update_metadata::Images;
 
     #[test]
@@ -667,6 +900,11 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            mode: UpdateMode::Automatic,
+            deny_versions: Vec::new(),
+            max_download_rate: None,
+            wave_labels: BTreeMap::new(),
+            channel: None,
         };
         let version = Version::parse("1.18.0").unwrap();
         let variant = String::from("bottlerocket-aws-eks");
@@ -677,8 +915,7 @@ mod tests {
                 &version,
                 &variant,
                 config.ignore_waves,
-                config.seed,
-                &config.version_lock,
+                &config,
                 None
             )
             .unwrap()
@@ -700,6 +937,11 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            mode: UpdateMode::Automatic,
+            deny_versions: Vec::new(),
+            max_download_rate: None,
+            wave_labels: BTreeMap::new(),
+            channel: None,
         };
 
         let version = Version::parse("0.1.3").unwrap();
@@ -709,8 +951,7 @@ mod tests {
             &version,
             &variant,
             config.ignore_waves,
-            config.seed,
-            &config.version_lock,
+            &config,
             None,
         )
         .unwrap();
@@ -738,6 +979,11 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            mode: UpdateMode::Automatic,
+            deny_versions: Vec::new(),
+            max_download_rate: None,
+            wave_labels: BTreeMap::new(),
+            channel: None,
         };
 
         let version = Version::parse("1.10.0").unwrap();
@@ -747,8 +993,7 @@ mod tests {
             &version,
             &variant,
             config.ignore_waves,
-            config.seed,
-            &config.version_lock,
+            &config,
             None,
         )
         .unwrap();
@@ -764,6 +1009,131 @@ mod tests {
         }
     }
 
+    #[test]
+    fn deny_versions() {
+        // Using the same manifest as test_multiple, denying 1.15.0 should make us pick the next
+        // newest update, 1.13.0.
+        let path = "tests/data/multiple.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 123,
+            version_lock: "latest".to_string(),
+            ignore_waves: false,
+            https_proxy: None,
+            no_proxy: None,
+            mode: UpdateMode::Automatic,
+            deny_versions: vec![FriendlyVersion::try_from("v1.15.0").unwrap()],
+            max_download_rate: None,
+            wave_labels: BTreeMap::new(),
+            channel: None,
+        };
+
+        let version = Version::parse("1.10.0").unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+        let result = update_required(&manifest, &version, &variant, false, &config, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.version, Version::parse("1.13.0").unwrap());
+
+        // A denied version can't be chosen through version-lock or --image either.
+        let forced = Version::parse("1.15.0").unwrap();
+        assert!(
+            update_required(&manifest, &version, &variant, false, &config, Some(forced))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn update_modes() {
+        let path = "tests/data/multiple.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let mut config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 123,
+            version_lock: "latest".to_string(),
+            ignore_waves: false,
+            https_proxy: None,
+            no_proxy: None,
+            mode: UpdateMode::Managed,
+            deny_versions: Vec::new(),
+            max_download_rate: None,
+            wave_labels: BTreeMap::new(),
+            channel: None,
+        };
+        let version = Version::parse("1.10.0").unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+
+        // Managed mode doesn't chase the latest version...
+        assert!(
+            update_required(&manifest, &version, &variant, false, &config, None)
+                .unwrap()
+                .is_none()
+        );
+        // ...but does move to a locked version.
+        config.version_lock = "v1.13.0".to_string();
+        let result = update_required(&manifest, &version, &variant, false, &config, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.version, Version::parse("1.13.0").unwrap());
+
+        // Disabled mode doesn't update at all.
+        config.mode = UpdateMode::Disabled;
+        let forced = Version::parse("1.15.0").unwrap();
+        assert!(
+            update_required(&manifest, &version, &variant, false, &config, Some(forced))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn config_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "foo"
+            targets_base_url = "bar"
+            seed = 123
+            version_lock = "latest"
+            ignore_waves = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mode, UpdateMode::Automatic);
+        assert!(config.deny_versions.is_empty());
+    }
+
+    #[test]
+    fn local_repository() {
+        let mut config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "https://example.com/metadata/"
+            targets_base_url = "https://example.com/targets/"
+            seed = 123
+            version_lock = "latest"
+            ignore_waves = false
+            "#,
+        )
+        .unwrap();
+        let repo = tempfile::tempdir().unwrap();
+        use_local_repository(&mut config, repo.path()).unwrap();
+
+        let expected = Url::from_directory_path(repo.path().canonicalize().unwrap()).unwrap();
+        assert_eq!(
+            config.metadata_base_url,
+            expected.join("metadata/").unwrap().to_string()
+        );
+        assert_eq!(
+            config.targets_base_url,
+            expected.join("targets/").unwrap().to_string()
+        );
+
+        assert!(use_local_repository(&mut config, &repo.path().join("missing")).is_err());
+    }
+
     #[test]
     fn force_update_version() {
         // A manifest with four updates; two valid, one which exceeds the max
@@ -780,6 +1150,11 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            mode: UpdateMode::Automatic,
+            deny_versions: Vec::new(),
+            max_download_rate: None,
+            wave_labels: BTreeMap::new(),
+            channel: None,
         };
 
         let version = Version::parse("1.10.0").unwrap();
@@ -790,8 +1165,7 @@ mod tests {
             &version,
             &variant,
             config.ignore_waves,
-            config.seed,
-            &config.version_lock,
+            &config,
             Some(forced),
         )
         .unwrap();
@@ -845,17 +1219,21 @@ mod tests {
             version: Version::parse("1.1.1").unwrap(),
             max_version: Version::parse("1.1.1").unwrap(),
             waves: BTreeMap::new(),
+            targeted_waves: Vec::new(),
             images: Images {
                 boot: String::from("boot"),
                 root: String::from("boot"),
                 hash: String::from("boot"),
             },
+            deltas: Vec::new(),
+            release_info: update_metadata::ReleaseInfo::default(),
+            channels: Vec::new(),
         };
 
         let current_version = Version::parse("1.0.0").unwrap();
         let variant = String::from("aws-k8s-1.15");
         let first_wave_seed = 0;
-        let config = Config {
+        let mut config = Config {
             metadata_base_url: String::from("foo"),
             targets_base_url: String::from("bar"),
             seed: first_wave_seed,
@@ -863,6 +1241,11 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            mode: UpdateMode::Automatic,
+            deny_versions: Vec::new(),
+            max_download_rate: None,
+            wave_labels: BTreeMap::new(),
+            channel: None,
         };
 
         // Two waves; the 1st wave that starts immediately, and the final wave which starts in one hour
@@ -878,8 +1261,7 @@ mod tests {
                 &current_version,
                 &variant,
                 config.ignore_waves,
-                config.seed,
-                &config.version_lock,
+                &config,
                 None,
             )
             .unwrap()
@@ -887,19 +1269,99 @@ mod tests {
             "1st wave doesn't appear ready"
         );
 
+        config.seed = 2000;
         assert!(
             update_required(
                 &manifest,
                 &current_version,
                 &variant,
                 config.ignore_waves,
-                2000,
-                &config.version_lock,
+                &config,
                 None,
             )
             .unwrap()
             .is_none(),
             "Later wave incorrectly sees update"
         );
+
+        // Hosts labeled for a targeted wave that has started see the update, whatever their seed.
+        manifest.updates[0]
+            .targeted_waves
+            .push(update_metadata::TargetedWave {
+                labels: vec![("pool".to_string(), "canary".to_string())]
+                    .into_iter()
+                    .collect(),
+                start_time: time,
+            });
+        config
+            .wave_labels
+            .insert("pool".to_string(), "canary".to_string());
+        assert!(
+            update_required(
+                &manifest,
+                &current_version,
+                &variant,
+                config.ignore_waves,
+                &config,
+                None,
+            )
+            .unwrap()
+            .is_some(),
+            "Targeted wave doesn't appear ready"
+        );
+    }
+
+    #[test]
+    /// Make sure hosts only see updates in the channel they follow.
+    fn check_update_channels() {
+        let mut manifest = Manifest::default();
+        manifest.updates.push(Update {
+            variant: String::from("aws-k8s-1.15"),
+            arch: String::from(TARGET_ARCH),
+            version: Version::parse("1.1.1").unwrap(),
+            max_version: Version::parse("1.1.1").unwrap(),
+            waves: BTreeMap::new(),
+            targeted_waves: Vec::new(),
+            images: Images {
+                boot: String::from("boot"),
+                root: String::from("boot"),
+                hash: String::from("boot"),
+            },
+            deltas: Vec::new(),
+            release_info: update_metadata::ReleaseInfo::default(),
+            channels: vec![String::from("beta")],
+        });
+
+        let current_version = Version::parse("1.0.0").unwrap();
+        let variant = String::from("aws-k8s-1.15");
+        let mut config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 0,
+            version_lock: "latest".to_string(),
+            ignore_waves: false,
+            https_proxy: None,
+            no_proxy: None,
+            mode: UpdateMode::Automatic,
+            deny_versions: Vec::new(),
+            max_download_rate: None,
+            wave_labels: BTreeMap::new(),
+            channel: None,
+        };
+        assert!(
+            update_required(&manifest, &current_version, &variant, false, &config, None)
+                .unwrap()
+                .is_none(),
+            "Default channel sees beta update"
+        );
+
+        config.channel = Some(String::from("beta"));
+        assert!(
+            update_required(&manifest, &current_version, &variant, false, &config, None)
+                .unwrap()
+                .is_some(),
+            "Beta channel doesn't see beta update"
+        );
+        assert_eq!(config.wave_labels()["channel"], "beta");
     }
 }
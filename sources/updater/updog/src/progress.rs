@@ -0,0 +1,99 @@
+use crate::error::{self, Result};
+use log::warn;
+use serde::Serialize;
+use snafu::ResultExt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How often we write out progress while downloading.
+const WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks how many bytes of an update have been downloaded, and writes it to a file that callers
+/// like thar-be-updates can watch.  Without a file, this just counts.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Progress {
+    bytes_downloaded: u64,
+    bytes_total: u64,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    last_write: Option<Instant>,
+}
+
+impl Progress {
+    pub(crate) fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            ..Self::default()
+        }
+    }
+
+    /// Starts counting again toward a new total, for example when falling back from a delta to
+    /// full images.
+    pub(crate) fn reset(&mut self, bytes_total: u64) {
+        self.bytes_downloaded = 0;
+        self.bytes_total = bytes_total;
+        self.report();
+    }
+
+    /// Writes out the final progress once downloads are done.
+    pub(crate) fn finish(&mut self) {
+        self.report();
+    }
+
+    /// Counts downloaded bytes, writing out the progress if it's been long enough since the last
+    /// write.
+    fn add(&mut self, bytes: u64) {
+        self.bytes_downloaded += bytes;
+        let due = match self.last_write {
+            Some(last) => last.elapsed() >= WRITE_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.report();
+        }
+    }
+
+    /// Failing to report progress shouldn't fail the download, so errors are only logged.
+    fn report(&mut self) {
+        if let Err(e) = self.write() {
+            warn!("{}", e);
+        }
+    }
+
+    /// Writes out the current progress.  The file is replaced atomically so readers never see
+    /// a partial write.
+    fn write(&mut self) -> Result<()> {
+        self.last_write = Some(Instant::now());
+        if let Some(path) = &self.path {
+            let tmp_path = path.with_extension("tmp");
+            let data = serde_json::to_vec(&self).context(error::ProgressSerialize)?;
+            fs::write(&tmp_path, data).context(error::ProgressWrite { path: &tmp_path })?;
+            fs::rename(&tmp_path, path).context(error::ProgressWrite { path })?;
+        }
+        Ok(())
+    }
+
+    /// Wraps a reader so that everything read from it counts as downloaded.
+    pub(crate) fn reader<R: Read>(&mut self, inner: R) -> ProgressRead<'_, R> {
+        ProgressRead {
+            inner,
+            progress: self,
+        }
+    }
+}
+
+pub(crate) struct ProgressRead<'a, R> {
+    inner: R,
+    progress: &'a mut Progress,
+}
+
+impl<R: Read> Read for ProgressRead<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.progress.add(count as u64);
+        Ok(count)
+    }
+}
@@ -1,19 +1,33 @@
+use std::convert::TryFrom;
+use std::io::{self, Read};
 use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use log::error;
-use tough::{HttpTransport, Transport, TransportError};
+use tough::{FilesystemTransport, HttpTransport, HttpTransportBuilder, Transport, TransportError};
 use url::Url;
 
+/// How many times we try a download, including picking it back up with a range request after the
+/// connection drops, before giving up.  Large images take a while, especially with a rate limit,
+/// so we allow for longer outages than `HttpTransport` does by default.
+const TRIES: u32 = 10;
+/// How long we wait before the first retry; this doubles with each retry, up to `MAX_BACKOFF`.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
 /// A shared pointer to a list of query params that the transport will add to HTTP calls.
 #[derive(Debug, Clone, Default)]
 pub(crate) struct QueryParams(Arc<RwLock<Vec<(String, String)>>>);
 
-/// A `tough` `Transport` that allows us to add query parameters to HTTP calls.
+/// A `tough` `Transport` that allows us to add query parameters to HTTP calls.  Repositories on
+/// local disk, given as `file://` URLs, are read directly instead.
 #[derive(Debug, Clone)]
 #[allow(clippy::module_name_repetitions)]
 pub(crate) struct HttpQueryTransport {
     pub inner: HttpTransport,
     parameters: QueryParams,
+    max_download_rate: Option<u64>,
 }
 
 impl QueryParams {
@@ -56,11 +70,23 @@ impl QueryParams {
 impl HttpQueryTransport {
     pub fn new() -> Self {
         Self {
-            inner: HttpTransport::default(),
+            inner: HttpTransportBuilder::new()
+                .tries(TRIES)
+                .initial_backoff(INITIAL_BACKOFF)
+                .max_backoff(MAX_BACKOFF)
+                .backoff_factor(2.0)
+                .build(),
             parameters: QueryParams::default(),
+            max_download_rate: None,
         }
     }
 
+    /// Limit all downloads through this transport to the given number of bytes per second.
+    pub fn with_max_download_rate(mut self, bytes_per_second: u64) -> Self {
+        self.max_download_rate = Some(bytes_per_second);
+        self
+    }
+
     /// Obtain a shared pointer to the query params for this transport.
     pub fn query_params(&self) -> QueryParams {
         QueryParams(Arc::clone(&self.parameters.0))
@@ -72,6 +98,148 @@ impl Transport for HttpQueryTransport {
         &self,
         url: Url,
     ) -> std::result::Result<Box<dyn std::io::Read + Send>, TransportError> {
-        self.inner.fetch(self.parameters.add_params_to_url(url))
+        if url.scheme() == "file" {
+            return FilesystemTransport.fetch(url);
+        }
+        // HttpTransport resumes interrupted downloads with range requests.  It only remembers how
+        // far it got in memory, so a download doesn't survive restarting updog or rebooting.
+        let reader = self.inner.fetch(self.parameters.add_params_to_url(url))?;
+        match self.max_download_rate {
+            Some(bytes_per_second) => Ok(Box::new(RateLimitedRead::new(reader, bytes_per_second))),
+            None => Ok(Box::new(reader)),
+        }
+    }
+}
+
+/// A `Read` that sleeps as needed to keep the average rate of reading at or below a limit.
+struct RateLimitedRead<R> {
+    inner: R,
+    bytes_per_second: u64,
+    start: Instant,
+    bytes_read: u64,
+}
+
+impl<R> RateLimitedRead<R> {
+    fn new(inner: R, bytes_per_second: u64) -> Self {
+        Self {
+            inner,
+            bytes_per_second,
+            start: Instant::now(),
+            bytes_read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for RateLimitedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Don't read more than a second's worth at a time so the rate stays smooth.
+        let limit = usize::try_from(self.bytes_per_second).unwrap_or(usize::MAX);
+        let len = std::cmp::min(buf.len(), limit);
+        let count = self.inner.read(&mut buf[..len])?;
+        self.bytes_read += count as u64;
+
+        #[allow(clippy::cast_precision_loss)]
+        let expected =
+            Duration::from_secs_f64(self.bytes_read as f64 / self.bytes_per_second as f64);
+        if let Some(wait) = expected.checked_sub(self.start.elapsed()) {
+            thread::sleep(wait);
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpQueryTransport, RateLimitedRead};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tough::Transport;
+    use url::Url;
+
+    #[test]
+    fn local_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1.root.json");
+        std::fs::write(&path, "{}").unwrap();
+
+        let transport = HttpQueryTransport::new();
+        transport.query_params().add("seed", "123");
+        let mut contents = String::new();
+        transport
+            .fetch(Url::from_file_path(&path).unwrap())
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "{}");
+    }
+
+    #[test]
+    fn rate_limited_read() {
+        let data = vec![7_u8; 3000];
+        let mut reader = RateLimitedRead::new(data.as_slice(), 10_000);
+        let start = Instant::now();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+        // 3000 bytes at 10000 bytes per second should take at least 0.3 seconds.
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn resumes_interrupted_download() {
+        let data: Vec<u8> = (0..200_u8).collect();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image", listener.local_addr().unwrap());
+
+        let body = data.clone();
+        let server = thread::spawn(move || {
+            let mut ranges = Vec::new();
+            for (i, stream) in listener.incoming().take(2).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("range: ") {
+                        range = Some(value.to_string());
+                    }
+                }
+                ranges.push(range);
+                if i == 0 {
+                    // Promise the whole file, then drop the connection halfway through.
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n\r\n",
+                        body.len()
+                    )
+                    .unwrap();
+                    stream.write_all(&body[..100]).unwrap();
+                } else {
+                    write!(
+                        stream,
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: 100\r\nContent-Range: bytes 100-199/200\r\nAccept-Ranges: bytes\r\n\r\n"
+                    )
+                    .unwrap();
+                    stream.write_all(&body[100..]).unwrap();
+                }
+            }
+            ranges
+        });
+
+        let mut output = Vec::new();
+        HttpQueryTransport::new()
+            .fetch(Url::parse(&url).unwrap())
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, data);
+        assert_eq!(
+            server.join().unwrap(),
+            vec![None, Some("bytes=100-".to_string())]
+        );
     }
 }
@@ -51,6 +51,9 @@ pub(crate) struct RepoArgs {
     #[structopt(long)]
     /// The variant of the update being added
     variant: String,
+    #[structopt(long = "channel")]
+    /// Channels to publish the update to, like "beta"; if none are given, it's only in "stable"
+    channels: Vec<String>,
 
     // The images to add in this update
     #[structopt(long, parse(from_os_str))]
@@ -99,7 +102,7 @@ pub(crate) struct RepoArgs {
     outdir: PathBuf,
 }
 
-/// Adds update, migrations, and waves to the Manifest
+/// Adds update, channels, migrations, and waves to the Manifest
 fn update_manifest(repo_args: &RepoArgs, manifest: &mut Manifest) -> Result<()> {
     // Add update   =^..^=   =^..^=   =^..^=   =^..^=
 
@@ -132,6 +135,21 @@ fn update_manifest(repo_args: &RepoArgs, manifest: &mut Manifest) -> Result<()>
         )
         .context(error::AddUpdate)?;
 
+    if !repo_args.channels.is_empty() {
+        info!(
+            "Publishing update to channels: {}",
+            repo_args.channels.join(", ")
+        );
+        manifest
+            .set_channels(
+                repo_args.variant.clone(),
+                repo_args.arch.clone(),
+                repo_args.version.clone(),
+                &repo_args.channels,
+            )
+            .context(error::SetChannels)?;
+    }
+
     // Add migrations   =^..^=   =^..^=   =^..^=   =^..^=
 
     info!(
@@ -708,6 +726,11 @@ mod error {
             source: tough::error::Error,
         },
 
+        #[snafu(display("Failed to set channels: {}", source))]
+        SetChannels {
+            source: update_metadata::error::Error,
+        },
+
         #[snafu(display("Failed to set targets expiration to {}: {}", expiration, source))]
         SetTargetsExpiration {
             expiration: DateTime<Utc>,